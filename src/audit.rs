@@ -0,0 +1,76 @@
+use std::fmt::Write;
+
+use crate::{Cluster, Lba};
+
+/* ==== STRUCTS ============================================================= */
+/** A single change made to the image, see FsOptions::audit. Paths are the
+ *  ones given to the call that made the change. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mutation {
+    EntryCreated { path: String },
+    EntryUpdated { path: String },
+    EntryRemoved { path: String },
+    ClustersAllocated(Vec<Cluster>),
+    ClustersFreed(Vec<Cluster>),
+
+    /** Sectors written, to the file or to the overlay. */
+    SectorsWritten { first: Lba, count: u64 },
+
+    /** Sectors of the overlay written to the file. */
+    Committed { sectors: usize },
+    Discarded { sectors: usize }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    /** Unix time, from the time source of the image. */
+    pub timestamp: u64,
+    pub mutation: Mutation
+}
+
+impl Mutation {
+    /** Short stable name of the kind of change, e.g. "entry_created". */
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Mutation::EntryCreated { .. } => "entry_created",
+            Mutation::EntryUpdated { .. } => "entry_updated",
+            Mutation::EntryRemoved { .. } => "entry_removed",
+            Mutation::ClustersAllocated(_) => "clusters_allocated",
+            Mutation::ClustersFreed(_) => "clusters_freed",
+            Mutation::SectorsWritten { .. } => "sectors_written",
+            Mutation::Committed { .. } => "committed",
+            Mutation::Discarded { .. } => "discarded"
+        }
+    }
+}
+
+impl AuditRecord {
+    /** Single line JSON object, e.g. {"time":0,"event":"entry_created","path":"/A.TXT"}. */
+    pub fn to_json(&self) -> String {
+        let mut json: String = format!("{{\"time\":{},\"event\":\"{}\"", self.timestamp, self.mutation.get_name());
+        let _ = match &self.mutation {
+            Mutation::EntryCreated { path } | Mutation::EntryUpdated { path } | Mutation::EntryRemoved { path } =>
+                write!(json, ",\"path\":\"{}\"", escape_json(path)),
+            Mutation::ClustersAllocated(clusters) | Mutation::ClustersFreed(clusters) =>
+                write!(json, ",\"clusters\":[{}]", clusters.iter().map(Cluster::to_string).collect::<Vec<_>>().join(",")),
+            Mutation::SectorsWritten { first, count } => write!(json, ",\"first\":{},\"count\":{}", first, count),
+            Mutation::Committed { sectors } | Mutation::Discarded { sectors } => write!(json, ",\"sectors\":{}", sectors)
+        };
+        json.push('}');
+        json
+    }
+}
+
+/* ==== UTILS =============================================================== */
+pub(crate) fn escape_json(text: &str) -> String {
+    let mut escaped: String = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => { let _ = write!(escaped, "\\u{:04x}", c as u32); },
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
@@ -0,0 +1,97 @@
+use crate::layout::VolumeLayout;
+use crate::Cluster;
+
+/* ==== STRUCTS ============================================================= */
+/** Define FAT12 headers and bootloader sector.
+ *  All the header values are mapped, but the bootloader code is ignored. */
+ // repr(C): ensures that the data layout is laid in "the C way" for FFI (Foreign Function Interface)
+ // repr(packed): ensures that no padding data is added between struct fields
+ #[repr(C, packed)]
+ #[derive(Debug, Clone, Copy)]
+pub struct BootSector {
+    // BIOS Parameter Block
+    pub jump_instruction: [u8; 3],
+    pub oem_id: [u8; 8],
+    pub bytes_per_sector: u16,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub fat_count: u8,
+    pub root_entries : u16,
+    pub sector_count: u16,
+    pub media_descriptor: u8,
+    pub sectors_per_fat: u16,
+    pub sectors_per_cylinder: u16,
+    pub heads_count: u16,
+    pub hidden_sectors_count: u32,
+    pub large_sector_count: u32,
+
+    // Extended Boot Record
+    pub drive_number: u8,
+    pub reserved: u8,
+    pub boot_signature: u8,         // 0x29 if the volume id, label and system id follow
+    pub volume_id : u32,
+    pub volume_label : [u8; 11],
+    pub system_id: [u8; 8]
+
+    // BootLoader code (ignored)
+}
+
+impl BootSector {
+    /** The numbers of the BPB the offsets below follow from, see
+     *  layout::VolumeLayout. */
+    pub fn get_layout(&self) -> VolumeLayout {
+        VolumeLayout {
+            bytes_per_sector: self.bytes_per_sector as u64,
+            sectors_per_cluster: self.sectors_per_cluster as u64,
+            reserved_sectors: self.reserved_sectors as u64,
+            fat_count: self.fat_count as u64,
+            sectors_per_fat: self.sectors_per_fat as u64,
+            root_entries: self.root_entries as u64,
+            total_sectors: self.total_sectors() as u64
+        }
+    }
+
+    pub fn get_fat_start(&self) -> u64 {
+        self.get_layout().fat_start()
+    }
+
+    pub fn get_fat_size(&self) -> u64 {
+        self.get_layout().fat_size()
+    }
+
+    pub fn get_root_dir_start(&self) -> u64 {
+        self.get_layout().root_dir_start()
+    }
+
+    pub fn get_root_dir_size(&self) -> u64 {
+        self.get_layout().root_dir_size()
+    }
+
+    /** Byte offset of the data area, at the first sector after the root. */
+    pub fn get_cluster_region_start(&self) -> u64 {
+        self.get_layout().data_start()
+    }
+
+    /** Byte offset of the given data cluster. */
+    pub fn get_cluster_start(&self, cluster: Cluster) -> u64 {
+        cluster.to_lba(self).to_offset(self)
+    }
+
+    pub fn get_cluster_size(&self) -> usize {
+        self.get_layout().cluster_size() as usize
+    }
+
+    /** Sectors in the volume: the 16 bit count is 0 on volumes that need
+     *  more, which store it in the 32 bit one instead. */
+    pub fn total_sectors(&self) -> u32 {
+        match self.sector_count {
+            0 => self.large_sector_count,
+            sector_count => sector_count as u32
+        }
+    }
+
+    /** Number of data clusters: whatever fits in the sectors after the root dir. */
+    pub fn get_cluster_count(&self) -> usize {
+        self.get_layout().cluster_count() as usize
+    }
+}
@@ -0,0 +1,297 @@
+use std::{fs::File, io};
+
+use crate::layout::VolumeLayout;
+use crate::{read_exact_at, struct_to_bytes, BootSector, Fat12Fs, FatMirroring};
+
+/* ==== STRUCTS ============================================================= */
+/** Numeric BPB fields that can be read and changed by name. total_sectors
+ *  stands for either sector_count or large_sector_count, whichever fits. */
+pub const FIELDS: &[&str] = &[
+    "bytes_per_sector", "sectors_per_cluster", "reserved_sectors", "fat_count", "root_entries", "sector_count",
+    "media_descriptor", "sectors_per_fat", "sectors_per_track", "heads_count", "hidden_sectors_count",
+    "large_sector_count", "drive_number", "total_sectors"
+];
+
+/** Values that follow from the BPB fields. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub total_sectors: u32,
+    pub data_start_sector: u32,
+    pub cluster_count: usize,
+
+    /** Sectors each FAT copy needs for the cluster count, 12 bits an entry. */
+    pub needed_fat_sectors: u32,
+
+    /** Whole cylinders, and sectors left over after them. */
+    pub cylinders: u32,
+    pub extra_sectors: u32
+}
+
+/** Extended BPB of FAT32 volumes, which have sectors_per_fat at 0 and these
+ *  fields at 0x24 in place of the extended boot record of FAT12 and FAT16. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fat32Fields {
+    pub sectors_per_fat: u32,
+    pub ext_flags: ExtFlags,
+
+    /** Major and minor version of the layout: anything but 0.0 is newer
+     *  than the drivers that read it. */
+    pub version: (u8, u8),
+    pub root_cluster: u32,
+    pub fs_info_sector: u16,
+    pub backup_boot_sector: u16
+}
+
+/** Extended flags of FAT32: the active FAT in bits 0-3, and bit 7 set when
+ *  changes go to that FAT only instead of to every copy. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ExtFlags(pub u16);
+
+impl Geometry {
+    /** FAT type the cluster count makes the volume, whatever its BPB says. */
+    pub fn get_fat_type(&self) -> &'static str {
+        match self.cluster_count {
+            0..4085 => "FAT12",
+            4085..65525 => "FAT16",
+            _ => "FAT32"
+        }
+    }
+}
+
+impl Fat32Fields {
+    /** Fields of the boot sector, None if it isn't laid out for FAT32. */
+    pub fn parse(sector: &[u8]) -> Option<Fat32Fields> {
+        if sector.len() < 0x34 || u16::from_le_bytes([sector[0x16], sector[0x17]]) != 0 { return None; }
+        let u16_at = |offset: usize| u16::from_le_bytes([sector[offset], sector[offset + 1]]);
+        let u32_at = |offset: usize| u32::from_le_bytes([sector[offset], sector[offset + 1], sector[offset + 2], sector[offset + 3]]);
+        Some(Fat32Fields {
+            sectors_per_fat: u32_at(0x24),
+            ext_flags: ExtFlags(u16_at(0x28)),
+            version: (sector[0x2B], sector[0x2A]),
+            root_cluster: u32_at(0x2C),
+            fs_info_sector: u16_at(0x30),
+            backup_boot_sector: u16_at(0x32)
+        })
+    }
+
+    /** Fields of the boot sector of the volume at that offset of the disk. */
+    pub fn read(disk: &File, offset: u64) -> io::Result<Option<Fat32Fields>> {
+        let mut sector: [u8; 0x34] = [0; 0x34];
+        read_exact_at(disk, &mut sector, offset)?;
+        Ok(Fat32Fields::parse(&sector))
+    }
+}
+
+impl ExtFlags {
+    pub fn get_active_fat(&self) -> u8 {
+        (self.0 & 0x0F) as u8
+    }
+
+    pub fn is_mirrored(&self) -> bool {
+        self.0 & 0x80 == 0
+    }
+
+    /** The copies of the FAT the flags say to read and write. */
+    pub fn get_fat_mirroring(&self) -> FatMirroring {
+        if self.is_mirrored() { FatMirroring::All } else { FatMirroring::Active(self.get_active_fat()) }
+    }
+}
+
+impl BootSector {
+    pub fn get_geometry(&self) -> Geometry {
+        let layout: VolumeLayout = self.get_layout();
+        let cylinder_size: u32 = (self.sectors_per_cylinder as u32 * self.heads_count as u32).max(1);
+        Geometry {
+            total_sectors: self.total_sectors(),
+            data_start_sector: layout.data_start_sector() as u32,
+            cluster_count: layout.cluster_count() as usize,
+            needed_fat_sectors: layout.needed_fat_sectors() as u32,
+            cylinders: self.total_sectors() / cylinder_size,
+            extra_sectors: self.total_sectors() % cylinder_size
+        }
+    }
+
+    /** Byte offset of one of the FIELDS in the boot sector, None for unknown
+     *  names. total_sectors is at the offset of the 16 bit count. */
+    pub fn get_field_offset(name: &str) -> Option<u64> {
+        Some(match name {
+            "bytes_per_sector" => 0x0B,
+            "sectors_per_cluster" => 0x0D,
+            "reserved_sectors" => 0x0E,
+            "fat_count" => 0x10,
+            "root_entries" => 0x11,
+            "sector_count" | "total_sectors" => 0x13,
+            "media_descriptor" => 0x15,
+            "sectors_per_fat" => 0x16,
+            "sectors_per_track" => 0x18,
+            "heads_count" => 0x1A,
+            "hidden_sectors_count" => 0x1C,
+            "large_sector_count" => 0x20,
+            "drive_number" => 0x24,
+            _ => return None
+        })
+    }
+
+    /** Bytes one of the FIELDS takes in the boot sector, None for unknown
+     *  names. */
+    pub fn get_field_size(name: &str) -> Option<usize> {
+        Some(match name {
+            "sectors_per_cluster" | "fat_count" | "media_descriptor" | "drive_number" => 1,
+            "hidden_sectors_count" | "large_sector_count" => 4,
+            _ => { BootSector::get_field_offset(name)?; 2 }
+        })
+    }
+
+    /** Value of one of the FIELDS, None for unknown names. */
+    pub fn get_field(&self, name: &str) -> Option<u32> {
+        Some(match name {
+            "bytes_per_sector" => self.bytes_per_sector as u32,
+            "sectors_per_cluster" => self.sectors_per_cluster as u32,
+            "reserved_sectors" => self.reserved_sectors as u32,
+            "fat_count" => self.fat_count as u32,
+            "root_entries" => self.root_entries as u32,
+            "sector_count" => self.sector_count as u32,
+            "media_descriptor" => self.media_descriptor as u32,
+            "sectors_per_fat" => self.sectors_per_fat as u32,
+            "sectors_per_track" => self.sectors_per_cylinder as u32,
+            "heads_count" => self.heads_count as u32,
+            "hidden_sectors_count" => self.hidden_sectors_count,
+            "large_sector_count" => self.large_sector_count,
+            "drive_number" => self.drive_number as u32,
+            "total_sectors" => self.total_sectors(),
+            _ => return None
+        })
+    }
+
+    /** Change one of the FIELDS, refusing values that don't fit it or that
+     *  would leave the geometry meaningless (e.g. no FAT). */
+    pub fn set_field(&mut self, name: &str, value: u32) -> io::Result<()> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid {} {}: {}", name, value, reason));
+        let check = |valid: bool, reason: &str| if valid { Ok(()) } else { Err(invalid(reason)) };
+        let to_u8 = |value: u32| u8::try_from(value).map_err(|_| invalid("more than 255"));
+        let to_u16 = |value: u32| u16::try_from(value).map_err(|_| invalid("more than 65535"));
+
+        match name {
+            "bytes_per_sector" => {
+                check(value.is_power_of_two() && (128..=4096).contains(&value), "not a power of two from 128 to 4096")?;
+                self.bytes_per_sector = to_u16(value)?;
+            },
+            "sectors_per_cluster" => {
+                check(value.is_power_of_two() && value <= 128, "not a power of two up to 128")?;
+                self.sectors_per_cluster = to_u8(value)?;
+            },
+            "reserved_sectors" => {
+                check(value >= 1, "the boot sector is reserved")?;
+                self.reserved_sectors = to_u16(value)?;
+            },
+            "fat_count" => {
+                check(value >= 1, "there must be a FAT")?;
+                self.fat_count = to_u8(value)?;
+            },
+            "root_entries" => self.root_entries = to_u16(value)?,
+            "sector_count" => self.sector_count = to_u16(value)?,
+            "media_descriptor" => {
+                check(value == 0xF0 || (0xF8..=0xFF).contains(&value), "not 0xF0 or 0xF8 to 0xFF")?;
+                self.media_descriptor = value as u8;
+            },
+            "sectors_per_fat" => {
+                check(value >= 1, "there must be a FAT")?;
+                self.sectors_per_fat = to_u16(value)?;
+            },
+            "sectors_per_track" => {
+                check((1..=63).contains(&value), "not from 1 to 63")?;
+                self.sectors_per_cylinder = value as u16;
+            },
+            "heads_count" => {
+                check((1..=255).contains(&value), "not from 1 to 255")?;
+                self.heads_count = value as u16;
+            },
+            "hidden_sectors_count" => self.hidden_sectors_count = value,
+            "large_sector_count" => self.large_sector_count = value,
+            "drive_number" => self.drive_number = to_u8(value)?,
+
+            // The 16 bit count when it fits, as DOS does, and 0 in the other one
+            "total_sectors" => match u16::try_from(value) {
+                Ok(sector_count) => (self.sector_count, self.large_sector_count) = (sector_count, 0),
+                Err(_) => (self.sector_count, self.large_sector_count) = (0, value)
+            },
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("unknown BPB field {}, expected one of {}", name, FIELDS.join(", "))))
+        }
+        Ok(())
+    }
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Change a BPB field and write the boot sector, returning warnings about
+     *  the geometry that results. The FAT and the directories are left as
+     *  they are: reopen the image after changing its layout. */
+    pub fn set_bpb_field(&mut self, name: &str, value: u32) -> io::Result<Vec<String>> {
+        self.check_writable()?;
+        let mut boot_sector: BootSector = self.boot_sector;
+        boot_sector.set_field(name, value)?;
+
+        let before: Geometry = self.boot_sector.get_geometry();
+        let after: Geometry = boot_sector.get_geometry();
+        let mut warnings: Vec<String> = vec![];
+        if after.cluster_count != before.cluster_count {
+            warnings.push(format!("the cluster count changes from {} to {}", before.cluster_count, after.cluster_count));
+        }
+        if after.get_fat_type() != before.get_fat_type() {
+            warnings.push(format!("{} clusters make it a {} volume, no longer {}", after.cluster_count, after.get_fat_type(), before.get_fat_type()));
+        }
+        if after.needed_fat_sectors > boot_sector.sectors_per_fat as u32 {
+            let sectors_per_fat: u16 = boot_sector.sectors_per_fat;
+            warnings.push(format!("{} clusters need {} sectors per FAT, there are {}", after.cluster_count, after.needed_fat_sectors, sectors_per_fat));
+        }
+        if after.extra_sectors != 0 {
+            warnings.push(format!("{} sectors are left over after {} whole cylinders", after.extra_sectors, after.cylinders));
+        }
+        let image_sectors: u64 = self.disk.metadata()?.len().saturating_sub(self.offset) / boot_sector.bytes_per_sector as u64;
+        if after.total_sectors as u64 > image_sectors {
+            warnings.push(format!("the volume has {} sectors, the image only holds {}", after.total_sectors, image_sectors));
+        }
+
+        // Written with the old sector size, which the I/O path still follows
+        let bytes: Vec<u8> = struct_to_bytes(&boot_sector);
+        self.write_disk_at(0, &bytes)?;
+        self.boot_sector = boot_sector;
+        Ok(warnings)
+    }
+
+    /** FAT32 extended BPB of the volume, None for FAT12 and FAT16 layouts. */
+    pub fn read_fat32_fields(&self) -> io::Result<Option<Fat32Fields>> {
+        Fat32Fields::read(&self.disk, self.offset)
+    }
+
+    /** Bytes of the reserved sectors after the boot sector, before the first
+     *  FAT: room for a stage 2 loader the filesystem never touches. */
+    pub fn get_reserved_region_size(&self) -> usize {
+        (self.boot_sector.reserved_sectors as usize).saturating_sub(1) * self.boot_sector.bytes_per_sector as usize
+    }
+
+    pub fn read_reserved_region(&mut self) -> io::Result<Vec<u8>> {
+        let size: usize = self.get_reserved_region_size();
+        self.read_disk_at(self.boot_sector.bytes_per_sector as u64, size)
+    }
+
+    /** Write the blob at the start of the reserved region, zeroing the rest
+     *  of it. Fails if it doesn't fit: see reserved_sectors_for. */
+    pub fn write_reserved_region(&mut self, data: &[u8]) -> io::Result<()> {
+        self.check_writable()?;
+        let size: usize = self.get_reserved_region_size();
+        if data.len() > size {
+            let needed: u16 = reserved_sectors_for(data.len(), self.boot_sector.bytes_per_sector);
+            return Err(io::Error::new(io::ErrorKind::StorageFull, format!("{} bytes don't fit the {} bytes of the reserved region, it needs {} reserved sectors", data.len(), size, needed)));
+        }
+        let mut region: Vec<u8> = data.to_vec();
+        region.resize(size, 0);
+        self.write_disk_at(self.boot_sector.bytes_per_sector as u64, &region)
+    }
+}
+
+/** Reserved sectors (the boot sector included) that hold a blob of that
+ *  size after the boot sector. */
+pub fn reserved_sectors_for(size: usize, bytes_per_sector: u16) -> u16 {
+    (1 + size.div_ceil(bytes_per_sector as usize)).min(u16::MAX as usize) as u16
+}
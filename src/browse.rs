@@ -0,0 +1,218 @@
+use std::{fs, io::{self, Read, Write}};
+use rs_disk_reader::{bulk, format_timestamp, Cluster, Directory, DirectoryEntry, Fat12Fs, DOT_NAME};
+
+use crate::terminal::{self, RawMode};
+
+/** Bytes of the selected file shown in the hexdump pane. */
+const PREVIEW_SIZE: usize = 4096;
+
+/* ==== STRUCTS ============================================================= */
+/** Keys the browser reacts to, decoded from the raw terminal input. */
+enum Key {
+    Up, Down, PageUp, PageDown, Enter, Back, Extract, Quit, Other
+}
+
+/** State of the two-pane browser: the directory listed on the left and the
+ *  selected entry, whose details and content are shown on the right. */
+struct Browser {
+    fs: Fat12Fs,
+    cwd: String,
+    entries: Vec<DirectoryEntry>,
+    selected: usize,
+    scroll: usize,
+    message: String
+}
+
+impl Browser {
+    /** List the given directory, dropping the volume label and "." but
+     *  keeping ".." so that it can be selected to go back up. */
+    fn change_dir(&mut self, path: String) -> io::Result<()> {
+        let directory: Directory = self.fs.read_dir(&path)?;
+        self.entries = directory.entries()
+            .filter(|entry| !entry.is_volume_label() && entry.name != DOT_NAME)
+            .copied()
+            .collect();
+        self.cwd = path;
+        self.selected = 0;
+        self.scroll = 0;
+        Ok(())
+    }
+
+    fn get_selected(&self) -> Option<&DirectoryEntry> {
+        self.entries.get(self.selected)
+    }
+
+    /** Path of the parent of the current directory. */
+    fn get_parent(&self) -> String {
+        match self.cwd.trim_end_matches('/').rsplit_once('/') {
+            Some(("", _)) | None => String::from("/"),
+            Some((parent, _)) => parent.to_string()
+        }
+    }
+
+    fn handle(&mut self, key: Key, rows: usize) -> io::Result<bool> {
+        let page: usize = rows.saturating_sub(3).max(1);
+        match key {
+            Key::Quit => return Ok(false),
+            Key::Up => self.selected = self.selected.saturating_sub(1),
+            Key::Down => self.selected = (self.selected + 1).min(self.entries.len().saturating_sub(1)),
+            Key::PageUp => self.selected = self.selected.saturating_sub(page),
+            Key::PageDown => self.selected = (self.selected + page).min(self.entries.len().saturating_sub(1)),
+            Key::Back => self.change_dir(self.get_parent())?,
+            Key::Enter => {
+                let Some(entry) = self.get_selected().copied() else { return Ok(true) };
+                if entry.is_dot_entry() { self.change_dir(self.get_parent())?; }
+                else if entry.is_directory() { self.change_dir(format!("{}/{}", self.cwd.trim_end_matches('/'), entry.get_name()))?; }
+            },
+            Key::Extract => {
+                let Some(entry) = self.get_selected().copied() else { return Ok(true) };
+                if entry.is_directory() { self.message = String::from("only files can be extracted"); return Ok(true); }
+
+                // Extract in the host working directory, with the 8.3 name
+                let name: String = entry.get_name();
+                bulk::check_host_name(&name)?;
+                let data: Vec<u8> = self.fs.read_file(&format!("{}/{}", self.cwd.trim_end_matches('/'), name))?;
+                fs::write(&name, data)?;
+                self.message = format!("extracted {}", name);
+            },
+            Key::Other => {}
+        }
+        Ok(true)
+    }
+
+    /** Draw the whole screen: listing on the left, details and hexdump of
+     *  the selected entry on the right, status line at the bottom. */
+    fn render(&mut self, rows: usize, columns: usize) -> io::Result<String> {
+        let left_width: usize = (columns / 3).clamp(20, 40).min(columns);
+        let right_width: usize = columns.saturating_sub(left_width + 3);
+        let body_rows: usize = rows.saturating_sub(2);
+
+        // Keep the selection visible
+        if self.selected < self.scroll { self.scroll = self.selected; }
+        if self.selected >= self.scroll + body_rows { self.scroll = self.selected + 1 - body_rows; }
+
+        let left: Vec<String> = self.entries.iter().enumerate().skip(self.scroll).take(body_rows)
+            .map(|(i, entry)| {
+                let name: String = if entry.is_directory() { format!("{}/", entry.get_name()) } else { entry.get_name() };
+                let line: String = fit(&format!(" {}", name), left_width);
+                if i == self.selected { format!("\x1b[7m{}\x1b[0m", line) } else { line }
+            })
+            .collect();
+        let right: Vec<String> = self.describe_selected(right_width)?;
+
+        let mut screen: String = String::from("\x1b[H\x1b[2J");
+        screen.push_str(&format!("\x1b[1m{}\x1b[0m\r\n", fit(&format!(" {}", self.cwd), columns)));
+        for row in 0..body_rows {
+            let left_cell: String = left.get(row).cloned().unwrap_or_else(|| " ".repeat(left_width));
+            let right_cell: &str = right.get(row).map(String::as_str).unwrap_or("");
+            screen.push_str(&format!("{} | {}\r\n", left_cell, fit(right_cell, right_width)));
+        }
+        let help: &str = "up/down move  enter open  backspace up  x extract  q quit";
+        screen.push_str(&format!("\x1b[7m{}\x1b[0m", fit(&format!(" {}  {}", help, self.message), columns)));
+        Ok(screen)
+    }
+
+    fn describe_selected(&mut self, width: usize) -> io::Result<Vec<String>> {
+        let Some(entry) = self.get_selected().copied() else { return Ok(vec![String::from("(empty directory)")]) };
+        let (file_size, first_cluster): (u32, Cluster) = (entry.file_size, entry.get_first_cluster());
+        let clusters: usize = self.fs.get_chain(first_cluster).len();
+
+        let mut lines: Vec<String> = vec![
+            format!("name:       {}", entry.get_name()),
+            format!("attributes: {}", entry.get_attributes_string()),
+            format!("size:       {}", file_size),
+            format!("cluster:    {} ({} in chain)", first_cluster, clusters),
+            format!("modified:   {}", format_timestamp(entry.get_last_change_timestamp())),
+            String::new()
+        ];
+        if entry.is_directory() { return Ok(lines); }
+
+        // Only the beginning of the file fits the pane anyway
+        let path: String = format!("{}/{}", self.cwd.trim_end_matches('/'), entry.get_name());
+        let mut data: Vec<u8> = self.fs.read_file(&path)?;
+        data.truncate(PREVIEW_SIZE);
+
+        let bytes_per_line: usize = if width >= 78 { 16 } else { 8 };
+        lines.extend(data.chunks(bytes_per_line).enumerate().map(|(i, chunk)| hexdump_line(i * bytes_per_line, chunk, bytes_per_line)));
+        Ok(lines)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** browse <image>: full screen read-only browser of the image tree. */
+pub fn run(image_path: &str) -> io::Result<()> {
+    let fs: Fat12Fs = crate::fs_options().open(image_path)?;
+    let mut browser: Browser = Browser { fs, cwd: String::new(), entries: vec![], selected: 0, scroll: 0, message: String::new() };
+    browser.change_dir(String::from("/"))?;
+
+    // Use the alternate screen, so that the shell content is back on exit
+    let raw_mode: RawMode = RawMode::enable();
+    write!(io::stdout().lock(), "\x1b[?1049h\x1b[?25l")?;
+
+    loop {
+        let (rows, columns): (usize, usize) = terminal::size();
+        match browser.render(rows, columns) {
+            Ok(screen) => write!(io::stdout().lock(), "{}", screen)?,
+            Err(e) => browser.message = format!("error: {}", e)
+        }
+        let _ = io::stdout().flush();
+
+        match browser.handle(read_key(), rows) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(e) => browser.message = format!("error: {}", e)
+        }
+    }
+
+    write!(io::stdout().lock(), "\x1b[?25h\x1b[?1049l")?;
+    let _ = io::stdout().flush();
+    drop(raw_mode);
+    Ok(())
+}
+
+/* ==== UTILS =============================================================== */
+/** Read a key press, decoding the escape sequences of the arrow keys. */
+fn read_key() -> Key {
+    let mut byte: [u8; 1] = [0];
+    if io::stdin().read(&mut byte).unwrap_or(0) == 0 { return Key::Quit; }
+    match byte[0] {
+        b'q' => Key::Quit,
+        b'k' => Key::Up,
+        b'j' => Key::Down,
+        b'\n' | b'\r' | b'l' => Key::Enter,
+        0x7F | 0x08 | b'h' => Key::Back,
+        b'x' => Key::Extract,
+        0x1B => {
+            // ESC [ A..D for arrows, ESC [ 5 ~ / ESC [ 6 ~ for page up/down
+            let mut sequence: [u8; 2] = [0; 2];
+            if io::stdin().read_exact(&mut sequence).is_err() || sequence[0] != b'[' { return Key::Other; }
+            match sequence[1] {
+                b'A' => Key::Up,
+                b'B' => Key::Down,
+                b'C' => Key::Enter,
+                b'D' => Key::Back,
+                b'5' | b'6' => {
+                    let _ = io::stdin().read(&mut byte);
+                    if sequence[1] == b'5' { Key::PageUp } else { Key::PageDown }
+                },
+                _ => Key::Other
+            }
+        },
+        _ => Key::Other
+    }
+}
+
+/** "00000010  eb 3c 90 4d ...  |.<.M|" */
+fn hexdump_line(offset: usize, chunk: &[u8], bytes_per_line: usize) -> String {
+    let hex: String = chunk.iter().map(|byte| format!("{:02x} ", byte)).collect();
+    let ascii: String = chunk.iter().map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' }).collect();
+    format!("{:08x}  {:<width$} |{}|", offset, hex, ascii, width = bytes_per_line * 3)
+}
+
+/** Cut or pad the text to exactly the given width. */
+fn fit(text: &str, width: usize) -> String {
+    let mut fitted: String = text.chars().take(width).collect();
+    let length: usize = fitted.chars().count();
+    fitted.push_str(&" ".repeat(width - length));
+    fitted
+}
@@ -0,0 +1,669 @@
+use std::{collections::{HashMap, HashSet}, fmt, fs, io::{self, Write}, path::{Path, PathBuf}, sync::Arc, time::UNIX_EPOCH};
+
+use crate::digest::{self, ContentDigest, Crc32};
+use crate::lfn::NamePair;
+use crate::{image_time_to_system_time, short_name_to_string, to_short_name, DirectoryEntry, Fat12Fs, ATTR_ARCHIVE, ATTR_READ_ONLY};
+use crate::source::{FileSource, HostFile};
+use crate::text::TextMode;
+use crate::unicode::fold_case;
+use crate::walk::Filter;
+use crate::warning::Warning;
+
+/* ==== STRUCTS ============================================================= */
+#[derive(Debug, Clone, Default)]
+pub struct BulkOptions {
+    /** Record per-file errors in the report and go on with the other files,
+     *  instead of stopping at the first one. */
+    pub keep_going: bool,
+
+    /** Offset from UTC of the image timestamps, in minutes. */
+    pub timezone_offset: i32,
+
+    /** Image entries to extract or check, the others are left out. */
+    pub filter: Filter,
+
+    /** Extract: give host files and directories the last change time of
+     *  their entry (import always keeps the host times). */
+    pub preserve_times: bool,
+
+    /** Extract: make the host files of read-only entries read-only. Import:
+     *  the other way around. */
+    pub preserve_read_only: bool,
+
+    /** Convert line endings and the code page of the files copied. */
+    pub text_mode: TextMode,
+
+    /** Extract: what the host files and directories are named after. */
+    pub host_names: HostNames,
+
+    /** Extract: what to do when entries of a directory get the same host
+     *  name. */
+    pub on_collision: NameCollision,
+
+    /** Extract: file listing the files already extracted, with the size and
+     *  CRC-32 of their host copy, one line added as each is written. A run
+     *  interrupted on a huge image resumes from it: listed files whose host
+     *  copy still has that size and digest are skipped, the others are
+     *  extracted again. It's kept after the run, delete it to start over. */
+    pub checkpoint: Option<PathBuf>,
+
+    /** Told of the progress as it's made, e.g. to drive a progress bar. */
+    pub observer: Option<Arc<dyn BulkObserver>>
+}
+
+/** What the host files and directories extracted are named after. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HostNames {
+    /** The long name in UTF-8, or the short name decoded with the code page
+     *  without one: the names ls shows. */
+    #[default]
+    Long,
+
+    /** The short name in ASCII, see Codepage::transliterate: for hosts and
+     *  tools that only take ASCII names. */
+    Transliterated,
+
+    /** The 8.3 name decoded with the code page, long names left out: the
+     *  names DOS shows. */
+    Short
+}
+
+/** What to do when two entries of a directory get the same host name,
+ *  compared without case as case-insensitive filesystems do: "readme.txt"
+ *  and "README.TXT" as long names, "CAFÉ.TXT" and "CAFE.TXT" transliterated.
+ *  The first entry always gets the name. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameCollision {
+    /** Add "~1", "~2"... to the name of the later ones, before the extension. */
+    #[default]
+    Rename,
+
+    /** Leave the later ones out (with their content), reported as skipped. */
+    Skip,
+
+    /** Fail the later ones, see keep_going. */
+    Error,
+
+    /** Write them all to the same host path: only the last file is left,
+     *  and directories are merged. */
+    Overwrite
+}
+
+/** Progress of a bulk operation, for frontends to show it as it goes: the
+ *  library itself prints nothing. Paths are the ones of the report; every
+ *  method does nothing unless implemented. Calls come from the thread
+ *  running the operation. */
+pub trait BulkObserver: fmt::Debug + Send + Sync {
+    /** A directory is about to be processed, before its content. */
+    fn directory_entered(&self, _path: &str) {}
+
+    /** A file is about to be copied or checked, of that size in the image
+     *  (or at the source, when importing). */
+    fn file_started(&self, _path: &str, _size: u64) {}
+
+    /** The file started last is done, copied or found intact. */
+    fn file_finished(&self, _path: &str) {}
+
+    /** A file or directory failed: the operation stops after it unless
+     *  keep_going is set. */
+    fn error(&self, _path: &str, _error: &io::Error) {}
+}
+
+/** Outcome of a bulk operation. Paths are relative to the directory the
+ *  operation started from, in the image "A/B.TXT" form. */
+#[derive(Debug, Default)]
+pub struct BulkReport {
+    /** Files (and directories) processed successfully. */
+    pub done: Vec<String>,
+
+    /** Files that failed, with the error, when keep_going is set. */
+    pub failed: Vec<(String, io::Error)>,
+
+    /** Files left as they were, extracted by an earlier run (see
+     *  BulkOptions::checkpoint), and those left out by NameCollision::Skip. */
+    pub skipped: Vec<String>
+}
+
+/** What an extraction carries along from file to file. */
+struct Extraction {
+    report: BulkReport,
+    checkpoint: Option<Checkpoint>,
+
+    // Host names given so far in each host directory, case folded
+    taken: HashMap<PathBuf, HashSet<String>>
+}
+
+/** Files extracted so far, as read from the checkpoint file, and the file
+ *  itself to add the next ones. */
+struct Checkpoint {
+    extracted: HashMap<PathBuf, (u64, Vec<u8>)>,
+    file: fs::File
+}
+
+/** Entry of a host directory, as walk_host_tree meets it. */
+pub(crate) struct HostChild {
+    pub path: PathBuf,
+    pub file_name: String,
+    pub file_type: fs::FileType
+}
+
+impl BulkOptions {
+    fn notify(&self, event: impl FnOnce(&dyn BulkObserver)) {
+        if let Some(observer) = &self.observer { event(observer.as_ref()); }
+    }
+}
+
+impl BulkReport {
+    /** Record the outcome of a single file, or stop on the error unless
+     *  keep_going is set. */
+    fn record(&mut self, path: String, result: io::Result<()>, options: &BulkOptions) -> io::Result<()> {
+        if let Err(e) = &result { options.notify(|observer| observer.error(&path, e)); }
+        match result {
+            Ok(()) => self.done.push(path),
+            Err(e) if options.keep_going => self.failed.push((path, e)),
+            Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path, e)))
+        }
+        Ok(())
+    }
+
+    /** Record an error of the walk: it comes right after the directory that
+     *  can't be listed, which then moves from done to failed. */
+    fn record_walk_error(&mut self, directory: &str, e: io::Error, options: &BulkOptions) -> io::Result<()> {
+        // The walk already names the directory in the error
+        if !options.keep_going {
+            options.notify(|observer| observer.error(directory, &e));
+            return Err(e);
+        }
+        if self.done.last().is_some_and(|done| done == directory) { self.done.pop(); }
+        self.record(directory.to_string(), Err(e), options)
+    }
+}
+
+impl HostNames {
+    pub fn from_name(name: &str) -> Option<HostNames> {
+        match name {
+            "long" => Some(HostNames::Long),
+            "transliterated" => Some(HostNames::Transliterated),
+            "short" => Some(HostNames::Short),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            HostNames::Long => "long",
+            HostNames::Transliterated => "transliterated",
+            HostNames::Short => "short"
+        }
+    }
+}
+
+impl NameCollision {
+    pub fn from_name(name: &str) -> Option<NameCollision> {
+        match name {
+            "rename" => Some(NameCollision::Rename),
+            "skip" => Some(NameCollision::Skip),
+            "error" => Some(NameCollision::Error),
+            "overwrite" => Some(NameCollision::Overwrite),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            NameCollision::Rename => "rename",
+            NameCollision::Skip => "skip",
+            NameCollision::Error => "error",
+            NameCollision::Overwrite => "overwrite"
+        }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Copy the tree under the image directory to the host directory. */
+pub fn extract(fs: &mut Fat12Fs, image_dir: &str, host_dir: &Path, options: &BulkOptions) -> io::Result<BulkReport> {
+    let mut extraction: Extraction = Extraction::new(options)?;
+    extract_tree(fs, image_dir, host_dir, "", &mut extraction, options)?;
+    Ok(extraction.report)
+}
+
+/** Copy exactly the listed paths, relative to the image directory, to the
+ *  same relative paths under the host directory; a directory brings its
+ *  whole tree, named as BulkOptions::host_names says. Nothing is extracted
+ *  if any of them is missing. */
+pub fn extract_files(fs: &mut Fat12Fs, image_dir: &str, paths: &[String], host_dir: &Path, options: &BulkOptions) -> io::Result<BulkReport> {
+    let mut selected: Vec<(String, Option<DirectoryEntry>)> = vec![];
+    let mut missing: Vec<&str> = vec![];
+    for path in paths {
+        let path: &str = path.trim_matches('/');
+        if path.split('/').any(|name| name == "." || name == "..") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: listed paths can't contain . or ..", path)));
+        }
+        if selected.iter().any(|(selected, _)| selected == path) { continue; }
+
+        // An empty path is the image directory itself
+        if path.is_empty() {
+            selected.push((String::new(), None));
+            continue;
+        }
+        match fs.get_entry(&join_image_path(image_dir, path)) {
+            Ok(entry) => selected.push((path.to_string(), Some(entry))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => missing.push(path),
+            Err(e) => return Err(io::Error::new(e.kind(), format!("{}: {}", path, e)))
+        }
+    }
+    if !missing.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} listed paths not found: {}", missing.len(), missing.join(", "))));
+    }
+
+    let mut extraction: Extraction = Extraction::new(options)?;
+    fs::create_dir_all(host_dir)?;
+    for (path, entry) in selected {
+        let host_path: PathBuf = match path.is_empty() {
+            true => host_dir.to_path_buf(),
+            false => safe_host_path(host_dir, &path)?
+        };
+        match entry {
+            Some(entry) if !entry.is_directory() => {
+                let image_path: String = join_image_path(image_dir, &path);
+                extract_file(fs, &path, &image_path, &host_path, &entry, &mut extraction, options)?;
+            },
+            _ => {
+                if !path.is_empty() { options.notify(|observer| observer.directory_entered(&path)); }
+                let prefix: String = if path.is_empty() { path.clone() } else { format!("{}/", path) };
+                extract_tree(fs, &join_image_path(image_dir, &path), &host_path, &prefix, &mut extraction, options)?;
+            }
+        }
+    }
+    Ok(extraction.report)
+}
+
+/** Copy the tree under the host directory to the image directory, creating
+ *  the missing directories. Host names must already fit 8.3. */
+pub fn import(fs: &mut Fat12Fs, host_dir: &Path, image_dir: &str, options: &BulkOptions) -> io::Result<BulkReport> {
+    let mut report: BulkReport = BulkReport::default();
+    import_directory(fs, host_dir, image_dir, &mut report, options)?;
+    Ok(report)
+}
+
+/** Write files that don't come from a host directory, e.g. generated ones,
+ *  under the image directory, creating the missing directories. Paths are
+ *  relative to it ("A/B.TXT") and must already fit 8.3; every file gets the
+ *  given timestamp. */
+pub fn import_sources(fs: &mut Fat12Fs, sources: &mut [(String, Box<dyn FileSource>)], image_dir: &str, timestamp: u64, options: &BulkOptions) -> io::Result<BulkReport> {
+    let mut report: BulkReport = BulkReport::default();
+    for (path, source) in sources.iter_mut() {
+        let image_path: String = join_image_path(image_dir, path);
+        let parent: &str = image_path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        let result: io::Result<()> = fs.create_dir_all(parent, timestamp)
+            .and_then(|_| write_source(fs, path, &image_path, source.as_mut(), timestamp, options));
+        if result.is_ok() { options.notify(|observer| observer.file_finished(path)); }
+        report.record(path.clone(), result, options)?;
+    }
+    Ok(report)
+}
+
+/** Read every directory and file under the image directory, checking that
+ *  each file's cluster chain is long enough for its size. */
+pub fn check(fs: &mut Fat12Fs, image_dir: &str, options: &BulkOptions) -> io::Result<BulkReport> {
+    let mut report: BulkReport = BulkReport::default();
+    let mut last_path: String = String::new();
+    for item in fs.walk(image_dir).collect::<Vec<_>>() {
+        let (path, entry): (String, DirectoryEntry) = match item {
+            Ok(item) => item,
+            Err(e) => { report.record_walk_error(&last_path, e, options)?; continue; }
+        };
+        last_path = path.clone();
+        if !options.filter.matches(&entry) { continue; }
+        if entry.is_directory() {
+            options.notify(|observer| observer.directory_entered(&path));
+            report.done.push(path);
+            continue;
+        }
+
+        options.notify(|observer| observer.file_started(&path, entry.file_size as u64));
+        let allocated: u64 = fs.get_allocation(&entry).allocated;
+        let result: io::Result<()> = match allocated < entry.file_size as u64 {
+            true => Err(io::Error::new(io::ErrorKind::InvalidData, format!("cluster chain holds {} bytes, the size is {}", allocated, entry.file_size as u64))),
+            false => fs.read_file(&join_image_path(image_dir, &path)).map(|_| ())
+        };
+        if result.is_ok() { options.notify(|observer| observer.file_finished(&path)); }
+        report.record(path, result, options)?;
+    }
+    Ok(report)
+}
+
+/** Host path of the image path ("A/B.TXT", relative to the host directory)
+ *  for writing, refusing what a crafted image could use to write outside of
+ *  the host directory: names that aren't plain file names, and symbolic
+ *  links already in the way, which are never followed. */
+pub fn safe_host_path(host_dir: &Path, path: &str) -> io::Result<PathBuf> {
+    let mut host_path: PathBuf = host_dir.to_path_buf();
+    for name in path.split('/') {
+        check_host_name(name)?;
+        host_path.push(name);
+        if fs::symlink_metadata(&host_path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is a symbolic link, it won't be followed", host_path.display())));
+        }
+    }
+    Ok(host_path)
+}
+
+/** Refuse names that aren't a single, portable file name on the host: "."
+ *  and "..", separators and other reserved characters, device names. */
+pub fn check_host_name(name: &str) -> io::Result<()> {
+    let unsafe_name = |reason: &str| io::Error::new(io::ErrorKind::InvalidData, format!("unsafe name {:?}: {}", name, reason));
+    if name.is_empty() || name == "." || name == ".." { return Err(unsafe_name("not a file name")); }
+    if let Some(c) = name.chars().find(|&c| c.is_control() || "/\\<>:\"|?*".contains(c)) {
+        return Err(unsafe_name(&format!("{:?} is reserved", c)));
+    }
+    if name.ends_with(['.', ' ']) { return Err(unsafe_name("it ends with a dot or a space")); }
+
+    let base: String = name.split('.').next().unwrap_or_default().to_ascii_uppercase();
+    let numbered: bool = base.len() == 4 && (base.starts_with("COM") || base.starts_with("LPT")) && base.as_bytes()[3].is_ascii_digit();
+    if ["CON", "PRN", "AUX", "NUL"].contains(&base.as_str()) || numbered { return Err(unsafe_name("it's a device name")); }
+    Ok(())
+}
+
+impl Extraction {
+    fn new(options: &BulkOptions) -> io::Result<Extraction> {
+        let checkpoint: Option<Checkpoint> = options.checkpoint.as_deref().map(Checkpoint::open).transpose()?;
+        Ok(Extraction { report: BulkReport::default(), checkpoint, taken: HashMap::new() })
+    }
+
+    /** Host path of an entry of the host directory, named as the options
+     *  say and checked as safe_host_path does. None when a collision leaves
+     *  it out. Names that aren't the one the walk gives are warned about,
+     *  under the image path. */
+    fn get_host_path(&mut self, fs: &mut Fat12Fs, host_dir: &Path, image_path: &str, name: &str, entry: &DirectoryEntry, options: &BulkOptions) -> io::Result<Option<PathBuf>> {
+        let host_name: String = get_host_name(fs, name, entry, options.host_names);
+        check_host_name(&host_name)?;
+
+        let taken: &mut HashSet<String> = self.taken.entry(host_dir.to_path_buf()).or_default();
+        let renamed: String = match (taken.contains(&fold_case(&host_name)), options.on_collision) {
+            (false, _) | (true, NameCollision::Overwrite) => host_name.clone(),
+            (true, NameCollision::Skip) => return Ok(None),
+            (true, NameCollision::Error) => return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("host name {:?} is already taken by another entry", host_name))),
+            (true, NameCollision::Rename) => (1..).map(|n| add_tail(&host_name, n)).find(|name| !taken.contains(&fold_case(name))).expect("a free tail")
+        };
+        taken.insert(fold_case(&renamed));
+        let host_path: PathBuf = safe_host_path(host_dir, &renamed)?;
+
+        if host_name != get_host_name(fs, name, entry, HostNames::Short) {
+            fs.warn(Warning::NameTransliterated { path: image_path.to_string(), host_name: host_name.clone() });
+        }
+        if renamed != host_name { fs.warn(Warning::NameCollisionRenamed { path: image_path.to_string(), host_name: renamed }); }
+        Ok(Some(host_path))
+    }
+}
+
+impl Checkpoint {
+    /** Read the checkpoint file, creating it if missing. Lines that can't be
+     *  read, e.g. the last one of a run killed while writing it, are left
+     *  out: their files get extracted again. */
+    fn open(path: &Path) -> io::Result<Checkpoint> {
+        let content: String = match fs::read(path) {
+            Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e)
+        };
+        let mut extracted: HashMap<PathBuf, (u64, Vec<u8>)> = HashMap::new();
+        for line in content.lines() {
+            let mut fields = line.splitn(3, ' ');
+            let (Some(hex), Some(size), Some(host_path)) = (fields.next(), fields.next(), fields.next()) else { continue };
+            let (Some(digest), Ok(size)) = (digest::from_hex(hex, Crc32::OUTPUT_SIZE), size.parse::<u64>()) else { continue };
+            extracted.insert(PathBuf::from(host_path), (size, digest));
+        }
+
+        // A last line cut short would run into the first one added
+        let mut file: fs::File = fs::OpenOptions::new().create(true).append(true).open(path)?;
+        if !content.is_empty() && !content.ends_with('\n') { file.write_all(b"\n")?; }
+        Ok(Checkpoint { extracted, file })
+    }
+
+    /** Whether the host file is the one the checkpoint lists, same size and
+     *  digest: anything else, a missing or partly written file, is not. */
+    fn is_extracted(&self, host_path: &Path) -> bool {
+        let Some((size, digest)) = self.extracted.get(host_path) else { return false };
+        if fs::metadata(host_path).map_or(true, |metadata| metadata.len() != *size) { return false; }
+        fs::read(host_path).is_ok_and(|data| Crc32::digest(&data) == *digest)
+    }
+
+    /** List a file just written, right away, so that an interruption loses
+     *  at most the files being written. */
+    fn add(&mut self, host_path: &Path, data: &[u8]) -> io::Result<()> {
+        let digest: Vec<u8> = Crc32::digest(data);
+        self.file.write_all(format!("{} {} {}\n", digest::to_hex(&digest), data.len(), host_path.display()).as_bytes())?;
+        self.file.flush()?;
+        self.extracted.insert(host_path.to_path_buf(), (data.len() as u64, digest));
+        Ok(())
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Copy the tree under the image directory to the host directory, its
+ *  paths reported after the prefix. */
+fn extract_tree(fs: &mut Fat12Fs, image_dir: &str, host_dir: &Path, prefix: &str, extraction: &mut Extraction, options: &BulkOptions) -> io::Result<()> {
+    fs::create_dir_all(host_dir)?;
+
+    // Host directory of each image one, by path: with other names or
+    // collisions renamed, it's not the image path. None for a directory a
+    // collision left out, with its content
+    let mut host_dirs: HashMap<String, Option<PathBuf>> = HashMap::from([(String::new(), Some(host_dir.to_path_buf()))]);
+
+    // Long names of the entries of each image directory by short name,
+    // read as the walk gets there: only the short names are extracted
+    let mut long_names: HashMap<String, HashMap<[u8; 11], String>> = HashMap::new();
+
+    // The walk keeps a borrow of the image: pick the entries first
+    let mut last_path: String = String::new();
+    let mut directories: Vec<(String, PathBuf, DirectoryEntry)> = vec![];
+    for item in fs.walk(image_dir).collect::<Vec<_>>() {
+        let (path, entry): (String, DirectoryEntry) = match item {
+            Ok(item) => item,
+
+            // A directory that can't be listed: its content is skipped
+            Err(e) => { extraction.report.record_walk_error(&last_path, e, options)?; continue; }
+        };
+        let reported: String = format!("{}{}", prefix, path);
+        last_path = reported.clone();
+
+        // Directories the filter leaves out are named all the same, for
+        // what it picks inside them
+        let matched: bool = options.filter.matches(&entry);
+        if !matched && !entry.is_directory() { continue; }
+
+        // Content of a directory that failed is left out, the directory reported
+        let (parent, name): (&str, &str) = path.rsplit_once('/').unwrap_or(("", &path));
+        let Some(parent_host) = host_dirs.get(parent).cloned() else { continue };
+        let host_path: Option<PathBuf> = match parent_host {
+            Some(parent_host) => match extraction.get_host_path(fs, &parent_host, &join_image_path(image_dir, &path), name, &entry, options) {
+                Ok(host_path) => host_path,
+                Err(e) => { extraction.report.record(reported, Err(e), options)?; continue; }
+            },
+            None => None
+        };
+        if entry.is_directory() { host_dirs.insert(path.clone(), host_path.clone()); }
+        if !matched { continue; }
+        let Some(host_path) = host_path else {
+            extraction.report.skipped.push(reported);
+            continue;
+        };
+        if !long_names.contains_key(parent) {
+            let pairs: Vec<NamePair> = fs.read_name_pairs(&join_image_path(image_dir, parent)).unwrap_or_default();
+            let names: HashMap<[u8; 11], String> = pairs.into_iter()
+                .filter_map(|pair| Some((pair.entry.get_short_name(), pair.long_name?))).collect();
+            long_names.insert(parent.to_string(), names);
+        }
+        if long_names.get(parent).is_some_and(|names| names.contains_key(&entry.get_short_name())) {
+            fs.warn(Warning::LongNameDropped { path: join_image_path(image_dir, &path), short_name: name.to_string() });
+        }
+
+        if entry.is_directory() {
+            options.notify(|observer| observer.directory_entered(&reported));
+            let result: io::Result<()> = fs::create_dir_all(&host_path).map(|_| directories.push((reported.clone(), host_path, entry)));
+            extraction.report.record(reported, result, options)?;
+            continue;
+        }
+        extract_file(fs, &reported, &join_image_path(image_dir, &path), &host_path, &entry, extraction, options)?;
+    }
+
+    // Writing the content changed the directory times: set them last, deepest first
+    for (reported, host_path, entry) in directories.into_iter().rev() {
+        if let Err(e) = preserve_metadata(&host_path, &entry, options) { extraction.report.record(reported, Err(e), options)?; }
+    }
+    Ok(())
+}
+
+/** Copy a file to the host, creating its directory: with a filter, it may
+ *  have been left out. Files the checkpoint says are already there are
+ *  reported as skipped. */
+fn extract_file(fs: &mut Fat12Fs, path: &str, image_path: &str, host_path: &Path, entry: &DirectoryEntry, extraction: &mut Extraction, options: &BulkOptions) -> io::Result<()> {
+    options.notify(|observer| observer.file_started(path, entry.file_size as u64));
+    if extraction.checkpoint.as_ref().is_some_and(|checkpoint| checkpoint.is_extracted(host_path)) {
+        options.notify(|observer| observer.file_finished(path));
+        extraction.report.skipped.push(path.to_string());
+        return Ok(());
+    }
+
+    let mut copy = || -> io::Result<()> {
+        if let Some(parent) = host_path.parent() { fs::create_dir_all(parent)?; }
+        let data: Vec<u8> = options.text_mode.to_host(&fs.read_entry_data(entry, image_path)?);
+        fs::write(host_path, &data)?;
+        preserve_metadata(host_path, entry, options)?;
+        if let Some(checkpoint) = &mut extraction.checkpoint { checkpoint.add(host_path, &data)?; }
+        Ok(())
+    };
+    let result: io::Result<()> = copy();
+    if result.is_ok() { options.notify(|observer| observer.file_finished(path)); }
+    extraction.report.record(path.to_string(), result, options)
+}
+
+fn import_directory(fs: &mut Fat12Fs, directory: &Path, image_dir: &str, report: &mut BulkReport, options: &BulkOptions) -> io::Result<()> {
+    walk_host_tree(directory, "", &mut |child: &HostChild, prefix: &str| {
+        let short_name: [u8; 11] = match fs.get_short_name(&join_image_path(image_dir, prefix), &child.file_name) {
+            Ok(short_name) => short_name,
+            Err(e) => {
+                report.record(format!("{}{}", prefix, child.file_name), Err(e), options)?;
+                return Ok(None);
+            }
+        };
+        let path: String = format!("{}{}", prefix, short_name_to_string(&short_name));
+        let image_path: String = join_image_path(image_dir, &path);
+
+        // The entry is written under its short name: a host name that
+        // doesn't fit 8.3 is lost
+        let dropped: Option<Warning> = (to_short_name(&child.file_name) != Some(short_name))
+            .then(|| Warning::LongNameDropped { path: join_image_path(image_dir, &format!("{}{}", prefix, child.file_name)), short_name: short_name_to_string(&short_name) });
+
+        if child.file_type.is_symlink() {
+            report.record(path, Err(io::Error::new(io::ErrorKind::InvalidInput, "symbolic link")), options)?;
+        } else if child.file_type.is_dir() {
+            options.notify(|observer| observer.directory_entered(&path));
+            let timestamp: u64 = host_timestamp(&child.path, options);
+            let result: io::Result<()> = fs.create_dir_all(&image_path, timestamp);
+            let created: bool = result.is_ok();
+            if let Some(warning) = dropped.filter(|_| created) { fs.warn(warning); }
+            report.record(path.clone(), result, options)?;
+            if created { return Ok(Some(format!("{}/", path))); }
+        } else {
+            let timestamp: u64 = host_timestamp(&child.path, options);
+            let read_only: bool = options.preserve_read_only && fs::metadata(&child.path).is_ok_and(|metadata| metadata.permissions().readonly());
+            let result: io::Result<()> = write_source(fs, &path, &image_path, &mut HostFile(child.path.clone()), timestamp, options)
+                .and_then(|_| if read_only { fs.set_attributes(&image_path, ATTR_ARCHIVE | ATTR_READ_ONLY) } else { Ok(()) });
+            if result.is_ok() {
+                options.notify(|observer| observer.file_finished(&path));
+                if let Some(warning) = dropped { fs.warn(warning); }
+            }
+            report.record(path, result, options)?;
+        }
+        Ok(None)
+    })
+}
+
+/** Walk the host tree under the directory, names sorted so that every run
+ *  copies in the same order. The visitor gets each entry with the prefix
+ *  of its directory and gives the prefix to walk into a directory with,
+ *  None to leave it out. Symlinks are never followed, whatever it gives:
+ *  they could point anywhere on the host. */
+pub(crate) fn walk_host_tree<F>(directory: &Path, prefix: &str, visit: &mut F) -> io::Result<()>
+where F: FnMut(&HostChild, &str) -> io::Result<Option<String>> {
+    let mut children: Vec<fs::DirEntry> = fs::read_dir(directory)?.collect::<io::Result<_>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let child: HostChild = HostChild { path: child.path(), file_name: child.file_name().to_string_lossy().into_owned(), file_type: child.file_type()? };
+        let child_prefix: Option<String> = visit(&child, prefix)?;
+        if let Some(child_prefix) = child_prefix.filter(|_| child.file_type.is_dir()) {
+            walk_host_tree(&child.path, &child_prefix, visit)?;
+        }
+    }
+    Ok(())
+}
+
+/** Write the content of the source to the image, converted as text if the
+ *  options ask. The observer hears of it once the content is read, with its
+ *  size. */
+fn write_source(fs: &mut Fat12Fs, path: &str, image_path: &str, source: &mut dyn FileSource, timestamp: u64, options: &BulkOptions) -> io::Result<()> {
+    let content: Vec<u8> = source.read_content()?;
+    options.notify(|observer| observer.file_started(path, content.len() as u64));
+    let data: Vec<u8> = options.text_mode.to_image(&content)?;
+    fs.write_file(image_path, &data, timestamp)?;
+    Ok(())
+}
+
+/** Give the host file the last change time and the read-only flag of the
+ *  entry, as far as the options ask. Read-only directories are left
+ *  writable: DOS ignores the flag on them. */
+pub(crate) fn preserve_metadata(host_path: &Path, entry: &DirectoryEntry, options: &BulkOptions) -> io::Result<()> {
+    if options.preserve_times {
+        let modified = image_time_to_system_time(entry.get_last_change_timestamp(), options.timezone_offset);
+        let file: fs::File = match entry.is_directory() {
+            true => fs::File::open(host_path)?,
+            false => fs::OpenOptions::new().write(true).open(host_path)?
+        };
+        file.set_modified(modified)?;
+    }
+    if options.preserve_read_only && !entry.is_directory() && entry.attributes & ATTR_READ_ONLY != 0 {
+        let mut permissions: fs::Permissions = fs::metadata(host_path)?.permissions();
+        permissions.set_readonly(true);
+        fs::set_permissions(host_path, permissions)?;
+    }
+    Ok(())
+}
+
+/** Modification time of the host file, as the image stores it. */
+pub(crate) fn host_timestamp(path: &Path, options: &BulkOptions) -> u64 {
+    let timestamp: u64 = fs::metadata(path).and_then(|metadata| metadata.modified())
+        .map(|modified| modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0))
+        .unwrap_or(0);
+    timestamp.saturating_add_signed(options.timezone_offset as i64 * 60)
+}
+
+/** Host name of an entry, as the walk names it (its long name if any) or
+ *  from its short name. */
+fn get_host_name(fs: &Fat12Fs, name: &str, entry: &DirectoryEntry, host_names: HostNames) -> String {
+    if host_names == HostNames::Long { return name.to_string(); }
+
+    let short_name: [u8; 11] = entry.get_short_name();
+    let decode = |bytes: &[u8]| -> String {
+        let decoded: String = match host_names {
+            HostNames::Transliterated => fs.codepage.transliterate(bytes),
+            _ => fs.codepage.decode(bytes)
+        };
+        decoded.trim_end().to_string()
+    };
+    let (base, extension): (String, String) = (decode(&short_name[..8]), decode(&short_name[8..]));
+    if extension.is_empty() { base } else { format!("{}.{}", base, extension) }
+}
+
+/** Name with "~n" before the extension, e.g. "README~1.TXT". */
+fn add_tail(name: &str, n: usize) -> String {
+    match name.rsplit_once('.').filter(|(base, _)| !base.is_empty()) {
+        Some((base, extension)) => format!("{}~{}.{}", base, n, extension),
+        None => format!("{}~{}", name, n)
+    }
+}
+
+pub(crate) fn join_image_path(image_dir: &str, path: &str) -> String {
+    format!("{}/{}", image_dir.trim_end_matches('/'), path)
+}
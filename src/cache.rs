@@ -0,0 +1,88 @@
+use std::{collections::{HashMap, VecDeque}, sync::{Arc, Mutex}};
+
+use crate::Lba;
+
+/* ==== STRUCTS ============================================================= */
+/** Least recently used cache of disk sectors, keyed by image and sector
+ *  number, so that a single cache can be shared by many opened images. */
+pub struct SectorCache {
+    capacity: usize,
+    sectors: HashMap<(usize, Lba), Vec<u8>>,
+
+    // Keys from the least to the most recently used
+    order: VecDeque<(usize, Lba)>,
+    hits: u64,
+    misses: u64
+}
+
+impl SectorCache {
+    /** Create a cache holding up to `capacity` sectors. */
+    pub fn new(capacity: usize) -> SectorCache {
+        SectorCache { capacity, sectors: HashMap::new(), order: VecDeque::new(), hits: 0, misses: 0 }
+    }
+
+    /** Wrap a new cache so that it can be shared between images. */
+    pub fn shared(capacity: usize) -> Arc<Mutex<SectorCache>> {
+        Arc::new(Mutex::new(SectorCache::new(capacity)))
+    }
+
+    pub fn get(&mut self, image_id: usize, sector: Lba) -> Option<&[u8]> {
+        if !self.sectors.contains_key(&(image_id, sector)) {
+            self.misses += 1;
+            return None;
+        }
+        self.hits += 1;
+        self.touch((image_id, sector));
+        self.sectors.get(&(image_id, sector)).map(Vec::as_slice)
+    }
+
+    pub fn insert(&mut self, image_id: usize, sector: Lba, data: Vec<u8>) {
+        if self.capacity == 0 { return; }
+
+        // Make room by dropping the least recently used sector
+        if !self.sectors.contains_key(&(image_id, sector)) && self.sectors.len() >= self.capacity {
+            if let Some(oldest) = self.order.pop_front() { self.sectors.remove(&oldest); }
+        }
+        self.sectors.insert((image_id, sector), data);
+        self.touch((image_id, sector));
+    }
+
+    /** Forget the given sector, e.g. because it has just been written. */
+    pub fn invalidate(&mut self, image_id: usize, sector: Lba) {
+        if self.sectors.remove(&(image_id, sector)).is_some() {
+            self.order.retain(|key| *key != (image_id, sector));
+        }
+    }
+
+    /** Forget every sector of the given image. */
+    pub fn invalidate_image(&mut self, image_id: usize) {
+        self.sectors.retain(|(id, _), _| *id != image_id);
+        self.order.retain(|(id, _)| *id != image_id);
+    }
+
+    pub fn len(&self) -> usize {
+        self.sectors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sectors.is_empty()
+    }
+
+    /** (hits, misses) since the cache was created. */
+    pub fn get_stats(&self) -> (u64, u64) {
+        (self.hits, self.misses)
+    }
+
+    /** Move the key to the most recently used end. */
+    fn touch(&mut self, key: (usize, Lba)) {
+        if let Some(i) = self.order.iter().position(|other| *other == key) { self.order.remove(i); }
+        self.order.push_back(key);
+    }
+}
+
+/** Cache shared by an opened image, with the id its sectors are stored under. */
+#[derive(Clone)]
+pub(crate) struct CacheHandle {
+    pub(crate) cache: Arc<Mutex<SectorCache>>,
+    pub(crate) image_id: usize
+}
@@ -0,0 +1,100 @@
+use std::{fs::{self, File}, io::{self, BufWriter, Write}, path::{Path, PathBuf}};
+use rs_disk_reader::{format_rfc3339, identify::{self, Identification}, volume, DirectoryEntry, Fat12Fs};
+
+use crate::serve::escape_json;
+
+/* ==== METHODS ============================================================= */
+/** catalog <dir> [--recursive] [--output <file>]: describe every image in
+ *  the directory in a single JSON document, to search a whole collection
+ *  at once (e.g. with jq) instead of opening the images one by one:
+ *
+ *  {"images":[
+ *  {"image":"disks/A.IMG","size":1474560,"type":"FAT12","label":"GAMES","serial":"1A2B-3C4D","files":[
+ *    {"path":"/README.TXT","directory":false,"size":120,"modified":"1994-03-01T12:00:00+00:00","attributes":"-----A"}]},
+ *  {"image":"disks/notes.txt","size":80,"type":null,"error":"..."}
+ *  ]}
+ *
+ *  Files that aren't images, or that can't be read, are listed with an
+ *  error instead of their files: one bad dump doesn't stop the pass. Gives
+ *  how many of them there were. */
+pub fn run(directory: &Path, recursive: bool, output: Option<&Path>) -> io::Result<usize> {
+    let mut images: Vec<PathBuf> = vec![];
+    collect_images(directory, recursive, &mut images)?;
+    images.sort();
+
+    let mut output: Box<dyn Write> = match output {
+        Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout().lock()))
+    };
+    let mut failed: usize = 0;
+    writeln!(output, "{{\"images\":[")?;
+    for (i, image) in images.iter().enumerate() {
+        let (record, error): (String, bool) = describe_image(image);
+        if error { failed += 1; }
+        writeln!(output, "{}{}", record, if i + 1 < images.len() { "," } else { "" })?;
+    }
+    writeln!(output, "]}}")?;
+    output.flush()?;
+    Ok(failed)
+}
+
+/* ==== UTILS =============================================================== */
+/** Every regular file in the directory, and in its subdirectories when
+ *  recursive. */
+fn collect_images(directory: &Path, recursive: bool, images: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(directory)? {
+        let entry: fs::DirEntry = entry?;
+        let file_type: fs::FileType = entry.file_type()?;
+        if file_type.is_file() { images.push(entry.path()); }
+        else if file_type.is_dir() && recursive { collect_images(&entry.path(), recursive, images)?; }
+    }
+    Ok(())
+}
+
+/** JSON object of a single image, and whether it's an error one. */
+fn describe_image(path: &Path) -> (String, bool) {
+    let path_text: String = path.to_string_lossy().to_string();
+    let identification: Option<Identification> = identify::identify(&path_text).ok();
+    let mut record: String = format!("{{\"image\":\"{}\",\"size\":{},\"type\":{}",
+        escape_json(&path_text),
+        identification.as_ref().map_or(0, |identification| identification.image_size),
+        json_string(identification.as_ref().and_then(|identification| identification.fat_type)));
+
+    match list_image(&path_text) {
+        Ok((label, serial, files)) => {
+            record.push_str(&format!(",\"label\":{},\"serial\":{},\"files\":[", json_string(label.as_deref()), json_string(serial.as_deref())));
+            record.push_str(&files.join(","));
+            record.push_str("]}");
+            (record, false)
+        },
+        Err(e) => {
+            record.push_str(&format!(",\"error\":\"{}\"}}", escape_json(&e.to_string())));
+            (record, true)
+        }
+    }
+}
+
+/** Label, serial and the JSON objects of every file and directory of the
+ *  image, in walk order. */
+fn list_image(path: &str) -> io::Result<(Option<String>, Option<String>, Vec<String>)> {
+    let mut fs: Fat12Fs = crate::fs_options().read_only(true).open(path)?;
+    let label: Option<String> = fs.get_volume_label()?;
+    let serial: Option<String> = fs.get_boot_sector().get_volume_id().map(volume::format_volume_id);
+
+    let entries: Vec<(String, DirectoryEntry)> = fs.walk("/").collect::<io::Result<_>>()?;
+    let files: Vec<String> = entries.iter().map(|(path, entry)| {
+        let file_size: u32 = entry.file_size;
+        format!("\n  {{\"path\":\"/{}\",\"directory\":{},\"size\":{},\"modified\":\"{}\",\"attributes\":\"{}\"}}",
+            escape_json(path), entry.is_directory(), file_size,
+            format_rfc3339(entry.get_last_change_timestamp(), crate::config().timezone_offset), entry.get_attributes_string())
+    }).collect();
+    Ok((label, serial, files))
+}
+
+/** JSON string, or null. */
+fn json_string(text: Option<&str>) -> String {
+    match text {
+        Some(text) => format!("\"{}\"", escape_json(text)),
+        None => String::from("null")
+    }
+}
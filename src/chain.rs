@@ -0,0 +1,109 @@
+use crate::{Cluster, Fat, Fat12Fs, END_OF_CHAIN, END_OF_CHAIN_MIN, FREE_CLUSTER};
+
+/** FAT value of a cluster marked bad. */
+const BAD_CLUSTER: u16 = 0x0FF7;
+
+/** First of the reserved values before the bad cluster marker. */
+const RESERVED_MIN: u16 = 0x0FF0;
+
+/* ==== STRUCTS ============================================================= */
+/** Iterator over the clusters of a chain, see Fat::chain. It stops at
+ *  whatever isn't the next cluster of the chain, and never yields a
+ *  cluster twice: get_end then tells why it stopped. */
+#[derive(Clone)]
+pub struct ClusterChain<'a> {
+    fat: &'a Fat,
+
+    // Cluster to yield next, as the previous entry (or the start) says
+    next: Option<Cluster>,
+
+    // Clusters yielded so far, by index, to tell a loop
+    visited: Vec<bool>,
+    end: Option<ChainEnd>
+}
+
+/** Why a ClusterChain stopped. Every variant but EndOfChain means a
+ *  corrupt chain, except Free for a start of 0: a file without data. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainEnd {
+    /** An end of chain marker (0xFF8 to 0xFFF): the chain is whole. */
+    EndOfChain,
+
+    /** A free entry (0) in the chain, which is cut there. */
+    Free,
+
+    /** A cluster marked bad (0xFF7) in the chain. */
+    Bad,
+
+    /** A reserved value: 1, or 0xFF0 to 0xFF6. */
+    Reserved(u16),
+
+    /** A cluster past the last data cluster of the volume, or past the
+     *  entries the FAT holds. */
+    OutOfRange(Cluster),
+
+    /** A cluster already in the chain, which would loop forever. */
+    Loop(Cluster)
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat {
+    /** Clusters of the chain starting at the cluster, on a volume with that
+     *  many data clusters, decoded from the FAT as they are needed, e.g.
+     *  for readers and analyzers of their own. Fat::get_chain collects them,
+     *  Fat12Fs::chain gives the cluster count of the volume. */
+    pub fn chain(&self, start: Cluster, cluster_count: usize) -> ClusterChain<'_> {
+        // Clusters past the last data cluster are out, as are the ones past
+        // the FAT or past the last one FAT12 numbers, on a bogus count
+        let capacity: usize = (cluster_count + 2).min(self.get_entry_count()).min(RESERVED_MIN as usize);
+        let mut chain: ClusterChain = ClusterChain { fat: self, next: None, visited: vec![false; capacity], end: None };
+        chain.follow(start);
+        chain
+    }
+}
+
+impl Fat12Fs {
+    /** Clusters of the chain starting at the cluster, see Fat::chain. */
+    pub fn chain(&self, start: Cluster) -> ClusterChain<'_> {
+        self.fat.chain(start, self.boot_sector.get_cluster_count())
+    }
+
+    /** Every cluster of the chain from the given one, see Fat::get_chain. */
+    pub fn get_chain(&self, first_cluster: Cluster) -> Vec<Cluster> {
+        self.chain(first_cluster).collect()
+    }
+}
+
+impl ClusterChain<'_> {
+    /** Why the chain stopped, None while it goes on. */
+    pub fn get_end(&self) -> Option<ChainEnd> {
+        self.end
+    }
+
+    /** Take the value of an entry (or the start) as the next cluster, or
+     *  as the end of the chain. */
+    fn follow(&mut self, cluster: Cluster) {
+        let value: u32 = cluster.0;
+        self.end = match value {
+            _ if value == FREE_CLUSTER as u32 => Some(ChainEnd::Free),
+            _ if value == BAD_CLUSTER as u32 => Some(ChainEnd::Bad),
+            _ if (END_OF_CHAIN_MIN as u32..=END_OF_CHAIN as u32).contains(&value) => Some(ChainEnd::EndOfChain),
+            _ if value == 1 || (RESERVED_MIN as u32..BAD_CLUSTER as u32).contains(&value) => Some(ChainEnd::Reserved(value as u16)),
+            _ if value as usize >= self.visited.len() => Some(ChainEnd::OutOfRange(cluster)),
+            _ if self.visited[cluster.0 as usize] => Some(ChainEnd::Loop(cluster)),
+            _ => None
+        };
+        self.next = if self.end.is_none() { Some(cluster) } else { None };
+    }
+}
+
+impl Iterator for ClusterChain<'_> {
+    type Item = Cluster;
+
+    fn next(&mut self) -> Option<Cluster> {
+        let cluster: Cluster = self.next.take()?;
+        self.visited[cluster.0 as usize] = true;
+        self.follow(Cluster::from(self.fat.get_entry(cluster)));
+        Some(cluster)
+    }
+}
@@ -0,0 +1,738 @@
+use std::collections::BTreeMap;
+
+use crate::error::{EXIT_CORRUPT, EXIT_DIFFERENT, EXIT_INVALID, EXIT_IO, EXIT_LOCKED, EXIT_NOT_FOUND, EXIT_NO_SPACE, EXIT_PERMISSION, EXIT_USAGE};
+
+/* ==== STRUCTS ============================================================= */
+/** What a value stands for, so that shells know how to complete it. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /** Image file on the host, or an alias from the config. */
+    Image,
+    HostPath,
+
+    /** "image:/path" or host path. */
+    Location,
+
+    /** One of a fixed list of words. */
+    Choice(&'static [&'static str]),
+    Text
+}
+
+/** Positional argument of a command. */
+pub struct Arg {
+    pub name: &'static str,
+    pub kind: ValueKind,
+    pub about: &'static str
+}
+
+/** --option of a command: a flag if it takes no value. */
+pub struct Opt {
+    pub long: &'static str,
+    pub value: Option<(&'static str, ValueKind)>,
+    pub about: &'static str
+}
+
+/** Subcommand definition: parsing, usage, completions and man page are all
+ *  built from these. */
+pub struct Command {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub args: &'static [Arg],
+    pub opts: &'static [Opt]
+}
+
+/** Arguments of a command, as parsed against its definition. */
+pub struct Matches {
+    positionals: Vec<String>,
+    opts: BTreeMap<&'static str, Option<String>>
+}
+
+pub const PROGRAM_NAME: &str = "rs-disk-reader";
+pub const CONFLICT_POLICIES: &[&str] = &["skip", "host", "image", "newer"];
+pub const SORT_KEYS: &[&str] = &["name", "size", "mtime", "cluster"];
+pub const SHELLS: &[&str] = &["bash", "zsh", "fish"];
+
+/** Exit codes with their meaning, for the man page. */
+const EXIT_CODES: &[(i32, &str)] = &[
+    (0, "Success."),
+    (EXIT_DIFFERENT, "The compared trees differ (diff), or conflicts were left (sync)."),
+    (EXIT_NOT_FOUND, "A file, directory, image or profile doesn't exist."),
+    (EXIT_CORRUPT, "The image (or config) is not valid."),
+    (EXIT_NO_SPACE, "The image is out of free clusters or root entries."),
+    (EXIT_IO, "Other I/O error."),
+    (EXIT_PERMISSION, "The image or a host file can't be written."),
+    (EXIT_INVALID, "The operation doesn't apply to the target, e.g. the name is not valid 8.3."),
+    (EXIT_LOCKED, "Another process has the image open for writing (see --force)."),
+    (EXIT_USAGE, "Command line mistake.")
+];
+
+/** Options accepted before the command. */
+pub const GLOBAL_OPTS: &[Opt] = &[
+    Opt { long: "profile", value: Some(("name", ValueKind::Text)), about: "Apply a [profile.<name>] section of the config" },
+    Opt { long: "quiet", value: None, about: "Don't print errors, only set the exit code" },
+    Opt { long: "errors-json", value: None, about: "Print errors as a single JSON line on stderr" },
+    Opt { long: "warnings-json", value: None, about: "Print warnings of lossy reads and writes (dropped long names, clamped times) as JSON lines on stderr" },
+    Opt { long: "stats", value: None, about: "Print the sectors read and written, cache hits and other counters on stderr at the end" },
+    Opt { long: "throttle", value: Some(("rate", ValueKind::Text)), about: "Read and write the images at most this many bytes per second, e.g. 64K (0 for no limit)" },
+    Opt { long: "force", value: None, about: "Open the images for writing even when another process holds their lock" },
+    Opt { long: "passphrase-file", value: Some(("file", ValueKind::HostPath)), about: "Open encrypted images with the passphrase on the first line of the file" },
+
+    // BPB overrides, see bpb::FIELDS: the long name is the field one with dashes
+    Opt { long: "bytes-per-sector", value: Some(("n", ValueKind::Text)), about: "Read the images as having this sector size, whatever their BPB says" },
+    Opt { long: "sectors-per-cluster", value: Some(("n", ValueKind::Text)), about: "Override the cluster size of the BPB, in sectors" },
+    Opt { long: "reserved-sectors", value: Some(("n", ValueKind::Text)), about: "Override the sectors before the first FAT" },
+    Opt { long: "fat-count", value: Some(("n", ValueKind::Text)), about: "Override the number of FAT copies" },
+    Opt { long: "root-entries", value: Some(("n", ValueKind::Text)), about: "Override the size of the root directory, in entries" },
+    Opt { long: "total-sectors", value: Some(("n", ValueKind::Text)), about: "Override the sector count of the volume" },
+    Opt { long: "media-descriptor", value: Some(("n", ValueKind::Text)), about: "Override the media descriptor, e.g. 0xF0" },
+    Opt { long: "sectors-per-fat", value: Some(("n", ValueKind::Text)), about: "Override the size of each FAT copy, in sectors" }
+];
+
+pub const COMMANDS: &[Command] = &[
+    Command {
+        name: "read",
+        about: "Print the content of a root directory file as hex (the command name can be omitted)",
+        args: &[
+            Arg { name: "image", kind: ValueKind::Image, about: "Image to read" },
+            Arg { name: "file", kind: ValueKind::Text, about: "Raw 8.3 name, e.g. \"KERNEL  BIN\"" }
+        ],
+        opts: &[]
+    },
+    Command {
+        name: "sync",
+        about: "Keep a host directory and an image directory in sync, both ways",
+        args: &[
+            Arg { name: "image", kind: ValueKind::Image, about: "Image to sync" },
+            Arg { name: "host-dir", kind: ValueKind::HostPath, about: "Host directory to sync" }
+        ],
+        opts: &[
+            Opt { long: "dir", value: Some(("image-dir", ValueKind::Text)), about: "Image directory to sync (default /)" },
+            Opt { long: "dry-run", value: None, about: "Only report what would be done" },
+            Opt { long: "on-conflict", value: Some(("policy", ValueKind::Choice(CONFLICT_POLICIES))), about: "What to do with files changed on both sides" }
+        ]
+    },
+    Command {
+        name: "shell",
+        about: "Open an interactive session over the image",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to open" }],
+        opts: &[
+            Opt { long: "read-only", value: None, about: "Don't allow changes to the image" },
+            Opt { long: "overlay", value: None, about: "Keep changes in memory until the commit command" }
+        ]
+    },
+    Command {
+        name: "batch",
+        about: "Run the shell commands of a script against the image, writing the changes at the end in one go",
+        args: &[
+            Arg { name: "script", kind: ValueKind::HostPath, about: "Script with one shell command per line, - for stdin" },
+            Arg { name: "image", kind: ValueKind::Image, about: "Image to change" }
+        ],
+        opts: &[Opt { long: "keep-going", value: None, about: "Skip the failing commands instead of stopping with the image untouched" }]
+    },
+    #[cfg(feature = "tui")]
+    Command {
+        name: "browse",
+        about: "Open a full screen, read-only browser over the image",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to browse" }],
+        opts: &[]
+    },
+    Command {
+        name: "serve",
+        about: "Expose the image read-only over HTTP",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to serve" }],
+        opts: &[Opt { long: "listen", value: Some(("address", ValueKind::Text)), about: "Address to listen on (default 127.0.0.1:8080)" }]
+    },
+    Command {
+        name: "export",
+        about: "Serve the raw image read-only over the NBD protocol",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to export" }],
+        opts: &[
+            Opt { long: "listen", value: Some(("address", ValueKind::Text)), about: "Address to listen on (default 127.0.0.1:10809)" },
+            Opt { long: "name", value: Some(("name", ValueKind::Text)), about: "Export name (default floppy)" }
+        ]
+    },
+    Command {
+        name: "cp",
+        about: "Copy a file between images, or between an image and the host",
+        args: &[
+            Arg { name: "from", kind: ValueKind::Location, about: "Source, as image:/path or host path" },
+            Arg { name: "to", kind: ValueKind::Location, about: "Destination, as image:/path or host path" }
+        ],
+        opts: &[
+            Opt { long: "append", value: None, about: "Add to the end of the image file instead of replacing it" },
+            Opt { long: "offset", value: Some(("bytes", ValueKind::Text)), about: "Write into the image file at that offset instead of replacing it" }
+        ]
+    },
+    Command {
+        name: "inspect",
+        about: "Identify an executable (MZ, COM or boot sector) and show its entry point and load address",
+        args: &[Arg { name: "file", kind: ValueKind::Location, about: "File, as image:/path or host path" }],
+        opts: &[]
+    },
+    Command {
+        name: "diff",
+        about: "Compare two directory trees, possibly on different images",
+        args: &[
+            Arg { name: "left", kind: ValueKind::Location, about: "First directory, as image:/path" },
+            Arg { name: "right", kind: ValueKind::Location, about: "Second directory, as image:/path" }
+        ],
+        opts: &[]
+    },
+    Command {
+        name: "hash",
+        about: "Print the digest of every file under an image directory, as md5sum and sha256sum do, or check them against such a list",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to hash" }],
+        opts: &[
+            Opt { long: "dir", value: Some(("image-dir", ValueKind::Text)), about: "Image directory to hash (default /)" },
+            Opt { long: "algorithm", value: Some(("name", ValueKind::Choice(&["crc32", "md5", "sha1", "sha256"]))), about: "Digest to use (default hash_algorithm in the config)" },
+            Opt { long: "check", value: Some(("list", ValueKind::HostPath)), about: "Check the files against a list of \"<digest>  <path>\" lines instead" }
+        ]
+    },
+    Command {
+        name: "fingerprint",
+        about: "Print a digest of the files, directories and their names, the same for copies of a volume that only differ in serial, timestamps, layout or slack",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to fingerprint" }],
+        opts: &[Opt { long: "algorithm", value: Some(("name", ValueKind::Choice(&["crc32", "md5", "sha1", "sha256"]))), about: "Digest to use (default sha256, whatever the config says, so that fingerprints compare)" }]
+    },
+    Command {
+        name: "ls",
+        about: "List an image directory, or the whole tree under it",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to list" }],
+        opts: &[
+            Opt { long: "dir", value: Some(("image-dir", ValueKind::Text)), about: "Image directory to list (default /)" },
+            Opt { long: "recursive", value: None, about: "Also list the content of subdirectories" },
+            Opt { long: "all", value: None, about: "Also list the . and .. entries of subdirectories" },
+            Opt { long: "short-names", value: None, about: "Show the 8.3 names, instead of the long names" },
+            Opt { long: "long", value: None, about: "One column each for attributes, size, last change and first cluster" },
+            Opt { long: "bytes", value: None, about: "Sizes in bytes, instead of K and M" },
+            Opt { long: "sort", value: Some(("key", ValueKind::Choice(SORT_KEYS))), about: "Sort by this instead of the directory order" },
+            Opt { long: "reverse", value: None, about: "Reverse the order" },
+            Opt { long: "hidden", value: None, about: "Only hidden entries" },
+            Opt { long: "system-only", value: None, about: "Only system entries" },
+            Opt { long: "attributes", value: Some(("mask", ValueKind::Text)), about: "Attributes that must be set, or clear after a -, e.g. HS-D" },
+            Opt { long: "min-size", value: Some(("bytes", ValueKind::Text)), about: "Only files at least this big" },
+            Opt { long: "max-size", value: Some(("bytes", ValueKind::Text)), about: "Only files at most this big" },
+            Opt { long: "newer-than", value: Some(("date", ValueKind::Text)), about: "Only entries changed after YYYY-MM-DD [HH:MM:SS]" },
+            Opt { long: "older-than", value: Some(("date", ValueKind::Text)), about: "Only entries changed before YYYY-MM-DD [HH:MM:SS]" }
+        ]
+    },
+    Command {
+        name: "extract",
+        about: "Copy an image directory tree to the host",
+        args: &[
+            Arg { name: "image", kind: ValueKind::Image, about: "Image to extract from" },
+            Arg { name: "host-dir", kind: ValueKind::HostPath, about: "Host directory to extract to" }
+        ],
+        opts: &[
+            Opt { long: "dir", value: Some(("image-dir", ValueKind::Text)), about: "Image directory to extract (default /)" },
+            Opt { long: "keep-going", value: None, about: "Report files that fail and go on with the others" },
+            Opt { long: "files-from", value: Some(("list", ValueKind::HostPath)), about: "Extract only the paths listed in the file (- for stdin), one per line" },
+            Opt { long: "null", value: None, about: "Paths in the --files-from list end with a NUL byte instead of a newline" },
+            Opt { long: "checkpoint", value: Some(("file", ValueKind::HostPath)), about: "List the extracted files in the file, and skip the ones it lists that are intact" },
+            Opt { long: "preserve-times", value: None, about: "Give the host files the last change time of their entry" },
+            Opt { long: "preserve-read-only", value: None, about: "Make the host files of read-only entries read-only" },
+            Opt { long: "text", value: None, about: "Text files: turn CR LF line endings into LF" },
+            Opt { long: "utf8", value: None, about: "Text files: turn the code page of the config into UTF-8" },
+            Opt { long: "host-names", value: Some(("names", ValueKind::Choice(&["long", "transliterated", "short"]))), about: "Name host files after the long name (default), the short name in ASCII, or the short name" },
+            Opt { long: "on-collision", value: Some(("policy", ValueKind::Choice(&["rename", "skip", "error", "overwrite"]))), about: "Entries with the same host name but for case: rename the later ones with ~1, ~2... (default), skip them, fail them, or overwrite" },
+            Opt { long: "hidden", value: None, about: "Only hidden entries" },
+            Opt { long: "system-only", value: None, about: "Only system entries" },
+            Opt { long: "attributes", value: Some(("mask", ValueKind::Text)), about: "Attributes that must be set, or clear after a -, e.g. HS-D" },
+            Opt { long: "min-size", value: Some(("bytes", ValueKind::Text)), about: "Only files at least this big" },
+            Opt { long: "max-size", value: Some(("bytes", ValueKind::Text)), about: "Only files at most this big" },
+            Opt { long: "newer-than", value: Some(("date", ValueKind::Text)), about: "Only entries changed after YYYY-MM-DD [HH:MM:SS]" },
+            Opt { long: "older-than", value: Some(("date", ValueKind::Text)), about: "Only entries changed before YYYY-MM-DD [HH:MM:SS]" }
+        ]
+    },
+    Command {
+        name: "import",
+        about: "Copy a host directory tree into the image",
+        args: &[
+            Arg { name: "image", kind: ValueKind::Image, about: "Image to import into" },
+            Arg { name: "host-dir", kind: ValueKind::HostPath, about: "Host directory to import" }
+        ],
+        opts: &[
+            Opt { long: "dir", value: Some(("image-dir", ValueKind::Text)), about: "Image directory to import into (default /)" },
+            Opt { long: "keep-going", value: None, about: "Report files that fail and go on with the others" },
+            Opt { long: "preserve-read-only", value: None, about: "Mark the entries of read-only host files read-only" },
+            Opt { long: "text", value: None, about: "Text files: turn LF line endings into CR LF" },
+            Opt { long: "utf8", value: None, about: "Text files: turn UTF-8 into the code page of the config, refusing characters it doesn't have" },
+            Opt { long: "audit-log", value: Some(("file", ValueKind::HostPath)), about: "Write every change made to the image to the file, as JSON lines" }
+        ]
+    },
+    Command {
+        name: "check",
+        about: "Read every directory and file of the image, reporting the corrupt ones",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to check" }],
+        opts: &[
+            Opt { long: "dir", value: Some(("image-dir", ValueKind::Text)), about: "Image directory to check (default /)" },
+            Opt { long: "keep-going", value: None, about: "Report files that fail and go on with the others" },
+            Opt { long: "mirror-fat", value: None, about: "First write the FAT in use (see active_fat in the config) to every copy" },
+            Opt { long: "rebuild-fat", value: None, about: "First replace the FAT with one built from the directory entries, taking files to be unfragmented" },
+            Opt { long: "repair", value: None, about: "Save the lost cluster chains as FILE0000.CHK and on in a FOUND.000 directory, as chkdsk does" },
+            Opt { long: "trust", value: Some(("source", ValueKind::Choice(&["bpb", "media"]))), about: "Geometry to use when the BPB and the media descriptor disagree (default geometry_source in the config)" }
+        ]
+    },
+    Command {
+        name: "split",
+        about: "Copy a host directory tree across a set of images, cutting the files that don't fit one, with a manifest on each",
+        args: &[
+            Arg { name: "host-dir", kind: ValueKind::HostPath, about: "Host directory to split" },
+            Arg { name: "images", kind: ValueKind::Text, about: "Images of the set in order, separated by commas" }
+        ],
+        opts: &[]
+    },
+    Command {
+        name: "merge",
+        about: "Put the files of a split set back together on the host",
+        args: &[
+            Arg { name: "images", kind: ValueKind::Text, about: "Images of the set in any order, separated by commas" },
+            Arg { name: "host-dir", kind: ValueKind::HostPath, about: "Host directory to merge to" }
+        ],
+        opts: &[Opt { long: "preserve-times", value: None, about: "Give the host files the last change time of their entry" }]
+    },
+    Command {
+        name: "label",
+        about: "Show or change the volume label",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to label" }],
+        opts: &[
+            Opt { long: "set", value: Some(("label", ValueKind::Text)), about: "New label, up to 11 characters" },
+            Opt { long: "clear", value: None, about: "Remove the label" }
+        ]
+    },
+    Command {
+        name: "info",
+        about: "Show what the image is, or with --diagnose every check it fails",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to describe" }],
+        opts: &[
+            Opt { long: "diagnose", value: None, about: "List every failed check, with offsets and expected values, even if the image can't be opened" },
+            Opt { long: "fsstat", value: None, about: "Report in the layout of Sleuth Kit's fsstat: file system, metadata and content information, FAT runs" }
+        ]
+    },
+    Command {
+        name: "bpb",
+        about: "Show the BIOS parameter block and the geometry it makes, or change a field of it",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to read or change" }],
+        opts: &[Opt { long: "set", value: Some(("field=value", ValueKind::Text)), about: "Change a field, e.g. heads_count=2 or media_descriptor=0xF0" }]
+    },
+    Command {
+        name: "sort-dir",
+        about: "Reorder the slots of a directory, for programs that list them as they are",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to change" }],
+        opts: &[
+            Opt { long: "dir", value: Some(("image-dir", ValueKind::Text)), about: "Image directory to sort (default /)" },
+            Opt { long: "sort", value: Some(("key", ValueKind::Choice(SORT_KEYS))), about: "Sort by this (default name)" },
+            Opt { long: "reverse", value: None, about: "Reverse the order" }
+        ]
+    },
+    Command {
+        name: "trim",
+        about: "Zero the free clusters that still hold deleted data, for flash media and sparse images",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image or device to trim" }],
+        opts: &[]
+    },
+    Command {
+        name: "sparse",
+        about: "Turn the sectors of an image holding only zeros into holes of a sparse host file",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image file to rewrite" }],
+        opts: &[Opt { long: "materialize", value: None, about: "Write every sector instead, holes included" }]
+    },
+    Command {
+        name: "resize",
+        about: "Grow or shrink an image, and with --filesystem the volume in it, e.g. to fit media of another size",
+        args: &[
+            Arg { name: "image", kind: ValueKind::Image, about: "Image file to resize" },
+            Arg { name: "size", kind: ValueKind::Text, about: "New size in bytes, with an optional K or M suffix, or min for the smallest it can take" }
+        ],
+        opts: &[
+            Opt { long: "filesystem", value: None, about: "Resize the volume too, growing the FAT as needed or cutting free clusters at its end" },
+            Opt { long: "dry-run", value: None, about: "Only report what would be done" }
+        ]
+    },
+    Command {
+        name: "reserved",
+        about: "Read or write the reserved sectors after the boot sector, e.g. a stage 2 loader outside of the filesystem",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to read or change" }],
+        opts: &[
+            Opt { long: "read", value: Some(("file", ValueKind::HostPath)), about: "Save the reserved region to a host file" },
+            Opt { long: "write", value: Some(("file", ValueKind::HostPath)), about: "Write a host file at the start of the reserved region, zeroing the rest" }
+        ]
+    },
+    Command {
+        name: "serial",
+        about: "Show or change the volume id (serial number), e.g. after cloning an image",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to read or change" }],
+        opts: &[
+            Opt { long: "regenerate", value: None, about: "Set a random volume id" },
+            Opt { long: "from-time", value: Some(("unix-seconds", ValueKind::Text)), about: "Set the volume id DOS would derive from this time" },
+            Opt { long: "set", value: Some(("XXXX-XXXX", ValueKind::Text)), about: "Set this volume id" }
+        ]
+    },
+    Command {
+        name: "map",
+        about: "Show the sectors a file takes, as runs of consecutive sectors",
+        args: &[
+            Arg { name: "image", kind: ValueKind::Image, about: "Image to read" },
+            Arg { name: "path", kind: ValueKind::Text, about: "File or directory in the image, e.g. /BOOT/KERNEL.BIN" }
+        ],
+        opts: &[
+            Opt { long: "chs", value: None, about: "Also give the cylinder/head/sector address of each run, from the BPB geometry" },
+            Opt { long: "entry", value: None, about: "Also give the sector and offset of the directory entry, and the first cluster" },
+            Opt { long: "image", value: None, about: "Draw every cluster of the volume instead, marking the ones of the path (/ for every file)" },
+            Opt { long: "svg", value: Some(("file", ValueKind::HostPath)), about: "With --image, write the drawing to an SVG file" }
+        ]
+    },
+    Command {
+        name: "df",
+        about: "Show free and used space, and the space files waste in their last cluster",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to measure" }],
+        opts: &[
+            Opt { long: "files", value: None, about: "Also list the allocation of every file" },
+            Opt { long: "slots", value: None, about: "Also list the used, long name, deleted and free slots of every directory" }
+        ]
+    },
+    Command {
+        name: "seal",
+        about: "Store checksums of the image in its reserved sectors, or check the image against them",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to seal or check" }],
+        opts: &[Opt { long: "verify", value: None, about: "Check the image against its seal instead, naming the regions that changed" }]
+    },
+    Command {
+        name: "encrypt",
+        about: "Store an image in a container encrypted with the passphrase, which the other commands then open",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to encrypt" }],
+        opts: &[
+            Opt { long: "output", value: Some(("file", ValueKind::HostPath)), about: "Write the container to the file instead of over the image" },
+            Opt { long: "iterations", value: Some(("n", ValueKind::Text)), about: "PBKDF2 iterations deriving the key (100000 by default)" }
+        ]
+    },
+    Command {
+        name: "decrypt",
+        about: "Take an image out of its encrypted container",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Container to decrypt" }],
+        opts: &[Opt { long: "output", value: Some(("file", ValueKind::HostPath)), about: "Write the image to the file instead of over the container" }]
+    },
+    Command {
+        name: "provision",
+        about: "Build a new image from a manifest giving its geometry, label, boot code and files",
+        args: &[
+            Arg { name: "manifest", kind: ValueKind::HostPath, about: "Manifest file, see the README" },
+            Arg { name: "image", kind: ValueKind::Image, about: "Image to create, replacing any file there" }
+        ],
+        opts: &[
+            Opt { long: "apply", value: None, about: "Update the existing image instead, changing only the entries that differ from the manifest" },
+            Opt { long: "materialize", value: None, about: "Write every sector of a new image instead of leaving the empty ones as holes" },
+            Opt { long: "dry-run", value: None, about: "With --apply, list the changes without making them" }
+        ]
+    },
+    Command {
+        name: "identify",
+        about: "Guess what the image is and what formatted it, from its size and boot sector, without opening it",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to identify" }],
+        opts: &[]
+    },
+    Command {
+        name: "catalog",
+        about: "Describe every image in a host directory (label, serial, type and files) in a single JSON document, to search the collection",
+        args: &[Arg { name: "host-dir", kind: ValueKind::HostPath, about: "Host directory of images" }],
+        opts: &[
+            Opt { long: "recursive", value: None, about: "Also catalog the images in its subdirectories" },
+            Opt { long: "output", value: Some(("file", ValueKind::HostPath)), about: "Write the catalog to the file instead of stdout" }
+        ]
+    },
+    Command {
+        name: "recover",
+        about: "Rebuild a zeroed or damaged boot sector from the size, the FAT and the directories of the image",
+        args: &[Arg { name: "image", kind: ValueKind::Image, about: "Image to recover" }],
+        opts: &[Opt { long: "write", value: None, about: "Write the rebuilt boot sector to the image" }]
+    },
+    Command {
+        name: "config",
+        about: "Print the config in use and where it was read from",
+        args: &[],
+        opts: &[]
+    },
+    Command {
+        name: "completions",
+        about: "Print the completion script for a shell",
+        args: &[Arg { name: "shell", kind: ValueKind::Choice(SHELLS), about: "Shell to complete for" }],
+        opts: &[]
+    },
+    Command {
+        name: "manpage",
+        about: "Print the man page, in roff format",
+        args: &[],
+        opts: &[]
+    }
+];
+
+impl Matches {
+    /** Positional argument, in definition order. */
+    pub fn get_arg(&self, index: usize) -> &str {
+        &self.positionals[index]
+    }
+
+    /** Value of an option, if it was given. */
+    pub fn get_opt(&self, long: &str) -> Option<&str> {
+        self.opts.get(long).and_then(|value| value.as_deref())
+    }
+
+    pub fn has_flag(&self, long: &str) -> bool {
+        self.opts.contains_key(long)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+pub fn find_command(name: &str) -> Option<&'static Command> {
+    COMMANDS.iter().find(|command| command.name == name)
+}
+
+/** Parse the arguments following the command name. Options can be mixed with
+ *  positionals, as "--opt value" or "--opt=value"; "--" ends the options. */
+pub fn parse(command: &Command, args: impl IntoIterator<Item = String>) -> Result<Matches, String> {
+    let mut matches: Matches = Matches { positionals: vec![], opts: BTreeMap::new() };
+    let mut args = args.into_iter();
+    let mut only_positionals: bool = false;
+
+    while let Some(arg) = args.next() {
+        let Some(long) = arg.strip_prefix("--").filter(|_| !only_positionals) else {
+            matches.positionals.push(arg);
+            continue;
+        };
+        if long.is_empty() {
+            only_positionals = true;
+            continue;
+        }
+
+        let (long, inline): (&str, Option<&str>) = match long.split_once('=') {
+            Some((long, value)) => (long, Some(value)),
+            None => (long, None)
+        };
+        let opt: &Opt = command.opts.iter().find(|opt| opt.long == long)
+            .ok_or_else(|| format!("unknown {} option --{}", command.name, long))?;
+
+        let value: Option<String> = match (opt.value, inline) {
+            (None, None) => None,
+            (None, Some(_)) => return Err(format!("--{} doesn't take a value", long)),
+            (Some(_), Some(value)) => Some(value.to_string()),
+            (Some((name, _)), None) => Some(args.next().ok_or_else(|| format!("--{} needs a <{}>", long, name))?)
+        };
+        if let (Some((name, ValueKind::Choice(choices))), Some(value)) = (opt.value, &value) {
+            if !choices.contains(&value.as_str()) {
+                return Err(format!("<{}> must be one of {}, not {}", name, choices.join(", "), value));
+            }
+        }
+        matches.opts.insert(opt.long, value);
+    }
+
+    if matches.positionals.len() < command.args.len() {
+        return Err(format!("missing <{}>", command.args[matches.positionals.len()].name));
+    }
+    if matches.positionals.len() > command.args.len() {
+        return Err(format!("unexpected argument {}", matches.positionals[command.args.len()]));
+    }
+    for (arg, value) in command.args.iter().zip(&matches.positionals) {
+        if let ValueKind::Choice(choices) = arg.kind {
+            if !choices.contains(&value.as_str()) {
+                return Err(format!("<{}> must be one of {}, not {}", arg.name, choices.join(", "), value));
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/** One line synopsis, e.g. "sync <image> <host-dir> [--dry-run] ...". */
+pub fn usage(command: &Command) -> String {
+    let mut usage: String = command.name.to_string();
+    for arg in command.args { usage.push_str(&format!(" <{}>", arg.name)); }
+    for opt in command.opts { usage.push_str(&format!(" [{}]", opt_synopsis(opt))); }
+    usage
+}
+
+/** Help for every command, as printed on usage errors. */
+pub fn help() -> String {
+    let globals: Vec<String> = GLOBAL_OPTS.iter().map(|opt| format!("[{}]", opt_synopsis(opt))).collect();
+    let mut help: String = format!("usage: {} {} <command> ...\n\ncommands:\n", PROGRAM_NAME, globals.join(" "));
+    for command in COMMANDS { help.push_str(&format!("  {}\n      {}\n", usage(command), command.about)); }
+    help.push_str(&format!("\nmtools style commands, also through links with their names: {}\n", crate::mtools::COMMANDS.join(", ")));
+    help
+}
+
+/** Completion script for the given shell, from the command definitions. */
+pub fn completions(shell: &str) -> Result<String, String> {
+    match shell {
+        "bash" => Ok(bash_completions()),
+        "zsh" => Ok(zsh_completions()),
+        "fish" => Ok(fish_completions()),
+        _ => Err(format!("no completions for {}, only for {}", shell, SHELLS.join(", ")))
+    }
+}
+
+/** Man page in roff format, from the command definitions. */
+pub fn manpage() -> String {
+    let mut page: String = String::new();
+    page.push_str(&format!(".TH {} 1\n", PROGRAM_NAME.to_uppercase()));
+    page.push_str(&format!(".SH NAME\n{} \\- read and manage FAT12 disk images\n", PROGRAM_NAME));
+    page.push_str(&format!(".SH SYNOPSIS\n.B {}\n[\\fIoptions\\fR] \\fIcommand\\fR ...\n", PROGRAM_NAME));
+    page.push_str(".SH OPTIONS\n");
+    for opt in GLOBAL_OPTS { page.push_str(&roff_opt(opt)); }
+
+    page.push_str(".SH COMMANDS\n");
+    for command in COMMANDS {
+        page.push_str(&format!(".SS {}\n", roff_escape(&usage(command))));
+        page.push_str(&format!("{}\n", roff_escape(command.about)));
+        for arg in command.args { page.push_str(&format!(".TP\n\\fI{}\\fR\n{}\n", roff_escape(arg.name), roff_escape(arg.about))); }
+        for opt in command.opts { page.push_str(&roff_opt(opt)); }
+    }
+
+    page.push_str(".SH \"EXIT STATUS\"\n");
+    for (code, meaning) in EXIT_CODES { page.push_str(&format!(".TP\n{}\n{}\n", code, roff_escape(meaning))); }
+
+    page.push_str(".SH FILES\n.TP\n~/.config/rsfat/config.toml\nUser config.\n.TP\n./rsfat.toml\nProject config, read after the user one.\n");
+    page.push_str(".SH ENVIRONMENT\n.TP\nRSFAT_PROFILE\nProfile to apply when \\fB\\-\\-profile\\fR isn't given.\n");
+    page.push_str(".TP\nRSFAT_PASSPHRASE\nPassphrase of encrypted images when \\fB\\-\\-passphrase\\-file\\fR isn't given.\n");
+    page
+}
+
+/* ==== UTILS =============================================================== */
+fn opt_synopsis(opt: &Opt) -> String {
+    match opt.value {
+        None => format!("--{}", opt.long),
+        Some((_, ValueKind::Choice(choices))) if choices.len() <= 4 => format!("--{} {}", opt.long, choices.join("|")),
+        Some((name, _)) => format!("--{} <{}>", opt.long, name)
+    }
+}
+
+fn roff_opt(opt: &Opt) -> String {
+    let value: String = opt.value.map(|(name, _)| format!(" \\fI{}\\fR", roff_escape(name))).unwrap_or_default();
+    format!(".TP\n\\fB\\-\\-{}\\fR{}\n{}\n", roff_escape(opt.long), value, roff_escape(opt.about))
+}
+
+fn roff_escape(text: &str) -> String {
+    let text: String = text.replace('\\', "\\\\").replace('-', "\\-");
+    // A leading dot or quote would be read as a request
+    if text.starts_with(['.', '\'']) { format!("\\&{}", text) } else { text }
+}
+
+/** Words a value can be completed with, or None for free text. */
+fn choices(kind: ValueKind) -> Option<&'static [&'static str]> {
+    match kind {
+        ValueKind::Choice(choices) => Some(choices),
+        _ => None
+    }
+}
+
+fn bash_completions() -> String {
+    let function: String = format!("_{}", PROGRAM_NAME.replace('-', "_"));
+    let commands: Vec<&str> = COMMANDS.iter().map(|command| command.name).collect();
+
+    let mut script: String = format!("# bash completion for {}\n{}() {{\n", PROGRAM_NAME, function);
+    script.push_str("    local cur=\"${COMP_WORDS[COMP_CWORD]}\" prev=\"${COMP_WORDS[COMP_CWORD-1]}\"\n");
+    script.push_str("    local i=1 command=\"\"\n");
+
+    // Skip the global options to find the command
+    script.push_str("    while [[ $i -lt $COMP_CWORD ]]; do\n        case \"${COMP_WORDS[i]}\" in\n");
+    for opt in GLOBAL_OPTS {
+        script.push_str(&format!("            --{}) i=$((i+{}));;\n", opt.long, if opt.value.is_some() { 2 } else { 1 }));
+    }
+    script.push_str("            *) break;;\n        esac\n    done\n");
+    script.push_str("    [[ $COMP_CWORD -gt $i ]] && command=\"${COMP_WORDS[i]}\"\n");
+    script.push_str("    if [[ -z \"$command\" ]]; then\n");
+    let globals: Vec<String> = GLOBAL_OPTS.iter().map(|opt| format!("--{}", opt.long)).collect();
+    script.push_str(&format!("        COMPREPLY=($(compgen -W \"{} {}\" -- \"$cur\"))\n", commands.join(" "), globals.join(" ")));
+    script.push_str("        return\n    fi\n    case \"$command\" in\n");
+    for command in COMMANDS {
+        script.push_str(&format!("        {})\n            case \"$prev\" in\n", command.name));
+        for opt in command.opts {
+            let Some((_, kind)) = opt.value else { continue };
+            let words: String = choices(kind).map(|choices| choices.join(" ")).unwrap_or_default();
+            script.push_str(&format!("                --{}) COMPREPLY=($(compgen -W \"{}\" -- \"$cur\")); return;;\n", opt.long, words));
+        }
+        script.push_str("            esac\n");
+        let opts: Vec<String> = command.opts.iter().map(|opt| format!("--{}", opt.long)).collect();
+        let words: Vec<&str> = command.args.iter().filter_map(|arg| choices(arg.kind)).flatten().copied().collect();
+        let files: bool = command.args.iter().any(|arg| matches!(arg.kind, ValueKind::Image | ValueKind::HostPath | ValueKind::Location));
+        script.push_str(&format!("            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"){})\n", opts.iter().map(String::as_str).chain(words).collect::<Vec<&str>>().join(" "),
+            if files { " $(compgen -f -- \"$cur\")" } else { "" }));
+        script.push_str("            ;;\n");
+    }
+    script.push_str(&format!("    esac\n}}\ncomplete -o filenames -F {} {}\n", function, PROGRAM_NAME));
+    script
+}
+
+fn zsh_completions() -> String {
+    let mut script: String = format!("#compdef {}\n\n_{}() {{\n", PROGRAM_NAME, PROGRAM_NAME.replace('-', "_"));
+    script.push_str("    local -a commands\n    commands=(\n");
+    for command in COMMANDS { script.push_str(&format!("        '{}:{}'\n", command.name, zsh_escape(command.about))); }
+    script.push_str("    )\n\n    _arguments -C \\\n");
+    for opt in GLOBAL_OPTS { script.push_str(&format!("        '--{}[{}]:{}' \\\n", opt.long, zsh_escape(opt.about), zsh_value(opt))); }
+    script.push_str("        '1:command:->command' \\\n        '*::arg:->args'\n\n    case $state in\n");
+    script.push_str("        command) _describe 'command' commands ;;\n        args)\n            case $words[1] in\n");
+    for command in COMMANDS {
+        script.push_str(&format!("                {})\n                    _arguments", command.name));
+        for opt in command.opts {
+            let value: String = if opt.value.is_some() { format!(":{}", zsh_value(opt)) } else { String::new() };
+            script.push_str(&format!(" \\\n                        '--{}[{}]{}'", opt.long, zsh_escape(opt.about), value));
+        }
+        for (i, arg) in command.args.iter().enumerate() {
+            script.push_str(&format!(" \\\n                        '{}:{}:{}'", i + 1, arg.name, zsh_action(arg.kind)));
+        }
+        script.push_str("\n                    ;;\n");
+    }
+    script.push_str("            esac\n            ;;\n    esac\n}\n\n");
+    script.push_str(&format!("_{} \"$@\"\n", PROGRAM_NAME.replace('-', "_")));
+    script
+}
+
+fn fish_completions() -> String {
+    let mut script: String = format!("# fish completion for {}\ncomplete -c {} -f\n", PROGRAM_NAME, PROGRAM_NAME);
+    for opt in GLOBAL_OPTS {
+        script.push_str(&format!("complete -c {} -n '__fish_use_subcommand' -l {} -r -d '{}'\n", PROGRAM_NAME, opt.long, fish_escape(opt.about)));
+    }
+    for command in COMMANDS {
+        script.push_str(&format!("complete -c {} -n '__fish_use_subcommand' -a {} -d '{}'\n", PROGRAM_NAME, command.name, fish_escape(command.about)));
+        let condition: String = format!("__fish_seen_subcommand_from {}", command.name);
+        for opt in command.opts {
+            let value: String = match opt.value {
+                None => String::new(),
+                Some((_, kind)) => match choices(kind) {
+                    Some(choices) => format!(" -x -a '{}'", choices.join(" ")),
+                    None => String::from(" -r")
+                }
+            };
+            script.push_str(&format!("complete -c {} -n '{}' -l {}{} -d '{}'\n", PROGRAM_NAME, condition, opt.long, value, fish_escape(opt.about)));
+        }
+        for arg in command.args {
+            let action: String = match arg.kind {
+                ValueKind::Choice(choices) => format!(" -a '{}'", choices.join(" ")),
+                ValueKind::Image | ValueKind::HostPath | ValueKind::Location => String::from(" -F"),
+                ValueKind::Text => continue
+            };
+            script.push_str(&format!("complete -c {} -n '{}'{}\n", PROGRAM_NAME, condition, action));
+        }
+    }
+    script
+}
+
+fn zsh_value(opt: &Opt) -> String {
+    match opt.value {
+        Some((name, kind)) => format!("{}:{}", name, zsh_action(kind)),
+        None => String::new()
+    }
+}
+
+fn zsh_action(kind: ValueKind) -> String {
+    match kind {
+        ValueKind::Image | ValueKind::HostPath | ValueKind::Location => String::from("_files"),
+        ValueKind::Choice(choices) => format!("({})", choices.join(" ")),
+        ValueKind::Text => String::from(" ")
+    }
+}
+
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''").replace('[', "\\[").replace(']', "\\]").replace(':', "\\:")
+}
+
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
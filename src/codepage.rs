@@ -0,0 +1,152 @@
+/** Upper half (0x80..=0xFF) of code page 437, the original IBM PC one. */
+const CP437_HIGH: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{A0}'
+];
+
+/** ASCII spelling of the characters of CP437_HIGH: letters lose their
+ *  accents, ligatures and Greek letters are spelled out, and symbols and box
+ *  drawing characters have none (""), becoming REPLACEMENT. */
+const CP437_ASCII: [&str; 128] = [
+    "C", "u", "e", "a", "a", "a", "a", "c", "e", "e", "e", "i", "i", "i", "A", "A",
+    "E", "ae", "AE", "o", "o", "o", "u", "u", "y", "O", "U", "c", "L", "Y", "Pts", "f",
+    "a", "i", "o", "u", "n", "N", "a", "o", "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+    "", "", "", "", "", "", "", "", "", "", "", "", "", "", "", "",
+    "a", "ss", "G", "p", "S", "s", "u", "t", "F", "Th", "O", "d", "", "f", "e", "",
+    "", "", "", "", "", "", "", "", "", "", "", "", "n", "2", "", ""
+];
+
+/** What unrepresentable characters become under Unrepresentable::Replace. */
+pub const REPLACEMENT: char = '_';
+
+/* ==== STRUCTS ============================================================= */
+/** OEM code page the bytes of short names are in. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Codepage {
+    /** Only 7-bit ASCII: other bytes show up as U+FFFD. */
+    Ascii,
+    #[default]
+    Cp437
+}
+
+impl Codepage {
+    /** Get the code page from its number as written in configs ("437"). */
+    pub fn from_name(name: &str) -> Option<Codepage> {
+        match name {
+            "ascii" => Some(Codepage::Ascii),
+            "437" | "cp437" => Some(Codepage::Cp437),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Codepage::Ascii => "ascii",
+            Codepage::Cp437 => "437"
+        }
+    }
+
+    pub fn decode_byte(&self, byte: u8) -> char {
+        match (self, byte) {
+            (_, 0x00..=0x7F) => byte as char,
+            (Codepage::Ascii, _) => char::REPLACEMENT_CHARACTER,
+            (Codepage::Cp437, _) => CP437_HIGH[(byte - 0x80) as usize]
+        }
+    }
+
+    pub fn decode(&self, bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| self.decode_byte(*byte)).collect()
+    }
+
+    /** Same as decode, in ASCII only: characters past it are spelled with
+     *  ASCII letters where they have an obvious spelling (É as E, ß as ss),
+     *  and are REPLACEMENT otherwise, as are the bytes Codepage::Ascii
+     *  doesn't have. */
+    pub fn transliterate(&self, bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| match (self, byte) {
+            (_, 0x00..=0x7F) => (*byte as char).to_string(),
+            (Codepage::Cp437, _) if !CP437_ASCII[(byte - 0x80) as usize].is_empty() => CP437_ASCII[(byte - 0x80) as usize].to_string(),
+            _ => REPLACEMENT.to_string()
+        }).collect()
+    }
+
+    /** Byte standing for the character, if the code page has it. */
+    pub fn encode_char(&self, c: char) -> Option<u8> {
+        match self {
+            _ if c.is_ascii() => Some(c as u8),
+            Codepage::Ascii => None,
+            Codepage::Cp437 => CP437_HIGH.iter().position(|other| *other == c).map(|i| 0x80 + i as u8)
+        }
+    }
+}
+
+/** What to do with characters a name can't hold: past U+FFFF in a long
+ *  name (UCS-2 has no surrogate pairs), or outside of what a short name
+ *  takes under NamePolicy::Sanitize. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Unrepresentable {
+    /** Refuse the name, telling which character. */
+    Error,
+
+    /** Put REPLACEMENT in its place. */
+    #[default]
+    Replace
+}
+
+impl Unrepresentable {
+    pub fn from_name(name: &str) -> Option<Unrepresentable> {
+        match name {
+            "error" => Some(Unrepresentable::Error),
+            "replace" => Some(Unrepresentable::Replace),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Unrepresentable::Error => "error",
+            Unrepresentable::Replace => "replace"
+        }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** UCS-2 code units of a long name, at most 255 of them. Characters past
+ *  U+FFFF are refused or replaced as the policy says: DOS and Windows 9x
+ *  would show a surrogate pair as two characters, and count both. */
+pub fn encode_ucs2(name: &str, policy: Unrepresentable) -> Result<Vec<u16>, String> {
+    validate_ucs2(&name.encode_utf16().collect::<Vec<u16>>(), policy)
+}
+
+/** Long name in UTF-16, e.g. a Windows host name, checked for UCS-2 as
+ *  encode_ucs2 does. UTF-16 can also hold unpaired surrogates, which no
+ *  string can: they are refused or replaced the same way. */
+pub fn validate_ucs2(units: &[u16], policy: Unrepresentable) -> Result<Vec<u16>, String> {
+    let mut checked: Vec<u16> = Vec::with_capacity(units.len());
+    for c in char::decode_utf16(units.iter().copied()) {
+        match (c.map(|c| (c, u16::try_from(c as u32))), policy) {
+            (Ok((_, Ok(unit))), _) => checked.push(unit),
+            (_, Unrepresentable::Replace) => checked.push(REPLACEMENT as u16),
+            (Ok((c, Err(_))), Unrepresentable::Error) => return Err(format!("{:?} (U+{:X}) doesn't fit UCS-2", c, c as u32)),
+            (Err(e), Unrepresentable::Error) => return Err(format!("unpaired surrogate U+{:X}", e.unpaired_surrogate()))
+        }
+    }
+    if checked.len() > 255 { return Err(format!("{} characters, long names take up to 255", checked.len())); }
+    Ok(checked)
+}
+
+/** Long name from its UCS-2 code units, stopping at the 0x0000 terminator.
+ *  Pairs of surrogates, as written by Windows 2000 and later, give the
+ *  character they stand for; unpaired ones become U+FFFD. */
+pub fn decode_ucs2(units: &[u16]) -> String {
+    let length: usize = units.iter().position(|unit| *unit == 0).unwrap_or(units.len());
+    char::decode_utf16(units[..length].iter().copied()).map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER)).collect()
+}
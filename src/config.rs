@@ -0,0 +1,434 @@
+use std::{collections::BTreeMap, env, fs, io, path::{Path, PathBuf}, process::Command, sync::Arc};
+use rs_disk_reader::{codepage::{Codepage, Unrepresentable}, digest::HashAlgorithm, posix::MountOptions, shortname::{self, TailStrategy}, sync::ConflictPolicy, unicode::NameMatching, FatMirroring, GeometrySource, Limits, NamePolicy, SlotPlacement, Validation};
+
+/** Project config, looked up in the working directory. */
+pub const PROJECT_CONFIG_NAME: &str = "rsfat.toml";
+
+/* ==== STRUCTS ============================================================= */
+/** Value of a config key: the subset of TOML we understand. */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool)
+}
+
+/** Named geometry for new images, as given in a [geometry.NAME] section. */
+#[derive(Debug, Clone, Default)]
+pub struct GeometryProfile {
+    pub bytes_per_sector: Option<u16>,
+    pub sectors_per_cluster: Option<u8>,
+    pub reserved_sectors: Option<u16>,
+    pub fat_count: Option<u8>,
+    pub root_entries: Option<u16>,
+    pub sector_count: Option<u16>,
+    pub media_descriptor: Option<u8>,
+    pub sectors_per_fat: Option<u16>,
+    pub sectors_per_cylinder: Option<u16>,
+    pub heads_count: Option<u16>
+}
+
+/** Defaults for the command line, merged from the user config
+ *  (~/.config/rsfat/config.toml), the project config (./rsfat.toml) and the
+ *  selected profile, in this order. */
+#[derive(Debug, Clone)]
+pub struct Config {
+    /** Code page of the names in the images ("437" or "ascii"). */
+    pub codepage: Codepage,
+
+    /** Offset from UTC of the FAT timestamps, in minutes. */
+    pub timezone_offset: i32,
+    pub conflict_policy: ConflictPolicy,
+
+    /** Digest sync, diff and hash compare the content of files with. */
+    pub hash_algorithm: HashAlgorithm,
+
+    /** How names that aren't valid 8.3 are handled when creating entries. */
+    pub name_policy: NamePolicy,
+
+    /** How much of the images is checked when opening and reading them. */
+    pub validation: Validation,
+
+    /** Refuse images that don't match their seal, see the seal command. */
+    pub verify_seal: bool,
+
+    /** Rebuild damaged boot sectors from the rest of the image. */
+    pub recover_boot_sector: bool,
+
+    /** Zero clusters as they are freed, see the trim command. */
+    pub discard: bool,
+
+    /** Sectors each opened image keeps in memory, 0 for none. */
+    pub cache_sectors: usize,
+
+    /** Open truncated images, reading the sectors they lack as zeros. */
+    pub zero_missing: bool,
+
+    /** Whether the BPB ("bpb") or the media descriptor ("media") gives the
+     *  geometry when they disagree. */
+    pub geometry_source: GeometrySource,
+
+    /** How names changed by the sanitize policy are made unique ("numeric"
+     *  or "hash"), None to only cut them to 8.3. */
+    pub short_name_tails: Option<Arc<dyn TailStrategy>>,
+
+    /** Whether characters names can't hold are refused or replaced. */
+    pub unrepresentable: Unrepresentable,
+
+    /** Which slot of their directory new entries take. */
+    pub slot_placement: SlotPlacement,
+
+    /** How names in paths are matched against long names. */
+    pub name_matching: NameMatching,
+
+    /** FAT copy read and written, from active_fat ("all" or a number). */
+    pub fat_mirroring: FatMirroring,
+
+    /** Caps on what an image can make the tool read, from [limits]. */
+    pub limits: Limits,
+
+    /** Owner and permissions entries show, from [mount]. */
+    pub mount: MountOptions,
+
+    /** Bytes per second the images can be read and written at, by command
+     *  name or "default", from [throttle]. */
+    pub throttle: BTreeMap<String, u64>,
+
+    /** Image paths by alias, usable wherever an image path is expected. */
+    pub images: BTreeMap<String, PathBuf>,
+    pub geometries: BTreeMap<String, GeometryProfile>,
+
+    /** Files the config was read from. */
+    pub sources: Vec<PathBuf>
+}
+
+impl GeometryProfile {
+    /** The keys given in the profile, in BootSector order, with their values. */
+    pub fn get_keys(&self) -> Vec<(&'static str, u16)> {
+        [
+            ("bytes_per_sector", self.bytes_per_sector),
+            ("sectors_per_cluster", self.sectors_per_cluster.map(u16::from)),
+            ("reserved_sectors", self.reserved_sectors),
+            ("fat_count", self.fat_count.map(u16::from)),
+            ("root_entries", self.root_entries),
+            ("sector_count", self.sector_count),
+            ("media_descriptor", self.media_descriptor.map(u16::from)),
+            ("sectors_per_fat", self.sectors_per_fat),
+            ("sectors_per_cylinder", self.sectors_per_cylinder),
+            ("heads_count", self.heads_count)
+        ].into_iter().filter_map(|(key, value)| value.map(|value| (key, value))).collect()
+    }
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            codepage: Codepage::default(),
+            timezone_offset: 0,
+            conflict_policy: ConflictPolicy::Skip,
+            hash_algorithm: HashAlgorithm::default(),
+            name_policy: NamePolicy::default(),
+            validation: Validation::default(),
+            verify_seal: false,
+            recover_boot_sector: false,
+            discard: false,
+            cache_sectors: 0,
+            zero_missing: false,
+            geometry_source: GeometrySource::default(),
+            short_name_tails: None,
+            unrepresentable: Unrepresentable::default(),
+            slot_placement: SlotPlacement::default(),
+            name_matching: NameMatching::default(),
+            fat_mirroring: FatMirroring::default(),
+            limits: Limits::default(),
+            mount: MountOptions::default(),
+            throttle: BTreeMap::new(),
+            images: BTreeMap::new(),
+            geometries: BTreeMap::new(),
+            sources: vec![]
+        }
+    }
+}
+
+/** Sections of a parsed file: "" for the keys before any header. */
+type Sections = BTreeMap<String, BTreeMap<String, Value>>;
+
+impl Config {
+    /** Load the user and project configs, then apply the given profile. */
+    pub fn load(profile: Option<&str>) -> io::Result<Config> {
+        let mut config: Config = Config::default();
+        let mut profiles: Sections = BTreeMap::new();
+
+        let project: PathBuf = env::current_dir()?.join(PROJECT_CONFIG_NAME);
+        for path in user_config_path().into_iter().chain([project]) {
+            let content: String = match fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e)
+            };
+            let sections: Sections = parse(&content).map_err(|e| invalid(&path, &e))?;
+            config.apply(&path, &sections, &mut profiles).map_err(|e| invalid(&path, &e))?;
+            config.sources.push(path);
+        }
+
+        if let Some(name) = profile {
+            let section: &BTreeMap<String, Value> = profiles.get(name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no profile named {}", name)))?;
+            config.apply_keys(section).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("profile {}: {}", name, e)))?;
+        }
+        Ok(config)
+    }
+
+    /** Get the image path behind an alias, or the argument as it is. */
+    pub fn resolve_image(&self, image: &str) -> String {
+        match self.images.get(image) {
+            Some(path) => path.to_string_lossy().into_owned(),
+            None => image.to_string()
+        }
+    }
+
+    /** Throughput limit of the command, in bytes per second: its own, else
+     *  the default one. None if unlimited. */
+    pub fn get_throttle(&self, command: &str) -> Option<u64> {
+        self.throttle.get(command).or(self.throttle.get("default")).copied().filter(|rate| *rate > 0)
+    }
+
+    /** Convert a host (UTC) time to the local time stored in the image. */
+    pub fn to_image_time(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_add_signed(self.timezone_offset as i64 * 60)
+    }
+
+    /** Merge the sections of one file, keeping the profiles for later. */
+    fn apply(&mut self, path: &Path, sections: &Sections, profiles: &mut Sections) -> Result<(), String> {
+        for (section, keys) in sections {
+            match section.split_once('.') {
+                None if section.is_empty() => self.apply_keys(keys)?,
+                None if section == "images" => {
+                    // Relative image paths are relative to the config file
+                    let base: &Path = path.parent().unwrap_or(Path::new("."));
+                    for (alias, value) in keys {
+                        let Value::String(image) = value else { return Err(format!("image {} must be a string", alias)) };
+                        self.images.insert(alias.clone(), base.join(image));
+                    }
+                },
+                None if section == "limits" => parse_limits(keys, &mut self.limits)?,
+                None if section == "mount" => parse_mount(keys, &mut self.mount)?,
+                None if section == "throttle" => parse_throttle(keys, &mut self.throttle)?,
+                Some(("geometry", name)) => { self.geometries.insert(name.to_string(), parse_geometry(keys)?); },
+                Some(("profile", name)) => profiles.entry(name.to_string()).or_default().extend(keys.clone()),
+                _ => return Err(format!("unknown section [{}]", section))
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_keys(&mut self, keys: &BTreeMap<String, Value>) -> Result<(), String> {
+        for (key, value) in keys {
+            match (key.as_str(), value) {
+                ("codepage", Value::String(codepage)) => self.codepage = Codepage::from_name(codepage)
+                    .ok_or_else(|| format!("unsupported codepage {}", codepage))?,
+                ("timezone_offset", Value::String(offset)) => self.timezone_offset = parse_offset(offset)?,
+                ("conflict_policy", Value::String(policy)) => self.conflict_policy = ConflictPolicy::from_name(policy)
+                    .ok_or_else(|| format!("conflict_policy must be skip, host, image or newer, not {}", policy))?,
+                ("hash_algorithm", Value::String(name)) => self.hash_algorithm = HashAlgorithm::from_name(name)
+                    .ok_or_else(|| format!("hash_algorithm must be crc32, md5, sha1 or sha256, not {}", name))?,
+                ("name_policy", Value::String(policy)) => self.name_policy = NamePolicy::from_name(policy)
+                    .ok_or_else(|| format!("name_policy must be strict, uppercase, sanitize or long-names, not {}", policy))?,
+                ("validation", Value::String(level)) => self.validation = Validation::from_name(level)
+                    .ok_or_else(|| format!("validation must be strict, normal or lenient, not {}", level))?,
+                ("verify_seal", Value::Boolean(verify_seal)) => self.verify_seal = *verify_seal,
+                ("recover_boot_sector", Value::Boolean(recover)) => self.recover_boot_sector = *recover,
+                ("discard", Value::Boolean(discard)) => self.discard = *discard,
+                ("cache_sectors", Value::Integer(sectors)) => self.cache_sectors = usize::try_from(*sectors)
+                    .map_err(|_| format!("invalid cache_sectors {}", sectors))?,
+                ("zero_missing", Value::Boolean(zero_missing)) => self.zero_missing = *zero_missing,
+                ("geometry_source", Value::String(source)) => self.geometry_source = GeometrySource::from_name(source)
+                    .ok_or_else(|| format!("geometry_source must be bpb or media, not {}", source))?,
+                ("short_name_tails", Value::String(name)) if name == "none" => self.short_name_tails = None,
+                ("short_name_tails", Value::String(name)) => self.short_name_tails = Some(shortname::from_name(name)
+                    .ok_or_else(|| format!("short_name_tails must be none, numeric or hash, not {}", name))?),
+                ("unrepresentable", Value::String(policy)) => self.unrepresentable = Unrepresentable::from_name(policy)
+                    .ok_or_else(|| format!("unrepresentable must be error or replace, not {}", policy))?,
+                ("slot_placement", Value::String(placement)) => self.slot_placement = SlotPlacement::from_name(placement)
+                    .ok_or_else(|| format!("slot_placement must be first-free or end, not {}", placement))?,
+                ("name_matching", Value::String(matching)) => self.name_matching = NameMatching::from_name(matching)
+                    .ok_or_else(|| format!("name_matching must be exact, ignore-case or normalized, not {}", matching))?,
+                ("active_fat", Value::String(all)) if all == "all" => self.fat_mirroring = FatMirroring::All,
+                ("active_fat", Value::Integer(index)) => self.fat_mirroring = FatMirroring::Active(u8::try_from(*index)
+                    .map_err(|_| format!("active_fat must be \"all\" or a FAT number, not {}", index))?),
+                _ => return Err(format!("unknown or mistyped key {}", key))
+            }
+        }
+        Ok(())
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** $XDG_CONFIG_HOME/rsfat/config.toml, or ~/.config/rsfat/config.toml. */
+pub fn user_config_path() -> Option<PathBuf> {
+    let base: PathBuf = match env::var_os("XDG_CONFIG_HOME") {
+        Some(base) if !base.is_empty() => PathBuf::from(base),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config")
+    };
+    Some(base.join("rsfat").join("config.toml"))
+}
+
+/** Parse the TOML subset used by the config: [section] headers and
+ *  key = value lines, with strings, integers and booleans as values. */
+pub fn parse(content: &str) -> Result<Sections, String> {
+    let mut sections: Sections = BTreeMap::new();
+    let mut section: String = String::new();
+
+    for (i, line) in content.lines().enumerate() {
+        let line: &str = strip_comment(line).trim();
+        if line.is_empty() { continue; }
+
+        if let Some(header) = line.strip_prefix('[').and_then(|line| line.strip_suffix(']')) {
+            section = header.trim().to_string();
+            sections.entry(section.clone()).or_default();
+            continue;
+        }
+
+        let (key, value): (&str, &str) = line.split_once('=').ok_or_else(|| format!("line {}: expected key = value", i + 1))?;
+        let value: Value = parse_value(value.trim()).ok_or_else(|| format!("line {}: invalid value {}", i + 1, value.trim()))?;
+        sections.entry(section.clone()).or_default().insert(key.trim().trim_matches('"').to_string(), value);
+    }
+    Ok(sections)
+}
+
+/** Bytes per second, with an optional K or M suffix: "64K", "1M", "9000". */
+pub fn parse_rate(rate: &str) -> Option<u64> {
+    let (number, unit): (&str, u64) = match rate.strip_suffix(['K', 'k']) {
+        Some(number) => (number, 1024),
+        None => match rate.strip_suffix(['M', 'm']) {
+            Some(number) => (number, 1048576),
+            None => (rate, 1)
+        }
+    };
+    number.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+/* ==== UTILS =============================================================== */
+fn parse_value(value: &str) -> Option<Value> {
+    if let Some(string) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) {
+        return Some(Value::String(string.replace("\\\"", "\"").replace("\\\\", "\\")));
+    }
+    match value {
+        "true" => Some(Value::Boolean(true)),
+        "false" => Some(Value::Boolean(false)),
+        _ => value.replace('_', "").parse::<i64>().ok().map(Value::Integer)
+    }
+}
+
+/** Drop a trailing "# comment", unless the # is inside a string. */
+fn strip_comment(line: &str) -> &str {
+    let mut in_string: bool = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+/** "+01:00" / "-05:30" / "Z" to minutes, or "local" for the current offset
+ *  of the host. */
+fn parse_offset(offset: &str) -> Result<i32, String> {
+    if offset == "Z" { return Ok(0); }
+    if offset == "local" { return host_offset().ok_or_else(|| String::from("timezone_offset = \"local\" needs the date command")); }
+    let error = || format!("timezone_offset must look like +01:00, not {}", offset);
+    let (sign, rest): (i32, &str) = match offset.split_at_checked(1).ok_or_else(error)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return Err(error())
+    };
+    let (hours, minutes): (&str, &str) = rest.split_once(':').ok_or_else(error)?;
+    let hours: i32 = hours.parse().map_err(|_| error())?;
+    let minutes: i32 = minutes.parse().map_err(|_| error())?;
+    if hours > 14 || minutes > 59 { return Err(error()); }
+    Ok(sign * (hours * 60 + minutes))
+}
+
+/** Offset of the host time zone right now, as "date +%z" prints it. */
+fn host_offset() -> Option<i32> {
+    let output = Command::new("date").arg("+%z").output().ok()?;
+    let offset: String = String::from_utf8(output.stdout).ok()?;
+    let (sign, digits): (&str, &str) = offset.trim().split_at_checked(1)?;
+    let (hours, minutes): (i32, i32) = (digits.get(..2)?.parse().ok()?, digits.get(2..4)?.parse().ok()?);
+    match sign {
+        "+" => Some(hours * 60 + minutes),
+        "-" => Some(-(hours * 60 + minutes)),
+        _ => None
+    }
+}
+
+fn parse_limits(keys: &BTreeMap<String, Value>, limits: &mut Limits) -> Result<(), String> {
+    for (key, value) in keys {
+        let Value::Integer(number) = value else { return Err(format!("limit {} must be an integer", key)) };
+        let out_of_range = || format!("limit {} out of range: {}", key, number);
+        match key.as_str() {
+            "max_file_size" => limits.max_file_size = u64::try_from(*number).map_err(|_| out_of_range())?,
+            "max_depth" => limits.max_depth = usize::try_from(*number).map_err(|_| out_of_range())?,
+            "max_entries" => limits.max_entries = usize::try_from(*number).map_err(|_| out_of_range())?,
+            "max_chain_length" => limits.max_chain_length = usize::try_from(*number).map_err(|_| out_of_range())?,
+            _ => return Err(format!("unknown limit {}", key))
+        }
+    }
+    Ok(())
+}
+
+fn parse_throttle(keys: &BTreeMap<String, Value>, throttle: &mut BTreeMap<String, u64>) -> Result<(), String> {
+    for (command, value) in keys {
+        let rate: Option<u64> = match value {
+            Value::Integer(rate) => u64::try_from(*rate).ok(),
+            Value::String(rate) => parse_rate(rate),
+            Value::Boolean(_) => None
+        };
+        throttle.insert(command.clone(), rate.ok_or_else(|| format!("throttle {} must be bytes per second, e.g. 65536 or \"64K\"", command))?);
+    }
+    Ok(())
+}
+
+fn parse_mount(keys: &BTreeMap<String, Value>, mount: &mut MountOptions) -> Result<(), String> {
+    for (key, value) in keys {
+        let mask = |text: &str| u32::from_str_radix(text, 8).ok().filter(|mask| *mask <= 0o777).ok_or_else(|| format!("{} must be octal permission bits, not {}", key, text));
+        match (key.as_str(), value) {
+            ("uid", Value::Integer(uid)) => mount.uid = u32::try_from(*uid).map_err(|_| format!("invalid uid {}", uid))?,
+            ("gid", Value::Integer(gid)) => mount.gid = u32::try_from(*gid).map_err(|_| format!("invalid gid {}", gid))?,
+            ("umask", Value::String(text)) => *mount = mount.umask(mask(text)?),
+            ("fmask", Value::String(text)) => mount.fmask = mask(text)?,
+            ("dmask", Value::String(text)) => mount.dmask = mask(text)?,
+            ("showexec", Value::Boolean(showexec)) => mount.showexec = *showexec,
+            _ => return Err(format!("unknown or mistyped mount key {}", key))
+        }
+    }
+    Ok(())
+}
+
+fn parse_geometry(keys: &BTreeMap<String, Value>) -> Result<GeometryProfile, String> {
+    let mut geometry: GeometryProfile = GeometryProfile::default();
+    for (key, value) in keys {
+        let Value::Integer(number) = value else { return Err(format!("geometry key {} must be an integer", key)) };
+        let out_of_range = || format!("geometry key {} out of range: {}", key, number);
+        match key.as_str() {
+            "bytes_per_sector" => geometry.bytes_per_sector = Some(u16::try_from(*number).map_err(|_| out_of_range())?),
+            "sectors_per_cluster" => geometry.sectors_per_cluster = Some(u8::try_from(*number).map_err(|_| out_of_range())?),
+            "reserved_sectors" => geometry.reserved_sectors = Some(u16::try_from(*number).map_err(|_| out_of_range())?),
+            "fat_count" => geometry.fat_count = Some(u8::try_from(*number).map_err(|_| out_of_range())?),
+            "root_entries" => geometry.root_entries = Some(u16::try_from(*number).map_err(|_| out_of_range())?),
+            "sector_count" => geometry.sector_count = Some(u16::try_from(*number).map_err(|_| out_of_range())?),
+            "media_descriptor" => geometry.media_descriptor = Some(u8::try_from(*number).map_err(|_| out_of_range())?),
+            "sectors_per_fat" => geometry.sectors_per_fat = Some(u16::try_from(*number).map_err(|_| out_of_range())?),
+            "sectors_per_cylinder" => geometry.sectors_per_cylinder = Some(u16::try_from(*number).map_err(|_| out_of_range())?),
+            "heads_count" => geometry.heads_count = Some(u16::try_from(*number).map_err(|_| out_of_range())?),
+            _ => return Err(format!("unknown geometry key {}", key))
+        }
+    }
+    Ok(geometry)
+}
+
+fn invalid(path: &Path, message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message))
+}
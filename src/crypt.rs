@@ -0,0 +1,494 @@
+use std::{env, fmt, fs::{self, File, OpenOptions}, io::{self, Read, Seek, SeekFrom}, path::{Path, PathBuf}, process, sync::{Arc, Mutex}};
+
+use crate::{digest::{ContentDigest, Sha256}, lock_image, read_exact_at, volume::random_volume_id, write_all_at, Fat12Fs, FsOptions};
+
+/** Magic at the start of a container made by Passphrase. */
+const CONTAINER_MAGIC: &[u8; 8] = b"RSFCRYPT";
+
+/** Version of the container format, after the magic. */
+const CONTAINER_VERSION: u8 = 1;
+
+/** Magic, version, PBKDF2 iterations, salt and nonce. */
+const HEADER_SIZE: usize = 8 + 1 + 4 + SALT_SIZE + NONCE_SIZE;
+const SALT_SIZE: usize = 16;
+
+/** Bytes of a ChaCha20-Poly1305 nonce, and of its tag. */
+pub const NONCE_SIZE: usize = 12;
+pub const TAG_SIZE: usize = 16;
+
+/** PBKDF2 iterations of new containers. */
+pub const DEFAULT_ITERATIONS: u32 = 100_000;
+
+/** Most iterations a container can ask for, so that a crafted one can't
+ *  keep the key derivation busy for hours. */
+const MAX_ITERATIONS: u32 = 10_000_000;
+
+/** Bytes of the start of a file that ContainerCipher::recognizes gets. */
+pub const RECOGNIZE_SIZE: usize = 64;
+
+/* ==== STRUCTS ============================================================= */
+/** Layer between the file and the FAT: the image is stored encrypted in a
+ *  container, decrypted when opened and encrypted again when changes are
+ *  flushed, see FsOptions::cipher. Passphrase is the one built in; age or
+ *  AES-GCM containers plug in with a cipher of their own. */
+pub trait ContainerCipher: fmt::Debug + Send + Sync {
+    /** Whether a file starting with these bytes (at most RECOGNIZE_SIZE of
+     *  them) is a container of this cipher. */
+    fn recognizes(&self, start: &[u8]) -> bool;
+
+    /** Image in the container. Fails if the key is wrong or the container
+     *  was changed. */
+    fn decrypt(&self, container: &[u8]) -> io::Result<Vec<u8>>;
+
+    /** Container of the image. */
+    fn encrypt(&self, image: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+/** ChaCha20-Poly1305 with a key derived from a passphrase by
+ *  PBKDF2-HMAC-SHA256. The container is the header (magic, version,
+ *  iterations, salt and nonce, authenticated too), the encrypted image and
+ *  the tag. */
+pub struct Passphrase {
+    passphrase: Vec<u8>,
+    iterations: u32,
+
+    // Salt and key of the last container, so that it's encrypted again
+    // without deriving the key a second time
+    key: Mutex<Option<DerivedKey>>
+}
+
+/** Key derived from the passphrase, with what it was derived with. */
+#[derive(Clone, Copy)]
+struct DerivedKey {
+    salt: [u8; SALT_SIZE],
+    iterations: u32,
+    key: [u8; 32]
+}
+
+/** Container an image was decrypted from, to encrypt it back into. */
+#[derive(Debug, Clone)]
+pub(crate) struct Container {
+    // Replaced by the copy it's sealed into, for every clone of the handle
+    file: Arc<Mutex<File>>,
+    path: PathBuf,
+    locked: bool,
+    cipher: Arc<dyn ContainerCipher>,
+    _scratch: Arc<ScratchFile>,
+
+    // Whether the decrypted image changed since the last time it was
+    // encrypted back
+    changed: bool
+}
+
+/** Name of the scratch file holding the decrypted image, removed with it.
+ *  None once removed, which Unix allows while the file is still open. */
+#[derive(Debug)]
+struct ScratchFile(Option<PathBuf>);
+
+impl Passphrase {
+    pub fn new(passphrase: &str) -> Passphrase {
+        Passphrase { passphrase: passphrase.as_bytes().to_vec(), iterations: DEFAULT_ITERATIONS, key: Mutex::new(None) }
+    }
+
+    /** PBKDF2 iterations of the containers it makes: more make guessing
+     *  the passphrase slower, and opening too. */
+    pub fn iterations(mut self, iterations: u32) -> Passphrase {
+        self.iterations = iterations.clamp(1, MAX_ITERATIONS);
+        self
+    }
+
+    /** Key of the salt and iterations, derived again only if they changed. */
+    fn get_key(&self, salt: &[u8; SALT_SIZE], iterations: u32) -> [u8; 32] {
+        let mut cached = self.key.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(derived) = cached.filter(|derived| derived.salt == *salt && derived.iterations == iterations) {
+            return derived.key;
+        }
+        let key: [u8; 32] = pbkdf2_sha256(&self.passphrase, salt, iterations);
+        *cached = Some(DerivedKey { salt: *salt, iterations, key });
+        key
+    }
+}
+
+/** The passphrase is left out. */
+impl fmt::Debug for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Passphrase").field("iterations", &self.iterations).finish_non_exhaustive()
+    }
+}
+
+impl ContainerCipher for Passphrase {
+    fn recognizes(&self, start: &[u8]) -> bool {
+        is_container(start)
+    }
+
+    fn decrypt(&self, container: &[u8]) -> io::Result<Vec<u8>> {
+        if !is_container(container) { return Err(io::Error::new(io::ErrorKind::InvalidData, "not an encrypted container")); }
+        if container.len() < HEADER_SIZE + TAG_SIZE { return Err(io::Error::new(io::ErrorKind::InvalidData, "the container is truncated")); }
+        if container[8] != CONTAINER_VERSION {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, format!("container version {}, only {} is known", container[8], CONTAINER_VERSION)));
+        }
+        let iterations: u32 = u32::from_le_bytes(container[9..13].try_into().expect("4 bytes"));
+        if iterations == 0 || iterations > MAX_ITERATIONS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} PBKDF2 iterations, at most {} are accepted", iterations, MAX_ITERATIONS)));
+        }
+        let salt: [u8; SALT_SIZE] = container[13..13 + SALT_SIZE].try_into().expect("salt size");
+        let nonce: [u8; NONCE_SIZE] = container[13 + SALT_SIZE..HEADER_SIZE].try_into().expect("nonce size");
+        let (header, sealed): (&[u8], &[u8]) = container.split_at(HEADER_SIZE);
+        open(&self.get_key(&salt, iterations), &nonce, header, sealed)
+            .map_err(|_| io::Error::new(io::ErrorKind::PermissionDenied, "wrong passphrase, or the container was changed"))
+    }
+
+    fn encrypt(&self, image: &[u8]) -> io::Result<Vec<u8>> {
+        // The salt (and so the key) of the container decrypted last is kept,
+        // the nonce is new every time
+        let cached: Option<([u8; SALT_SIZE], u32)> = self.key.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+            .map(|derived| (derived.salt, derived.iterations));
+        let (salt, iterations): ([u8; SALT_SIZE], u32) = match cached {
+            Some(cached) => cached,
+            None => (random_bytes(SALT_SIZE)?.try_into().expect("salt size"), self.iterations)
+        };
+        let nonce: [u8; NONCE_SIZE] = random_bytes(NONCE_SIZE)?.try_into().expect("nonce size");
+        let key: [u8; 32] = self.get_key(&salt, iterations);
+
+        let mut container: Vec<u8> = Vec::with_capacity(HEADER_SIZE + image.len() + TAG_SIZE);
+        container.extend_from_slice(CONTAINER_MAGIC);
+        container.push(CONTAINER_VERSION);
+        container.extend_from_slice(&iterations.to_le_bytes());
+        container.extend_from_slice(&salt);
+        container.extend_from_slice(&nonce);
+        let sealed: Vec<u8> = seal(&key, &nonce, &container, image);
+        container.extend_from_slice(&sealed);
+        Ok(container)
+    }
+}
+
+impl Drop for ScratchFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.0 { let _ = fs::remove_file(path); }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+impl FsOptions {
+    /** Open the image in the container: it's decrypted to a scratch file
+     *  the FAT layer works on (plaintext on the disk, see
+     *  FsOptions::cipher), and the container is locked instead of it when
+     *  writable. */
+    pub(crate) fn open_container(&self, container: File, path: &Path) -> io::Result<Fat12Fs> {
+        let Some(cipher) = &self.cipher else {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the image is encrypted, a passphrase is needed"));
+        };
+        let locked: bool = !self.read_only && !self.ignore_lock;
+        if locked { lock_image(&container)?; }
+        let mut data: Vec<u8> = vec![];
+        (&container).seek(SeekFrom::Start(0))?;
+        (&container).read_to_end(&mut data)?;
+        let image: Vec<u8> = cipher.decrypt(&data)?;
+
+        let (scratch, scratch_file): (File, ScratchFile) = create_scratch()?;
+        write_all_at(&scratch, &image, 0)?;
+        let mut fs: Fat12Fs = self.clone().ignore_lock(true).open_file(scratch)?;
+        fs.container = Some(Container {
+            file: Arc::new(Mutex::new(container)),
+            path: path.to_path_buf(),
+            locked,
+            cipher: cipher.clone(),
+            _scratch: Arc::new(scratch_file),
+            changed: false
+        });
+        Ok(fs)
+    }
+
+    /** Whether the file is a container to open with open_container: one the
+     *  cipher recognizes, or one of Passphrase when there is no cipher, to
+     *  ask for it rather than fail on a garbled boot sector. */
+    pub(crate) fn is_container_file(&self, disk: &File) -> io::Result<bool> {
+        let mut start: Vec<u8> = vec![];
+        disk.take(RECOGNIZE_SIZE as u64).read_to_end(&mut start)?;
+        (&*disk).seek(SeekFrom::Start(0))?;
+        Ok(match &self.cipher {
+            Some(cipher) => cipher.recognizes(&start),
+            None => is_container(&start)
+        })
+    }
+}
+
+impl Fat12Fs {
+    /** Whether the image was decrypted from a container, see
+     *  FsOptions::cipher. */
+    pub fn is_encrypted(&self) -> bool {
+        self.container.is_some()
+    }
+
+    /** The decrypted image changed, and must be encrypted back. */
+    pub(crate) fn touch_container(&mut self) {
+        if let Some(container) = &mut self.container { container.changed = true; }
+    }
+
+    /** Encrypt the decrypted image back into its container, if it changed.
+     *  The container is replaced rather than written over, so that a crash
+     *  or a full disk leaves the old one whole. */
+    pub(crate) fn seal_container(&mut self) -> io::Result<()> {
+        let Some(container) = self.container.as_mut().filter(|container| container.changed) else { return Ok(()) };
+        let mut image: Vec<u8> = vec![0; self.disk.metadata()?.len() as usize];
+        read_exact_at(&self.disk, &mut image, 0)?;
+        let sealed: Vec<u8> = container.cipher.encrypt(&image)?;
+        let copy: File = replace_file(&container.path, &sealed, container.locked)?;
+        *container.file.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = copy;
+        container.changed = false;
+        Ok(())
+    }
+}
+
+/** Whether the bytes start a container made by Passphrase. */
+pub fn is_container(start: &[u8]) -> bool {
+    start.starts_with(CONTAINER_MAGIC)
+}
+
+/** Encrypt the image file into a container at output, which can be the
+ *  image itself: it's written next to it, then renamed over it. */
+pub fn encrypt_file(path: &Path, output: &Path, cipher: &dyn ContainerCipher) -> io::Result<()> {
+    let image: Vec<u8> = fs::read(path)?;
+    if cipher.recognizes(&image[..image.len().min(RECOGNIZE_SIZE)]) || is_container(&image) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is already encrypted", path.display())));
+    }
+    replace_file(output, &cipher.encrypt(&image)?, false).map(drop)
+}
+
+/** Decrypt the container into the image file at output, which can be the
+ *  container itself. */
+pub fn decrypt_file(path: &Path, output: &Path, cipher: &dyn ContainerCipher) -> io::Result<()> {
+    let container: Vec<u8> = fs::read(path)?;
+    if !cipher.recognizes(&container[..container.len().min(RECOGNIZE_SIZE)]) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not an encrypted container", path.display())));
+    }
+    replace_file(output, &cipher.decrypt(&container)?, false).map(drop)
+}
+
+/** ChaCha20-Poly1305 (RFC 8439 2.8): the plaintext encrypted, followed by
+ *  the tag over it and the additional data. The nonce must never be used
+ *  twice with the same key. */
+pub fn seal(key: &[u8; 32], nonce: &[u8; NONCE_SIZE], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut sealed: Vec<u8> = plaintext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut sealed);
+    let tag: [u8; TAG_SIZE] = aead_tag(key, nonce, aad, &sealed);
+    sealed.extend_from_slice(&tag);
+    sealed
+}
+
+/** Plaintext of what seal gave. Fails with InvalidData if the tag doesn't
+ *  match: wrong key, nonce or additional data, or changed bytes. */
+pub fn open(key: &[u8; 32], nonce: &[u8; NONCE_SIZE], aad: &[u8], sealed: &[u8]) -> io::Result<Vec<u8>> {
+    let Some(split) = sealed.len().checked_sub(TAG_SIZE) else { return Err(io::Error::new(io::ErrorKind::InvalidData, "shorter than a tag")) };
+    let (ciphertext, tag): (&[u8], &[u8]) = sealed.split_at(split);
+    let expected: [u8; TAG_SIZE] = aead_tag(key, nonce, aad, ciphertext);
+
+    // Compared in constant time, not to tell how much of the tag matched
+    if expected.iter().zip(tag).fold(0, |difference, (a, b)| difference | (a ^ b)) != 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "the tag doesn't match"));
+    }
+    let mut plaintext: Vec<u8> = ciphertext.to_vec();
+    chacha20_xor(key, 1, nonce, &mut plaintext);
+    Ok(plaintext)
+}
+
+/* ==== UTILS =============================================================== */
+/** Write the file through a copy next to it, with the permissions of the
+ *  file it replaces, synced and renamed over it once whole. The copy is
+ *  locked before it takes the name if asked, and is returned open. */
+fn replace_file(path: &Path, data: &[u8], lock: bool) -> io::Result<File> {
+    let copy_path: PathBuf = path.with_extension("crypt.tmp");
+    match write_copy(&copy_path, path, data, lock).and_then(|copy| fs::rename(&copy_path, path).map(|_| copy)) {
+        Ok(copy) => Ok(copy),
+        Err(e) => {
+            let _ = fs::remove_file(&copy_path);
+            Err(e)
+        }
+    }
+}
+
+fn write_copy(copy_path: &Path, path: &Path, data: &[u8], lock: bool) -> io::Result<File> {
+    let copy: File = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(copy_path)?;
+    if lock { lock_image(&copy)?; }
+    if let Ok(metadata) = fs::metadata(path) { copy.set_permissions(metadata.permissions())?; }
+    write_all_at(&copy, data, 0)?;
+    copy.sync_all()?;
+    Ok(copy)
+}
+
+/** New file in the temporary directory, readable by the owner only. On
+ *  Unix its name is removed at once, on Windows the file is deleted when
+ *  closed, so that nothing is left behind by a crash. Its content can still
+ *  reach the disk, see FsOptions::cipher. */
+fn create_scratch() -> io::Result<(File, ScratchFile)> {
+    let path: PathBuf = env::temp_dir().join(format!("rsfat-{}-{:08x}.img", process::id(), random_volume_id()));
+    let mut options: OpenOptions = OpenOptions::new();
+    options.read(true).write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    // FILE_FLAG_DELETE_ON_CLOSE, and FILE_ATTRIBUTE_TEMPORARY to keep it in
+    // the cache rather than write it out
+    #[cfg(windows)]
+    std::os::windows::fs::OpenOptionsExt::custom_flags(&mut options, 0x04000000);
+    #[cfg(windows)]
+    std::os::windows::fs::OpenOptionsExt::attributes(&mut options, 0x100);
+    let file: File = options.open(&path)?;
+    let removed: bool = cfg!(unix) && fs::remove_file(&path).is_ok();
+    Ok((file, ScratchFile(if removed { None } else { Some(path) })))
+}
+
+/** Bytes from the system random source. Salts and nonces come from it, so
+ *  without one nothing is encrypted rather than falling back to a weaker
+ *  source. */
+fn random_bytes(size: usize) -> io::Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = vec![0; size];
+    File::open("/dev/urandom").and_then(|mut random| random.read_exact(&mut bytes))
+        .map_err(|e| io::Error::new(e.kind(), format!("no system random source for the salt and nonce: {}", e)))?;
+    Ok(bytes)
+}
+
+/** Poly1305 tag of the AEAD construction (RFC 8439): the one-time key from
+ *  block 0, over the padded additional data and ciphertext and their
+ *  lengths. */
+fn aead_tag(key: &[u8; 32], nonce: &[u8; NONCE_SIZE], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_SIZE] {
+    let one_time_key: [u8; 32] = chacha20_block(key, 0, nonce)[..32].try_into().expect("32 bytes");
+    let mut data: Vec<u8> = Vec::with_capacity(aad.len() + ciphertext.len() + 48);
+    for part in [aad, ciphertext] {
+        data.extend_from_slice(part);
+        data.resize(data.len().next_multiple_of(16), 0);
+    }
+    data.extend_from_slice(&(aad.len() as u64).to_le_bytes());
+    data.extend_from_slice(&(ciphertext.len() as u64).to_le_bytes());
+    poly1305(&one_time_key, &data)
+}
+
+/** XOR the data with the ChaCha20 key stream, starting at that block. */
+fn chacha20_xor(key: &[u8; 32], counter: u32, nonce: &[u8; NONCE_SIZE], data: &mut [u8]) {
+    for (i, chunk) in data.chunks_mut(64).enumerate() {
+        let block: [u8; 64] = chacha20_block(key, counter.wrapping_add(i as u32), nonce);
+        for (byte, key_byte) in chunk.iter_mut().zip(block) { *byte ^= key_byte; }
+    }
+}
+
+/** ChaCha20 block function (RFC 8439 2.3). */
+fn chacha20_block(key: &[u8; 32], counter: u32, nonce: &[u8; NONCE_SIZE]) -> [u8; 64] {
+    let mut state: [u32; 16] = [0x61707865, 0x3320646E, 0x79622D32, 0x6B206574, 0, 0, 0, 0, 0, 0, 0, 0, counter, 0, 0, 0];
+    for (i, word) in key.chunks_exact(4).enumerate() { state[4 + i] = u32::from_le_bytes(word.try_into().expect("4 bytes")); }
+    for (i, word) in nonce.chunks_exact(4).enumerate() { state[13 + i] = u32::from_le_bytes(word.try_into().expect("4 bytes")); }
+
+    let mut working: [u32; 16] = state;
+    for _ in 0..10 {
+        for (a, b, c, d) in [(0, 4, 8, 12), (1, 5, 9, 13), (2, 6, 10, 14), (3, 7, 11, 15), (0, 5, 10, 15), (1, 6, 11, 12), (2, 7, 8, 13), (3, 4, 9, 14)] {
+            working[a] = working[a].wrapping_add(working[b]); working[d] = (working[d] ^ working[a]).rotate_left(16);
+            working[c] = working[c].wrapping_add(working[d]); working[b] = (working[b] ^ working[c]).rotate_left(12);
+            working[a] = working[a].wrapping_add(working[b]); working[d] = (working[d] ^ working[a]).rotate_left(8);
+            working[c] = working[c].wrapping_add(working[d]); working[b] = (working[b] ^ working[c]).rotate_left(7);
+        }
+    }
+    let mut block: [u8; 64] = [0; 64];
+    for (i, word) in working.iter().enumerate() {
+        block[i * 4..i * 4 + 4].copy_from_slice(&word.wrapping_add(state[i]).to_le_bytes());
+    }
+    block
+}
+
+/** Poly1305 (RFC 8439 2.5), with the accumulator in five 26 bit limbs.
+ *  The key is for a single message. */
+pub fn poly1305(key: &[u8; 32], message: &[u8]) -> [u8; TAG_SIZE] {
+    const MASK: u64 = 0x3FFFFFF;
+    let word = |bytes: &[u8], at: usize| -> u64 { u32::from_le_bytes(bytes[at..at + 4].try_into().expect("4 bytes")) as u64 };
+
+    // r, clamped
+    let r: [u64; 5] = [word(key, 0) & 0x3FFFFFF, (word(key, 3) >> 2) & 0x3FFFF03, (word(key, 6) >> 4) & 0x3FFC0FF,
+        (word(key, 9) >> 6) & 0x3F03FFF, (word(key, 12) >> 8) & 0x00FFFFF];
+    let s: [u64; 5] = [0, r[1] * 5, r[2] * 5, r[3] * 5, r[4] * 5];
+
+    let mut h: [u64; 5] = [0; 5];
+    for chunk in message.chunks(16) {
+        // The block with a 1 byte after it, past the 16 for whole blocks
+        let mut block: [u8; 17] = [0; 17];
+        block[..chunk.len()].copy_from_slice(chunk);
+        block[chunk.len()] = 1;
+        h[0] += word(&block, 0) & MASK;
+        h[1] += (word(&block, 3) >> 2) & MASK;
+        h[2] += (word(&block, 6) >> 4) & MASK;
+        h[3] += (word(&block, 9) >> 6) & MASK;
+        h[4] += (word(&block, 12) >> 8) | (block[16] as u64) << 24;
+
+        let d: [u64; 5] = [
+            h[0] * r[0] + h[1] * s[4] + h[2] * s[3] + h[3] * s[2] + h[4] * s[1],
+            h[0] * r[1] + h[1] * r[0] + h[2] * s[4] + h[3] * s[3] + h[4] * s[2],
+            h[0] * r[2] + h[1] * r[1] + h[2] * r[0] + h[3] * s[4] + h[4] * s[3],
+            h[0] * r[3] + h[1] * r[2] + h[2] * r[1] + h[3] * r[0] + h[4] * s[4],
+            h[0] * r[4] + h[1] * r[3] + h[2] * r[2] + h[3] * r[1] + h[4] * r[0]
+        ];
+        let mut carry: u64 = 0;
+        for i in 0..5 {
+            let limb: u64 = d[i] + carry;
+            h[i] = limb & MASK;
+            carry = limb >> 26;
+        }
+        h[0] += carry * 5;
+        h[1] += h[0] >> 26;
+        h[0] &= MASK;
+    }
+
+    // Fully carried, then h - p taken instead of h if it doesn't go negative
+    let mut carry: u64 = h[1] >> 26;
+    h[1] &= MASK;
+    for limb in &mut h[2..] {
+        *limb += carry;
+        carry = *limb >> 26;
+        *limb &= MASK;
+    }
+    h[0] += carry * 5;
+    h[1] += h[0] >> 26;
+    h[0] &= MASK;
+
+    let mut g: [u64; 5] = [0; 5];
+    let mut carry: u64 = 5;
+    for i in 0..5 {
+        let limb: u64 = h[i] + carry;
+        g[i] = limb & MASK;
+        carry = limb >> 26;
+    }
+    // carry is 1 when h + 5 reaches 2^130, that is h >= p
+    let select: u64 = 0u64.wrapping_sub(carry);
+    for i in 0..5 { h[i] = (h[i] & !select) | (g[i] & select); }
+
+    // h mod 2^128, plus s
+    let h128: u128 = h.iter().enumerate().fold(0u128, |sum, (i, limb)| sum.wrapping_add((*limb as u128) << (26 * i)));
+    let s128: u128 = u128::from_le_bytes(key[16..].try_into().expect("16 bytes"));
+    h128.wrapping_add(s128).to_le_bytes()
+}
+
+/** PBKDF2 with HMAC-SHA256 (RFC 8018), a single 32 byte block. */
+pub fn pbkdf2_sha256(passphrase: &[u8], salt: &[u8], iterations: u32) -> [u8; 32] {
+    // Keys longer than a block are hashed first, as HMAC does
+    let mut key: [u8; 64] = [0; 64];
+    let hashed: Vec<u8>;
+    let passphrase: &[u8] = if passphrase.len() > 64 { hashed = Sha256::digest(passphrase); &hashed } else { passphrase };
+    key[..passphrase.len()].copy_from_slice(passphrase);
+
+    // Both pads hashed once, the state cloned for every HMAC
+    let mut inner: Sha256 = Sha256::new();
+    inner.update(&key.map(|byte| byte ^ 0x36));
+    let mut outer: Sha256 = Sha256::new();
+    outer.update(&key.map(|byte| byte ^ 0x5C));
+    let hmac = |message: &[u8]| -> Vec<u8> {
+        let mut inner: Sha256 = inner.clone();
+        inner.update(message);
+        let mut outer: Sha256 = outer.clone();
+        outer.update(&inner.finalize());
+        outer.finalize()
+    };
+
+    let mut block: Vec<u8> = hmac(&[salt, &1u32.to_be_bytes()].concat());
+    let mut result: [u8; 32] = block.clone().try_into().expect("SHA-256 size");
+    for _ in 1..iterations {
+        block = hmac(&block);
+        for (byte, next) in result.iter_mut().zip(&block) { *byte ^= next; }
+    }
+    result
+}
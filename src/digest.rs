@@ -0,0 +1,341 @@
+use std::io;
+
+use crate::Fat12Fs;
+
+/** Round constants of SHA-256: the first 32 bits of the fractional parts of
+ *  the cube roots of the first 64 primes. */
+const SHA256_K: [u32; 64] = [
+    0x428A2F98, 0x71374491, 0xB5C0FBCF, 0xE9B5DBA5, 0x3956C25B, 0x59F111F1, 0x923F82A4, 0xAB1C5ED5,
+    0xD807AA98, 0x12835B01, 0x243185BE, 0x550C7DC3, 0x72BE5D74, 0x80DEB1FE, 0x9BDC06A7, 0xC19BF174,
+    0xE49B69C1, 0xEFBE4786, 0x0FC19DC6, 0x240CA1CC, 0x2DE92C6F, 0x4A7484AA, 0x5CB0A9DC, 0x76F988DA,
+    0x983E5152, 0xA831C66D, 0xB00327C8, 0xBF597FC7, 0xC6E00BF3, 0xD5A79147, 0x06CA6351, 0x14292967,
+    0x27B70A85, 0x2E1B2138, 0x4D2C6DFC, 0x53380D13, 0x650A7354, 0x766A0ABB, 0x81C2C92E, 0x92722C85,
+    0xA2BFE8A1, 0xA81A664B, 0xC24B8B70, 0xC76C51A3, 0xD192E819, 0xD6990624, 0xF40E3585, 0x106AA070,
+    0x19A4C116, 0x1E376C08, 0x2748774C, 0x34B0BCB5, 0x391C0CB3, 0x4ED8AA4A, 0x5B9CCA4F, 0x682E6FF3,
+    0x748F82EE, 0x78A5636F, 0x84C87814, 0x8CC70208, 0x90BEFFFA, 0xA4506CEB, 0xBEF9A3F7, 0xC67178F2
+];
+
+/** Initial state of SHA-256: the fractional parts of the square roots of
+ *  the first 8 primes. */
+const SHA256_H: [u32; 8] = [0x6A09E667, 0xBB67AE85, 0x3C6EF372, 0xA54FF53A, 0x510E527F, 0x9B05688C, 0x1F83D9AB, 0x5BE0CD19];
+
+/** Round constants of MD5: the integer parts of |sin(i + 1)| * 2^32. */
+const MD5_K: [u32; 64] = [
+    0xD76AA478, 0xE8C7B756, 0x242070DB, 0xC1BDCEEE, 0xF57C0FAF, 0x4787C62A, 0xA8304613, 0xFD469501,
+    0x698098D8, 0x8B44F7AF, 0xFFFF5BB1, 0x895CD7BE, 0x6B901122, 0xFD987193, 0xA679438E, 0x49B40821,
+    0xF61E2562, 0xC040B340, 0x265E5A51, 0xE9B6C7AA, 0xD62F105D, 0x02441453, 0xD8A1E681, 0xE7D3FBC8,
+    0x21E1CDE6, 0xC33707D6, 0xF4D50D87, 0x455A14ED, 0xA9E3E905, 0xFCEFA3F8, 0x676F02D9, 0x8D2A4C8A,
+    0xFFFA3942, 0x8771F681, 0x6D9D6122, 0xFDE5380C, 0xA4BEEA44, 0x4BDECFA9, 0xF6BB4B60, 0xBEBFBC70,
+    0x289B7EC6, 0xEAA127FA, 0xD4EF3085, 0x04881D05, 0xD9D4D039, 0xE6DB99E5, 0x1FA27CF8, 0xC4AC5665,
+    0xF4292244, 0x432AFF97, 0xAB9423A7, 0xFC93A039, 0x655B59C3, 0x8F0CCC92, 0xFFEFF47D, 0x85845DD1,
+    0x6FA87E4F, 0xFE2CE6E0, 0xA3014314, 0x4E0811A1, 0xF7537E82, 0xBD3AF235, 0x2AD7D2BB, 0xEB86D391
+];
+
+/** Left rotations of MD5, by round and step within the round. */
+const MD5_SHIFTS: [[u32; 4]; 4] = [[7, 12, 17, 22], [5, 9, 14, 20], [4, 11, 16, 23], [6, 10, 15, 21]];
+
+/** Initial state of MD5 and of the first four words of SHA-1. */
+const MD_H: [u32; 4] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476];
+
+/* ==== STRUCTS ============================================================= */
+/** Hash function the content of files is compared and verified with: sync,
+ *  diff and the hash command are generic over it, so that other algorithms
+ *  plug in without touching them.
+ *
+ *  This is not the digest crate's Digest, and it's named apart so that the
+ *  two aren't mistaken for each other: the crate has no dependencies, and
+ *  this trait also carries the NAME that state files and configs record.
+ *  A RustCrypto hasher plugs in through a small wrapper type implementing
+ *  it with the hasher's own new, update and finalize. */
+pub trait ContentDigest {
+    /** Name in state files, configs and manifests, e.g. "sha256". */
+    const NAME: &'static str;
+
+    /** Bytes of the digest. */
+    const OUTPUT_SIZE: usize;
+
+    fn new() -> Self;
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self) -> Vec<u8>;
+
+    /** Digest of data given at once. */
+    fn digest(data: &[u8]) -> Vec<u8> where Self: Sized {
+        let mut digest: Self = Self::new();
+        digest.update(data);
+        digest.finalize()
+    }
+}
+
+/** CRC-32 (IEEE), the default: cheap, and enough to tell changed files
+ *  apart. Its digest is the CRC big endian, as printed in hex. */
+#[derive(Debug, Clone)]
+pub struct Crc32(u32);
+
+/** MD5, for the md5sum manifests of old archives. Broken for anything
+ *  adversarial. */
+#[derive(Debug, Clone)]
+pub struct Md5 {
+    state: [u32; 4],
+    blocks: Blocks
+}
+
+#[derive(Debug, Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    blocks: Blocks
+}
+
+#[derive(Debug, Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    blocks: Blocks
+}
+
+/** One of the ContentDigest implementations, chosen at run time, e.g. from the
+ *  config. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HashAlgorithm {
+    #[default]
+    Crc32,
+    Md5,
+    Sha1,
+    Sha256
+}
+
+/** 64 byte blocks of the MD5 and SHA families, with the padding they share:
+ *  0x80, zeros, then the length in bits (little endian for MD5). */
+#[derive(Debug, Clone)]
+struct Blocks {
+    buffer: [u8; 64],
+    filled: usize,
+    length: u64
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        match name {
+            Crc32::NAME => Some(HashAlgorithm::Crc32),
+            Md5::NAME => Some(HashAlgorithm::Md5),
+            Sha1::NAME => Some(HashAlgorithm::Sha1),
+            Sha256::NAME => Some(HashAlgorithm::Sha256),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Crc32 => Crc32::NAME,
+            HashAlgorithm::Md5 => Md5::NAME,
+            HashAlgorithm::Sha1 => Sha1::NAME,
+            HashAlgorithm::Sha256 => Sha256::NAME
+        }
+    }
+
+    pub fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgorithm::Crc32 => Crc32::digest(data),
+            HashAlgorithm::Md5 => Md5::digest(data),
+            HashAlgorithm::Sha1 => Sha1::digest(data),
+            HashAlgorithm::Sha256 => Sha256::digest(data)
+        }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** CRC-32 (IEEE, the zip/ethernet one) of the given data. */
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc: Crc32 = Crc32::new();
+    crc.update(data);
+    crc.get_value()
+}
+
+impl ContentDigest for Crc32 {
+    const NAME: &'static str = "crc32";
+    const OUTPUT_SIZE: usize = 4;
+
+    fn new() -> Crc32 {
+        Crc32(0xFFFFFFFF)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.0 ^= *byte as u32;
+            for _ in 0..8 {
+                // Shift out the lowest bit, xoring the polynomial if it was set
+                let mask: u32 = (self.0 & 1).wrapping_neg();
+                self.0 = (self.0 >> 1) ^ (0xEDB88320 & mask);
+            }
+        }
+    }
+
+    fn finalize(self) -> Vec<u8> {
+        self.get_value().to_be_bytes().to_vec()
+    }
+}
+
+impl Crc32 {
+    /** CRC of the data so far, as a number. */
+    pub fn get_value(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl ContentDigest for Md5 {
+    const NAME: &'static str = "md5";
+    const OUTPUT_SIZE: usize = 16;
+
+    fn new() -> Md5 {
+        Md5 { state: MD_H, blocks: Blocks::new() }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.blocks.update(data, |block| md5_compress(&mut self.state, block));
+    }
+
+    fn finalize(mut self) -> Vec<u8> {
+        self.blocks.finish(false, |block| md5_compress(&mut self.state, block));
+        self.state.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+}
+
+impl ContentDigest for Sha1 {
+    const NAME: &'static str = "sha1";
+    const OUTPUT_SIZE: usize = 20;
+
+    fn new() -> Sha1 {
+        Sha1 { state: [MD_H[0], MD_H[1], MD_H[2], MD_H[3], 0xC3D2E1F0], blocks: Blocks::new() }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.blocks.update(data, |block| sha1_compress(&mut self.state, block));
+    }
+
+    fn finalize(mut self) -> Vec<u8> {
+        self.blocks.finish(true, |block| sha1_compress(&mut self.state, block));
+        self.state.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+}
+
+impl ContentDigest for Sha256 {
+    const NAME: &'static str = "sha256";
+    const OUTPUT_SIZE: usize = 32;
+
+    fn new() -> Sha256 {
+        Sha256 { state: SHA256_H, blocks: Blocks::new() }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.blocks.update(data, |block| sha256_compress(&mut self.state, block));
+    }
+
+    fn finalize(mut self) -> Vec<u8> {
+        self.blocks.finish(true, |block| sha256_compress(&mut self.state, block));
+        self.state.iter().flat_map(|word| word.to_be_bytes()).collect()
+    }
+}
+
+impl Blocks {
+    fn new() -> Blocks {
+        Blocks { buffer: [0; 64], filled: 0, length: 0 }
+    }
+
+    /** Add the data, compressing every block it completes. */
+    fn update(&mut self, mut data: &[u8], mut compress: impl FnMut(&[u8; 64])) {
+        self.length = self.length.wrapping_add(data.len() as u64);
+        while !data.is_empty() {
+            let taken: usize = (64 - self.filled).min(data.len());
+            self.buffer[self.filled..self.filled + taken].copy_from_slice(&data[..taken]);
+            (self.filled, data) = (self.filled + taken, &data[taken..]);
+            if self.filled == 64 {
+                compress(&self.buffer);
+                self.filled = 0;
+            }
+        }
+    }
+
+    fn finish(&mut self, big_endian: bool, mut compress: impl FnMut(&[u8; 64])) {
+        let bits: u64 = self.length.wrapping_mul(8);
+        let mut padding: Vec<u8> = vec![0x80];
+        padding.resize((119 - self.filled) % 64 + 1, 0);
+        padding.extend(if big_endian { bits.to_be_bytes() } else { bits.to_le_bytes() });
+        self.update(&padding, &mut compress);
+    }
+}
+
+impl Fat12Fs {
+    /** Digest of the content of the file, e.g. Fat12Fs::hash_file::<Sha256>
+     *  to check it against a sha256sum manifest. */
+    pub fn hash_file<D: ContentDigest>(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        Ok(D::digest(&self.read_file(path)?))
+    }
+}
+
+/** Lower case hex, as the *sum tools print digests. */
+pub fn to_hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/** Inverse of to_hex, None unless it's a digest of that many bytes. */
+pub fn from_hex(text: &str, size: usize) -> Option<Vec<u8>> {
+    if text.len() != size * 2 || !text.is_ascii() { return None; }
+    (0..size).map(|i| u8::from_str_radix(&text[i * 2..i * 2 + 2], 16).ok()).collect()
+}
+
+/* ==== UTILS =============================================================== */
+fn md5_compress(state: &mut [u32; 4], block: &[u8; 64]) {
+    let words: Vec<u32> = block.chunks_exact(4).map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])).collect();
+    let [mut a, mut b, mut c, mut d]: [u32; 4] = *state;
+    for i in 0..64 {
+        let (f, g): (u32, usize) = match i / 16 {
+            0 => ((b & c) | (!b & d), i),
+            1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+            2 => (b ^ c ^ d, (3 * i + 5) % 16),
+            _ => (c ^ (b | !d), (7 * i) % 16)
+        };
+        let sum: u32 = a.wrapping_add(f).wrapping_add(MD5_K[i]).wrapping_add(words[g]);
+        (a, d, c) = (d, c, b);
+        b = b.wrapping_add(sum.rotate_left(MD5_SHIFTS[i / 16][i % 4]));
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d]) { *word = word.wrapping_add(value); }
+}
+
+fn sha1_compress(state: &mut [u32; 5], block: &[u8; 64]) {
+    let mut words: [u32; 80] = [0; 80];
+    for (i, bytes) in block.chunks_exact(4).enumerate() { words[i] = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]); }
+    for i in 16..80 { words[i] = (words[i - 3] ^ words[i - 8] ^ words[i - 14] ^ words[i - 16]).rotate_left(1); }
+
+    let [mut a, mut b, mut c, mut d, mut e]: [u32; 5] = *state;
+    for (i, word) in words.iter().enumerate() {
+        let (f, k): (u32, u32) = match i / 20 {
+            0 => ((b & c) | (!b & d), 0x5A827999),
+            1 => (b ^ c ^ d, 0x6ED9EBA1),
+            2 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+            _ => (b ^ c ^ d, 0xCA62C1D6)
+        };
+        let temp: u32 = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+        (e, d, c, b, a) = (d, c, b.rotate_left(30), a, temp);
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e]) { *word = word.wrapping_add(value); }
+}
+
+fn sha256_compress(state: &mut [u32; 8], block: &[u8; 64]) {
+    let mut words: [u32; 64] = [0; 64];
+    for (i, bytes) in block.chunks_exact(4).enumerate() { words[i] = u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]); }
+    for i in 16..64 {
+        let s0: u32 = words[i - 15].rotate_right(7) ^ words[i - 15].rotate_right(18) ^ (words[i - 15] >> 3);
+        let s1: u32 = words[i - 2].rotate_right(17) ^ words[i - 2].rotate_right(19) ^ (words[i - 2] >> 10);
+        words[i] = words[i - 16].wrapping_add(s0).wrapping_add(words[i - 7]).wrapping_add(s1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h]: [u32; 8] = *state;
+    for i in 0..64 {
+        let s1: u32 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+        let choice: u32 = (e & f) ^ (!e & g);
+        let temp1: u32 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(SHA256_K[i]).wrapping_add(words[i]);
+        let s0: u32 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+        let majority: u32 = (a & b) ^ (a & c) ^ (b & c);
+        let temp2: u32 = s0.wrapping_add(majority);
+        (h, g, f, e, d, c, b, a) = (g, f, e, d.wrapping_add(temp1), c, b, a, temp1.wrapping_add(temp2));
+    }
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) { *word = word.wrapping_add(value); }
+}
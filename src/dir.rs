@@ -0,0 +1,343 @@
+use crate::{Cluster, DataStart};
+
+/* ==== CONSTANTS =========================================================== */
+/** Directory entry attribute flags. */
+pub const ATTR_READ_ONLY: u8 = 0x01;
+pub const ATTR_HIDDEN: u8 = 0x02;
+pub const ATTR_SYSTEM: u8 = 0x04;
+pub const ATTR_VOLUME_ID: u8 = 0x08;
+pub const ATTR_DIRECTORY: u8 = 0x10;
+pub const ATTR_ARCHIVE: u8 = 0x20;
+pub const ATTR_LFN: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_VOLUME_ID;
+
+/** First name byte of an entry that has been deleted and can be reused. */
+pub(crate) const DELETED_ENTRY: u8 = 0xE5;
+
+/** First name byte standing for an actual 0xE5 first character (a common
+ *  Shift JIS lead byte), which would otherwise read as deleted. */
+const ESCAPED_E5: u8 = 0x05;
+
+/** Raw names of the entries at the start of every subdirectory, pointing to
+ *  itself and to its parent (cluster 0 for the root). */
+pub const DOT_NAME: [u8; 11] = *b".          ";
+pub const DOT_DOT_NAME: [u8; 11] = *b"..         ";
+
+/* ==== STRUCTS ============================================================= */
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct DirectoryEntry {
+    pub name: [u8; 11],
+    pub attributes: u8,             // READ_ONLY=0x01 HIDDEN=0x02 SYSTEM=0x04 VOLUME_ID=0x08 DIRECTORY=0x10 ARCHIVE=0x20 LFN=READ_ONLY|HIDDEN|SYSTEM|VOLUME_ID (LFN means that this entry is a long file name entry)
+    pub reserved: u8,
+    pub creation_time_tenths: u8,
+    pub creation_time: u16,
+    pub creation_date: u16,
+    pub last_access_date: u16,
+    pub upper_first_cluster: u16,
+    pub last_change_time: u16,
+    pub last_change_date: u16,
+    pub lower_first_cluster: u16,
+    pub file_size: u32
+}   // 32 byte
+
+impl DirectoryEntry {
+    /** Build a fresh entry with the given name and attributes, stamped with
+     *  the given unix time for creation and last change. */
+    pub fn new(name: [u8; 11], attributes: u8, timestamp: u64) -> DirectoryEntry {
+        let (date, time): (u16, u16) = unix_to_fat_datetime(timestamp);
+        DirectoryEntry {
+            name: escape_short_name(name), attributes,
+            reserved: 0,
+            creation_time_tenths: 0,
+            creation_time: time,
+            creation_date: date,
+            last_access_date: date,
+            upper_first_cluster: 0,
+            last_change_time: time,
+            last_change_date: date,
+            lower_first_cluster: 0,
+            file_size: 0
+        }
+    }
+
+    /** NULL first byte: this entry and all the following ones are unused. */
+    pub fn is_end(&self) -> bool {
+        self.name[0] == 0x00
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.name[0] == DELETED_ENTRY
+    }
+
+    /** Entries that are neither free nor part of a long file name. */
+    pub fn is_used(&self) -> bool {
+        !self.is_end() && !self.is_deleted() && self.attributes != ATTR_LFN
+    }
+
+    pub fn is_directory(&self) -> bool {
+        self.attributes & ATTR_DIRECTORY != 0
+    }
+
+    pub fn is_volume_label(&self) -> bool {
+        self.attributes & ATTR_VOLUME_ID != 0
+    }
+
+    /** "." and ".." entries found at the start of every subdirectory. */
+    pub fn is_dot_entry(&self) -> bool {
+        self.name[0] == b'.'
+    }
+
+    /** Display form of the 8.3 name ("KERNEL  BIN" -> "KERNEL.BIN"). */
+    pub fn get_name(&self) -> String {
+        short_name_to_string(&self.get_short_name())
+    }
+
+    /** The 11 bytes of the name, with a 0x05 first byte back to the 0xE5
+     *  character it stands for. */
+    pub fn get_short_name(&self) -> [u8; 11] {
+        let mut name: [u8; 11] = self.name;
+        if name[0] == ESCAPED_E5 { name[0] = DELETED_ENTRY; }
+        name
+    }
+
+    /** Store the 11 bytes of the name, escaping a 0xE5 first character. */
+    pub fn set_short_name(&mut self, name: [u8; 11]) {
+        self.name = escape_short_name(name);
+    }
+
+    /** Attribute flags as letters, "-" for the unset ones ("RHSVDA"). */
+    pub fn get_attributes_string(&self) -> String {
+        attributes_to_string(self.attributes)
+    }
+
+    /** First cluster of the data (0 for empty files and the root). The
+     *  upper half is only meaningful on FAT32, so it is left out. */
+    pub fn get_first_cluster(&self) -> Cluster {
+        Cluster::from(self.lower_first_cluster)
+    }
+
+    pub(crate) fn get_data_start(&self) -> DataStart {
+        match self.get_first_cluster() {
+            cluster if cluster.is_data() => DataStart::Chain(cluster),
+            Cluster(0) if self.name == DOT_DOT_NAME => DataStart::Root,
+            _ => DataStart::Empty
+        }
+    }
+
+    pub fn set_first_cluster(&mut self, cluster: Cluster) {
+        self.lower_first_cluster = cluster.0 as u16;
+    }
+
+    /** Last change date and time converted to unix seconds. */
+    pub fn get_last_change_timestamp(&self) -> u64 {
+        fat_datetime_to_unix(self.last_change_date, self.last_change_time)
+    }
+
+    pub fn set_last_change_timestamp(&mut self, timestamp: u64) {
+        let (date, time): (u16, u16) = unix_to_fat_datetime(timestamp);
+        self.last_change_date = date;
+        self.last_change_time = time;
+        self.last_access_date = date;
+    }
+}
+
+pub struct Directory {
+    pub(crate) entries: Vec<DirectoryEntry>,
+
+    // Clusters holding the entries, in order. Empty for the root directory,
+    // which lives in its own fixed region instead of the data clusters.
+    pub(crate) clusters: Vec<Cluster>
+
+    // ! Readonly (immutable slice reference)
+    // entries: &'static[DirectoryEntry]
+
+    // ! Unsafe if memory allocation is not handled, memory can be overwritten
+    // entries: *const DirectoryEntry,
+    // entries_count: u16
+}
+
+impl Directory {
+    pub fn get_entry(&self, name: &str) -> Option<&DirectoryEntry> {
+        for i in 0..self.entries.len() {
+            // Get ith entry in the directory
+            let entry: &DirectoryEntry = self.entries.get(i)?;
+
+            // If the first byte is NULL, the previous entry was the last one
+            if *entry.name.first()? == 0x00 { break; }
+
+            // If the name is equal to the input, this is the entry
+            if name.as_bytes().eq(&entry.get_short_name()) { return Some(entry); }
+        }
+        None
+    }
+
+    /** Iterate over the used entries (no free slots or long file names). */
+    pub fn entries(&self) -> impl Iterator<Item = &DirectoryEntry> {
+        self.entries.iter().take_while(|entry| !entry.is_end()).filter(|entry| entry.is_used())
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.clusters.is_empty()
+    }
+
+    /** First cluster of the directory, 0 for the root. */
+    pub fn get_first_cluster(&self) -> Cluster {
+        self.clusters.first().copied().unwrap_or_default()
+    }
+
+    /** Index of the used entry with the given raw 11 bytes name. */
+    pub(crate) fn find_index(&self, name: &[u8; 11]) -> Option<usize> {
+        self.entries.iter()
+            .take_while(|entry| !entry.is_end())
+            .position(|entry| entry.is_used() && entry.get_short_name() == *name)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Convert a name in the usual "NAME.EXT" form to the raw 11 bytes stored in
+ *  the entries ("NAME    EXT"). Lowercase letters are uppercased; names that
+ *  don't fit 8.3 or contain forbidden characters are rejected. */
+pub fn to_short_name(name: &str) -> Option<[u8; 11]> {
+    let (base, extension): (&str, &str) = match name.rsplit_once('.') {
+        Some((base, extension)) => (base, extension),
+        None => (name, "")
+    };
+    if base.is_empty() || base.len() > 8 || extension.len() > 3 { return None; }
+
+    let mut short_name: [u8; 11] = [b' '; 11];
+    for (i, c) in base.bytes().enumerate() { short_name[i] = to_short_name_char(c)?; }
+    for (i, c) in extension.bytes().enumerate() { short_name[8 + i] = to_short_name_char(c)?; }
+    Some(short_name)
+}
+
+/** Inverse of to_short_name: "NAME    EXT" to "NAME.EXT". */
+pub fn short_name_to_string(name: &[u8; 11]) -> String {
+    let base: String = String::from_utf8_lossy(&name[..8]).trim_end().to_string();
+    let extension: String = String::from_utf8_lossy(&name[8..]).trim_end().to_string();
+    if extension.is_empty() { base } else { format!("{}.{}", base, extension) }
+}
+
+/** Convert FAT date and time to unix seconds.
+ *  Date: bits 15-9 year from 1980, 8-5 month, 4-0 day.
+ *  Time: bits 15-11 hours, 10-5 minutes, 4-0 seconds / 2. */
+pub fn fat_datetime_to_unix(date: u16, time: u16) -> u64 {
+    let year: i64 = 1980 + (date >> 9) as i64;
+    let month: i64 = ((date >> 5) & 0x0F).max(1) as i64;
+    let day: i64 = (date & 0x1F).max(1) as i64;
+    let seconds: i64 = (time >> 11) as i64 * 3600 + ((time >> 5) & 0x3F) as i64 * 60 + (time & 0x1F) as i64 * 2;
+    (days_from_civil(year, month, day) * 86400 + seconds) as u64
+}
+
+/** Convert unix seconds to FAT date and time, clamping to the FAT range. */
+pub fn unix_to_fat_datetime(timestamp: u64) -> (u16, u16) {
+    let (year, month, day): (i64, i64, i64) = civil_from_days((timestamp / 86400) as i64);
+    if year < 1980 { return ((1 << 5) | 1, 0); }
+    if year > 2107 { return ((127 << 9) | (12 << 5) | 31, (23 << 11) | (59 << 5) | 29); }
+
+    let seconds: u64 = timestamp % 86400;
+    let date: u16 = (((year - 1980) << 9) | (month << 5) | day) as u16;
+    let time: u16 = (((seconds / 3600) << 11) | (((seconds / 60) % 60) << 5) | ((seconds % 60) / 2)) as u16;
+    (date, time)
+}
+
+/** Format unix seconds as "YYYY-MM-DD HH:MM:SS". */
+pub fn format_timestamp(timestamp: u64) -> String {
+    let (year, month, day): (i64, i64, i64) = civil_from_days((timestamp / 86400) as i64);
+    let seconds: u64 = timestamp % 86400;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", year, month, day, seconds / 3600, (seconds / 60) % 60, seconds % 60)
+}
+
+/** Format a timestamp of the image, which is local time, as RFC 3339 with
+ *  the given offset from UTC in minutes, e.g. "2023-11-30T22:10:42+01:00". */
+pub fn format_rfc3339(timestamp: u64, offset: i32) -> String {
+    let (sign, minutes): (char, i32) = if offset < 0 { ('-', -offset) } else { ('+', offset) };
+    format!("{}{}{:02}:{:02}", format_timestamp(timestamp).replace(' ', "T"), sign, minutes / 60, minutes % 60)
+}
+
+/** Convert a timestamp of the image, local time at the given offset from
+ *  UTC in minutes, to the instant it stands for. */
+pub fn image_time_to_system_time(timestamp: u64, offset: i32) -> std::time::SystemTime {
+    std::time::UNIX_EPOCH + std::time::Duration::from_secs(timestamp.saturating_add_signed(-(offset as i64) * 60))
+}
+
+/** Attribute flags as letters, "-" for the unset ones ("RHSVDA"). */
+pub fn attributes_to_string(attributes: u8) -> String {
+    [(ATTR_READ_ONLY, 'R'), (ATTR_HIDDEN, 'H'), (ATTR_SYSTEM, 'S'), (ATTR_VOLUME_ID, 'V'), (ATTR_DIRECTORY, 'D'), (ATTR_ARCHIVE, 'A')]
+        .iter()
+        .map(|(flag, letter)| if attributes & flag != 0 { *letter } else { '-' })
+        .collect()
+}
+
+/** Inverse of format_timestamp, the time being optional: "YYYY-MM-DD" is
+ *  the start of the day. */
+pub fn parse_timestamp(text: &str) -> Option<u64> {
+    let (date, time): (&str, &str) = text.trim().split_once([' ', 'T']).unwrap_or((text.trim(), "00:00:00"));
+    let numbers = |text: &str, separator: char| -> Option<Vec<i64>> { text.split(separator).map(|number| number.parse().ok()).collect() };
+    let (date, time): (Vec<i64>, Vec<i64>) = (numbers(date, '-')?, numbers(time, ':')?);
+    let (&[year, month, day], &[hours, minutes, seconds]) = (&date[..], &time[..]) else { return None; };
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=31).contains(&day) || !(0..24).contains(&hours) || !(0..60).contains(&minutes) || !(0..60).contains(&seconds) {
+        return None;
+    }
+    Some((days_from_civil(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds) as u64)
+}
+
+/* ==== UTILS =============================================================== */
+/** Stored form of the name: a 0xE5 first character becomes 0x05. */
+fn escape_short_name(mut name: [u8; 11]) -> [u8; 11] {
+    if name[0] == DELETED_ENTRY { name[0] = ESCAPED_E5; }
+    name
+}
+
+/** Short name made of whatever fits of the name, see NamePolicy::Sanitize.
+ *  None if nothing is left of the base name. */
+pub(crate) fn sanitize_short_name(name: &str) -> Option<[u8; 11]> {
+    let name: &str = name.trim_start_matches(['.', ' ']);
+    let (base, extension): (&str, &str) = name.rsplit_once('.').unwrap_or((name, ""));
+    let convert = |part: &str, length: usize| -> Vec<u8> {
+        part.chars().filter(|&c| c != ' ' && c != '.')
+            .map(|c| u8::try_from(c).ok().and_then(to_short_name_char).unwrap_or(b'_'))
+            .take(length)
+            .collect()
+    };
+    let (base, extension): (Vec<u8>, Vec<u8>) = (convert(base, 8), convert(extension, 3));
+    if base.is_empty() { return None; }
+
+    let mut short_name: [u8; 11] = [b' '; 11];
+    short_name[..base.len()].copy_from_slice(&base);
+    short_name[8..8 + extension.len()].copy_from_slice(&extension);
+    Some(short_name)
+}
+
+/** Uppercase a name character, rejecting the ones DOS doesn't allow. */
+pub(crate) fn to_short_name_char(c: u8) -> Option<u8> {
+    match c {
+        b'a'..=b'z' => Some(c.to_ascii_uppercase()),
+        b'A'..=b'Z' | b'0'..=b'9' => Some(c),
+        b'!' | b'#' | b'$' | b'%' | b'&' | b'\'' | b'(' | b')' | b'-' | b'@' | b'^' | b'_' | b'`' | b'{' | b'}' | b'~' => Some(c),
+        _ => None
+    }
+}
+
+/** Days since 1970-01-01 of the given civil date (proleptic gregorian). */
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // Count years from march, so that the leap day is the last of the year
+    let year: i64 = if month <= 2 { year - 1 } else { year };
+    let era: i64 = year.div_euclid(400);
+    let year_of_era: i64 = year - era * 400;
+    let day_of_year: i64 = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era: i64 = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/** Inverse of days_from_civil: (year, month, day) of the given day number. */
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days: i64 = days + 719468;
+    let era: i64 = days.div_euclid(146097);
+    let day_of_era: i64 = days - era * 146097;
+    let year_of_era: i64 = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year: i64 = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_from_march: i64 = (5 * day_of_year + 2) / 153;
+    let day: i64 = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+    let month: i64 = if month_from_march < 10 { month_from_march + 3 } else { month_from_march - 9 };
+    (if month <= 2 { year_of_era + era * 400 + 1 } else { year_of_era + era * 400 }, month, day)
+}
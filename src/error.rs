@@ -0,0 +1,176 @@
+use std::{fmt::Display, io, process};
+use rs_disk_reader::{bpb, validation::{Diagnostic, Report}, Validation};
+
+use crate::serve::escape_json;
+
+/* Exit codes: stable, so that scripts can branch on them */
+/** Not an error: the compared sides are different (diff, sync conflicts). */
+pub const EXIT_DIFFERENT: i32 = 1;
+pub const EXIT_NOT_FOUND: i32 = 2;
+pub const EXIT_CORRUPT: i32 = 3;
+pub const EXIT_NO_SPACE: i32 = 4;
+pub const EXIT_IO: i32 = 5;
+pub const EXIT_PERMISSION: i32 = 6;
+
+/** The operation doesn't make sense for the target: it's a directory, it
+ *  already exists, the name isn't valid 8.3, ... */
+pub const EXIT_INVALID: i32 = 7;
+
+/** Another process has the image open for writing, see --force. */
+pub const EXIT_LOCKED: i32 = 8;
+
+/** The reader of the output went away, e.g. `| head` had enough of it:
+ *  the command stops there, quietly, with the code a shell gives programs
+ *  killed by SIGPIPE. */
+pub const EXIT_BROKEN_PIPE: i32 = 141;
+pub const EXIT_USAGE: i32 = 64;
+
+/* ==== STRUCTS ============================================================= */
+/** How errors are reported on stderr. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorFormat {
+    Text,
+
+    /** Only the exit code. */
+    Quiet,
+
+    /** A single {"error": ..., "code": ..., "message": ...} line. */
+    Json
+}
+
+/** Failure of a command, with the exit code it maps to. */
+#[derive(Debug)]
+pub struct CliError {
+    pub code: i32,
+
+    /** Short stable name of the code, e.g. "not_found". */
+    pub kind: &'static str,
+    pub message: String,
+
+    /** Usage line to print along with the message, for command line mistakes. */
+    pub usage: Option<String>,
+
+    /** Longer form of the message for the terminal: the bytes behind a
+     *  validation failure, with hints. */
+    pub details: Option<String>
+}
+
+impl CliError {
+    pub fn usage(message: impl Display, usage: String) -> CliError {
+        CliError { code: EXIT_USAGE, kind: "usage", message: message.to_string(), usage: Some(usage), details: None }
+    }
+
+    pub fn invalid(message: impl Display) -> CliError {
+        CliError { code: EXIT_INVALID, kind: "invalid", message: message.to_string(), usage: None, details: None }
+    }
+
+    /** Classify an I/O error, prefixing the message with what was being done. */
+    pub fn from_io(context: &str, e: &io::Error) -> CliError {
+        let (code, kind): (i32, &'static str) = match e.kind() {
+            io::ErrorKind::NotFound => (EXIT_NOT_FOUND, "not_found"),
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => (EXIT_CORRUPT, "corrupt"),
+            io::ErrorKind::StorageFull => (EXIT_NO_SPACE, "no_space"),
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ReadOnlyFilesystem => (EXIT_PERMISSION, "permission"),
+            io::ErrorKind::ResourceBusy => (EXIT_LOCKED, "locked"),
+            io::ErrorKind::BrokenPipe => (EXIT_BROKEN_PIPE, "broken_pipe"),
+            io::ErrorKind::InvalidInput | io::ErrorKind::AlreadyExists | io::ErrorKind::NotADirectory
+                | io::ErrorKind::IsADirectory | io::ErrorKind::DirectoryNotEmpty => (EXIT_INVALID, "invalid"),
+            _ => (EXIT_IO, "io")
+        };
+        let report: Option<&Report> = e.get_ref().and_then(|inner| inner.downcast_ref::<Report>());
+        let details: Option<String> = report.map(|report| format!("{}: not a valid FAT12 image\n{}", context, render_report(report)));
+        CliError { code, kind, message: format!("{}: {}", context, e), usage: None, details }
+    }
+
+    /** Whether the reader of the output went away, see EXIT_BROKEN_PIPE:
+     *  loops going on after errors stop there. */
+    pub fn is_broken_pipe(&self) -> bool {
+        self.kind == "broken_pipe"
+    }
+
+    /** Print the error in the given format and quit with its exit code.
+     *  A broken pipe is not reported: there is no one left to read it. */
+    pub fn exit(&self, format: ErrorFormat) -> ! {
+        if self.is_broken_pipe() { process::exit(self.code); }
+        match format {
+            ErrorFormat::Text => {
+                eprintln!("error: {}", self.details.as_ref().unwrap_or(&self.message));
+                if let Some(usage) = &self.usage { eprint!("{}", usage); }
+            },
+            ErrorFormat::Json => eprintln!("{{\"error\":\"{}\",\"code\":{},\"message\":\"{}\"}}", self.kind, self.code, escape_json(&self.message)),
+            ErrorFormat::Quiet => {}
+        }
+        process::exit(self.code);
+    }
+}
+
+/** Turn an I/O error into a CliError, saying what was being done. */
+pub trait Context<T> {
+    fn context(self, context: &str) -> Result<T, CliError>;
+}
+
+impl<T> Context<T> for io::Result<T> {
+    fn context(self, context: &str) -> Result<T, CliError> {
+        self.map_err(|e| CliError::from_io(context, &e))
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Every diagnostic of the report with the image bytes it points to, the
+ *  value underlined, and what to do about it:
+ *
+ *    bytes_per_sector = 0, expected a power of two, at least 128
+ *         |
+ *    0000 | EB 3C 90 4D 53 57 49 4E 34 2E 31 00 00 01 01 00  .<.MSWIN4.1.....
+ *         |                                  ^^ ^^
+ *         = hint: --bytes-per-sector <n> reads the image with another value */
+fn render_report(report: &Report) -> String {
+    let mut text: String = String::new();
+    for diagnostic in &report.diagnostics {
+        text.push_str(&format!("  {} = {}, expected {}\n", diagnostic.check, diagnostic.observed, diagnostic.expected));
+        if let Some(offset) = diagnostic.offset.filter(|_| !diagnostic.bytes.is_empty()) {
+            // Offsets past 0xFFFF widen the gutter
+            let start: u64 = offset / 16 * 16;
+            let width: usize = format!("{:X}", start + diagnostic.bytes.len() as u64).len().max(4);
+            let gutter: String = " ".repeat(width + 3);
+            text.push_str(&format!("{}|\n", gutter));
+            for (index, row) in diagnostic.bytes.chunks(16).enumerate() {
+                let row_start: u64 = start + index as u64 * 16;
+                let hex: Vec<String> = row.iter().map(|byte| format!("{:02X}", byte)).collect();
+                let ascii: String = row.iter().map(|&byte| if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' }).collect();
+                text.push_str(&format!("  {:0width$X} | {:<47}  {}\n", row_start, hex.join(" "), ascii, width = width));
+
+                // Carets under the bytes of the value in this row
+                let marks: String = (row_start..row_start + row.len() as u64)
+                    .map(|position| if position >= offset && position < offset + diagnostic.length.max(1) as u64 { "^^" } else { "  " })
+                    .collect::<Vec<&str>>().join(" ");
+                if marks.contains('^') { text.push_str(&format!("{}| {}\n", gutter, marks.trim_end())); }
+            }
+        }
+        for hint in get_hints(diagnostic) { text.push_str(&format!("       = hint: {}\n", hint)); }
+    }
+    text.trim_end().to_string()
+}
+
+/** What the command line offers to read or fix an image failing the check. */
+fn get_hints(diagnostic: &Diagnostic) -> Vec<String> {
+    let check: &str = diagnostic.check.as_str();
+    let mut hints: Vec<String> = vec![];
+    if bpb::FIELDS.contains(&check) {
+        hints.push(format!("--{} <n> reads the image with another value, bpb --set {}=<n> writes it", check.replace('_', "-"), check));
+    }
+    match check {
+        "media_geometry" => hints.push(String::from("check --trust media, or geometry_source = \"media\", reads it with the geometry of the media descriptor")),
+        "image_size" => hints.push(String::from("zero_missing = true opens it, reading the missing sectors as zeros")),
+        "fat_id" => hints.push(String::from("validation = \"lenient\" reads the first FAT copy with a valid ID")),
+        _ if check.starts_with("fat_copy_") => hints.push(String::from("check --mirror-fat writes the first FAT to every copy")),
+        _ if check.ends_with("first_cluster") => hints.push(String::from("validation = \"lenient\" reads the entry as empty")),
+        _ => {}
+    }
+    match diagnostic.level {
+        Validation::Strict => hints.push(String::from("only the strict validation refuses it, validation = \"normal\" reads the image anyway")),
+        _ if bpb::FIELDS.contains(&check) => hints.push(String::from("validation = \"lenient\" guesses a value from the rest of the image")),
+        _ => {}
+    }
+    hints
+}
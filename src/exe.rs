@@ -0,0 +1,99 @@
+/* ==== STRUCTS ============================================================= */
+/** Header of a DOS MZ executable, the words at the start of the file. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MzHeader {
+    pub last_page_bytes: u16,
+    pub pages: u16,
+    pub relocations: u16,
+    pub header_paragraphs: u16,
+    pub min_alloc: u16,
+    pub max_alloc: u16,
+    pub ss: u16,
+    pub sp: u16,
+    pub checksum: u16,
+    pub ip: u16,
+    pub cs: u16,
+    pub relocation_offset: u16,
+    pub overlay: u16,
+
+    /** Offset of the newer header a Windows or OS/2 program has after the
+     *  DOS stub, with its signature ("PE", "NE", "LE" or "LX"). */
+    pub new_header: Option<(u32, [u8; 2])>
+}
+
+impl MzHeader {
+    pub fn get_header_size(&self) -> u32 {
+        self.header_paragraphs as u32 * 16
+    }
+
+    /** Bytes loaded in memory: the file size the header gives, less the
+     *  header itself. */
+    pub fn get_load_size(&self) -> u32 {
+        let file_size: u32 = match self.last_page_bytes {
+            0 => self.pages as u32 * 512,
+            bytes => (self.pages as u32).saturating_sub(1) * 512 + bytes as u32
+        };
+        file_size.saturating_sub(self.get_header_size())
+    }
+}
+
+/** What a file runs as, and where it starts. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Executable {
+    /** DOS relocatable executable: CS:IP and SS:SP are relative to the load
+     *  segment, the PSP segment + 0x10. */
+    Mz(MzHeader),
+
+    /** Flat binary loaded at offset 0x100 of the PSP segment, entry there. */
+    Com { size: usize },
+
+    /** 512 bytes ending with 0x55AA, loaded at 0000:7C00; the entry is
+     *  where the first jump lands, if it starts with one. */
+    BootSector { jump_target: Option<u16> },
+    Unknown
+}
+
+/** Largest COM program: a segment less the PSP and the initial stack word. */
+pub const COM_MAX_SIZE: usize = 0xFF00;
+
+/* ==== METHODS ============================================================= */
+/** Tell what the file is from its signature, the name deciding for COM
+ *  programs, which have none. */
+pub fn inspect(data: &[u8], name: &str) -> Executable {
+    if data.len() >= 28 && (data.starts_with(b"MZ") || data.starts_with(b"ZM")) {
+        return Executable::Mz(read_mz_header(data));
+    }
+    if data.len() == 512 && data[510..512] == [0x55, 0xAA] {
+        let jump_target: Option<u16> = match data[0] {
+            0xEB => Some((0x7C02 + data[1] as i8 as i32) as u16),
+            0xE9 => Some((0x7C03 + i16::from_le_bytes([data[1], data[2]]) as i32) as u16),
+            _ => None
+        };
+        return Executable::BootSector { jump_target };
+    }
+    if name.to_ascii_uppercase().ends_with(".COM") { return Executable::Com { size: data.len() }; }
+    Executable::Unknown
+}
+
+/* ==== UTILS =============================================================== */
+fn read_mz_header(data: &[u8]) -> MzHeader {
+    let word = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]);
+    let relocation_offset: u16 = word(0x18);
+
+    // Only headers that leave room for it have the offset at 0x3C
+    let new_header: Option<(u32, [u8; 2])> = match relocation_offset >= 0x40 && data.len() >= 0x40 {
+        true => {
+            let offset: u32 = u32::from_le_bytes([data[0x3C], data[0x3D], data[0x3E], data[0x3F]]);
+            data.get(offset as usize..offset as usize + 2)
+                .map(|signature| [signature[0], signature[1]])
+                .filter(|signature| [*b"PE", *b"NE", *b"LE", *b"LX"].contains(signature))
+                .map(|signature| (offset, signature))
+        },
+        false => None
+    };
+    MzHeader {
+        last_page_bytes: word(0x02), pages: word(0x04), relocations: word(0x06), header_paragraphs: word(0x08),
+        min_alloc: word(0x0A), max_alloc: word(0x0C), ss: word(0x0E), sp: word(0x10), checksum: word(0x12),
+        ip: word(0x14), cs: word(0x16), relocation_offset, overlay: word(0x1A), new_header
+    }
+}
@@ -0,0 +1,211 @@
+use std::{fs::File, io::{self, Read, Seek, SeekFrom, Write}, net::{TcpListener, TcpStream}, thread};
+
+/** Default address: the NBD well known port, local connections only. */
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:10809";
+
+/* ==== NBD PROTOCOL ======================================================== */
+// Handshake ("fixed newstyle" negotiation)
+const NBD_MAGIC: u64 = 0x4E42444D41474943;          // "NBDMAGIC"
+const NBD_IHAVEOPT: u64 = 0x49484156454F5054;       // "IHAVEOPT"
+const NBD_OPTION_REPLY_MAGIC: u64 = 0x0003E889045565A9;
+const NBD_FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const NBD_FLAG_NO_ZEROES: u16 = 1 << 1;
+const NBD_FLAG_C_NO_ZEROES: u32 = 1 << 1;
+
+// Options sent by the client, and our replies
+const NBD_OPT_EXPORT_NAME: u32 = 1;
+const NBD_OPT_ABORT: u32 = 2;
+const NBD_OPT_LIST: u32 = 3;
+const NBD_OPT_INFO: u32 = 6;
+const NBD_OPT_GO: u32 = 7;
+const NBD_REP_ACK: u32 = 1;
+const NBD_REP_SERVER: u32 = 2;
+const NBD_REP_INFO: u32 = 3;
+const NBD_REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const NBD_REP_ERR_UNKNOWN: u32 = (1 << 31) + 6;
+const NBD_REP_ERR_TOO_BIG: u32 = (1 << 31) + 9;
+const NBD_INFO_EXPORT: u16 = 0;
+
+// Most option data taken from a client: an export name (at most 4096 bytes)
+// and a few info requests, with room to spare
+const MAX_OPTION_LENGTH: u32 = 16 * 1024;
+
+// Transmission: the export is always read only
+const NBD_FLAG_HAS_FLAGS: u16 = 1 << 0;
+const NBD_FLAG_READ_ONLY: u16 = 1 << 1;
+const NBD_REQUEST_MAGIC: u32 = 0x25609513;
+const NBD_SIMPLE_REPLY_MAGIC: u32 = 0x67446698;
+const NBD_CMD_READ: u16 = 0;
+const NBD_CMD_WRITE: u16 = 1;
+const NBD_CMD_DISC: u16 = 2;
+const NBD_EPERM: u32 = 1;
+const NBD_EIO: u32 = 5;
+const NBD_EINVAL: u32 = 22;
+
+/* ==== METHODS ============================================================= */
+/** export <image> [--listen <address>] [--name <name>]: serve the raw image
+ *  read-only over the NBD protocol, so that QEMU and nbd-client can attach
+ *  to it (e.g. "-drive file=nbd://127.0.0.1:10809/floppy,format=raw,if=floppy,readonly=on").
+ *  Every client gets its own thread and its own handle on the image. */
+pub fn run(image_path: &str, listen: &str, name: &str) -> io::Result<()> {
+    // Fail early if the image isn't readable, instead of on the first client
+    File::open(image_path)?;
+
+    let listener: TcpListener = TcpListener::bind(listen)?;
+    writeln!(io::stdout().lock(), "exporting {} as \"{}\" on nbd://{}/{}", image_path, name, listen, name)?;
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let (image_path, name): (String, String) = (image_path.to_string(), name.to_string());
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &image_path, &name) { eprintln!("connection error: {}", e); }
+        });
+    }
+    Ok(())
+}
+
+/* ==== UTILS =============================================================== */
+fn handle(mut stream: TcpStream, image_path: &str, name: &str) -> io::Result<()> {
+    let mut image: File = File::open(image_path)?;
+    let size: u64 = image.metadata()?.len();
+
+    // Greet the client and agree on the flags
+    write_u64(&mut stream, NBD_MAGIC)?;
+    write_u64(&mut stream, NBD_IHAVEOPT)?;
+    write_u16(&mut stream, NBD_FLAG_FIXED_NEWSTYLE | NBD_FLAG_NO_ZEROES)?;
+    let no_zeroes: bool = read_u32(&mut stream)? & NBD_FLAG_C_NO_ZEROES != 0;
+
+    // Answer options until one of them starts the transmission phase
+    let flags: u16 = NBD_FLAG_HAS_FLAGS | NBD_FLAG_READ_ONLY;
+    loop {
+        if read_u64(&mut stream)? != NBD_IHAVEOPT { return Err(io::Error::new(io::ErrorKind::InvalidData, "bad option magic")); }
+        let option: u32 = read_u32(&mut stream)?;
+        let length: u32 = read_u32(&mut stream)?;
+        if length > MAX_OPTION_LENGTH {
+            // Skipped rather than read, whatever the client claims to send;
+            // EXPORT_NAME has no error reply, the connection just ends
+            if option == NBD_OPT_EXPORT_NAME { return Err(io::Error::new(io::ErrorKind::InvalidData, format!("export name of {} bytes", length))); }
+            io::copy(&mut (&mut stream).take(length as u64), &mut io::sink())?;
+            write_option_reply(&mut stream, option, NBD_REP_ERR_TOO_BIG, b"option data too long")?;
+            continue;
+        }
+        let mut data: Vec<u8> = vec![0; length as usize];
+        stream.read_exact(&mut data)?;
+
+        match option {
+            NBD_OPT_EXPORT_NAME => {
+                // Old style: no reply header, just size, flags and padding
+                write_u64(&mut stream, size)?;
+                write_u16(&mut stream, flags)?;
+                if !no_zeroes { stream.write_all(&[0; 124])?; }
+                break;
+            },
+            NBD_OPT_ABORT => {
+                write_option_reply(&mut stream, option, NBD_REP_ACK, &[])?;
+                return Ok(());
+            },
+            NBD_OPT_LIST => {
+                let mut reply: Vec<u8> = (name.len() as u32).to_be_bytes().to_vec();
+                reply.extend_from_slice(name.as_bytes());
+                write_option_reply(&mut stream, option, NBD_REP_SERVER, &reply)?;
+                write_option_reply(&mut stream, option, NBD_REP_ACK, &[])?;
+            },
+            NBD_OPT_INFO | NBD_OPT_GO => {
+                // Data: name length, name, info requests (ignored, we only have one)
+                let length: usize = data.get(..4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize).unwrap_or(0);
+                let requested: &[u8] = data.get(4..4 + length).unwrap_or(&[]);
+                if !requested.is_empty() && requested != name.as_bytes() {
+                    write_option_reply(&mut stream, option, NBD_REP_ERR_UNKNOWN, b"no such export")?;
+                    continue;
+                }
+
+                let mut info: Vec<u8> = NBD_INFO_EXPORT.to_be_bytes().to_vec();
+                info.extend_from_slice(&size.to_be_bytes());
+                info.extend_from_slice(&flags.to_be_bytes());
+                write_option_reply(&mut stream, option, NBD_REP_INFO, &info)?;
+                write_option_reply(&mut stream, option, NBD_REP_ACK, &[])?;
+                if option == NBD_OPT_GO { break; }
+            },
+            _ => write_option_reply(&mut stream, option, NBD_REP_ERR_UNSUP, &[])?
+        }
+    }
+
+    // Transmission: serve reads until the client disconnects
+    loop {
+        if read_u32(&mut stream)? != NBD_REQUEST_MAGIC { return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request magic")); }
+        let _command_flags: u16 = read_u16(&mut stream)?;
+        let command: u16 = read_u16(&mut stream)?;
+        let handle: u64 = read_u64(&mut stream)?;
+        let offset: u64 = read_u64(&mut stream)?;
+        let length: u32 = read_u32(&mut stream)?;
+
+        match command {
+            NBD_CMD_READ if offset.checked_add(length as u64).is_none_or(|end| end > size) => write_simple_reply(&mut stream, NBD_EINVAL, handle, &[])?,
+            NBD_CMD_READ => {
+                let mut buffer: Vec<u8> = vec![0; length as usize];
+                let result: io::Result<()> = image.seek(SeekFrom::Start(offset)).and_then(|_| image.read_exact(&mut buffer));
+                match result {
+                    Ok(()) => write_simple_reply(&mut stream, 0, handle, &buffer)?,
+                    Err(_) => write_simple_reply(&mut stream, NBD_EIO, handle, &[])?
+                }
+            },
+            NBD_CMD_DISC => return Ok(()),
+
+            // Writes carry a payload that must be consumed before answering
+            NBD_CMD_WRITE => {
+                io::copy(&mut (&mut stream).take(length as u64), &mut io::sink())?;
+                write_simple_reply(&mut stream, NBD_EPERM, handle, &[])?;
+            },
+            _ => write_simple_reply(&mut stream, NBD_EINVAL, handle, &[])?
+        }
+    }
+}
+
+fn write_option_reply(stream: &mut TcpStream, option: u32, reply: u32, data: &[u8]) -> io::Result<()> {
+    write_u64(stream, NBD_OPTION_REPLY_MAGIC)?;
+    write_u32(stream, option)?;
+    write_u32(stream, reply)?;
+    write_u32(stream, data.len() as u32)?;
+    stream.write_all(data)
+}
+
+fn write_simple_reply(stream: &mut TcpStream, error: u32, handle: u64, data: &[u8]) -> io::Result<()> {
+    // Build the whole reply first, so that it goes out in a single write
+    let mut reply: Vec<u8> = Vec::with_capacity(16 + data.len());
+    reply.extend_from_slice(&NBD_SIMPLE_REPLY_MAGIC.to_be_bytes());
+    reply.extend_from_slice(&error.to_be_bytes());
+    reply.extend_from_slice(&handle.to_be_bytes());
+    reply.extend_from_slice(data);
+    stream.write_all(&reply)
+}
+
+/* NBD is big endian on the wire */
+fn read_u16(stream: &mut TcpStream) -> io::Result<u16> {
+    let mut bytes: [u8; 2] = [0; 2];
+    stream.read_exact(&mut bytes)?;
+    Ok(u16::from_be_bytes(bytes))
+}
+
+fn read_u32(stream: &mut TcpStream) -> io::Result<u32> {
+    let mut bytes: [u8; 4] = [0; 4];
+    stream.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(stream: &mut TcpStream) -> io::Result<u64> {
+    let mut bytes: [u8; 8] = [0; 8];
+    stream.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn write_u16(stream: &mut TcpStream, value: u16) -> io::Result<()> {
+    stream.write_all(&value.to_be_bytes())
+}
+
+fn write_u32(stream: &mut TcpStream, value: u32) -> io::Result<()> {
+    stream.write_all(&value.to_be_bytes())
+}
+
+fn write_u64(stream: &mut TcpStream, value: u64) -> io::Result<()> {
+    stream.write_all(&value.to_be_bytes())
+}
@@ -0,0 +1,213 @@
+use std::io;
+
+use crate::layout;
+use crate::{BootSector, Lba};
+
+/* ==== CONSTANTS =========================================================== */
+/** FAT values: free cluster, lowest "end of chain" marker and the one we write. */
+pub(crate) const FREE_CLUSTER: u16 = 0x0000;
+pub(crate) const END_OF_CHAIN_MIN: u16 = 0x0FF8;
+pub(crate) const END_OF_CHAIN: u16 = 0x0FFF;
+
+/* ==== STRUCTS ============================================================= */
+/** Index of a cluster, as found in the FAT and in directory entries.
+ *  0 and 1 are reserved: data clusters start at 2, and a directory entry
+ *  pointing to 0 means "no data" (or the root, for ".." entries). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Cluster(pub u32);
+
+impl Cluster {
+    /** Whether this is a data cluster rather than one of the reserved ones. */
+    pub fn is_data(&self) -> bool {
+        self.0 >= 2
+    }
+
+    /** First sector of the cluster (it must be a data cluster). */
+    pub fn to_lba(&self, boot_sector: &BootSector) -> Lba {
+        Lba(boot_sector.get_layout().cluster_lba(self.0 as u64).expect("reserved clusters have no sector"))
+    }
+
+    /** Position of the cluster in the FAT, for indexing its entry. */
+    fn index(&self) -> u64 {
+        self.0 as u64
+    }
+}
+
+impl From<u16> for Cluster {
+    fn from(cluster: u16) -> Cluster {
+        Cluster(cluster as u32)
+    }
+}
+
+impl std::fmt::Display for Cluster {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/** What the first cluster of an entry stands for, with the reserved values
+ *  told apart from data clusters: 0 is the root in ".." entries and no data
+ *  anywhere else, so that it never gets read as a cluster of the data area. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DataStart {
+    Root,
+    Empty,
+    Chain(Cluster)
+}
+
+/** FAT entries 0 and 1, which don't stand for any cluster: the first one
+ *  echoes the media descriptor (with the upper bits set), the second one is
+ *  an end of chain marker. FAT16 keeps the clean shutdown and no errors
+ *  flags in the top bits of entry 1: some drivers do the same on FAT12. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FatHeader {
+    pub fat_id: u16,
+    pub end_of_chain: u16
+}
+
+impl FatHeader {
+    pub fn get_media_descriptor(&self) -> u8 {
+        self.fat_id as u8
+    }
+
+    /** Whether the FAT ID is the media descriptor of the boot sector, with
+     *  the upper bits set as they should be. */
+    pub fn matches_media(&self, boot_sector: &BootSector) -> bool {
+        self.fat_id & 0xF00 == 0xF00 && self.get_media_descriptor() == boot_sector.media_descriptor
+    }
+
+    /** Clear means the volume was not unmounted cleanly. */
+    pub fn is_clean(&self) -> bool {
+        self.end_of_chain & 0x800 != 0
+    }
+
+    /** Clear means a disk I/O error was met the last time it was mounted. */
+    pub fn has_errors(&self) -> bool {
+        self.end_of_chain & 0x400 == 0
+    }
+}
+
+#[derive(Clone)]
+pub struct Fat {
+    pub(crate) entries: Vec<u8>
+
+    // ! Readonly (immutable reference)
+    // entries: &'static[u8]
+
+    // ! Unsafe if memory allocation is not handled, memory can be overwritten
+    //entries: *const u8
+}
+
+impl Fat{
+    pub fn get_entry(&self, cluster: Cluster) -> u16 {
+        //! Unsafe: we're not checking FAT size against input cluster
+        // Get single byte position and find index array (element = 2B)
+        let (i, odd): (u64, bool) = layout::fat_entry_offset(cluster.index());
+        let i: usize = i as usize;
+
+        // Get 4 if the reminder is 1 (odd number), 0 otherwise (even number)
+        // This number is used for bitshifting by half byte
+        let c: usize = odd as usize * 4;
+
+        // First element contains the least significant byte
+        // If the reminder is odd, we only need the upper 4 bits
+        let lsb: u8 = self.get_byte(i) & (0xFF << c);
+
+        // Second element contains the most significant byte
+        // If the reminder is even, we only need the lower 4 bits
+        let msb: u8 = self.get_byte(i+1) & (0xFF >> (4-c));
+
+        // "Concat" the two bytes in a word
+        let word: u16 = ((msb as u16) * 256) + lsb as u16;
+
+        // If the reminder is odd, the entry is in the upper 12bits, right shift
+        // If the reminder is even, we need to remove the upper 4bits
+        (word >> c) & 0x0FFF
+    }
+
+    /** Byte of the FAT, unchecked with unsafe-fast (panics when out of
+     *  bounds otherwise). */
+    #[cfg(feature = "unsafe-fast")]
+    fn get_byte(&self, i: usize) -> u8 {
+        unsafe { *self.entries.get(i).unwrap_unchecked() }
+    }
+
+    #[cfg(not(feature = "unsafe-fast"))]
+    fn get_byte(&self, i: usize) -> u8 {
+        self.entries[i]
+    }
+
+    /** Inverse of get_entry: store a 12bit value without touching the nibble
+     *  that belongs to the neighbouring cluster. Clusters past the entries
+     *  the FAT holds and values over 12 bits are refused, leaving it as is. */
+    pub fn set_entry(&mut self, cluster: Cluster, value: u16) -> io::Result<()> {
+        let (i, odd): (u64, bool) = layout::fat_entry_offset(cluster.index());
+        if i + 1 >= self.entries.len() as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("cluster {} is past the {} entries of the FAT", cluster, self.get_entry_count())));
+        }
+        if value > 0x0FFF { return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("0x{:X} doesn't fit a 12 bit FAT entry", value))); }
+        let i: usize = i as usize;
+
+        if !odd {
+            // Even: low byte is the whole first byte, high nibble goes in the
+            // lower half of the second byte (the upper half is the next entry)
+            self.entries[i] = value as u8;
+            self.entries[i+1] = (self.entries[i+1] & 0xF0) | (value >> 8) as u8;
+        } else {
+            // Odd: low nibble goes in the upper half of the first byte (the
+            // lower half is the previous entry), the rest is the second byte
+            self.entries[i] = (self.entries[i] & 0x0F) | (value << 4) as u8;
+            self.entries[i+1] = (value >> 4) as u8;
+        }
+        Ok(())
+    }
+
+    /** Entries the FAT holds, 12 bits each, those of clusters 0 and 1
+     *  included: it may have room for more than the volume has clusters. */
+    pub fn get_entry_count(&self) -> usize {
+        self.entries.len() * 2 / 3
+    }
+
+    /** Entries 0 and 1, decoded. */
+    pub fn get_header(&self) -> FatHeader {
+        FatHeader { fat_id: self.get_entry(Cluster(0)), end_of_chain: self.get_entry(Cluster(1)) }
+    }
+
+    /** FAT from its raw bytes, e.g. a FAT built from scratch or read from
+     *  elsewhere: as many entries as the bytes hold, see get_entry_count. */
+    pub fn from_bytes(bytes: Vec<u8>) -> Fat {
+        Fat { entries: bytes }
+    }
+
+    /** Raw FAT bytes, as they are laid out on disk. */
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.entries
+    }
+
+    /** Follow the chain from the given cluster, returning every cluster in
+     *  it up to whatever ends it, see Fat::chain. */
+    pub fn get_chain(&self, first_cluster: Cluster, cluster_count: usize) -> Vec<Cluster> {
+        self.chain(first_cluster, cluster_count).collect()
+    }
+
+    /** Free clusters among the valid data clusters. */
+    pub(crate) fn count_free_clusters(&self, cluster_count: usize) -> usize {
+        (2..self.get_scan_end(cluster_count)).filter(|cluster| self.get_entry(Cluster(*cluster)) == FREE_CLUSTER).count()
+    }
+
+    /** Find up to `count` free clusters among the valid data clusters,
+     *  starting the search from the given one. */
+    pub(crate) fn find_free_clusters(&self, count: usize, cluster_count: usize, start: Cluster) -> Vec<Cluster> {
+        (start.0.max(2)..self.get_scan_end(cluster_count))
+            .map(Cluster)
+            .filter(|cluster| self.get_entry(*cluster) == FREE_CLUSTER)
+            .take(count)
+            .collect()
+    }
+
+    /** End of the data clusters that have an entry in this FAT, which may
+     *  hold fewer than the volume has clusters. */
+    fn get_scan_end(&self, cluster_count: usize) -> u32 {
+        (cluster_count + 2).min(self.get_entry_count()) as u32
+    }
+}
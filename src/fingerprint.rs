@@ -0,0 +1,43 @@
+use std::{collections::BTreeMap, io};
+
+use crate::{digest::{ContentDigest, Sha256}, DirectoryEntry, Fat12Fs};
+
+/** Start of what gets hashed, to be bumped if the records ever change so
+ *  that old and new fingerprints can't match by accident. */
+const FINGERPRINT_VERSION: &[u8] = b"rs-fat12-fingerprint-1";
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** SHA-256 of what the volume holds, see fingerprint_with. */
+    pub fn fingerprint(&mut self) -> io::Result<Vec<u8>> {
+        self.fingerprint_with::<Sha256>()
+    }
+
+    /** Digest of what the volume holds: the path, size and content of each
+     *  file and the path of each directory, in path order. Everything else
+     *  is left out (boot sector, volume label and serial, timestamps and
+     *  attributes, where the clusters are, the slack after the end of each
+     *  file, deleted entries), so that copies of a floppy written out again
+     *  by another tool get the same fingerprint. */
+    pub fn fingerprint_with<D: ContentDigest>(&mut self) -> io::Result<Vec<u8>> {
+        // Sorted by path, so that the order of the directory slots doesn't count
+        let entries: BTreeMap<String, DirectoryEntry> = self.walk("/").collect::<io::Result<_>>()?;
+
+        let mut digest: D = D::new();
+        digest.update(FINGERPRINT_VERSION);
+        for (path, entry) in &entries {
+            // Every field has a fixed size or a length before it, so that
+            // records can't run into each other
+            let kind: u8 = if entry.is_directory() { b'D' } else { b'F' };
+            digest.update(&[kind]);
+            digest.update(&(path.len() as u32).to_le_bytes());
+            digest.update(path.as_bytes());
+            if entry.is_directory() { continue; }
+
+            let data: Vec<u8> = self.read_file(&format!("/{}", path))?;
+            digest.update(&(data.len() as u64).to_le_bytes());
+            digest.update(&data);
+        }
+        Ok(digest.finalize())
+    }
+}
@@ -0,0 +1,1567 @@
+use std::{collections::BTreeMap, fs::{File, OpenOptions}, io::{self, Seek, SeekFrom, Write}, mem, path::{Path, PathBuf}, sync::{Arc, Mutex, MutexGuard}};
+
+use crate::audit::{AuditRecord, Mutation};
+use crate::bpb::Fat32Fields;
+use crate::cache::{CacheHandle, SectorCache};
+use crate::codepage::{Codepage, Unrepresentable};
+use crate::crypt::{Container, ContainerCipher};
+use crate::layout::VolumeLayout;
+use crate::recovery::FloppyFormat;
+use crate::shortname::TailStrategy;
+use crate::stats::{Counter, Counters};
+use crate::throttle::Throttle;
+use crate::unicode::NameMatching;
+use crate::usage::Placement;
+use crate::validation::{GeometryConflict, Report, Truncation};
+use crate::warning::Warning;
+use crate::{partition, recovery, validation};
+use crate::{fat_datetime_to_unix, into_struct_vec, lock_image, no_clusters, read_boot_sector, read_exact_at, read_padded_at, sanitize_short_name, short_name_to_string,
+    struct_to_bytes, to_short_name, to_short_name_char, unix_to_fat_datetime, write_all_at, BootSector, Cluster, DataStart, Directory, DirectoryEntry, Fat, Lba,
+    ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM, DELETED_ENTRY, DOT_DOT_NAME, DOT_NAME, END_OF_CHAIN, FREE_CLUSTER};
+
+/* ==== STRUCTS ============================================================= */
+/** Content of changed sectors, by address. */
+type Sectors = BTreeMap<Lba, Vec<u8>>;
+
+/** Handle to an opened image that keeps the disk, boot sector and FAT
+ *  together, so that paths can be resolved and files read and written
+ *  without passing every piece around. Clones are cheap: they share the
+ *  file, the unflushed sectors of write-back mode and the FAT, which is
+ *  copied only when a clone changes it. Write through a single one of
+ *  them, see Fat12Fs::reader for the others. */
+#[derive(Clone)]
+pub struct Fat12Fs {
+    pub(crate) disk: Arc<File>,
+    pub(crate) boot_sector: BootSector,
+    pub(crate) fat: Arc<Fat>,
+    read_only: bool,
+
+    // Free data clusters, and the first one that may be free (every one
+    // before it is used), kept up to date as clusters are allocated and freed
+    pub(crate) free_clusters: usize,
+    pub(crate) next_free: Cluster,
+
+    // Where the filesystem starts in the disk file (0 unless partitioned)
+    pub(crate) offset: u64,
+    pub(crate) codepage: Codepage,
+    quirks: Quirks,
+    pub(crate) name_policy: NamePolicy,
+    pub(crate) validation: Validation,
+    pub(crate) limits: Limits,
+    time_source: fn() -> u64,
+
+    // Sector cache, possibly shared with other images (see Workspace)
+    cache: Option<CacheHandle>,
+
+    // Sectors changed in overlay mode, not written to the disk until commit
+    pub(crate) overlay: Option<BTreeMap<Lba, Vec<u8>>>,
+
+    // Sectors written in write-back mode, not written to the disk until
+    // flush: shared with the clones, so that a single copy of them is ever
+    // written back, the latest one
+    dirty: Option<Arc<Mutex<Sectors>>>,
+
+    // Changes made so far, if asked to keep track of them
+    audit: Option<Vec<AuditRecord>>,
+
+    // What couldn't be kept as it was, since the last take_warnings
+    warnings: Vec<Warning>,
+
+    // Clusters freed since the last FAT flush, to be zeroed by it, if asked
+    pub(crate) discard: Option<Vec<Cluster>>,
+
+    // How sanitized names are made unique, if at all
+    pub(crate) short_name_tails: Option<Arc<dyn TailStrategy>>,
+    unrepresentable: Unrepresentable,
+    slot_placement: SlotPlacement,
+    fat_mirroring: FatMirroring,
+    pub(crate) name_matching: NameMatching,
+
+    // Work done so far, shared with the clones and with the handles opened
+    // with the same counters
+    pub(crate) stats: Arc<Counters>,
+
+    // Size of an image shorter than its geometry, opened with its missing
+    // sectors read as zeros
+    pub(crate) truncation: Option<Truncation>,
+
+    // How the BPB disagreed with the media descriptor, whichever was used
+    geometry_conflict: Option<GeometryConflict>,
+
+    // Cap on the image I/O, shared with the clones and with the handles
+    // opened with the same throttle
+    throttle: Option<Arc<Throttle>>,
+
+    // Container the image was decrypted from, to encrypt it back into
+    pub(crate) container: Option<Container>,
+
+    // File the image was opened from, for the rewrites that go through a
+    // copy renamed over it. None for an already opened file
+    pub(crate) path: Option<PathBuf>
+}
+
+/** How much of the image is checked, when opening it and when reading its
+ *  directories. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Validation {
+    /** Also require the 0x55AA signature, a standard sector size, a known
+     *  media descriptor echoed by the FAT ID, a cluster count that actually
+     *  means FAT12, identical FAT copies, and directory entries with DOS
+     *  names and clusters inside the volume. */
+    Strict,
+
+    /** Only reject what would make the geometry meaningless (zero sizes)
+     *  or leave clusters without a FAT entry. */
+    #[default]
+    Normal,
+
+    /** Fix what can be guessed instead of rejecting it: zero sizes in the
+     *  boot sector, a FAT too small for the clusters, a FAT copy with a
+     *  broken ID, entries pointing outside the volume (read as empty). */
+    Lenient
+}
+
+/** Deviations from the spec, written by some systems, to be honored. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /** Windows NT keeps 8.3 names lower case by setting bits 3 (name) and
+     *  4 (extension) of the reserved byte, instead of writing a LFN. */
+    pub nt_case_bits: bool
+}
+
+/** Caps on what an image can make the filesystem read, so that a crafted
+ *  one can't make it allocate gigabytes or recurse forever. The defaults
+ *  fit any sane FAT12 volume: lower them for untrusted images. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /** Bytes of a file that can be read at once. */
+    pub max_file_size: u64,
+
+    /** Directories that can be nested, in paths and in walks. */
+    pub max_depth: usize,
+
+    /** Slots of a single directory. */
+    pub max_entries: usize,
+
+    /** Clusters of a single chain. */
+    pub max_chain_length: usize
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { max_file_size: 256 * 1024 * 1024, max_depth: 64, max_entries: 65536, max_chain_length: 4085 }
+    }
+}
+
+/** What to do, when creating entries, with names that DOS wouldn't take
+ *  as they are. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NamePolicy {
+    /** Refuse anything but a valid, upper case 8.3 name. */
+    Strict,
+
+    /** Upper case the letters, refuse anything else that isn't valid. */
+    #[default]
+    Uppercase,
+
+    /** Also replace forbidden characters with "_", drop spaces, extra dots
+     *  and leading dots, and cut the name to 8.3. */
+    Sanitize,
+
+    /** Keep names that aren't a valid, upper case 8.3 name as long names,
+     *  as Windows does: the entry gets a short name made as Sanitize makes
+     *  it, with a tail from FsOptions::short_name_tails (numeric without
+     *  one) unless only the case changed. */
+    LongNames
+}
+
+impl NamePolicy {
+    pub fn from_name(name: &str) -> Option<NamePolicy> {
+        match name {
+            "strict" => Some(NamePolicy::Strict),
+            "uppercase" => Some(NamePolicy::Uppercase),
+            "sanitize" => Some(NamePolicy::Sanitize),
+            "long-names" => Some(NamePolicy::LongNames),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            NamePolicy::Strict => "strict",
+            NamePolicy::Uppercase => "uppercase",
+            NamePolicy::Sanitize => "sanitize",
+            NamePolicy::LongNames => "long-names"
+        }
+    }
+
+    /** Raw 11 bytes of the name of a new entry, None if the policy refuses it.
+     *  Under LongNames, the basis its short name is made from. */
+    pub fn to_short_name(&self, name: &str) -> Option<[u8; 11]> {
+        match self {
+            NamePolicy::Strict => to_short_name(name).filter(|short_name| short_name_to_string(short_name) == name),
+            NamePolicy::Uppercase => to_short_name(name),
+            NamePolicy::Sanitize | NamePolicy::LongNames => sanitize_short_name(name)
+        }
+    }
+}
+
+/** Where new entries go in their directory: DOS, and the boot menus and
+ *  other programs that list the slots as they are, show them in this order. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SlotPlacement {
+    /** Reuse the first deleted or free slot. */
+    #[default]
+    FirstFree,
+
+    /** After every slot ever taken, deleted ones included, so that entries
+     *  list in the order they were created. See Fat12Fs::sort_dir. */
+    End
+}
+
+impl SlotPlacement {
+    pub fn from_name(name: &str) -> Option<SlotPlacement> {
+        match name {
+            "first-free" => Some(SlotPlacement::FirstFree),
+            "end" => Some(SlotPlacement::End),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            SlotPlacement::FirstFree => "first-free",
+            SlotPlacement::End => "end"
+        }
+    }
+}
+
+/** Which copies of the FAT are read and written. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FatMirroring {
+    /** Read the first copy, write all fat_count of them, as DOS does. */
+    #[default]
+    All,
+
+    /** Read and write only that copy, counting from 0, as FAT32 does when
+     *  its extended flags disable mirroring (they select it on volumes that
+     *  have them): the others stay as they are, e.g. as a known good state
+     *  to go back to. Copies differing fail the strict validation, see
+     *  Fat12Fs::mirror_fat to make them agree. */
+    Active(u8)
+}
+
+/** Which of the BPB and the media descriptor gives the geometry when they
+ *  disagree, see validation::GeometryConflict. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GeometrySource {
+    /** Use the BPB as it is, the conflict failing only the strict
+     *  validation. */
+    #[default]
+    Bpb,
+
+    /** Use the standard floppy format of the media descriptor, for images
+     *  whose formatter wrote a stale BPB: its sector count, cluster size,
+     *  root entries, FAT size and CHS geometry replace the BPB's. */
+    MediaDescriptor
+}
+
+impl GeometrySource {
+    pub fn from_name(name: &str) -> Option<GeometrySource> {
+        match name {
+            "bpb" => Some(GeometrySource::Bpb),
+            "media" => Some(GeometrySource::MediaDescriptor),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            GeometrySource::Bpb => "bpb",
+            GeometrySource::MediaDescriptor => "media"
+        }
+    }
+}
+
+/** Settings used to open an image, see Fat12Fs::options. */
+#[derive(Debug, Clone)]
+pub struct FsOptions {
+    pub(crate) read_only: bool,
+    partition_offset: Option<u64>,
+    validation: Validation,
+    codepage: Codepage,
+    cache_sectors: usize,
+    quirks: Quirks,
+    name_policy: NamePolicy,
+    limits: Limits,
+    time_source: fn() -> u64,
+    overlay: bool,
+    write_back: bool,
+    verify_seal: bool,
+    recover_boot_sector: bool,
+    audit: bool,
+    discard: bool,
+    short_name_tails: Option<Arc<dyn TailStrategy>>,
+    unrepresentable: Unrepresentable,
+    slot_placement: SlotPlacement,
+    fat_mirroring: FatMirroring,
+    zero_missing: bool,
+    bpb_overrides: Vec<(String, u32)>,
+    name_matching: NameMatching,
+    stats: Option<Arc<Counters>>,
+    geometry_source: GeometrySource,
+    throttle: Option<Arc<Throttle>>,
+    pub(crate) ignore_lock: bool,
+    pub(crate) cipher: Option<Arc<dyn ContainerCipher>>
+}
+
+impl FsOptions {
+    /** Open for reading only (the default) or also for writing. */
+    pub fn read_only(mut self, read_only: bool) -> FsOptions {
+        self.read_only = read_only;
+        self
+    }
+
+    /** Byte offset of the filesystem in the file, e.g. a partition start.
+     *  Without it, an MBR is told from a boot sector and its first FAT
+     *  partition is used (see partition::detect_layout). */
+    pub fn partition_offset(mut self, offset: u64) -> FsOptions {
+        self.partition_offset = Some(offset);
+        self
+    }
+
+    pub fn validation(mut self, validation: Validation) -> FsOptions {
+        self.validation = validation;
+        self
+    }
+
+    /** Code page to decode names with (CP437 by default). */
+    pub fn codepage(mut self, codepage: Codepage) -> FsOptions {
+        self.codepage = codepage;
+        self
+    }
+
+    /** Keep up to this many sectors in a private cache (0, the default, for
+     *  none). Images added to a Workspace use its shared cache instead. */
+    pub fn cache_sectors(mut self, sectors: usize) -> FsOptions {
+        self.cache_sectors = sectors;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> FsOptions {
+        self.quirks = quirks;
+        self
+    }
+
+    /** How the names of new files and directories are checked. */
+    pub fn name_policy(mut self, name_policy: NamePolicy) -> FsOptions {
+        self.name_policy = name_policy;
+        self
+    }
+
+    /** Caps on file sizes, nesting, directory slots and chain lengths. */
+    pub fn limits(mut self, limits: Limits) -> FsOptions {
+        self.limits = limits;
+        self
+    }
+
+    /** Function giving the current unix time, for the timestamps the
+     *  filesystem picks by itself (the system clock by default). */
+    pub fn time_source(mut self, time_source: fn() -> u64) -> FsOptions {
+        self.time_source = time_source;
+        self
+    }
+
+    /** Keep every change in memory, leaving the file untouched until
+     *  Fat12Fs::commit. Changes are accepted even when opened read-only:
+     *  read_only only decides whether they can be committed. */
+    pub fn overlay(mut self, overlay: bool) -> FsOptions {
+        self.overlay = overlay;
+        self
+    }
+
+    /** Keep written sectors in memory until Fat12Fs::flush (or the handle
+     *  is dropped), so that a change touching the same sectors many times
+     *  writes them once, in runs of consecutive sectors. */
+    pub fn write_back(mut self, write_back: bool) -> FsOptions {
+        self.write_back = write_back;
+        self
+    }
+
+    /** Refuse images that don't match the seal stored in their reserved
+     *  sectors, or don't have one (see Fat12Fs::seal). */
+    pub fn verify_seal(mut self, verify_seal: bool) -> FsOptions {
+        self.verify_seal = verify_seal;
+        self
+    }
+
+    /** Rebuild the boot sector from the rest of the image when it doesn't
+     *  look like one (zeroed, overwritten), so that the files can still be
+     *  read. See recovery::recover_boot_sector; nothing is written unless
+     *  Fat12Fs::restore_boot_sector is called. */
+    pub fn recover_boot_sector(mut self, recover_boot_sector: bool) -> FsOptions {
+        self.recover_boot_sector = recover_boot_sector;
+        self
+    }
+
+    /** Record every change made to the image, see Fat12Fs::get_audit_log. */
+    pub fn audit(mut self, audit: bool) -> FsOptions {
+        self.audit = audit;
+        self
+    }
+
+    /** Zero the clusters as they are freed, so that deleted data doesn't
+     *  linger on flash media (CF and SD cards in vintage machines) and sparse
+     *  image files can drop it. See Fat12Fs::trim for a later pass instead. */
+    pub fn discard(mut self, discard: bool) -> FsOptions {
+        self.discard = discard;
+        self
+    }
+
+    /** Give the names Sanitize changes a "~N" or hash tail picked by the
+     *  strategy, so that they don't replace each other (None, the default,
+     *  only cuts them to 8.3). See shortname::NumericTail and HashTail. */
+    pub fn short_name_tails(mut self, tails: Option<Arc<dyn TailStrategy>>) -> FsOptions {
+        self.short_name_tails = tails;
+        self
+    }
+
+    /** Whether names with characters they can't hold are refused or get
+     *  codepage::REPLACEMENT instead (the default), see Unrepresentable. */
+    pub fn unrepresentable(mut self, unrepresentable: Unrepresentable) -> FsOptions {
+        self.unrepresentable = unrepresentable;
+        self
+    }
+
+    /** Which slot of their directory new entries take. */
+    pub fn slot_placement(mut self, slot_placement: SlotPlacement) -> FsOptions {
+        self.slot_placement = slot_placement;
+        self
+    }
+
+    /** Which copies of the FAT are read and written. */
+    pub fn fat_mirroring(mut self, fat_mirroring: FatMirroring) -> FsOptions {
+        self.fat_mirroring = fat_mirroring;
+        self
+    }
+
+    /** How names in paths are matched against long names, see NameMatching. */
+    pub fn name_matching(mut self, name_matching: NameMatching) -> FsOptions {
+        self.name_matching = name_matching;
+        self
+    }
+
+    /** Count the work of the handles opened with these options on the same
+     *  counters, e.g. to see the total for a command opening several images.
+     *  Without it, each handle gets its own. */
+    pub fn stats(mut self, counters: Arc<Counters>) -> FsOptions {
+        self.stats = Some(counters);
+        self
+    }
+
+    /** Open images shorter than their geometry, e.g. dumps trimmed after
+     *  the last used sector, reading the sectors they lack as zeros instead
+     *  of refusing them. See Fat12Fs::get_truncation. */
+    pub fn zero_missing(mut self, zero_missing: bool) -> FsOptions {
+        self.zero_missing = zero_missing;
+        self
+    }
+
+    /** Use that value for one of the bpb::FIELDS instead of the one in the
+     *  boot sector, for images whose BPB is known to be wrong. The image is
+     *  left as it is, but what writes the boot sector (labels, serials,
+     *  set_bpb_field) stores the overridden value too. Values set_field
+     *  refuses make open fail. */
+    pub fn bpb_override(mut self, name: &str, value: u32) -> FsOptions {
+        self.bpb_overrides.push((name.to_string(), value));
+        self
+    }
+
+    /** Which of the BPB and the media descriptor to trust when they
+     *  disagree. See Fat12Fs::get_geometry_conflict. */
+    pub fn geometry_source(mut self, geometry_source: GeometrySource) -> FsOptions {
+        self.geometry_source = geometry_source;
+        self
+    }
+
+    /** Read and write the image no faster than the throttle allows, shared
+     *  by every handle opened with it. The boot sector and the FAT read
+     *  while opening don't count. */
+    pub fn throttle(mut self, throttle: Arc<Throttle>) -> FsOptions {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /** Open for writing even when another handle holds the lock of the
+     *  image. Writable handles take an exclusive advisory lock on the file
+     *  (flock, LockFileEx) until they are dropped, and refuse to open while
+     *  another one, in this process or another, holds it: two writers each
+     *  keep their own FAT, and the last one to flush undoes the other. The
+     *  lock is only advisory, tools that don't ask for it aren't stopped;
+     *  read-only handles never take it. */
+    pub fn ignore_lock(mut self, ignore_lock: bool) -> FsOptions {
+        self.ignore_lock = ignore_lock;
+        self
+    }
+
+    /** Decrypt images stored in a container of the cipher, e.g.
+     *  crypt::Passphrase: the FAT layer works on a scratch copy in the
+     *  temporary directory, encrypted back into the container on flush (and
+     *  so when the handle goes away). Images that aren't encrypted open as
+     *  usual; encrypted ones are refused without a cipher.
+     *
+     *  The scratch copy is plaintext in a file: it's readable by the owner
+     *  only and has no name while open (Unix) or is deleted when closed
+     *  (Windows), but its sectors can still be written to the disk the
+     *  temporary directory is on, and from memory to swap, and stay there
+     *  until overwritten. Put the temporary directory on an encrypted disk
+     *  or a tmpfs without swap when that matters. */
+    pub fn cipher(mut self, cipher: Option<Arc<dyn ContainerCipher>>) -> FsOptions {
+        self.cipher = cipher;
+        self
+    }
+
+    pub fn open(&self, path: &str) -> io::Result<Fat12Fs> {
+        let disk: File = OpenOptions::new().read(true).write(!self.read_only).open(path)?;
+        if self.is_container_file(&disk)? { return self.open_container(disk, Path::new(path)); }
+        let mut fs: Fat12Fs = self.open_file(disk)?;
+        fs.path = Some(PathBuf::from(path));
+        Ok(fs)
+    }
+
+    /** Use an already opened file (it must be writable unless read-only). */
+    pub fn open_file(&self, mut disk: File) -> io::Result<Fat12Fs> {
+        if !self.read_only && !self.ignore_lock { lock_image(&disk)?; }
+        let offset: u64 = self.get_offset(&disk)?;
+        disk.seek(SeekFrom::Start(offset))?;
+        let mut boot_sector: BootSector = match self.recover_boot_sector && recovery::is_damaged(&disk, offset) {
+            true => recovery::recover_boot_sector(&disk, offset)?.boot_sector,
+            false => read_boot_sector(&mut disk)?
+        };
+        self.apply_overrides(&mut boot_sector)?;
+        let geometry_conflict: Option<GeometryConflict> = validation::check_media_geometry(&boot_sector);
+        if let Some(conflict) = geometry_conflict.as_ref().filter(|_| self.geometry_source == GeometrySource::MediaDescriptor) {
+            let media_format: &FloppyFormat = conflict.get_media_format(disk.metadata()?.len().saturating_sub(offset));
+            boot_sector.set_floppy_geometry(media_format);
+        }
+        if self.validation == Validation::Lenient { boot_sector.repair(disk.metadata()?.len().saturating_sub(offset)); }
+        self.validate(&mut disk, &boot_sector, offset)?;
+        let truncation: Option<Truncation> = validation::check_size(&disk, &boot_sector, offset)?;
+        if let Some(truncation) = truncation.filter(|_| !self.zero_missing) {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, truncation));
+        }
+        // FAT32 layouts say themselves which FAT is in use, and refuse the
+        // drivers older than their version
+        let fat32: Option<Fat32Fields> = Fat32Fields::read(&disk, offset)?;
+        if let Some(version @ (major, minor)) = fat32.map(|fields| fields.version) {
+            if version != (0, 0) { return Err(io::Error::new(io::ErrorKind::Unsupported, format!("FAT32 version {}.{}", major, minor))); }
+        }
+        let fat_mirroring: FatMirroring = match (self.fat_mirroring, fat32) {
+            (FatMirroring::All, Some(fields)) => fields.ext_flags.get_fat_mirroring(),
+            (fat_mirroring, _) => fat_mirroring
+        };
+        let fat: Fat = validation::read_fat_in_use(&disk, &boot_sector, offset, fat_mirroring, self.validation)?;
+
+        let mut fs: Fat12Fs = Fat12Fs {
+            disk: Arc::new(disk),
+            free_clusters: fat.count_free_clusters(boot_sector.get_cluster_count()),
+            next_free: Cluster(2),
+            fat: Arc::new(fat),
+            boot_sector,
+            read_only: self.read_only,
+            offset,
+            codepage: self.codepage,
+            quirks: self.quirks,
+            name_policy: self.name_policy,
+            validation: self.validation,
+            limits: self.limits,
+            time_source: self.time_source,
+            cache: None,
+            overlay: if self.overlay { Some(BTreeMap::new()) } else { None },
+            dirty: if self.write_back { Some(Arc::new(Mutex::new(BTreeMap::new()))) } else { None },
+            audit: if self.audit { Some(vec![]) } else { None },
+            warnings: vec![],
+            discard: if self.discard { Some(vec![]) } else { None },
+            short_name_tails: self.short_name_tails.clone(),
+            unrepresentable: self.unrepresentable,
+            slot_placement: self.slot_placement,
+            fat_mirroring,
+            name_matching: self.name_matching,
+            stats: self.stats.clone().unwrap_or_default(),
+            truncation,
+            geometry_conflict,
+            throttle: self.throttle.clone(),
+            container: None,
+            path: None
+        };
+        if self.cache_sectors > 0 { fs.attach_cache(SectorCache::shared(self.cache_sectors), 0); }
+        if self.verify_seal { fs.verify_seal()?; }
+        Ok(fs)
+    }
+
+    /** Refuse the image if it fails a check the validation level cares
+     *  about, with the Report of those checks as the error payload. */
+    fn validate(&self, disk: &mut File, boot_sector: &BootSector, offset: u64) -> io::Result<()> {
+        let report: Report = validation::diagnose_at(disk, boot_sector, offset, self.validation == Validation::Strict)?;
+        let refused: Report = report.refused_by(self.validation);
+        if refused.is_empty() { Ok(()) } else { Err(io::Error::new(io::ErrorKind::InvalidData, refused)) }
+    }
+
+    /** Run every check on the image as it is on disk, BPB overrides
+     *  applied but before any lenient fix, whatever the validation level:
+     *  tells why open refuses it. */
+    pub fn diagnose(&self, path: &str) -> io::Result<Report> {
+        let mut disk: File = File::open(path)?;
+        let offset: u64 = self.get_offset(&disk)?;
+        disk.seek(SeekFrom::Start(offset))?;
+        let mut boot_sector: BootSector = read_boot_sector(&mut disk)?;
+        self.apply_overrides(&mut boot_sector)?;
+        validation::diagnose_at(&mut disk, &boot_sector, offset, true)
+    }
+
+    fn apply_overrides(&self, boot_sector: &mut BootSector) -> io::Result<()> {
+        for (name, value) in &self.bpb_overrides { boot_sector.set_field(name, *value)?; }
+        Ok(())
+    }
+
+    /** Offset of the filesystem: the one given, or the detected one. */
+    fn get_offset(&self, disk: &File) -> io::Result<u64> {
+        match self.partition_offset {
+            Some(offset) => Ok(offset),
+            None => partition::detect_layout(disk).map(|layout| layout.get_offset())
+        }
+    }
+}
+
+impl Fat12Fs {
+    /** Builder to open an image with settings other than the defaults:
+     *  read-only, normal validation, CP437 names, no cache. */
+    pub fn options() -> FsOptions {
+        FsOptions {
+            read_only: true,
+            partition_offset: None,
+            validation: Validation::default(),
+            codepage: Codepage::default(),
+            cache_sectors: 0,
+            quirks: Quirks::default(),
+            name_policy: NamePolicy::default(),
+            limits: Limits::default(),
+            time_source: system_time,
+            overlay: false,
+            write_back: false,
+            verify_seal: false,
+            recover_boot_sector: false,
+            audit: false,
+            discard: false,
+            short_name_tails: None,
+            unrepresentable: Unrepresentable::default(),
+            slot_placement: SlotPlacement::default(),
+            fat_mirroring: FatMirroring::default(),
+            zero_missing: false,
+            bpb_overrides: vec![],
+            name_matching: NameMatching::default(),
+            stats: None,
+            geometry_source: GeometrySource::default(),
+            throttle: None,
+            ignore_lock: false,
+            cipher: None
+        }
+    }
+
+    /** Open the image for reading only. */
+    pub fn open(path: &str) -> io::Result<Fat12Fs> {
+        Fat12Fs::options().open(path)
+    }
+
+    /** Open the image for both reading and writing. */
+    pub fn open_rw(path: &str) -> io::Result<Fat12Fs> {
+        Fat12Fs::options().read_only(false).open(path)
+    }
+
+    /** Another handle on the image, read-only, for a different thread: a
+     *  clone sharing the file, the FAT and the sector cache, with a copy of
+     *  the overlay and the unflushed sectors as they are now. Reads don't
+     *  move the file cursor, so readers and the writer can run at the same
+     *  time; later changes made through this handle aren't seen by the
+     *  reader. */
+    pub fn reader(&self) -> Fat12Fs {
+        let mut reader: Fat12Fs = self.clone();
+        reader.read_only = true;
+        reader.dirty = self.dirty.as_ref().map(|dirty| Arc::new(Mutex::new(lock_dirty(dirty).clone())));
+        reader.audit = None;
+        reader.warnings = vec![];
+        reader
+    }
+
+    /** Serve reads through the given cache, storing sectors under the id. */
+    pub(crate) fn attach_cache(&mut self, cache: Arc<Mutex<SectorCache>>, image_id: usize) {
+        self.cache = Some(CacheHandle { cache, image_id });
+    }
+
+    /** Read and write the image through another file, giving back the one
+     *  used so far: what was cached or left unflushed of it is dropped. */
+    pub(crate) fn replace_disk(&mut self, disk: Arc<File>) -> Arc<File> {
+        if let Some(dirty) = &self.dirty { lock_dirty(dirty).clear(); }
+        if let Some(handle) = &self.cache {
+            handle.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).invalidate_image(handle.image_id);
+        }
+        mem::replace(&mut self.disk, disk)
+    }
+
+    pub fn get_boot_sector(&self) -> &BootSector {
+        &self.boot_sector
+    }
+
+    pub fn get_fat(&self) -> &Fat {
+        &self.fat
+    }
+
+    /** Byte offset of the filesystem in the image: 0 unless it was opened
+     *  inside a partition. */
+    pub fn get_partition_offset(&self) -> u64 {
+        self.offset
+    }
+
+    /** Free data clusters, without scanning the FAT. */
+    pub fn get_free_clusters(&self) -> usize {
+        self.free_clusters
+    }
+
+    /** First free data cluster, the next one to be allocated, if any. */
+    pub fn get_next_free_cluster(&self) -> Option<Cluster> {
+        self.fat.find_free_clusters(1, self.boot_sector.get_cluster_count(), self.next_free).first().copied()
+    }
+
+    /** Whether changes are refused (an overlay accepts them anyway). */
+    pub fn is_read_only(&self) -> bool {
+        self.read_only && self.overlay.is_none()
+    }
+
+    pub fn get_codepage(&self) -> Codepage {
+        self.codepage
+    }
+
+    pub fn get_name_policy(&self) -> NamePolicy {
+        self.name_policy
+    }
+
+    pub fn get_name_matching(&self) -> NameMatching {
+        self.name_matching
+    }
+
+    pub fn get_unrepresentable(&self) -> Unrepresentable {
+        self.unrepresentable
+    }
+
+    /** FAT copies read and written, as the options or the FAT32 extended
+     *  flags chose them. */
+    pub fn get_fat_mirroring(&self) -> FatMirroring {
+        self.fat_mirroring
+    }
+
+    /** Sizes of the image, if it was opened shorter than its geometry with
+     *  FsOptions::zero_missing. */
+    pub fn get_truncation(&self) -> Option<Truncation> {
+        self.truncation
+    }
+
+    /** How the BPB disagreed with its media descriptor, if it did. Whether
+     *  the volume was then read with the geometry of one or the other
+     *  follows FsOptions::geometry_source. */
+    pub fn get_geometry_conflict(&self) -> Option<&GeometryConflict> {
+        self.geometry_conflict.as_ref()
+    }
+
+    pub fn get_limits(&self) -> Limits {
+        self.limits
+    }
+
+    /** Current unix time, according to the time source of the options. */
+    pub fn now(&self) -> u64 {
+        (self.time_source)()
+    }
+
+    /** Name of the entry decoded with the code page, honoring the quirks. */
+    pub fn get_name(&self, entry: &DirectoryEntry) -> String {
+        // Volume labels are 11 characters, without the 8.3 split
+        let name: [u8; 11] = entry.get_short_name();
+        if entry.is_volume_label() { return self.codepage.decode(&name).trim_end().to_string(); }
+
+        let mut base: String = self.codepage.decode(&name[..8]).trim_end().to_string();
+        let mut extension: String = self.codepage.decode(&name[8..]).trim_end().to_string();
+        if self.quirks.nt_case_bits {
+            if entry.reserved & 0x08 != 0 { base = base.to_lowercase(); }
+            if entry.reserved & 0x10 != 0 { extension = extension.to_lowercase(); }
+        }
+        if extension.is_empty() { base } else { format!("{}.{}", base, extension) }
+    }
+
+    /* ---- Reading --------------------------------------------------------- */
+    /** Read the directory at the given path ("" or "/" for the root). */
+    pub fn read_dir(&mut self, path: &str) -> io::Result<Directory> {
+        self.read_dir_at(Cluster(0), path)
+    }
+
+    /** read_dir relative to the directory starting at the given cluster
+     *  (0 for the root). Absolute paths ignore the base, like openat does. */
+    pub(crate) fn read_dir_at(&mut self, base: Cluster, path: &str) -> io::Result<Directory> {
+        let base: Cluster = if path.starts_with(['/', '\\']) { Cluster(0) } else { base };
+        if split_path(path).count() > self.limits.max_depth { return Err(limit_exceeded(path, "depth", self.limits.max_depth)); }
+        let mut directory: Directory = self.read_directory_cluster(base)?;
+        for component in split_path(path) {
+            // The root has no dot entries: its parent is itself
+            let entry: DirectoryEntry = match component {
+                "." => continue,
+                ".." if directory.is_root() => continue,
+                ".." => directory.find_index(&DOT_DOT_NAME).map(|index| directory.entries[index]).ok_or_else(|| not_found(component))?,
+                _ => self.find_component(&directory, component)?
+            };
+            if !entry.is_directory() { return Err(not_a_directory(component)); }
+            directory = self.read_directory_entry(&entry)?;
+        }
+        Ok(directory)
+    }
+
+    /** Read the content of a subdirectory, given its entry. */
+    pub fn read_directory_entry(&mut self, entry: &DirectoryEntry) -> io::Result<Directory> {
+        match entry.get_data_start() {
+            DataStart::Root => self.read_root_dir(),
+            DataStart::Chain(cluster) => self.read_directory_cluster(cluster),
+            DataStart::Empty => Err(no_clusters(&entry.get_name()))
+        }
+    }
+
+    /** Read the directory starting at the given cluster. */
+    fn read_directory_cluster(&mut self, cluster: Cluster) -> io::Result<Directory> {
+        // A ".." entry pointing to cluster 0 refers to the root directory
+        if cluster == Cluster(0) { return self.read_root_dir(); }
+
+        let (clusters, buffer): (Vec<Cluster>, Vec<u8>) = self.read_chain(cluster)?;
+        let entry_count: usize = buffer.len() / mem::size_of::<DirectoryEntry>();
+        if entry_count > self.limits.max_entries { return Err(limit_exceeded(&format!("directory at cluster {}", cluster), "entries", self.limits.max_entries)); }
+        let mut directory: Directory = Directory { entries: into_struct_vec::<DirectoryEntry>(buffer), clusters };
+        self.check_directory(&mut directory)?;
+        Ok(directory)
+    }
+
+    /** Get the entry of the file or directory at the given path. */
+    pub fn get_entry(&mut self, path: &str) -> io::Result<DirectoryEntry> {
+        self.get_entry_at(Cluster(0), path)
+    }
+
+    pub(crate) fn get_entry_at(&mut self, base: Cluster, path: &str) -> io::Result<DirectoryEntry> {
+        // A last "." or ".." gives the dot entry as stored in the directory
+        let components: Vec<&str> = split_path(path).collect();
+        if let Some((last @ (&"." | &".."), parents)) = components.split_last() {
+            let base: Cluster = if path.starts_with(['/', '\\']) { Cluster(0) } else { base };
+            let parent: Directory = self.read_dir_at(base, &parents.join("/"))?;
+            let name: [u8; 11] = if *last == "." { DOT_NAME } else { DOT_DOT_NAME };
+            return parent.find_index(&name).map(|index| parent.entries[index]).ok_or_else(|| not_found(path));
+        }
+
+        let (parent, name): (Directory, [u8; 11]) = self.resolve_parent(base, path, NamePolicy::Uppercase)?;
+        let index: usize = parent.find_index(&name).ok_or_else(|| not_found(path))?;
+        Ok(parent.entries[index])
+    }
+
+    /** Check whether something exists at the given path. */
+    pub fn exists(&mut self, path: &str) -> bool {
+        self.get_entry(path).is_ok()
+    }
+
+    /** Read the whole content of the file at the given path. */
+    pub fn read_file(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        self.read_file_at(Cluster(0), path)
+    }
+
+    pub(crate) fn read_file_at(&mut self, base: Cluster, path: &str) -> io::Result<Vec<u8>> {
+        let entry: DirectoryEntry = self.get_entry_at(base, path)?;
+        self.read_entry_data(&entry, path)
+    }
+
+    /** Content of the file of the entry, found at the path, which only names
+     *  it in errors: names the path lookup can't take (code page characters
+     *  in a short name) are read all the same. */
+    pub(crate) fn read_entry_data(&mut self, entry: &DirectoryEntry, path: &str) -> io::Result<Vec<u8>> {
+        if entry.is_directory() { return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a directory", path))); }
+
+        // Empty files don't own any cluster: there is nothing to read
+        if entry.file_size == 0 { return Ok(vec![]); }
+        if entry.file_size as u64 > self.limits.max_file_size { return Err(limit_exceeded(path, "file size", self.limits.max_file_size)); }
+
+        // Cluster data is padded to the cluster size, cut it to the file size
+        let clusters: Vec<Cluster> = self.get_file_chain(entry)?;
+        let mut content: Vec<u8> = self.read_clusters(&clusters)?;
+        content.truncate(entry.file_size as usize);
+        if content.len() < entry.file_size as usize {
+            self.warn(Warning::ContentTruncated { path: path.to_string(), size: entry.file_size as u64, read: content.len() as u64 });
+        }
+        self.stats.add(Counter::BytesCopied, content.len() as u64);
+        Ok(content)
+    }
+
+    /** Clusters holding the data of the file: the chain, cut to as many as
+     *  the size needs. A chain too short for the size is an error, unless
+     *  the validation is lenient, which takes what the chain holds. */
+    pub fn get_file_chain(&self, entry: &DirectoryEntry) -> io::Result<Vec<Cluster>> {
+        self.stats.add(Counter::ChainsWalked, 1);
+        let mut clusters: Vec<Cluster> = match entry.get_data_start() {
+            DataStart::Chain(cluster) => self.get_chain(cluster),
+            _ => vec![]
+        };
+        if clusters.len() > self.limits.max_chain_length { return Err(limit_exceeded(&entry.get_name(), "chain length", self.limits.max_chain_length)); }
+        let cluster_size: usize = self.boot_sector.get_cluster_size();
+        let needed: usize = self.boot_sector.get_layout().chain_length(entry.file_size as u64) as usize;
+        if clusters.len() < needed && self.validation != Validation::Lenient {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: cluster chain holds {} bytes, the size is {}",
+                entry.get_name(), clusters.len() * cluster_size, entry.file_size as u64)));
+        }
+        clusters.truncate(needed);
+        Ok(clusters)
+    }
+
+    /* ---- Writing --------------------------------------------------------- */
+    /** Create or overwrite the file at the given path with the given data,
+     *  stamping it with the given unix time as last change. Gives where the
+     *  entry and the data landed. */
+    pub fn write_file(&mut self, path: &str, data: &[u8], timestamp: u64) -> io::Result<Placement> {
+        self.write_file_at(Cluster(0), path, data, timestamp)
+    }
+
+    pub(crate) fn write_file_at(&mut self, base: Cluster, path: &str, data: &[u8], timestamp: u64) -> io::Result<Placement> {
+        self.check_writable()?;
+        let (mut parent, name): (Directory, [u8; 11]) = self.resolve_parent(base, path, self.name_policy)?;
+        let last: &str = split_path(path).last().unwrap_or_default();
+        let name: [u8; 11] = self.unique_short_name(&parent, last, name)?;
+
+        // Reuse the existing entry if there is one, dropping its old data
+        let (index, mut entry, long_name, mutation): (usize, DirectoryEntry, Vec<u16>, Mutation) = match parent.find_index(&name) {
+            Some(index) => {
+                let entry: DirectoryEntry = parent.entries[index];
+                if entry.is_directory() { return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a directory", path))); }
+                (index, entry, vec![], Mutation::EntryUpdated { path: path.to_string() })
+            },
+            None => {
+                let long_name: Vec<u16> = self.make_long_name(last, &name)?;
+                let index: usize = self.allocate_named_slot(&mut parent, &long_name)?;
+                (index, DirectoryEntry::new(name, ATTR_ARCHIVE, timestamp), long_name, Mutation::EntryCreated { path: path.to_string() })
+            }
+        };
+
+        // Allocate the new chain before freeing the old one: if there is no
+        // space left the old content is still there
+        let cluster_size: usize = self.boot_sector.get_cluster_size();
+        let chain: Vec<Cluster> = self.allocate_chain(self.boot_sector.get_layout().chain_length(data.len() as u64) as usize)?;
+        self.free_chain(entry.get_first_cluster())?;
+
+        // Write the data cluster by cluster, padding the last one with zeros
+        for (cluster, chunk) in chain.iter().zip(data.chunks(cluster_size)) {
+            let mut buffer: Vec<u8> = chunk.to_vec();
+            buffer.resize(cluster_size, 0);
+            self.write_disk_at(self.boot_sector.get_cluster_start(*cluster), &buffer)?;
+        }
+        self.stats.add(Counter::BytesCopied, data.len() as u64);
+
+        // Point the entry to the new data; empty files keep cluster 0
+        entry.set_first_cluster(chain.first().copied().unwrap_or_default());
+        entry.file_size = data.len() as u32;
+        entry.attributes |= ATTR_ARCHIVE;
+        entry.set_last_change_timestamp(timestamp);
+
+        self.write_long_name(&parent, index, &name, &long_name)?;
+        self.write_directory_entry(&parent, index, &entry)?;
+        if matches!(mutation, Mutation::EntryCreated { .. }) && long_name.is_empty() { self.check_long_name(path, &name); }
+        self.check_timestamp(path, timestamp);
+        self.record(mutation);
+        self.flush_fat()?;
+        Ok(self.get_placement(&parent, index, &entry))
+    }
+
+    /** Create an empty directory at the given path, giving where it landed. */
+    pub fn create_dir(&mut self, path: &str, timestamp: u64) -> io::Result<Placement> {
+        self.create_dir_at(Cluster(0), path, timestamp)
+    }
+
+    pub(crate) fn create_dir_at(&mut self, base: Cluster, path: &str, timestamp: u64) -> io::Result<Placement> {
+        self.check_writable()?;
+        let (mut parent, name): (Directory, [u8; 11]) = self.resolve_parent(base, path, self.name_policy)?;
+        let last: &str = split_path(path).last().unwrap_or_default();
+        let name: [u8; 11] = self.unique_short_name(&parent, last, name)?;
+        if parent.find_index(&name).is_some() { return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", path))); }
+
+        let long_name: Vec<u16> = self.make_long_name(last, &name)?;
+        let index: usize = self.allocate_named_slot(&mut parent, &long_name)?;
+        let cluster: Cluster = self.allocate_chain(1)?[0];
+
+        // Every subdirectory starts with "." (itself) and ".." (the parent,
+        // which is cluster 0 if the parent is the root directory)
+        let mut dot: DirectoryEntry = DirectoryEntry::new(DOT_NAME, ATTR_DIRECTORY, timestamp);
+        dot.set_first_cluster(cluster);
+        let mut dot_dot: DirectoryEntry = DirectoryEntry::new(DOT_DOT_NAME, ATTR_DIRECTORY, timestamp);
+        dot_dot.set_first_cluster(parent.clusters.first().copied().unwrap_or_default());
+
+        let mut buffer: Vec<u8> = vec![0; self.boot_sector.get_cluster_size()];
+        buffer[..32].copy_from_slice(&struct_to_bytes(&dot));
+        buffer[32..64].copy_from_slice(&struct_to_bytes(&dot_dot));
+        self.write_disk_at(self.boot_sector.get_cluster_start(cluster), &buffer)?;
+
+        let mut entry: DirectoryEntry = DirectoryEntry::new(name, ATTR_DIRECTORY, timestamp);
+        entry.set_first_cluster(cluster);
+        self.write_long_name(&parent, index, &name, &long_name)?;
+        self.write_directory_entry(&parent, index, &entry)?;
+        if long_name.is_empty() { self.check_long_name(path, &name); }
+        self.check_timestamp(path, timestamp);
+        self.record(Mutation::EntryCreated { path: path.to_string() });
+        self.flush_fat()?;
+        Ok(self.get_placement(&parent, index, &entry))
+    }
+
+    /** Create the directory at the given path and all of its missing parents. */
+    pub fn create_dir_all(&mut self, path: &str, timestamp: u64) -> io::Result<()> {
+        let mut current: String = String::new();
+        for component in split_path(path) {
+            current = format!("{}/{}", current, component);
+            match self.get_entry(&current) {
+                Ok(entry) if entry.is_directory() => {},
+                Ok(_) => return Err(not_a_directory(component)),
+                Err(_) => { self.create_dir(&current, timestamp)?; }
+            }
+        }
+        Ok(())
+    }
+
+    /** Remove the file or empty directory at the given path. */
+    pub fn remove(&mut self, path: &str) -> io::Result<()> {
+        self.remove_at(Cluster(0), path)
+    }
+
+    pub(crate) fn remove_at(&mut self, base: Cluster, path: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let (parent, name): (Directory, [u8; 11]) = self.resolve_parent(base, path, NamePolicy::Uppercase)?;
+        let index: usize = parent.find_index(&name).ok_or_else(|| not_found(path))?;
+        let mut entry: DirectoryEntry = parent.entries[index];
+
+        if entry.is_directory() {
+            let directory: Directory = self.read_directory_entry(&entry)?;
+            if directory.entries().any(|entry| !entry.is_dot_entry()) {
+                return Err(io::Error::new(io::ErrorKind::DirectoryNotEmpty, format!("{} is not empty", path)));
+            }
+        }
+
+        // Mark the slot and its long name ones as deleted and give the
+        // clusters back
+        self.free_chain(entry.get_first_cluster())?;
+        self.remove_long_name(&parent, index)?;
+        entry.name[0] = DELETED_ENTRY;
+        self.write_directory_entry(&parent, index, &entry)?;
+        self.record(Mutation::EntryRemoved { path: path.to_string() });
+        self.flush_fat()
+    }
+
+    /** Change the read-only, hidden, system and archive flags of the entry;
+     *  the directory and volume label ones are kept as they are. */
+    pub fn set_attributes(&mut self, path: &str, attributes: u8) -> io::Result<()> {
+        self.check_writable()?;
+        let (parent, name): (Directory, [u8; 11]) = self.resolve_parent(Cluster(0), path, NamePolicy::Uppercase)?;
+        let index: usize = parent.find_index(&name).ok_or_else(|| not_found(path))?;
+        let mut entry: DirectoryEntry = parent.entries[index];
+
+        let settable: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_ARCHIVE;
+        entry.attributes = (entry.attributes & !settable) | (attributes & settable);
+        self.write_directory_entry(&parent, index, &entry)?;
+        self.record(Mutation::EntryUpdated { path: path.to_string() });
+        Ok(())
+    }
+
+    /* ---- Audit ----------------------------------------------------------- */
+    /** Changes made so far, oldest first. Empty unless FsOptions::audit. */
+    pub fn get_audit_log(&self) -> &[AuditRecord] {
+        self.audit.as_deref().unwrap_or(&[])
+    }
+
+    /** Hand over the changes made so far, starting a new log. */
+    pub fn take_audit_log(&mut self) -> Vec<AuditRecord> {
+        self.audit.as_mut().map(mem::take).unwrap_or_default()
+    }
+
+    pub(crate) fn record(&mut self, mutation: Mutation) {
+        let timestamp: u64 = (self.time_source)();
+        if let Some(audit) = &mut self.audit { audit.push(AuditRecord { timestamp, mutation }); }
+    }
+
+    /* ---- Warnings -------------------------------------------------------- */
+    /** What reads and writes couldn't keep as it was so far, oldest first:
+     *  dropped long names, clamped times, cut content. They don't fail the
+     *  operation, see Warning. */
+    pub fn get_warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /** Hand over the warnings so far, e.g. after each operation to tell
+     *  which one they come from. */
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        mem::take(&mut self.warnings)
+    }
+
+    pub(crate) fn warn(&mut self, warning: Warning) {
+        self.warnings.push(warning);
+    }
+
+    /** Warn if the entry at the path can't get that time as it is. Within
+     *  the range, FAT times only lose the odd second. */
+    pub(crate) fn check_timestamp(&mut self, path: &str, timestamp: u64) {
+        let (date, time): (u16, u16) = unix_to_fat_datetime(timestamp);
+        let clamped: u64 = fat_datetime_to_unix(date, time);
+        if clamped.abs_diff(timestamp) > 1 { self.warn(Warning::TimestampClamped { path: path.to_string(), timestamp, clamped }); }
+    }
+
+    /** Warn if a new entry for the last component of the path doesn't get
+     *  the name as given, but a short one made from it. */
+    fn check_long_name(&mut self, path: &str, name: &[u8; 11]) {
+        let last: &str = split_path(path).last().unwrap_or_default();
+        if to_short_name(last) == Some(*name) { return; }
+        self.warn(Warning::LongNameDropped { path: path.to_string(), short_name: short_name_to_string(name) });
+    }
+
+    /* ---- Overlay --------------------------------------------------------- */
+    /** Whether changes are kept in memory, see FsOptions::overlay. */
+    pub fn has_overlay(&self) -> bool {
+        self.overlay.is_some()
+    }
+
+    /** Number of sectors changed in the overlay and not committed yet. */
+    pub fn get_overlay_size(&self) -> usize {
+        self.overlay.as_ref().map_or(0, BTreeMap::len)
+    }
+
+    /** Write the changes kept in the overlay to the file. The overlay stays
+     *  on, empty, for the changes to come. */
+    pub fn commit(&mut self) -> io::Result<()> {
+        if self.read_only { return Err(io::Error::new(io::ErrorKind::PermissionDenied, "image is opened read-only, changes can't be committed")); }
+        let Some(overlay) = self.overlay.take() else { return Ok(()) };
+
+        let result: io::Result<()> = self.write_sectors(&overlay);
+        self.overlay = Some(BTreeMap::new());
+        result?;
+        self.record(Mutation::Committed { sectors: overlay.len() });
+        (&*self.disk).flush()?;
+        self.seal_container()
+    }
+
+    /** Drop the changes kept in the overlay, going back to the file content. */
+    pub fn discard(&mut self) -> io::Result<()> {
+        let Some(overlay) = &mut self.overlay else { return Ok(()) };
+        let sectors: usize = overlay.len();
+        overlay.clear();
+        self.record(Mutation::Discarded { sectors });
+
+        // The FAT in memory has the changes too: read back the one in use
+        self.fat = Arc::new(validation::read_fat_in_use(&self.disk, &self.boot_sector, self.offset, self.fat_mirroring, self.validation)?);
+        self.free_clusters = self.fat.count_free_clusters(self.boot_sector.get_cluster_count());
+        self.next_free = Cluster(2);
+        Ok(())
+    }
+
+    /* ---- Write-back ------------------------------------------------------ */
+    /** Number of sectors written in write-back mode and not flushed yet. */
+    pub fn get_dirty_size(&self) -> usize {
+        self.dirty.as_ref().map_or(0, |dirty| lock_dirty(dirty).len())
+    }
+
+    /** Write the sectors kept in write-back mode to the file, like
+     *  File::flush, then encrypt the image back into its container if it
+     *  was decrypted from one. Nothing to do for read-only handles, nor
+     *  without write-back or a container. */
+    pub fn flush(&mut self) -> io::Result<()> {
+        if self.read_only { return Ok(()); }
+        if let Some(dirty) = self.dirty.clone() {
+            let sectors: BTreeMap<Lba, Vec<u8>> = mem::take(&mut *lock_dirty(&dirty));
+            if let Err(e) = self.write_sectors(&sectors) {
+                // Sectors that couldn't be written stay dirty, for the next
+                // attempt, unless a clone changed them again in the meantime
+                let mut dirty = lock_dirty(&dirty);
+                for (sector, data) in sectors { dirty.entry(sector).or_insert(data); }
+                return Err(e);
+            }
+            (&*self.disk).flush()?;
+        }
+        self.seal_container()
+    }
+
+    /** Write the FAT in use to every copy, e.g. to make them agree again
+     *  after working on a single one, see FatMirroring::Active. */
+    pub fn mirror_fat(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+        let layout: VolumeLayout = self.boot_sector.get_layout();
+        let entries: Vec<u8> = self.fat.entries.clone();
+        for i in 0..layout.fat_count {
+            self.write_disk_at(layout.fat_copy_start(i), &entries)?;
+        }
+        self.flush()
+    }
+
+    /** Flush, then wait for the data to reach the device, like
+     *  File::sync_all. */
+    pub fn sync_all(&mut self) -> io::Result<()> {
+        self.flush()?;
+        if self.read_only { return Ok(()); }
+        self.disk.sync_all()
+    }
+
+    /* ---- Internals ------------------------------------------------------- */
+    pub(crate) fn check_writable(&self) -> io::Result<()> {
+        if self.is_read_only() { return Err(io::Error::new(io::ErrorKind::PermissionDenied, "image is opened read-only")); }
+        Ok(())
+    }
+
+    /** Resolve every component of the path but the last one, starting from
+     *  the base directory, returning the parent directory and the raw name of
+     *  the last component, as the policy makes it. */
+    pub(crate) fn resolve_parent(&mut self, base: Cluster, path: &str, policy: NamePolicy) -> io::Result<(Directory, [u8; 11])> {
+        let base: Cluster = if path.starts_with(['/', '\\']) { Cluster(0) } else { base };
+        let components: Vec<&str> = split_path(path).collect();
+        let (last, parents): (&&str, &[&str]) = components.split_last().ok_or_else(|| not_found(path))?;
+        let parent: Directory = self.read_dir_at(base, &parents.join("/"))?;
+
+        // A long name stands for the short one of its entry, unless the name
+        // is a short one found in the directory
+        let short_name: io::Result<[u8; 11]> = self.make_short_name(last, policy);
+        let found: bool = short_name.as_ref().is_ok_and(|name| parent.find_index(name).is_some());
+        let name: [u8; 11] = match self.find_long_name(&parent, last).filter(|_| !found) {
+            Some(index) => parent.entries[index].get_short_name(),
+            None => short_name?
+        };
+        Ok((parent, name))
+    }
+
+    /** Find the used entry with that name, in the "NAME.EXT" form or as its
+     *  long name. */
+    fn find_component(&self, directory: &Directory, component: &str) -> io::Result<DirectoryEntry> {
+        let short_name: Option<[u8; 11]> = to_short_name(component);
+        let index: Option<usize> = short_name.and_then(|name| directory.find_index(&name)).or_else(|| self.find_long_name(directory, component));
+        match (index, short_name) {
+            (Some(index), _) => Ok(directory.entries[index]),
+            (None, Some(_)) => Err(not_found(component)),
+            (None, None) => Err(invalid_name(component))
+        }
+    }
+
+    /** Raw 11 bytes of the name as the policy makes it. Under Sanitize, the
+     *  characters a short name can't hold are replaced or refused, as
+     *  FsOptions::unrepresentable says. */
+    pub(crate) fn make_short_name(&self, name: &str, policy: NamePolicy) -> io::Result<[u8; 11]> {
+        if policy == NamePolicy::Sanitize && self.unrepresentable == Unrepresentable::Error {
+            let unrepresentable: Option<char> = name.chars()
+                .find(|&c| c != ' ' && c != '.' && u8::try_from(c).ok().and_then(to_short_name_char).is_none());
+            if let Some(c) = unrepresentable {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{:?} can't be in a short name", c)));
+            }
+        }
+        policy.to_short_name(name).ok_or_else(|| invalid_name(name))
+    }
+
+    /** Find a free slot in the directory as the slot placement says,
+     *  growing it by one cluster if it's a full subdirectory. The root
+     *  directory has a fixed size. */
+    pub(crate) fn allocate_entry_slot(&mut self, directory: &mut Directory) -> io::Result<usize> {
+        self.allocate_entry_slots(directory, 1)
+    }
+
+    /** Find `count` free slots in a row, for an entry and its long name, as
+     *  allocate_entry_slot does, growing a subdirectory by as many clusters
+     *  as it takes. Gives the first one. */
+    pub(crate) fn allocate_entry_slots(&mut self, directory: &mut Directory, count: usize) -> io::Result<usize> {
+        // Every slot from the end marker on is free, whatever it holds; a
+        // run can go past the last slot of a subdirectory, which then grows
+        let length: usize = directory.entries.len();
+        let end: usize = directory.entries.iter().position(|entry| entry.is_end()).unwrap_or(length);
+        let free = |start: &usize| (*start..(start + count).min(length)).all(|index| index >= end || directory.entries[index].is_deleted())
+            && (start + count <= length || !directory.is_root());
+        let start: usize = match self.slot_placement {
+            SlotPlacement::FirstFree => (0..=length).find(free).unwrap_or(length),
+            SlotPlacement::End => end
+        };
+        if start + count <= length {
+            // Taking the end marker: the slot after the run becomes the end,
+            // whatever it held, so that the stale entries after it stay hidden
+            if start + count > end && directory.entries.get(start + count).is_some_and(|next| !next.is_end()) {
+                let mut next: DirectoryEntry = directory.entries[start + count];
+                next.name[0] = 0x00;
+                self.write_directory_entry(directory, start + count, &next)?;
+                directory.entries[start + count] = next;
+            }
+            return Ok(start);
+        }
+        if directory.is_root() { return Err(io::Error::new(io::ErrorKind::StorageFull, "root directory is full")); }
+
+        // Link new zeroed clusters at the end of the directory chain, the
+        // run going on from the free slots at its end
+        let cluster_size: usize = self.boot_sector.get_cluster_size();
+        let entries_per_cluster: usize = cluster_size / mem::size_of::<DirectoryEntry>();
+        let clusters: Vec<Cluster> = self.allocate_chain((start + count - length).div_ceil(entries_per_cluster))?;
+        let last: Cluster = *directory.clusters.last().unwrap_or(&clusters[0]);
+        Arc::make_mut(&mut self.fat).set_entry(last, clusters[0].0 as u16)?;
+        for cluster in &clusters {
+            self.write_disk_at(self.boot_sector.get_cluster_start(*cluster), &vec![0; cluster_size])?;
+            directory.clusters.push(*cluster);
+            directory.entries.extend(into_struct_vec::<DirectoryEntry>(vec![0; cluster_size]));
+        }
+        Ok(start)
+    }
+
+    /** Mark `count` free clusters as a single chain and return them. */
+    pub(crate) fn allocate_chain(&mut self, count: usize) -> io::Result<Vec<Cluster>> {
+        if count > self.free_clusters { return Err(io::Error::new(io::ErrorKind::StorageFull, "not enough free clusters")); }
+        let chain: Vec<Cluster> = self.fat.find_free_clusters(count, self.boot_sector.get_cluster_count(), self.next_free);
+        if chain.len() < count { return Err(io::Error::new(io::ErrorKind::StorageFull, "not enough free clusters")); }
+
+        // Every cluster points to the next one, the last one ends the chain
+        let fat: &mut Fat = Arc::make_mut(&mut self.fat);
+        for (i, cluster) in chain.iter().enumerate() {
+            let next: u16 = chain.get(i + 1).map(|next| next.0 as u16).unwrap_or(END_OF_CHAIN);
+            fat.set_entry(*cluster, next)?;
+        }
+        self.free_clusters -= chain.len();
+        if let Some(last) = chain.last() { self.next_free = Cluster(last.0 + 1); }
+        if !chain.is_empty() { self.record(Mutation::ClustersAllocated(chain.clone())); }
+        Ok(chain)
+    }
+
+    fn free_chain(&mut self, first_cluster: Cluster) -> io::Result<()> {
+        self.stats.add(Counter::ChainsWalked, 1);
+        let chain: Vec<Cluster> = self.get_chain(first_cluster);
+        let cluster_count: u32 = self.boot_sector.get_cluster_count() as u32;
+        let fat: &mut Fat = Arc::make_mut(&mut self.fat);
+        for cluster in &chain {
+            // A corrupt chain may end on a free cluster, or loop over itself
+            if fat.get_entry(*cluster) != FREE_CLUSTER && cluster.0 < cluster_count + 2 { self.free_clusters += 1; }
+            fat.set_entry(*cluster, FREE_CLUSTER)?;
+        }
+        if let Some(first) = chain.iter().min() { self.next_free = self.next_free.min(*first); }
+        if let Some(discard) = &mut self.discard { discard.extend(chain.iter().filter(|cluster| cluster.0 < cluster_count + 2)); }
+        if !chain.is_empty() { self.record(Mutation::ClustersFreed(chain)); }
+        Ok(())
+    }
+
+    /** Write the entry in its slot, both in memory and on disk. */
+    pub(crate) fn write_directory_entry(&mut self, directory: &Directory, index: usize, entry: &DirectoryEntry) -> io::Result<()> {
+        let offset: u64 = self.get_entry_offset(directory, index);
+        self.write_disk_at(offset, &struct_to_bytes(entry))
+    }
+
+    /** Byte offset of the slot of the entry at the given index. */
+    pub(crate) fn get_entry_offset(&self, directory: &Directory, index: usize) -> u64 {
+        let entry_size: usize = mem::size_of::<DirectoryEntry>();
+        if directory.is_root() {
+            self.boot_sector.get_root_dir_start() + (index * entry_size) as u64
+        } else {
+            let entries_per_cluster: usize = self.boot_sector.get_cluster_size() / entry_size;
+            let cluster: Cluster = directory.clusters[index / entries_per_cluster];
+            self.boot_sector.get_cluster_start(cluster) + ((index % entries_per_cluster) * entry_size) as u64
+        }
+    }
+
+    /** Write the in memory FAT over every FAT copy on disk. */
+    pub(crate) fn flush_fat(&mut self) -> io::Result<()> {
+        let layout: VolumeLayout = self.boot_sector.get_layout();
+        let entries: Vec<u8> = self.fat.entries.clone();
+        let copies: Vec<u64> = match self.fat_mirroring {
+            FatMirroring::All => (0..layout.fat_count).collect(),
+            FatMirroring::Active(index) => vec![index as u64]
+        };
+        for i in copies {
+            self.write_disk_at(layout.fat_copy_start(i), &entries)?;
+        }
+        self.discard_freed()?;
+        (&*self.disk).flush()
+    }
+
+    pub(crate) fn read_root_dir(&mut self) -> io::Result<Directory> {
+        let root_entries: usize = self.boot_sector.root_entries as usize;
+        if root_entries > self.limits.max_entries { return Err(limit_exceeded("root directory", "entries", self.limits.max_entries)); }
+        let start: u64 = self.boot_sector.get_root_dir_start();
+        let buffer: Vec<u8> = self.read_disk_at(start, self.boot_sector.get_root_dir_size() as usize)?;
+        let mut directory: Directory = Directory { entries: into_struct_vec::<DirectoryEntry>(buffer), clusters: vec![] };
+        self.check_directory(&mut directory)?;
+        Ok(directory)
+    }
+
+    /** Read every cluster of the chain in a single buffer. */
+    fn read_chain(&mut self, first_cluster: Cluster) -> io::Result<(Vec<Cluster>, Vec<u8>)> {
+        self.stats.add(Counter::ChainsWalked, 1);
+        let clusters: Vec<Cluster> = self.get_chain(first_cluster);
+        if clusters.len() > self.limits.max_chain_length { return Err(limit_exceeded(&format!("chain at cluster {}", first_cluster), "chain length", self.limits.max_chain_length)); }
+        let buffer: Vec<u8> = self.read_clusters(&clusters)?;
+        Ok((clusters, buffer))
+    }
+
+    /** Read the given clusters, in order, in a single buffer. */
+    pub(crate) fn read_clusters(&mut self, clusters: &[Cluster]) -> io::Result<Vec<u8>> {
+        let cluster_size: usize = self.boot_sector.get_cluster_size();
+
+        // Fill a single buffer cluster by cluster, so that it has the exact size
+        let mut buffer: Vec<u8> = vec![0; clusters.len() * cluster_size];
+        for (cluster, chunk) in clusters.iter().zip(buffer.chunks_mut(cluster_size)) {
+            let data: Vec<u8> = self.read_disk_at(self.boot_sector.get_cluster_start(*cluster), cluster_size)?;
+            chunk.copy_from_slice(&data);
+        }
+        Ok(buffer)
+    }
+
+    /** Read from the disk, going through the overlay and the sector cache
+     *  if there are any. */
+    pub(crate) fn read_disk_at(&mut self, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+        if self.cache.is_none() && self.overlay.is_none() && self.dirty.is_none() { return self.read_disk(offset, size); }
+
+        // Collect every sector the range touches
+        let sector_size: u64 = self.boot_sector.bytes_per_sector as u64;
+        let first_sector: u64 = offset / sector_size;
+        let last_sector: u64 = (offset + size as u64).div_ceil(sector_size);
+        let mut buffer: Vec<u8> = Vec::with_capacity(((last_sector - first_sector) * sector_size) as usize);
+        for sector in (first_sector..last_sector).map(Lba) {
+            buffer.extend_from_slice(&self.read_sector(sector)?);
+        }
+
+        // Cut the sectors down to the requested range
+        let start: usize = (offset - first_sector * sector_size) as usize;
+        Ok(buffer[start..start + size].to_vec())
+    }
+
+    /** Read a whole sector: from the overlay if it was changed there, then
+     *  from the unflushed sectors, then from the cache, then from the disk
+     *  (filling the cache). */
+    fn read_sector(&mut self, sector: Lba) -> io::Result<Vec<u8>> {
+        if let Some(data) = self.overlay.as_ref().and_then(|overlay| overlay.get(&sector)) { return Ok(data.clone()); }
+        if let Some(data) = self.dirty.as_ref().and_then(|dirty| lock_dirty(dirty).get(&sector).cloned()) { return Ok(data); }
+
+        let sector_size: usize = self.boot_sector.bytes_per_sector as usize;
+        let Some(handle) = &self.cache else { return self.read_disk(sector.to_offset(&self.boot_sector), sector_size) };
+        let mut cache = handle.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if let Some(data) = cache.get(handle.image_id, sector) {
+            self.stats.add(Counter::CacheHits, 1);
+            return Ok(data.to_vec());
+        }
+        self.stats.add(Counter::CacheMisses, 1);
+
+        let data: Vec<u8> = self.read_disk(sector.to_offset(&self.boot_sector), sector_size)?;
+        cache.insert(handle.image_id, sector, data.clone());
+        Ok(data)
+    }
+
+    /** Write to the overlay if there is one, then to the unflushed sectors
+     *  in write-back mode, to the disk otherwise. */
+    pub(crate) fn write_disk_at(&mut self, offset: u64, buffer: &[u8]) -> io::Result<()> {
+        let sector_size: u64 = self.boot_sector.bytes_per_sector as u64;
+        let end: u64 = offset + buffer.len() as u64;
+        self.record(Mutation::SectorsWritten { first: Lba(offset / sector_size), count: end.div_ceil(sector_size) - offset / sector_size });
+        if self.overlay.is_none() && self.dirty.is_none() { return self.write_disk(offset, buffer); }
+
+        // Patch every sector the range touches, starting from its current content
+        for sector in (offset / sector_size..end.div_ceil(sector_size)).map(Lba) {
+            let start: u64 = sector.to_offset(&self.boot_sector);
+            let mut data: Vec<u8> = self.read_sector(sector)?;
+            let (from, to): (u64, u64) = (offset.max(start), end.min(start + sector_size));
+            data[(from - start) as usize..(to - start) as usize].copy_from_slice(&buffer[(from - offset) as usize..(to - offset) as usize]);
+            match (&mut self.overlay, &self.dirty) {
+                (Some(overlay), _) => { overlay.insert(sector, data); },
+                (None, Some(dirty)) => { lock_dirty(dirty).insert(sector, data); },
+                (None, None) => unreachable!("overlay or write-back checked above")
+            }
+        }
+        Ok(())
+    }
+
+    /** Write whole sectors to the disk, a single write for each run of
+     *  consecutive ones. */
+    fn write_sectors(&mut self, sectors: &BTreeMap<Lba, Vec<u8>>) -> io::Result<()> {
+        let mut run: Option<(Lba, Vec<u8>)> = None;
+        for (sector, data) in sectors {
+            match &mut run {
+                Some((first, buffer)) if first.0 + (buffer.len() / data.len()) as u64 == sector.0 => buffer.extend_from_slice(data),
+                _ => {
+                    if let Some((first, buffer)) = run.take() { self.write_disk(first.to_offset(&self.boot_sector), &buffer)?; }
+                    run = Some((*sector, data.clone()));
+                }
+            }
+        }
+        if let Some((first, buffer)) = run { self.write_disk(first.to_offset(&self.boot_sector), &buffer)?; }
+        Ok(())
+    }
+
+    /** Sectors a range of bytes touches, at least partly. */
+    fn count_sectors(&self, offset: u64, size: usize) -> u64 {
+        let sector_size: u64 = self.boot_sector.bytes_per_sector as u64;
+        (offset + size as u64).div_ceil(sector_size) - offset / sector_size
+    }
+
+    /** Read straight from the file, at an offset from the filesystem start. */
+    pub(crate) fn read_disk(&self, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+        self.stats.add(Counter::SectorsRead, self.count_sectors(offset, size));
+        if let Some(throttle) = &self.throttle { throttle.wait(size as u64); }
+        let mut buffer: Vec<u8> = vec![0; size];
+        match self.truncation {
+            Some(_) => read_padded_at(&self.disk, &mut buffer, self.offset + offset)?,
+            None => read_exact_at(&self.disk, &mut buffer, self.offset + offset)?
+        }
+        Ok(buffer)
+    }
+
+    fn write_disk(&mut self, offset: u64, buffer: &[u8]) -> io::Result<()> {
+        if let Some(throttle) = &self.throttle { throttle.wait(buffer.len() as u64); }
+        write_all_at(&self.disk, buffer, self.offset + offset)?;
+        self.touch_container();
+        self.stats.add(Counter::SectorsWritten, self.count_sectors(offset, buffer.len()));
+
+        // Cached copies of the written sectors are now stale
+        if let Some(handle) = &self.cache {
+            let sector_size: u64 = self.boot_sector.bytes_per_sector as u64;
+            let mut cache = handle.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            for sector in (offset / sector_size..(offset + buffer.len() as u64).div_ceil(sector_size)).map(Lba) {
+                cache.invalidate(handle.image_id, sector);
+            }
+        }
+        Ok(())
+    }
+}
+
+/** Unflushed sectors are written when the handle goes away, as File does,
+ *  with a warning if that fails. */
+impl Drop for Fat12Fs {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() { eprintln!("warning: unflushed changes to the image are lost: {}", e); }
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Split a path on both separators, ignoring empty components. */
+pub(crate) fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split(['/', '\\']).filter(|component| !component.is_empty())
+}
+
+pub(crate) fn not_found(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+}
+
+fn not_a_directory(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::NotADirectory, format!("{} is not a directory", path))
+}
+
+/** The image asks for more than the Limits allow. */
+pub(crate) fn limit_exceeded(what: &str, limit: &str, value: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("{}: {} over the limit of {}", what, limit, value))
+}
+
+pub(crate) fn invalid_name(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a valid 8.3 name", name))
+}
+
+/** Unflushed sectors of a write-back handle, whatever a panicking clone
+ *  left them as. */
+fn lock_dirty(dirty: &Mutex<Sectors>) -> MutexGuard<'_, Sectors> {
+    dirty.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/** Default time source: the system clock, as unix seconds. */
+fn system_time() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
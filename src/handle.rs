@@ -0,0 +1,78 @@
+use std::io;
+
+use crate::usage::Placement;
+use crate::{split_path, Cluster, Directory, DirectoryEntry, Fat12Fs};
+
+/* ==== STRUCTS ============================================================= */
+/** An opened directory: paths given to its methods are resolved starting
+ *  from it instead of from the root, like openat does. Absolute paths still
+ *  start from the root. */
+pub struct DirHandle<'a> {
+    fs: &'a mut Fat12Fs,
+
+    // First cluster of the directory, 0 for the root
+    cluster: Cluster
+}
+
+impl Fat12Fs {
+    /** Open the directory at the given path ("" or "/" for the root). */
+    pub fn open_dir(&mut self, path: &str) -> io::Result<DirHandle<'_>> {
+        let cluster: Cluster = dir_cluster(self, Cluster(0), path)?;
+        Ok(DirHandle { fs: self, cluster })
+    }
+}
+
+impl DirHandle<'_> {
+    pub fn is_root(&self) -> bool {
+        self.cluster == Cluster(0)
+    }
+
+    /** Open a directory relative to this one. */
+    pub fn open_dir(&mut self, path: &str) -> io::Result<DirHandle<'_>> {
+        let cluster: Cluster = dir_cluster(self.fs, self.cluster, path)?;
+        Ok(DirHandle { fs: self.fs, cluster })
+    }
+
+    /** Read this directory ("" or ".") or one relative to it. */
+    pub fn read_dir(&mut self, path: &str) -> io::Result<Directory> {
+        self.fs.read_dir_at(self.cluster, path)
+    }
+
+    pub fn get_entry(&mut self, path: &str) -> io::Result<DirectoryEntry> {
+        self.fs.get_entry_at(self.cluster, path)
+    }
+
+    pub fn exists(&mut self, path: &str) -> bool {
+        self.get_entry(path).is_ok()
+    }
+
+    pub fn read_file(&mut self, path: &str) -> io::Result<Vec<u8>> {
+        self.fs.read_file_at(self.cluster, path)
+    }
+
+    /** Create the file, or overwrite it if it already exists. */
+    pub fn create_file(&mut self, path: &str, data: &[u8], timestamp: u64) -> io::Result<Placement> {
+        self.fs.write_file_at(self.cluster, path, data, timestamp)
+    }
+
+    pub fn create_dir(&mut self, path: &str, timestamp: u64) -> io::Result<Placement> {
+        self.fs.create_dir_at(self.cluster, path, timestamp)
+    }
+
+    pub fn remove(&mut self, path: &str) -> io::Result<()> {
+        self.fs.remove_at(self.cluster, path)
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** First cluster of the directory at the path, relative to the base one. */
+fn dir_cluster(fs: &mut Fat12Fs, base: Cluster, path: &str) -> io::Result<Cluster> {
+    // Empty paths (or just "/") refer to the base (or the root) itself
+    if split_path(path).next().is_none() {
+        return Ok(if path.starts_with(['/', '\\']) { Cluster(0) } else { base });
+    }
+
+    // Reading the directory follows . and .. as stored, cluster 0 included
+    let directory: Directory = fs.read_dir_at(base, path)?;
+    Ok(directory.get_first_cluster())
+}
@@ -0,0 +1,217 @@
+use std::{fmt, fs::File, io, mem};
+
+use crate::bpb::Fat32Fields;
+use crate::crypt;
+use crate::partition::{self, looks_like_boot_sector, Layout};
+use crate::recovery::{FloppyFormat, FLOPPY_FORMATS};
+use crate::{into_struct_vec, read_exact_at, BootSector};
+
+/** Messages of the boot code the formatters write, and who writes them. */
+const BOOT_MESSAGES: &[(&str, Formatter)] = &[
+    ("This is not a bootable disk", Formatter::MkfsFat),
+    ("BOOTMGR", Formatter::Windows),
+    ("NTLDR", Formatter::Windows),
+    ("Invalid system disk", Formatter::Windows),
+    ("Non-System disk", Formatter::Dos),
+    ("FreeDOS", Formatter::FreeDos)
+];
+
+/* ==== STRUCTS ============================================================= */
+/** Best guess at what an image is and what made it, from its size and
+ *  first sectors only, e.g. to catalog an archive of unlabeled dumps. Every
+ *  field but the size may be None: nothing here needs the image to open. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identification {
+    pub image_size: u64,
+    pub size_class: SizeClass,
+
+    /** Where the filesystem is, see partition::detect_layout. */
+    pub layout: Layout,
+
+    /** "FAT12", "FAT16" or "FAT32", as the cluster count makes the volume,
+     *  None without a boot sector. */
+    pub fat_type: Option<&'static str>,
+    pub oem_id: Option<String>,
+    pub formatter: Option<Formatter>,
+
+    /** DOS or Windows version the boot sector points to, e.g. "MS-DOS 3.3"
+     *  or "Windows 95 OSR2 or later". */
+    pub dos_version: Option<String>,
+
+    /** What each guess is based on, to tell how far to trust it. */
+    pub notes: Vec<String>
+}
+
+/** Program that formatted the volume, as its boot sector tells. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Formatter {
+    MkfsFat,
+    Mtools,
+    Windows,
+
+    /** MS-DOS or PC DOS. */
+    Dos,
+    FreeDos,
+
+    /** This crate, see provision::floppy_boot_sector. */
+    RsFat
+}
+
+/** What the size of the image makes it. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SizeClass {
+    /** One of the standard floppy formats, 160K to 2.88M or 8". */
+    Floppy(&'static FloppyFormat),
+
+    /** Up to the size of a 2.88M floppy, but none of the standard ones:
+     *  DMF and XDF formats, truncated dumps. */
+    OtherFloppy,
+
+    /** Larger: hard disks, memory cards, USB sticks. */
+    Disk
+}
+
+impl Formatter {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Formatter::MkfsFat => "mkfs.fat",
+            Formatter::Mtools => "mtools",
+            Formatter::Windows => "windows",
+            Formatter::Dos => "dos",
+            Formatter::FreeDos => "freedos",
+            Formatter::RsFat => "rs-disk-reader"
+        }
+    }
+}
+
+impl SizeClass {
+    pub fn from_size(image_size: u64) -> SizeClass {
+        let largest: u64 = FLOPPY_FORMATS.iter().map(FloppyFormat::get_size).max().unwrap_or(0);
+        match FloppyFormat::from_size(image_size) {
+            Some(format) => SizeClass::Floppy(format),
+            None if image_size <= largest => SizeClass::OtherFloppy,
+            None => SizeClass::Disk
+        }
+    }
+}
+
+impl fmt::Display for SizeClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SizeClass::Floppy(format) => write!(f, "standard {} floppy ({})", format.name, format.media),
+            SizeClass::OtherFloppy => write!(f, "floppy sized, no standard format"),
+            SizeClass::Disk => write!(f, "disk")
+        }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Identify the image at the path, see Identification. */
+pub fn identify(path: &str) -> io::Result<Identification> {
+    identify_file(&File::open(path)?)
+}
+
+pub fn identify_file(disk: &File) -> io::Result<Identification> {
+    let image_size: u64 = disk.metadata()?.len();
+    let layout: Layout = partition::detect_layout(disk)?;
+    let size_class: SizeClass = SizeClass::from_size(image_size);
+    let mut identification: Identification = Identification {
+        image_size,
+        size_class,
+        layout,
+        fat_type: None,
+        oem_id: None,
+        formatter: None,
+        dos_version: None,
+        notes: vec![format!("{} bytes: {}", image_size, size_class)]
+    };
+
+    let mut sector: [u8; 512] = [0; 512];
+    let read: bool = read_exact_at(disk, &mut sector, layout.get_offset()).is_ok();
+    if read && crypt::is_container(&sector) {
+        identification.notes.push(String::from("encrypted container, opened with its passphrase"));
+        return Ok(identification);
+    }
+    if !read || !looks_like_boot_sector(&sector) {
+        identification.notes.push(format!("no boot sector at byte {}", layout.get_offset()));
+
+        // DOS 1.x wrote no BPB: the media byte starting the FAT tells the
+        // format, with the size
+        if let SizeClass::Floppy(format) = size_class {
+            let mut fat_start: [u8; 3] = [0; 3];
+            let fat_offset: u64 = format.reserved_sectors as u64 * format.bytes_per_sector as u64;
+            if read_exact_at(disk, &mut fat_start, fat_offset).is_ok() && fat_start == [format.media_descriptor, 0xFF, 0xFF] {
+                identification.fat_type = Some("FAT12");
+                identification.notes.push(format!("FAT with media byte 0x{:02X} at byte {} and no BPB, as early DOS versions wrote the {} format: open it with recover_boot_sector",
+                    format.media_descriptor, fat_offset, format.name));
+            }
+        }
+        return Ok(identification);
+    }
+    let boot_sector: BootSector = into_struct_vec::<BootSector>(sector[..mem::size_of::<BootSector>()].to_vec())[0];
+    identification.fat_type = Some(match Fat32Fields::parse(&sector) {
+        Some(_) => "FAT32",
+        None => boot_sector.get_geometry().get_fat_type()
+    });
+    if let Some(format) = FloppyFormat::from_geometry(boot_sector.bytes_per_sector, boot_sector.total_sectors()) {
+        identification.notes.push(format!("BPB geometry of the {} format, {}", format.name, format.media));
+    }
+
+    // Printable OEM IDs name the formatter or the DOS version, the boot code
+    // messages tell the ones that share an OEM ID apart
+    let oem_bytes: [u8; 8] = boot_sector.oem_id;
+    let oem_id: String = String::from_utf8_lossy(&oem_bytes).trim_end().to_string();
+    let message: Option<&(&str, Formatter)> = BOOT_MESSAGES.iter().find(|(text, _)| contains(&sector, text.as_bytes()));
+    if let Some((text, _)) = message { identification.notes.push(format!("boot code says \"{}\"", text)); }
+    let oem_formatter: Option<Formatter> = get_oem_formatter(&oem_id);
+    if oem_formatter.is_some() { identification.notes.push(format!("OEM ID {}", oem_id)); }
+    identification.formatter = oem_formatter.or(message.map(|(_, formatter)| *formatter));
+    if oem_id == "MSWIN4.1" {
+        identification.notes.push(String::from("MSWIN4.1 is the OEM ID the spec recommends, formatters other than Windows write it too"));
+    }
+
+    identification.dos_version = get_dos_version(&oem_id, message.map(|(text, _)| *text));
+    if identification.dos_version.is_none() && matches!(sector[0x26], 0x28 | 0x29) && identification.fat_type != Some("FAT32") {
+        identification.dos_version = Some(String::from("DOS 4.0 or later"));
+        identification.notes.push(format!("extended boot signature 0x{:02X}", sector[0x26]));
+    }
+    identification.oem_id = Some(oem_id);
+    Ok(identification)
+}
+
+/* ==== UTILS =============================================================== */
+/** Formatter whose OEM ID nobody else writes. */
+fn get_oem_formatter(oem_id: &str) -> Option<Formatter> {
+    match oem_id {
+        _ if oem_id.starts_with("mkfs.fat") || oem_id.starts_with("mkdosfs") => Some(Formatter::MkfsFat),
+        _ if oem_id.starts_with("MTOO") => Some(Formatter::Mtools),
+        _ if oem_id.starts_with("RSFAT") => Some(Formatter::RsFat),
+        _ if oem_id.starts_with("FRDOS") || oem_id.starts_with("FreeDOS") => Some(Formatter::FreeDos),
+        _ if oem_id.starts_with("IBM ") => Some(Formatter::Dos),
+        _ => None
+    }
+}
+
+/** Version the OEM ID names, e.g. "IBM  3.3", told apart by the boot code
+ *  message for the "MSDOS5.0" that Windows NT and later write too. */
+fn get_dos_version(oem_id: &str, message: Option<&str>) -> Option<String> {
+    let version: &str = oem_id.get(oem_id.len().saturating_sub(3)..).filter(|version| {
+        let bytes: &[u8] = version.as_bytes();
+        bytes.len() == 3 && bytes[0].is_ascii_digit() && bytes[1] == b'.' && bytes[2].is_ascii_digit()
+    })?;
+    Some(match oem_id {
+        "MSWIN4.1" => String::from("Windows 95 OSR2 or later"),
+        "MSWIN4.0" => String::from("Windows 95"),
+        "MSDOS5.0" if message == Some("NTLDR") => String::from("Windows NT to XP"),
+        "MSDOS5.0" if message == Some("BOOTMGR") => String::from("Windows Vista or later"),
+        "MSDOS5.0" => String::from("MS-DOS 5.0 or later"),
+        _ if oem_id.starts_with("MSDOS") => format!("MS-DOS {}", version),
+        _ if oem_id.starts_with("IBM ") => format!("PC DOS {}", version),
+        _ if oem_id.starts_with("FRDOS") => format!("FreeDOS {}", version),
+        _ => return None
+    })
+}
+
+fn contains(data: &[u8], text: &[u8]) -> bool {
+    data.windows(text.len()).any(|window| window == text)
+}
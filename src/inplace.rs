@@ -0,0 +1,100 @@
+use std::{io, sync::Arc};
+
+use crate::audit::Mutation;
+use crate::layout::VolumeLayout;
+use crate::stats::Counter;
+use crate::usage::Placement;
+use crate::{not_found, Cluster, Directory, DirectoryEntry, Fat12Fs, NamePolicy, ATTR_ARCHIVE};
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Add the data at the end of the file, e.g. a log kept by an emulated
+     *  guest: clusters get linked to the end of the chain, the ones already
+     *  there only have their free tail written. See write_at. */
+    pub fn append(&mut self, path: &str, data: &[u8], timestamp: u64) -> io::Result<Placement> {
+        let size: u32 = self.get_entry(path)?.file_size;
+        self.write_at(path, size as u64, data, timestamp)
+    }
+
+    /** Write the data into the file at that offset, e.g. to patch a binary,
+     *  touching only the clusters it falls in. Writing past the end grows
+     *  the file, any gap reading as zeros. The file can't grow past the
+     *  max_file_size of the limits, so that it stays readable. */
+    pub fn write_at(&mut self, path: &str, offset: u64, data: &[u8], timestamp: u64) -> io::Result<Placement> {
+        self.check_writable()?;
+        let (parent, index, mut entry): (Directory, usize, DirectoryEntry) = self.find_file(path)?;
+        let size: u64 = entry.file_size as u64;
+        let new_size: u64 = size.max(offset + data.len() as u64);
+        if new_size > self.limits.max_file_size.min(u32::MAX as u64) {
+            return Err(io::Error::new(io::ErrorKind::FileTooLarge, format!("{} would grow to {} bytes", path, new_size)));
+        }
+
+        // Added clusters are zeroed, only the old last one may hold stale
+        // bytes after the end of the file
+        let chain: Vec<Cluster> = self.extend_chain(&mut entry, new_size)?;
+        let cluster_size: u64 = self.boot_sector.get_cluster_size() as u64;
+        let gap_end: u64 = offset.min(size.div_ceil(cluster_size) * cluster_size);
+        if gap_end > size { self.write_range(&chain, size, &vec![0; (gap_end - size) as usize])?; }
+        self.write_range(&chain, offset, data)?;
+        self.stats.add(Counter::BytesCopied, data.len() as u64);
+
+        entry.file_size = new_size as u32;
+        entry.attributes |= ATTR_ARCHIVE;
+        entry.set_last_change_timestamp(timestamp);
+        self.write_directory_entry(&parent, index, &entry)?;
+        self.check_timestamp(path, timestamp);
+        self.record(Mutation::EntryUpdated { path: path.to_string() });
+        self.flush_fat()?;
+        Ok(self.get_placement(&parent, index, &entry))
+    }
+
+    /** Directory, slot and entry of the file at the path. */
+    fn find_file(&mut self, path: &str) -> io::Result<(Directory, usize, DirectoryEntry)> {
+        let (parent, name): (Directory, [u8; 11]) = self.resolve_parent(Cluster(0), path, NamePolicy::Uppercase)?;
+        let index: usize = parent.find_index(&name).ok_or_else(|| not_found(path))?;
+        let entry: DirectoryEntry = parent.entries[index];
+        if entry.is_directory() { return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is a directory", path))); }
+        Ok((parent, index, entry))
+    }
+
+    /** Chain of the file, with zeroed clusters linked at its end until it
+     *  holds the size. An empty file gets its first cluster in the entry. */
+    fn extend_chain(&mut self, entry: &mut DirectoryEntry, size: u64) -> io::Result<Vec<Cluster>> {
+        self.stats.add(Counter::ChainsWalked, 1);
+        let cluster_size: usize = self.boot_sector.get_cluster_size();
+        let mut chain: Vec<Cluster> = self.get_chain(entry.get_first_cluster());
+        let layout: VolumeLayout = self.boot_sector.get_layout();
+        if (chain.len() as u64) < layout.chain_length(entry.file_size as u64) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "cluster chain shorter than the file size"));
+        }
+        let needed: usize = (layout.chain_length(size) as usize).saturating_sub(chain.len());
+        if needed == 0 { return Ok(chain); }
+
+        let added: Vec<Cluster> = self.allocate_chain(needed)?;
+        for cluster in &added {
+            self.write_disk_at(self.boot_sector.get_cluster_start(*cluster), &vec![0; cluster_size])?;
+        }
+        match chain.last() {
+            Some(last) => Arc::make_mut(&mut self.fat).set_entry(*last, added[0].0 as u16)?,
+            None => entry.set_first_cluster(added[0])
+        }
+        chain.extend(added);
+        Ok(chain)
+    }
+
+    /** Write the data at that offset of the file whose chain is given, which
+     *  must be long enough. */
+    fn write_range(&mut self, chain: &[Cluster], offset: u64, data: &[u8]) -> io::Result<()> {
+        let cluster_size: u64 = self.boot_sector.get_cluster_size() as u64;
+        let mut written: usize = 0;
+        while written < data.len() {
+            let position: u64 = offset + written as u64;
+            let within: u64 = position % cluster_size;
+            let length: usize = ((cluster_size - within) as usize).min(data.len() - written);
+            let cluster: Cluster = chain[(position / cluster_size) as usize];
+            self.write_disk_at(self.boot_sector.get_cluster_start(cluster) + within, &data[written..written + length])?;
+            written += length;
+        }
+        Ok(())
+    }
+}
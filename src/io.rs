@@ -0,0 +1,313 @@
+use std::{fs::{File, TryLockError}, io::{self, Read, Seek, SeekFrom}, mem};
+
+#[cfg(not(feature = "unsafe-fast"))]
+use crate::raw::RawStruct;
+use crate::{BootSector, Cluster, DataStart, Directory, DirectoryEntry, Fat};
+
+/* ==== METHODS ============================================================= */
+pub fn open_disk(path: &str) -> io::Result<File> {
+    File::open(path)
+}
+
+pub fn read_boot_sector(disk: &mut File) -> io::Result<BootSector> {
+    read_struct::<BootSector>(disk)
+}
+
+pub fn read_fat(disk: &mut File, boot_sector: &BootSector) -> io::Result<Fat> {
+    read_fat_at(disk, boot_sector, 0)
+}
+
+/** Read the FAT of a filesystem starting at the given byte offset. */
+fn read_fat_at(disk: &mut File, boot_sector: &BootSector, base: u64) -> io::Result<Fat> {
+
+    // Calculate fat offset and size using boot sector data
+    let fat_offset_start: u64 = boot_sector.get_fat_start();
+    let fat_size: u64 = boot_sector.get_fat_size();
+
+    // Seek the file to the correct location so that we can read the FAT
+    disk.seek(SeekFrom::Start(base + fat_offset_start))?;
+
+    // Create a Vec already filled with disk data from seeked point
+    let buffer: Vec<u8> = read_buffer(disk, fat_size as usize)?;
+
+    // Create Fat struct with the retrieved allocated data pointer
+    // Give Vec ownership to the struct so that it can write to the data
+    Ok( Fat { entries: buffer } )
+}
+
+pub fn read_root_directory(disk: &mut File, boot_sector: &BootSector) -> io::Result<Directory> {
+
+    // Calculate fat offset and size using boot sector data
+    let start: u64 = boot_sector.get_root_dir_start();
+    let size: usize = boot_sector.get_root_dir_size() as usize;
+
+    // Seek the file to the correct location so that we can read the FAT
+    disk.seek(SeekFrom::Start(start))?;
+
+    // Create a Vec already filled with disk data from seeked point
+    let temp_buffer: Vec<u8> = read_buffer(disk, size)?;
+
+    // Transmute the Vec<u8> into Vec<MyStruct>
+    let buffer: Vec<DirectoryEntry> = into_struct_vec::<DirectoryEntry>(temp_buffer);
+
+    // Create Fat struct with the retrieved allocated data pointer
+    // Give Vec ownership to the struct so that it can write to the data
+    Ok( Directory { entries: buffer, clusters: vec![] } )
+}
+
+/** Read a subdirectory: unlike the root, its entries are stored in a
+ *  cluster chain just like file content. */
+pub fn read_directory(disk: &mut File, entry: &DirectoryEntry, fat: &Fat, boot_sector: &BootSector) -> io::Result<Directory> {
+
+    let clusters: Vec<Cluster> = match entry.get_data_start() {
+        DataStart::Root => return read_root_directory(disk, boot_sector),
+        DataStart::Chain(cluster) => fat.get_chain(cluster, boot_sector.get_cluster_count()),
+        DataStart::Empty => return Err(no_clusters(&entry.get_name()))
+    };
+    let cluster_size: usize = boot_sector.get_cluster_size();
+
+    // Fill a single buffer cluster by cluster, so that it has the exact size
+    let mut temp_buffer: Vec<u8> = vec![0; clusters.len() * cluster_size];
+    for (cluster, chunk) in clusters.iter().zip(temp_buffer.chunks_mut(cluster_size)) {
+        disk.seek(SeekFrom::Start(boot_sector.get_cluster_start(*cluster)))?;
+        disk.read_exact(chunk)?;
+    }
+
+    Ok( Directory { entries: into_struct_vec::<DirectoryEntry>(temp_buffer), clusters } )
+}
+
+/** Content of the file of the entry, whole clusters of it. A chain too
+ *  short for the size is an UnexpectedEof error: Fat12Fs::read_file, which
+ *  follows the validation level, can give what such a chain holds. */
+pub fn read_entry_content(disk: &mut File, entry: &DirectoryEntry, fat: &Fat, boot_sector: &BootSector) -> io::Result<Vec<u8>> {
+
+    // Empty files keep cluster 0, which is not a data cluster: nothing to read
+    let first_cluster: Cluster = match entry.get_data_start() {
+        DataStart::Chain(cluster) if entry.file_size != 0 => cluster,
+        _ => return Ok(vec![])
+    };
+
+    // Get the size of the disk data that needs to be read
+    let cluster_size: usize = boot_sector.get_cluster_size();
+
+    // Setup data accumulator and temporary buffer
+    let mut accumulator: Vec<u8> = vec![];
+    let mut temp_buffer: Vec<u8>;
+
+    // Follow the chain until the end of chain marker, which get_chain stops
+    // at, like it stops at free or reserved clusters found in the way, but
+    // no further than the clusters the file size needs
+    let needed: usize = boot_sector.get_layout().chain_length(entry.file_size as u64) as usize;
+    for current_cluster in fat.get_chain(first_cluster, boot_sector.get_cluster_count()).into_iter().take(needed) {
+        // Get offset of the given cluster in the disk
+        let cluster_offset_start: u64 = boot_sector.get_cluster_start(current_cluster);
+
+        // Seek the file to the correct location so that we can read the file
+        disk.seek(SeekFrom::Start(cluster_offset_start))?;
+
+        // Create a Vec already filled with disk data from seeked point
+        temp_buffer = read_buffer(disk, cluster_size)?;
+
+        // Concatenate previously retrieved data with the new data
+        // Values are moved but ownership is given to accumulator again
+        accumulator = [accumulator, temp_buffer].concat();
+    }
+
+    // The chain ended before the size: don't pass a part for the whole
+    if accumulator.len() < needed * cluster_size {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{}: cluster chain holds {} bytes, the size is {}",
+            entry.get_name(), accumulator.len(), entry.file_size as u64)));
+    }
+
+    // Return the accumulated data
+    Ok(accumulator)
+}
+
+/* ==== UTILS =============================================================== */
+/** A subdirectory (other than "..") whose entry points to no cluster: it
+ *  can't be the root, so it has no content at all. */
+pub(crate) fn no_clusters(name: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("directory {} has no clusters", name))
+}
+
+/** Read from file and fill bytebuffer of given size with the retrieved data. */
+pub(crate) fn read_buffer(disk: &mut File, size: usize) -> io::Result<Vec<u8>> {
+    // Buffer size known at run time: allocated in the heap
+    // Create an uninitialized Vec, initialize bytes with resize to 0 fill it
+    //* let mut buffer = Vec::with_capacity(buffer_size_runtime);
+    //* buffer.resize(buffer_size_runtime, 0);
+
+    // Create a Vec already filled with 0
+    let mut buffer: Vec<u8> = vec![0; size];
+
+    // Popolate the buffer with the first chunk of file content
+    disk.read_exact(&mut buffer)?;
+
+    // Print out buffer content
+    //* println!("Buffer: {:02X?}", buffer);
+
+    Ok(buffer)
+}
+
+/** Read at the given offset without moving the file cursor, so that the
+ *  readers sharing the file (see Fat12Fs::reader) don't get in each other's
+ *  way. */
+#[cfg(unix)]
+pub(crate) fn read_exact_at(disk: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(disk, buffer, offset)
+}
+
+/** Read at the given offset like read_exact_at, the part of the buffer past
+ *  the end of the file left as zeros. */
+pub(crate) fn read_padded_at(disk: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buffer.is_empty() {
+        match read_some_at(disk, buffer, offset) {
+            Ok(0) => { buffer.fill(0); break; }
+            Ok(read) => { buffer = &mut buffer[read..]; offset += read as u64; }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e)
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn read_some_at(disk: &File, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(disk, buffer, offset)
+}
+
+#[cfg(unix)]
+pub(crate) fn write_all_at(disk: &File, buffer: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(disk, buffer, offset)
+}
+
+// Windows only has reads and writes that move the cursor as they go
+#[cfg(windows)]
+pub(crate) fn read_exact_at(disk: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()> {
+    while !buffer.is_empty() {
+        match std::os::windows::fs::FileExt::seek_read(disk, buffer, offset)? {
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "failed to fill whole buffer")),
+            read => { buffer = &mut buffer[read..]; offset += read as u64; }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn read_some_at(disk: &File, buffer: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(disk, buffer, offset)
+}
+
+#[cfg(windows)]
+pub(crate) fn write_all_at(disk: &File, mut buffer: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buffer.is_empty() {
+        match std::os::windows::fs::FileExt::seek_write(disk, buffer, offset)? {
+            0 => return Err(io::Error::new(io::ErrorKind::WriteZero, "failed to write whole buffer")),
+            written => { buffer = &buffer[written..]; offset += written as u64; }
+        }
+    }
+    Ok(())
+}
+
+/** Read from file and fill the given struct with the retrieved data. */
+#[cfg(feature = "unsafe-fast")]
+fn read_struct<T>(disk: &mut File) -> io::Result<T> {
+
+    // Get buffer size dinamically - not known until runtime (we need the type)
+    let type_size: usize = std::mem::size_of::<T>();
+
+    // Create a Vec already filled with disk data from seeked point
+    let buffer: Vec<u8> = read_buffer(disk, type_size)?;
+
+    // Convert the buffer into the struct - Cast only isn't enought,
+    // since we have to deal with byte alignment, and it's unsafe anyway
+    // Take the buffer bytes as is, and convert pointer to our struct's pointer,
+    // "assuming" that the raw data will fit correctly in the struct fields.
+    // The Vec actually contains more data than the raw bytes of the file,
+    // such as instance metadata: the "as_ptr" returns the pointer to raw data.
+    let strct: T = unsafe { std::ptr::read_unaligned(buffer.as_ptr() as *const T) };
+
+
+    /* COMPILE TIME OPTIMIZED IMPLEMENTATION
+     * to be used when the size buffer is known - such as this case, but I wanted to try):
+
+     * Get buffer size - fixed, known at compile time knowing the type
+    let buffer_size = std::mem::size_of::<BootSector>();
+
+     * Size known at compile time: create plain array allocated in the stack
+    let mut buffer = [0; buffer_size];
+
+     * Popolate the buffer with the first chunk of file content
+    disk.read_exact(&mut buffer)?;
+
+     * Convert the raw data of the array in struct data (avoid byte alignments)
+    let strct: BootSector = unsafe { std::ptr::read_unaligned(&buffer as *const _ as *const BootSector) };
+     */
+
+    // Return the "filled" data structure
+    Ok(strct)
+}
+
+#[cfg(not(feature = "unsafe-fast"))]
+fn read_struct<T: RawStruct>(disk: &mut File) -> io::Result<T> {
+    let buffer: Vec<u8> = read_buffer(disk, mem::size_of::<T>())?;
+    Ok(struct_from_bytes(&buffer))
+}
+
+/** Build a packed struct from the first bytes of the slice. */
+#[cfg(feature = "unsafe-fast")]
+pub(crate) fn struct_from_bytes<T>(bytes: &[u8]) -> T {
+    assert!(bytes.len() >= mem::size_of::<T>(), "not enough bytes for the struct");
+    unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) }
+}
+
+#[cfg(not(feature = "unsafe-fast"))]
+pub(crate) fn struct_from_bytes<T: RawStruct>(bytes: &[u8]) -> T {
+    T::decode(&bytes[..mem::size_of::<T>()])
+}
+
+/** Reinterpret a byte buffer as a Vec of packed structs, without copying.
+ *  Only valid for repr(packed) types (alignment 1) and buffers whose length
+ *  and capacity are an exact multiple of the struct size. */
+#[cfg(feature = "unsafe-fast")]
+pub(crate) fn into_struct_vec<T>(buffer: Vec<u8>) -> Vec<T> {
+    let count: usize = buffer.len() / mem::size_of::<T>();
+
+    // Transmute the Vec<u8> into Vec<MyStruct>
+    let strcts: Vec<T> = unsafe { Vec::from_raw_parts(buffer.as_ptr() as *mut T, count, count) };
+    // let buffer: &[DirectoryEntry] = unsafe { from_raw_parts(buffer.as_ptr() as *const DirectoryEntry, count as usize) };
+
+    // Prevent the Vec<u8> from deallocating new buffer's memory
+    // This prevents .drop call, implemented in Vec with dealloc of pointed data
+    mem::forget(buffer);
+
+    strcts
+}
+
+#[cfg(not(feature = "unsafe-fast"))]
+pub(crate) fn into_struct_vec<T: RawStruct>(buffer: Vec<u8>) -> Vec<T> {
+    buffer.chunks_exact(mem::size_of::<T>()).map(T::decode).collect()
+}
+
+/** Raw bytes of a packed struct, ready to be written to disk. */
+#[cfg(feature = "unsafe-fast")]
+pub(crate) fn struct_to_bytes<T>(strct: &T) -> Vec<u8> {
+    unsafe { std::slice::from_raw_parts(strct as *const T as *const u8, mem::size_of::<T>()) }.to_vec()
+}
+
+#[cfg(not(feature = "unsafe-fast"))]
+pub(crate) fn struct_to_bytes<T: RawStruct>(strct: &T) -> Vec<u8> {
+    strct.encode()
+}
+
+/** Take the exclusive lock of an image opened for writing, see
+ *  FsOptions::ignore_lock. Where the filesystem has no locks, it's written
+ *  without one. */
+pub(crate) fn lock_image(disk: &File) -> io::Result<()> {
+    match disk.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => Err(io::Error::new(io::ErrorKind::ResourceBusy, "the image is locked by another writer")),
+        Err(TryLockError::Error(e)) if e.kind() == io::ErrorKind::Unsupported => Ok(()),
+        Err(TryLockError::Error(e)) => Err(e)
+    }
+}
@@ -0,0 +1,199 @@
+use crate::BootSector;
+
+/** Bytes of a directory entry, in the root region as in directory clusters. */
+pub const DIRECTORY_ENTRY_SIZE: u64 = 32;
+
+/** First data cluster: 0 and 1 stand for the FAT header. */
+pub const FIRST_DATA_CLUSTER: u64 = 2;
+
+/* ==== STRUCTS ============================================================= */
+/** The BPB numbers every offset of a volume follows from, widened to u64
+ *  whatever their width on disk, and the offsets themselves: where the FAT
+ *  copies, the root directory and each cluster are, as the FAT spec lays
+ *  them out. Nothing here reads the image, and the read and write paths
+ *  all go through it (see BootSector::get_layout).
+ *
+ *  Zero sizes, which only a corrupt BPB has, make counts 0 instead of
+ *  dividing by zero. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VolumeLayout {
+    pub bytes_per_sector: u64,
+    pub sectors_per_cluster: u64,
+    pub reserved_sectors: u64,
+    pub fat_count: u64,
+    pub sectors_per_fat: u64,
+    pub root_entries: u64,
+    pub total_sectors: u64
+}
+
+/** Logical block address: index of a sector from the start of the
+ *  filesystem (the boot sector is 0). */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct Lba(pub u64);
+
+impl Lba {
+    /** Sector containing the given byte offset. */
+    pub fn from_offset(offset: u64, boot_sector: &BootSector) -> Lba {
+        Lba(offset / boot_sector.bytes_per_sector as u64)
+    }
+
+    /** Byte offset of the start of the sector. */
+    pub fn to_offset(&self, boot_sector: &BootSector) -> u64 {
+        self.0 * boot_sector.bytes_per_sector as u64
+    }
+}
+
+impl std::fmt::Display for Lba {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/** Cylinder, head and sector address, as INT 13h reads take it. Cylinders
+ *  and heads count from 0, sectors from 1. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Chs {
+    pub cylinder: u32,
+    pub head: u32,
+    pub sector: u32
+}
+
+impl Lba {
+    /** Address on the disk with the BPB geometry (sectors_per_cylinder is the
+     *  sectors of a track), counting the hidden sectors before the volume.
+     *  None if the BPB has no geometry. */
+    pub fn to_chs(&self, boot_sector: &BootSector) -> Option<Chs> {
+        let (sectors_per_track, heads): (u64, u64) = (boot_sector.sectors_per_cylinder as u64, boot_sector.heads_count as u64);
+        if sectors_per_track == 0 || heads == 0 { return None; }
+
+        let lba: u64 = self.0 + boot_sector.hidden_sectors_count as u64;
+        Some(Chs {
+            cylinder: u32::try_from(lba / (sectors_per_track * heads)).ok()?,
+            head: ((lba / sectors_per_track) % heads) as u32,
+            sector: (lba % sectors_per_track) as u32 + 1
+        })
+    }
+}
+
+impl Chs {
+    /** Inverse of Lba::to_chs. None if the BPB has no geometry, if the head
+     *  or sector is out of it, or if the address comes before the volume. */
+    pub fn to_lba(&self, boot_sector: &BootSector) -> Option<Lba> {
+        let (sectors_per_track, heads): (u64, u64) = (boot_sector.sectors_per_cylinder as u64, boot_sector.heads_count as u64);
+        if self.sector == 0 || self.sector as u64 > sectors_per_track || self.head as u64 >= heads { return None; }
+
+        let lba: u64 = (self.cylinder as u64 * heads + self.head as u64) * sectors_per_track + self.sector as u64 - 1;
+        lba.checked_sub(boot_sector.hidden_sectors_count as u64).map(Lba)
+    }
+}
+
+impl std::fmt::Display for Chs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}/{}", self.cylinder, self.head, self.sector)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+impl VolumeLayout {
+    /** Byte offset of the first FAT, right after the reserved sectors. */
+    pub fn fat_start(&self) -> u64 {
+        self.reserved_sectors * self.bytes_per_sector
+    }
+
+    /** Bytes of each FAT copy. */
+    pub fn fat_size(&self) -> u64 {
+        self.sectors_per_fat * self.bytes_per_sector
+    }
+
+    /** Byte offset of a FAT copy, the first one being 0. */
+    pub fn fat_copy_start(&self, copy: u64) -> u64 {
+        self.fat_start() + copy * self.fat_size()
+    }
+
+    /** Byte offset of the root directory, after every FAT copy. */
+    pub fn root_dir_start(&self) -> u64 {
+        self.fat_copy_start(self.fat_count)
+    }
+
+    pub fn root_dir_size(&self) -> u64 {
+        self.root_entries * DIRECTORY_ENTRY_SIZE
+    }
+
+    /** Sectors of the root directory, the last one possibly partly used
+     *  (RootDirSectors in the spec). */
+    pub fn root_dir_sectors(&self) -> u64 {
+        div_ceil(self.root_dir_size(), self.bytes_per_sector)
+    }
+
+    /** First sector of the data area, that of cluster 2 (FirstDataSector in
+     *  the spec). */
+    pub fn data_start_sector(&self) -> u64 {
+        self.reserved_sectors + self.fat_count * self.sectors_per_fat + self.root_dir_sectors()
+    }
+
+    /** Byte offset of the data area. */
+    pub fn data_start(&self) -> u64 {
+        self.data_start_sector() * self.bytes_per_sector
+    }
+
+    pub fn cluster_size(&self) -> u64 {
+        self.sectors_per_cluster * self.bytes_per_sector
+    }
+
+    /** Data clusters: whole clusters in the sectors after the root
+     *  directory, the ones left over at the end belonging to none. */
+    pub fn cluster_count(&self) -> u64 {
+        self.total_sectors.saturating_sub(self.data_start_sector()).checked_div(self.sectors_per_cluster).unwrap_or(0)
+    }
+
+    /** First sector of a data cluster, None for the reserved ones. Clusters
+     *  past the cluster count get the sector they would have. */
+    pub fn cluster_lba(&self, cluster: u64) -> Option<u64> {
+        let index: u64 = cluster.checked_sub(FIRST_DATA_CLUSTER)?;
+        Some(self.data_start_sector() + index * self.sectors_per_cluster)
+    }
+
+    /** Byte offset of a data cluster, None for the reserved ones. */
+    pub fn cluster_offset(&self, cluster: u64) -> Option<u64> {
+        self.cluster_lba(cluster).map(|lba| lba * self.bytes_per_sector)
+    }
+
+    /** Data cluster holding the sector, None before the data area and past
+     *  the last cluster. */
+    pub fn lba_cluster(&self, lba: u64) -> Option<u64> {
+        let index: u64 = lba.checked_sub(self.data_start_sector())?.checked_div(self.sectors_per_cluster)?;
+        if index >= self.cluster_count() { return None; }
+        Some(index + FIRST_DATA_CLUSTER)
+    }
+
+    /** Clusters a chain needs to hold that many bytes. */
+    pub fn chain_length(&self, size: u64) -> u64 {
+        div_ceil(size, self.cluster_size())
+    }
+
+    /** Sectors each FAT copy needs for the cluster count, 12 bits an entry
+     *  for the data clusters and the 2 entries of the header. */
+    pub fn needed_fat_sectors(&self) -> u64 {
+        let bytes: u64 = ((self.cluster_count() + FIRST_DATA_CLUSTER) * 3).div_ceil(2);
+        div_ceil(bytes, self.bytes_per_sector)
+    }
+
+    /** Bytes of a volume with this layout and that many clusters. */
+    pub fn volume_size(&self, cluster_count: u64) -> u64 {
+        (self.data_start_sector() + cluster_count * self.sectors_per_cluster) * self.bytes_per_sector
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Byte of the FAT where the 12 bit entry of the cluster starts, and
+ *  whether it is an odd one: odd entries take the upper nibble of that
+ *  byte and the whole next one, even entries the whole byte and the lower
+ *  nibble of the next one. */
+pub fn fat_entry_offset(cluster: u64) -> (u64, bool) {
+    (cluster * 3 / 2, cluster & 1 == 1)
+}
+
+/** Units of that size needed to hold the bytes, 0 for a zero size. */
+fn div_ceil(bytes: u64, size: u64) -> u64 {
+    if size == 0 { 0 } else { bytes.div_ceil(size) }
+}
@@ -0,0 +1,174 @@
+use std::{io, mem};
+
+use crate::codepage::{decode_ucs2, encode_ucs2};
+use crate::unicode;
+use crate::{invalid_name, struct_from_bytes, struct_to_bytes, Directory, DirectoryEntry, Fat12Fs, NamePolicy, ATTR_LFN, DELETED_ENTRY};
+
+/** Flag of the sequence number of the slot holding the end of a long name,
+ *  which comes first in the directory. */
+pub const LAST_SLOT: u8 = 0x40;
+
+/** Offsets in a slot of the 13 UCS-2 characters it holds, in order. */
+const CHARACTER_OFFSETS: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+
+/** Slots of the longest name, 255 characters. */
+const MAX_SLOTS: usize = 20;
+
+/** Characters Windows refuses in long names, besides the control ones. */
+const FORBIDDEN_CHARACTERS: &str = "\"*/:<>?\\|";
+
+/* ==== STRUCTS ============================================================= */
+/** A file or directory with both its names, for tools that reason about the
+ *  two namespaces at once: deduplication, renames keeping the 8.3 names. */
+#[derive(Debug, Clone)]
+pub struct NamePair {
+    /** Long name, None without one or when the slots before the entry don't
+     *  belong to it (broken sequence or checksum), as Windows then ignores
+     *  them. */
+    pub long_name: Option<String>,
+
+    /** 8.3 name as DOS shows it, e.g. "LONGFI~1.TXT". */
+    pub short_name: String,
+    pub entry: DirectoryEntry,
+
+    /** Slot of the entry in the directory, its long name slots being the
+     *  ones right before it. */
+    pub index: usize,
+
+    /** Raw long name slots, in directory order (the end of the name first),
+     *  empty without a long name. */
+    pub lfn_slots: Vec<DirectoryEntry>
+}
+
+impl NamePair {
+    /** Long name if there is one, the short one otherwise, as Windows
+     *  shows the entry. */
+    pub fn get_display_name(&self) -> &str {
+        self.long_name.as_deref().unwrap_or(&self.short_name)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Checksum of the raw 11 bytes of a short name, which every slot of its
+ *  long name carries. */
+pub fn checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0, |sum: u8, byte| sum.rotate_right(1).wrapping_add(*byte))
+}
+
+/** Long name the slots before the entry give it, in directory order. None
+ *  unless they are a whole sequence, from the one flagged LAST_SLOT down to
+ *  1, all with the checksum of the entry. */
+pub fn decode_long_name(lfn_slots: &[DirectoryEntry], entry: &DirectoryEntry) -> Option<String> {
+    if lfn_slots.is_empty() || lfn_slots.len() > MAX_SLOTS { return None; }
+    let checksum: u8 = checksum(&entry.name);
+    let mut units: Vec<u16> = Vec::with_capacity(lfn_slots.len() * CHARACTER_OFFSETS.len());
+    for (position, slot) in lfn_slots.iter().enumerate().rev() {
+        let bytes: Vec<u8> = struct_to_bytes(slot);
+        let sequence: u8 = (lfn_slots.len() - position) as u8 | if position == 0 { LAST_SLOT } else { 0 };
+        if bytes[0] != sequence || bytes[13] != checksum { return None; }
+        units.extend(CHARACTER_OFFSETS.iter().map(|offset| u16::from_le_bytes([bytes[*offset], bytes[offset + 1]])));
+    }
+    Some(decode_ucs2(&units))
+}
+
+/** Slots holding the UCS-2 long name of the entry with the short name, in
+ *  directory order, as decode_long_name reads them: the name ends with a
+ *  0x0000 unless it fills the last slot, which is padded with 0xFFFF. */
+pub fn encode_long_name(units: &[u16], short_name: &[u8; 11]) -> Vec<DirectoryEntry> {
+    let checksum: u8 = checksum(short_name);
+    let mut units: Vec<u16> = units.to_vec();
+    if !units.len().is_multiple_of(CHARACTER_OFFSETS.len()) { units.push(0x0000); }
+    units.resize(units.len().div_ceil(CHARACTER_OFFSETS.len()) * CHARACTER_OFFSETS.len(), 0xFFFF);
+
+    let count: usize = units.len() / CHARACTER_OFFSETS.len();
+    units.chunks(CHARACTER_OFFSETS.len()).enumerate().rev().map(|(i, chunk)| {
+        let mut bytes: [u8; 32] = [0; 32];
+        bytes[0] = (i + 1) as u8 | if i + 1 == count { LAST_SLOT } else { 0 };
+        bytes[11] = ATTR_LFN;
+        bytes[13] = checksum;
+        for (unit, offset) in chunk.iter().zip(CHARACTER_OFFSETS) { bytes[offset..offset + 2].copy_from_slice(&unit.to_le_bytes()); }
+        struct_from_bytes(&bytes)
+    }).collect()
+}
+
+impl Fat12Fs {
+    /** UCS-2 long name a new entry with the name and short name gets, empty
+     *  unless the name policy is LongNames and the short name isn't the
+     *  name as it is. Characters UCS-2 can't hold go as
+     *  FsOptions::unrepresentable says. */
+    pub(crate) fn make_long_name(&self, name: &str, short_name: &[u8; 11]) -> io::Result<Vec<u16>> {
+        if self.name_policy != NamePolicy::LongNames || NamePolicy::Strict.to_short_name(name) == Some(*short_name) { return Ok(vec![]); }
+        if name.chars().any(|c| c < ' ' || FORBIDDEN_CHARACTERS.contains(c)) { return Err(invalid_name(name)); }
+        encode_ucs2(name, self.get_unrepresentable()).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {}", name, e)))
+    }
+
+    /** Take free slots in a row for a new entry and its long name, giving
+     *  the slot of the entry, right after the ones of the name. */
+    pub(crate) fn allocate_named_slot(&mut self, directory: &mut Directory, long_name: &[u16]) -> io::Result<usize> {
+        let slots: usize = long_name.len().div_ceil(CHARACTER_OFFSETS.len());
+        Ok(self.allocate_entry_slots(directory, slots + 1)? + slots)
+    }
+
+    /** Write the long name slots of the entry in the slot, before it. */
+    pub(crate) fn write_long_name(&mut self, directory: &Directory, index: usize, short_name: &[u8; 11], long_name: &[u16]) -> io::Result<()> {
+        if long_name.is_empty() { return Ok(()); }
+        let slots: Vec<DirectoryEntry> = encode_long_name(long_name, short_name);
+        for (i, slot) in slots.iter().enumerate() { self.write_directory_entry(directory, index - slots.len() + i, slot)?; }
+        Ok(())
+    }
+
+    /** Mark the long name slots of the entry in the slot as deleted. */
+    pub(crate) fn remove_long_name(&mut self, directory: &Directory, index: usize) -> io::Result<()> {
+        let Some(pair) = self.get_name_pairs(directory).into_iter().find(|pair| pair.index == index) else { return Ok(()) };
+        for slot_index in index - pair.lfn_slots.len()..index {
+            let mut slot: DirectoryEntry = directory.entries[slot_index];
+            slot.name[0] = DELETED_ENTRY;
+            self.write_directory_entry(directory, slot_index, &slot)?;
+        }
+        Ok(())
+    }
+
+    /** Files and directories of the directory at the path with both their
+     *  names, in directory order. Dot entries and the volume label are left
+     *  out. */
+    pub fn read_name_pairs(&mut self, path: &str) -> io::Result<Vec<NamePair>> {
+        let directory: Directory = self.read_dir(path)?;
+        Ok(self.get_name_pairs(&directory))
+    }
+
+    pub fn get_name_pairs(&self, directory: &Directory) -> Vec<NamePair> {
+        let mut pairs: Vec<NamePair> = vec![];
+        let mut pending: Vec<DirectoryEntry> = vec![];
+        for (index, entry) in directory.entries.iter().enumerate().take_while(|(_, entry)| !entry.is_end()) {
+            if entry.is_deleted() {
+                pending.clear();
+                continue;
+            }
+            if entry.attributes == ATTR_LFN {
+                pending.push(*entry);
+                continue;
+            }
+
+            let lfn_slots: Vec<DirectoryEntry> = mem::take(&mut pending);
+            if entry.is_dot_entry() || entry.is_volume_label() { continue; }
+            let long_name: Option<String> = decode_long_name(&lfn_slots, entry);
+            let lfn_slots: Vec<DirectoryEntry> = if long_name.is_some() { lfn_slots } else { vec![] };
+            pairs.push(NamePair {
+                long_name,
+                short_name: self.get_name(entry),
+                entry: *entry,
+                index,
+                lfn_slots
+            });
+        }
+        pairs
+    }
+
+    /** Slot of the entry whose long name matches, as FsOptions::name_matching
+     *  says. */
+    pub(crate) fn find_long_name(&self, directory: &Directory, name: &str) -> Option<usize> {
+        self.get_name_pairs(directory).into_iter()
+            .find(|pair| pair.long_name.as_deref().is_some_and(|long_name| unicode::names_match(long_name, name, self.name_matching)))
+            .map(|pair| pair.index)
+    }
+}
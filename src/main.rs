@@ -1,20 +1,1331 @@
-use std::{env::{self, Args}, fs::File};
-use rs_disk_reader::{BootSector, open_disk, read_boot_sector, Fat, read_fat, Directory, read_root_directory, DirectoryEntry, read_entry_content};
+use std::{collections::HashMap, env, fs::{self, File}, io, path::{Path, PathBuf}, sync::{Arc, OnceLock}, time::{SystemTime, UNIX_EPOCH}};
+use rs_disk_reader::{BootSector, Chs, Cluster, Lba, open_disk, read_boot_sector, Fat, read_fat, Directory, read_root_directory, DirectoryEntry, read_entry_content, Fat12Fs, FatMirroring, FsOptions, GeometrySource, Limits, format_timestamp, parse_timestamp};
+use rs_disk_reader::bpb::{self, Geometry};
+use rs_disk_reader::bulk::{self, BulkOptions, BulkReport, HostNames, NameCollision};
+use rs_disk_reader::crypt::{self, ContainerCipher, Passphrase};
+use rs_disk_reader::digest::{self, Crc32, HashAlgorithm, Md5, Sha1, Sha256};
+use rs_disk_reader::exe::{self, Executable};
+use rs_disk_reader::identify::{self, Identification};
+use rs_disk_reader::lfn::NamePair;
+use rs_disk_reader::posix::MountOptions;
+use rs_disk_reader::text::TextMode;
+use rs_disk_reader::trim::TrimReport;
+use rs_disk_reader::sync::{self, ConflictPolicy, SyncOptions, SyncReport, SyncAction};
+use rs_disk_reader::usage::{Allocation, ClusterMap, ClusterState, Extent, Placement, SlotUsage, Usage};
+use rs_disk_reader::validation::Report;
+use rs_disk_reader::metadata::{Metadata, SortKey};
+use rs_disk_reader::partition::Layout;
+use rs_disk_reader::provision::{self, Change, Template};
+use rs_disk_reader::recovery::{self, Recovery};
+use rs_disk_reader::repair::{FatRebuild, OrphanRecovery};
+use rs_disk_reader::resize::{ResizeOptions, ResizeReport};
+use rs_disk_reader::seal::Seal;
+use rs_disk_reader::span::{self, Manifest};
+use rs_disk_reader::sparse::{self, SparseReport};
+use rs_disk_reader::stats::{Counters, Stats};
+use rs_disk_reader::throttle::Throttle;
+use rs_disk_reader::volume;
+use rs_disk_reader::walk::Filter;
+use rs_disk_reader::workspace::{parse_location, DiffEntry, Workspace};
+use cli::{Command, Matches};
+use config::Config;
+use error::{CliError, Context, ErrorFormat, EXIT_DIFFERENT};
+
+/** print! to the locked stdout, giving up the command on a write error
+ *  instead of panicking: a closed pipe, e.g. `| head`, ends it quietly, see
+ *  CliError::exit. For functions returning a CliError. */
+macro_rules! out {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        crate::error::Context::context(write!(std::io::stdout().lock(), $($arg)*), "Could not write output")?
+    }};
+}
+
+/** println! to the locked stdout, as out! does. */
+macro_rules! outln {
+    ($($arg:tt)*) => {{
+        use std::io::Write as _;
+        crate::error::Context::context(writeln!(std::io::stdout().lock(), $($arg)*), "Could not write output")?
+    }};
+}
+
+mod catalog;
+mod cli;
+mod config;
+mod error;
+mod export;
+mod manifest;
+mod mtools;
+mod serve;
+mod shell;
+mod terminal;
+#[cfg(feature = "tui")]
+mod browse;
+
+/** Defaults loaded from the config files before running any command. */
+static CONFIG: OnceLock<Config> = OnceLock::new();
+
+/** Counters of every image opened, with --stats. */
+static STATS: OnceLock<Arc<Counters>> = OnceLock::new();
+
+/** Throughput limit of the command, shared by every image it opens. */
+static THROTTLE: OnceLock<Arc<Throttle>> = OnceLock::new();
+
+/** Print the warnings of lossy reads and writes as JSON lines, with
+ *  --warnings-json. */
+static WARNINGS_JSON: OnceLock<bool> = OnceLock::new();
+
+/** Write to images locked by another writer, with --force. */
+static FORCE: OnceLock<bool> = OnceLock::new();
+
+/** Passphrase of encrypted images, from --passphrase-file or
+ *  RSFAT_PASSPHRASE. */
+static PASSPHRASE: OnceLock<String> = OnceLock::new();
+
+/** Cipher built from the passphrase, shared by every image opened so that
+ *  the key of a container is derived once. */
+static CIPHER: OnceLock<Arc<dyn ContainerCipher>> = OnceLock::new();
+
+/** BPB values given on the command line, applied to every image opened. */
+static BPB_OVERRIDES: OnceLock<Vec<(String, u32)>> = OnceLock::new();
 
 /* ==== MAIN ================================================================ */
 fn main() {
-    let mut args: Args = env::args();
-    
-    args.next();
-    let image_path: String = args.next().expect( "Didn't get an image path");
-    let file_name: String = args.next().expect("Didn't get a file to be read");
-
-    let mut disk: File = open_disk(&image_path).expect("Could not open image");
-    let boot_sector: BootSector = read_boot_sector(&mut disk).expect("Could not read image");
-    let fat: Fat = read_fat(&mut disk, &boot_sector).expect("Could not read FAT from image");
-    let root_directory: Directory = read_root_directory(&mut disk, &boot_sector).expect("Could not read Root Dir from image");
-    let kernel_entry: &DirectoryEntry = root_directory.get_entry(&file_name).expect("Could not find file in image");
-    let kernel_binary: Vec<u8> = read_entry_content(&mut disk, &kernel_entry, &fat, &boot_sector).expect("Could not read file from image");
-    
-    println!("File content: {:02X?}", kernel_binary);
-}
\ No newline at end of file
+    let mut args: Vec<String> = env::args().skip(1).collect();
+
+    // The profile can come from the command line or the environment
+    let mut profile: Option<String> = env::var("RSFAT_PROFILE").ok().filter(|profile| !profile.is_empty());
+    let mut errors: ErrorFormat = ErrorFormat::Text;
+    let mut overrides: Vec<(String, u32)> = vec![];
+    let mut throttle: Option<u64> = None;
+    while !args.is_empty() {
+        match args[0].as_str() {
+            "--profile" if args.len() > 1 => profile = Some(args.remove(1)),
+            "--profile" => CliError::usage("--profile needs a <name>", cli::help()).exit(errors),
+            "--quiet" => errors = ErrorFormat::Quiet,
+            "--errors-json" => errors = ErrorFormat::Json,
+            "--warnings-json" => { let _ = WARNINGS_JSON.set(true); },
+            "--stats" => { let _ = STATS.set(Arc::default()); },
+            "--force" => { let _ = FORCE.set(true); },
+            "--passphrase-file" if args.len() > 1 => match fs::read_to_string(args.remove(1)).context("Could not read passphrase file") {
+                Ok(text) => { let _ = PASSPHRASE.set(text.lines().next().unwrap_or("").to_string()); },
+                Err(e) => e.exit(errors)
+            },
+            "--passphrase-file" => CliError::usage("--passphrase-file needs a <file>", cli::help()).exit(errors),
+            "--throttle" => match args.get(1).and_then(|rate| config::parse_rate(rate)) {
+                Some(rate) => { throttle = Some(rate); args.remove(1); },
+                None => CliError::usage("--throttle needs a rate in bytes per second, e.g. 64K", cli::help()).exit(errors)
+            },
+            flag => match get_override_field(flag) {
+                Some(field) => match args.get(1).and_then(|value| parse_number(value)) {
+                    Some(value) => { overrides.push((field, value)); args.remove(1); },
+                    None => CliError::usage(format!("{} needs a number", flag), cli::help()).exit(errors)
+                },
+                None => break
+            }
+        }
+        args.remove(0);
+    }
+    let _ = BPB_OVERRIDES.set(overrides);
+    if let Some(passphrase) = env::var("RSFAT_PASSPHRASE").ok().filter(|passphrase| !passphrase.is_empty()) { let _ = PASSPHRASE.set(passphrase); }
+
+    // Called through a link named after an mtools command, e.g. mcopy
+    let program: Option<String> = env::args().next().as_deref().map(Path::new).and_then(Path::file_stem).map(|name| name.to_string_lossy().into_owned());
+    if let Some(program) = program.filter(|program| mtools::COMMANDS.contains(&program.as_str())) { args.insert(0, program); }
+
+    let result: Result<(), CliError> = run(args, profile.as_deref(), throttle);
+    if let Some(counters) = STATS.get() { print_stats(&counters.get()); }
+    if let Err(e) = result { e.exit(errors); }
+}
+
+/** Parse the command line (global options excluded) and run the command.
+ *  The throttle given on the command line wins over the one of the config. */
+fn run(mut args: Vec<String>, profile: Option<&str>, throttle: Option<u64>) -> Result<(), CliError> {
+    if args.is_empty() { return Err(CliError::usage("missing <command>", cli::help())); }
+    if args[0] == "--help" || args[0] == "help" {
+        out!("{}", cli::help());
+        return Ok(());
+    }
+
+    // mtools commands have arguments of their own kind, see mtools.rs
+    if mtools::COMMANDS.contains(&args[0].as_str()) {
+        let name: String = args.remove(0);
+        let _ = CONFIG.set(Config::load(profile).context("Could not load config")?);
+        set_throttle(throttle, &name);
+        return mtools::run(&name, args);
+    }
+
+    // Without a known command name, this is the original "<image> <file>" form
+    let command: &Command = match cli::find_command(&args[0]) {
+        Some(command) => { args.remove(0); command },
+        None => cli::find_command("read").expect("read command not defined")
+    };
+    let matches: Matches = cli::parse(command, args).map_err(|e| usage_error(command, e))?;
+
+    let _ = CONFIG.set(Config::load(profile).context("Could not load config")?);
+    set_throttle(throttle, command.name);
+    match command.name {
+        "read" => run_read(&matches),
+        "sync" => run_sync(&matches),
+        "shell" => shell::run(&image_arg(&matches, 0), matches.has_flag("read-only"), matches.has_flag("overlay")).context("Could not open image"),
+        "batch" => shell::run_batch(matches.get_arg(0), &image_arg(&matches, 1), matches.has_flag("keep-going")),
+        "serve" => serve::run(&image_arg(&matches, 0), matches.get_opt("listen").unwrap_or(serve::DEFAULT_LISTEN)).context("Could not serve image"),
+        "export" => export::run(&image_arg(&matches, 0), matches.get_opt("listen").unwrap_or(export::DEFAULT_LISTEN), matches.get_opt("name").unwrap_or("floppy"))
+            .context("Could not export image"),
+        "cp" => run_cp(&matches),
+        "inspect" => run_inspect(&matches),
+        "diff" => run_diff(&matches),
+        "hash" => run_hash(&matches),
+        "fingerprint" => run_fingerprint(&matches),
+        "ls" => run_ls(&matches),
+        "extract" | "import" | "check" => run_bulk(command.name, &matches),
+        "split" | "merge" => run_span(command.name, &matches),
+        "label" => run_label(&matches),
+        "info" => run_info(&matches),
+        "bpb" => run_bpb(&matches),
+        "sort-dir" => run_sort_dir(&matches),
+        "trim" => run_trim(&matches),
+        "sparse" => run_sparse(&matches),
+        "resize" => run_resize(&matches),
+        "reserved" => run_reserved(&matches),
+        "serial" => run_serial(&matches),
+        "map" => run_map(&matches),
+        "df" => run_df(&matches),
+        "seal" => run_seal(&matches),
+        "encrypt" | "decrypt" => run_crypt(command.name, &matches),
+        "provision" => run_provision(&matches),
+        "identify" => run_identify(&matches),
+        "catalog" => run_catalog(&matches),
+        "recover" => run_recover(&matches),
+        "config" => run_config(),
+        "completions" => {
+            out!("{}", cli::completions(matches.get_arg(0)).map_err(|e| usage_error(command, e))?);
+            Ok(())
+        },
+        "manpage" => {
+            out!("{}", cli::manpage());
+            Ok(())
+        },
+        #[cfg(feature = "tui")]
+        "browse" => browse::run(&image_arg(&matches, 0)).context("Could not browse image"),
+        _ => unreachable!("command {} defined but not handled", command.name)
+    }
+}
+
+/** Command line mistake, reported with the usage of the command. */
+fn usage_error(command: &Command, message: String) -> CliError {
+    CliError::usage(message, format!("usage: {} {}\n", cli::PROGRAM_NAME, cli::usage(command)))
+}
+
+/** Loaded config, or the defaults if main didn't get to load it. */
+fn config() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
+
+/** Options to open images with, following the config. */
+fn fs_options() -> FsOptions {
+    let options: FsOptions = Fat12Fs::options().codepage(config().codepage).name_policy(config().name_policy).validation(config().validation).limits(config().limits)
+        .verify_seal(config().verify_seal).recover_boot_sector(config().recover_boot_sector).discard(config().discard)
+        .short_name_tails(config().short_name_tails.clone()).unrepresentable(config().unrepresentable)
+        .slot_placement(config().slot_placement).fat_mirroring(config().fat_mirroring).zero_missing(config().zero_missing)
+        .name_matching(config().name_matching).cache_sectors(config().cache_sectors).geometry_source(config().geometry_source)
+        .ignore_lock(FORCE.get().copied().unwrap_or(false)).cipher(cipher());
+    let options: FsOptions = match STATS.get() {
+        Some(counters) => options.stats(counters.clone()),
+        None => options
+    };
+    let options: FsOptions = match THROTTLE.get() {
+        Some(throttle) => options.throttle(throttle.clone()),
+        None => options
+    };
+    BPB_OVERRIDES.get().into_iter().flatten().fold(options, |options, (field, value)| options.bpb_override(field, *value))
+}
+
+/** Cipher to open encrypted images with, if a passphrase was given. */
+fn cipher() -> Option<Arc<dyn ContainerCipher>> {
+    let passphrase: &String = PASSPHRASE.get()?;
+    Some(CIPHER.get_or_init(|| Arc::new(Passphrase::new(passphrase))).clone())
+}
+
+/** Limit the images of the command to the given rate, or to the one the
+ *  config has for it. 0 means no limit. */
+fn set_throttle(rate: Option<u64>, command: &str) {
+    let rate: Option<u64> = match rate {
+        Some(rate) => Some(rate).filter(|rate| *rate > 0),
+        None => config().get_throttle(command)
+    };
+    if let Some(rate) = rate { let _ = THROTTLE.set(Arc::new(Throttle::new(rate))); }
+}
+
+/** Image path given as a positional argument, resolving config aliases. */
+fn image_arg(matches: &Matches, index: usize) -> String {
+    config().resolve_image(matches.get_arg(index))
+}
+
+/** BPB field a global option overrides, e.g. sectors_per_fat for
+ *  --sectors-per-fat. */
+fn get_override_field(flag: &str) -> Option<String> {
+    let long: &str = flag.strip_prefix("--")?;
+    let field: String = long.replace('-', "_");
+    let known: bool = cli::GLOBAL_OPTS.iter().any(|opt| opt.long == long) && bpb::FIELDS.contains(&field.as_str());
+    if known { Some(field) } else { None }
+}
+
+/** --stats: the counters of the images the command opened. */
+fn print_stats(stats: &Stats) {
+    eprintln!("sectors read:    {}", stats.sectors_read);
+    eprintln!("sectors written: {}", stats.sectors_written);
+    match stats.get_hit_rate() {
+        Some(rate) => eprintln!("cache:           {} hits, {} misses ({:.0}% hits)", stats.cache_hits, stats.cache_misses, rate * 100.0),
+        None => eprintln!("cache:           not used")
+    }
+    eprintln!("chains walked:   {}", stats.chains_walked);
+    eprintln!("bytes copied:    {}", stats.bytes_copied);
+}
+
+/** Warnings of the image since the last call, on stderr: "warning: ..."
+ *  lines, or JSON lines with --warnings-json. */
+fn print_warnings(fs: &mut Fat12Fs) {
+    for warning in fs.take_warnings() {
+        match WARNINGS_JSON.get().copied().unwrap_or(false) {
+            true => eprintln!("{}", warning.to_json()),
+            false => eprintln!("warning: {}", warning)
+        }
+    }
+}
+
+/** Decimal number, or hex with a 0x prefix. */
+fn parse_number(text: &str) -> Option<u32> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => text.parse().ok()
+    }
+}
+
+/** Open an "image:/path" location in the workspace, unless already there.
+ *  The id is kept as given, so aliases show up as such. */
+fn open_location(workspace: &mut Workspace, location: &str, write: bool) -> Result<(), CliError> {
+    let (id, _): (&str, &str) = parse_location(location).ok_or_else(|| CliError::invalid(format!("{} is not in the image:/path form", location)))?;
+    if workspace.get(id).is_some() { return Ok(()); }
+    let image_path: String = config().resolve_image(id);
+    fs_options().read_only(!write).open(&image_path)
+        .and_then(|fs| workspace.add(id, fs))
+        .context(&format!("Could not open image {}", image_path))
+}
+
+/* ==== COMMANDS ============================================================ */
+/** read <image> <file>: print the content of a root directory file as hex. */
+fn run_read(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let file_name: &str = matches.get_arg(1);
+
+    let mut disk: File = open_disk(&image_path).context("Could not open image")?;
+    let boot_sector: BootSector = read_boot_sector(&mut disk).context("Could not read image")?;
+    let fat: Fat = read_fat(&mut disk, &boot_sector).context("Could not read FAT from image")?;
+    let root_directory: Directory = read_root_directory(&mut disk, &boot_sector).context("Could not read Root Dir from image")?;
+    let kernel_entry: &DirectoryEntry = root_directory.get_entry(file_name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found", file_name.trim_end()))).context("Could not find file in image")?;
+    let kernel_binary: Vec<u8> = read_entry_content(&mut disk, kernel_entry, &fat, &boot_sector).context("Could not read file from image")?;
+
+    outln!("File content: {:02X?}", kernel_binary);
+    Ok(())
+}
+
+/** sync <image> <host dir> [--dir <image dir>] [--dry-run] [--on-conflict skip|host|image|newer] */
+fn run_sync(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let host_dir: &str = matches.get_arg(1);
+    let image_dir: &str = matches.get_opt("dir").unwrap_or("/");
+
+    // The parser already checked the policy name
+    let options: SyncOptions = SyncOptions {
+        dry_run: matches.has_flag("dry-run"),
+        on_conflict: matches.get_opt("on-conflict").and_then(ConflictPolicy::from_name).unwrap_or(config().conflict_policy),
+        timezone_offset: config().timezone_offset
+    };
+
+    let mut fs: Fat12Fs = fs_options().read_only(options.dry_run).open(&image_path).context("Could not open image")?;
+    let host_dir: &Path = Path::new(host_dir);
+    let report: Result<SyncReport, CliError> = match config().hash_algorithm {
+        HashAlgorithm::Crc32 => sync::sync_with::<Crc32>(&mut fs, image_dir, host_dir, &options),
+        HashAlgorithm::Md5 => sync::sync_with::<Md5>(&mut fs, image_dir, host_dir, &options),
+        HashAlgorithm::Sha1 => sync::sync_with::<Sha1>(&mut fs, image_dir, host_dir, &options),
+        HashAlgorithm::Sha256 => sync::sync_with::<Sha256>(&mut fs, image_dir, host_dir, &options)
+    }.context("Could not sync image");
+    print_warnings(&mut fs);
+    let report: SyncReport = report?;
+
+    for action in &report.actions {
+        match action {
+            SyncAction::CopyToImage(path) => outln!("host  -> image  {}", path),
+            SyncAction::CopyToHost(path) => outln!("image -> host   {}", path),
+            SyncAction::DeleteFromImage(path) => outln!("delete (image)  {}", path),
+            SyncAction::DeleteFromHost(path) => outln!("delete (host)   {}", path)
+        }
+    }
+    for (path, reason) in &report.skipped {
+        outln!("skipped         {} ({})", path.display(), reason);
+    }
+    for conflict in &report.conflicts {
+        outln!("CONFLICT        {} ({})", conflict.path, conflict.reason);
+    }
+
+    // Conflicts left behind mean the two sides are still different
+    if !report.conflicts.is_empty() { std::process::exit(EXIT_DIFFERENT); }
+    Ok(())
+}
+
+/** cp <from> <to> [--append | --offset <bytes>]: each side is either "image:/path" or a host path. */
+fn run_cp(matches: &Matches) -> Result<(), CliError> {
+    let from: &str = matches.get_arg(0);
+    let to: &str = matches.get_arg(1);
+    let from_image: Option<(&str, &str)> = parse_location(from);
+    let to_image: Option<(&str, &str)> = parse_location(to);
+
+    // Only the destination needs writing
+    let mut workspace: Workspace = Workspace::default();
+    if to_image.is_some() { open_location(&mut workspace, to, true)?; }
+    if from_image.is_some() { open_location(&mut workspace, from, false)?; }
+
+    // Appending and patching read the source whole, from either side
+    let offset: Option<u64> = matches.get_opt("offset")
+        .map(|offset| offset.parse().map_err(|_| CliError::invalid(format!("--offset {} is not a number of bytes", offset)))).transpose()?;
+    if matches.has_flag("append") || offset.is_some() {
+        if to_image.is_none() { return Err(CliError::invalid("--append and --offset need the destination in the image:/path form")); }
+        let data: Vec<u8> = match from_image {
+            Some(_) => workspace.read_file(from).context("Could not read file from image")?,
+            None => std::fs::read(from).context("Could not read file")?
+        };
+        let (fs, path): (&mut Fat12Fs, &str) = workspace.resolve(to).context("Could not open image")?;
+        let timestamp: u64 = config().to_image_time(fs.now());
+        match offset {
+            Some(offset) => fs.write_at(path, offset, &data, timestamp).context("Could not write into file in image")?,
+            None => fs.append(path, &data, timestamp).context("Could not append to file in image")?
+        };
+        print_warnings(fs);
+        return Ok(());
+    }
+
+    let result: Result<(), CliError> = match (from_image, to_image) {
+        (Some(_), Some(_)) => workspace.copy(from, to).context("Could not copy file"),
+        (Some(_), None) => {
+            let data: Vec<u8> = workspace.read_file(from).context("Could not read file from image")?;
+            std::fs::write(to, data).context("Could not write file")
+        },
+        (None, Some(_)) => {
+            let data: Vec<u8> = std::fs::read(from).context("Could not read file")?;
+            let modified: SystemTime = std::fs::metadata(from).and_then(|metadata| metadata.modified()).context("Could not read file")?;
+            let timestamp: u64 = config().to_image_time(modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+            let (fs, path): (&mut Fat12Fs, &str) = workspace.resolve(to).context("Could not open image")?;
+            fs.write_file(path, &data, timestamp).context("Could not write file to image")?;
+            Ok(())
+        },
+        (None, None) => Err(CliError::invalid("at least one side must be in the image:/path form"))
+    };
+    let ids: Vec<String> = workspace.get_ids().map(str::to_string).collect();
+    for id in ids { if let Some(fs) = workspace.get(&id) { print_warnings(fs); } }
+    result
+}
+
+/** inspect <image:/path | host path>: what the executable is, where it's
+ *  loaded and where it starts. */
+fn run_inspect(matches: &Matches) -> Result<(), CliError> {
+    let file: &str = matches.get_arg(0);
+    let data: Vec<u8> = match parse_location(file) {
+        Some(_) => {
+            let mut workspace: Workspace = Workspace::default();
+            open_location(&mut workspace, file, false)?;
+            workspace.read_file(file).context("Could not read file from image")?
+        },
+        None => std::fs::read(file).context("Could not read file")?
+    };
+
+    match exe::inspect(&data, file) {
+        Executable::Mz(header) => {
+            outln!("type:          MZ executable");
+            outln!("entry point:   CS:IP {:04X}:{:04X}, relative to the load segment (PSP + 10h)", header.cs, header.ip);
+            outln!("stack:         SS:SP {:04X}:{:04X}", header.ss, header.sp);
+            outln!("header:        {} bytes, {} relocations at {:04X}h", header.get_header_size(), header.relocations, header.relocation_offset);
+            outln!("load size:     {} bytes, file {} bytes", header.get_load_size(), data.len());
+            outln!("extra memory:  {:04X}h to {:04X}h paragraphs", header.min_alloc, header.max_alloc);
+            if header.overlay != 0 { outln!("overlay:       {}", header.overlay); }
+            if let Some((offset, signature)) = header.new_header {
+                outln!("new header:    {} at {:X}h (DOS stub of a newer format)", String::from_utf8_lossy(&signature), offset);
+            }
+        },
+        Executable::Com { size } => {
+            outln!("type:          COM program");
+            outln!("entry point:   PSP:0100, loaded at offset 0100h of the PSP segment");
+            outln!("size:          {} bytes of at most {}", size, exe::COM_MAX_SIZE);
+            if size > exe::COM_MAX_SIZE { outln!("warning: too big to load as a COM program"); }
+        },
+        Executable::BootSector { jump_target } => {
+            outln!("type:          boot sector");
+            outln!("load address:  0000:7C00");
+            match jump_target {
+                Some(target) => outln!("entry point:   0000:{:04X}, where the first jump lands", target),
+                None => outln!("entry point:   0000:7C00, no jump at the start")
+            }
+        },
+        Executable::Unknown => return Err(CliError::invalid(format!("{} has no MZ or boot sector signature and is not a .COM", file)))
+    }
+    Ok(())
+}
+
+/** diff <image:/dir> <image:/dir>: compare two directory trees. */
+fn run_diff(matches: &Matches) -> Result<(), CliError> {
+    let left: &str = matches.get_arg(0);
+    let right: &str = matches.get_arg(1);
+
+    let mut workspace: Workspace = Workspace::default();
+    for location in [left, right] { open_location(&mut workspace, location, false)?; }
+
+    let differences: Vec<DiffEntry> = match config().hash_algorithm {
+        HashAlgorithm::Crc32 => workspace.diff_with::<Crc32>(left, right),
+        HashAlgorithm::Md5 => workspace.diff_with::<Md5>(left, right),
+        HashAlgorithm::Sha1 => workspace.diff_with::<Sha1>(left, right),
+        HashAlgorithm::Sha256 => workspace.diff_with::<Sha256>(left, right)
+    }.context("Could not compare directories")?;
+    for difference in &differences {
+        match difference {
+            DiffEntry::OnlyLeft(path) => outln!("< {}", path),
+            DiffEntry::OnlyRight(path) => outln!("> {}", path),
+            DiffEntry::Different(path) => outln!("! {}", path)
+        }
+    }
+    if !differences.is_empty() { std::process::exit(EXIT_DIFFERENT); }
+    Ok(())
+}
+
+/** hash <image> [--dir <image dir>] [--algorithm <name>] [--check <list>]:
+ *  digests of the files in the "<digest>  <path>" lines of md5sum, or the
+ *  files of such a list checked, the paths in it taken from the directory. */
+fn run_hash(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let image_dir: &str = matches.get_opt("dir").unwrap_or("/");
+    let algorithm: HashAlgorithm = matches.get_opt("algorithm").and_then(HashAlgorithm::from_name).unwrap_or(config().hash_algorithm);
+    let mut fs: Fat12Fs = fs_options().open(&image_path).context("Could not open image")?;
+    let join = |path: &str| format!("{}/{}", image_dir.trim_end_matches('/'), path.trim_start_matches('/'));
+
+    let Some(list) = matches.get_opt("check") else {
+        let entries: Vec<(String, DirectoryEntry)> = fs.walk(image_dir).collect::<io::Result<_>>().context("Could not read image")?;
+        for (path, _) in entries.iter().filter(|(_, entry)| !entry.is_directory()) {
+            let data: Vec<u8> = fs.read_file(&join(path)).context(&format!("Could not read {}", path))?;
+            outln!("{}  {}", digest::to_hex(&algorithm.digest(&data)), path);
+        }
+        return Ok(());
+    };
+
+    // "*" before the path marks the binary mode lines of the sum tools
+    let content: String = std::fs::read_to_string(list).context("Could not read list")?;
+    let mut failed: usize = 0;
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((hex, path)) = line.split_once(' ') else { return Err(CliError::invalid(format!("invalid line: {}", line))) };
+        let path: &str = path.trim_start_matches(' ').trim_start_matches('*');
+        let matching: bool = match fs.read_file(&join(path)) {
+            Ok(data) => digest::to_hex(&algorithm.digest(&data)).eq_ignore_ascii_case(hex),
+            Err(_) => false
+        };
+        outln!("{}: {}", path, if matching { "OK" } else { "FAILED" });
+        if !matching { failed += 1; }
+    }
+    if failed > 0 {
+        eprintln!("{} files failed the check", failed);
+        std::process::exit(EXIT_DIFFERENT);
+    }
+    Ok(())
+}
+
+/** fingerprint <image> [--algorithm <name>]: print the content fingerprint,
+ *  as the sum tools print digests, so that lists of them sort and dedupe. */
+fn run_fingerprint(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let algorithm: HashAlgorithm = matches.get_opt("algorithm").and_then(HashAlgorithm::from_name).unwrap_or(HashAlgorithm::Sha256);
+    let mut fs: Fat12Fs = fs_options().open(&image_path).context("Could not open image")?;
+
+    let fingerprint: Vec<u8> = match algorithm {
+        HashAlgorithm::Crc32 => fs.fingerprint_with::<Crc32>(),
+        HashAlgorithm::Md5 => fs.fingerprint_with::<Md5>(),
+        HashAlgorithm::Sha1 => fs.fingerprint_with::<Sha1>(),
+        HashAlgorithm::Sha256 => fs.fingerprint_with::<Sha256>()
+    }.context("Could not read image")?;
+    outln!("{}  {}", digest::to_hex(&fingerprint), image_path);
+    Ok(())
+}
+
+/** ls <image> [--dir <image dir>] [--recursive] [--long] [--bytes] [--sort <key>]
+ *  [--reverse] [filters]: list a directory. */
+fn run_ls(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let filter: Filter = filter_arg(matches)?;
+    let mut fs: Fat12Fs = fs_options().open(&image_path).context("Could not open image")?;
+
+    let max_depth: usize = if matches.has_flag("recursive") { usize::MAX } else { 1 };
+    let dir: &str = matches.get_opt("dir").unwrap_or("/");
+    let short_names: bool = matches.has_flag("short-names");
+
+    // Long path of each directory walked, by short path; the filter is
+    // applied here so that the directories it leaves out are named all the
+    // same, for what it picks inside them
+    let mut long_paths: HashMap<String, String> = HashMap::from([(String::new(), String::new())]);
+    let mut long_names: HashMap<String, HashMap<[u8; 11], String>> = HashMap::new();
+    let mut listing: Vec<(String, Metadata)> = vec![];
+    for item in fs.walk(dir).max_depth(max_depth).dot_entries(matches.has_flag("all")).collect::<Vec<_>>() {
+        let (path, entry): (String, DirectoryEntry) = item.context("Could not read directory")?;
+        let (parent, name): (&str, &str) = path.rsplit_once('/').unwrap_or(("", &path));
+        let shown: String = match short_names {
+            true => path.clone(),
+            false => {
+                if !long_names.contains_key(parent) {
+                    let pairs: Vec<NamePair> = fs.read_name_pairs(&format!("{}/{}", dir.trim_end_matches('/'), parent)).context("Could not read directory")?;
+                    long_names.insert(parent.to_string(), pairs.into_iter().filter_map(|pair| Some((pair.entry.get_short_name(), pair.long_name?))).collect());
+                }
+                let name: &str = long_names[parent].get(&entry.get_short_name()).filter(|_| !entry.is_dot_entry()).map_or(name, String::as_str);
+                match long_paths.get(parent).map_or(parent, String::as_str) {
+                    "" => name.to_string(),
+                    parent => format!("{}/{}", parent, name)
+                }
+            }
+        };
+        if entry.is_directory() && !entry.is_dot_entry() { long_paths.insert(path.clone(), shown.clone()); }
+        if filter.matches(&entry) { listing.push((shown, fs.get_metadata(&entry))); }
+    }
+
+    // Sorting by name sorts by path, so that each tree stays together
+    if let Some(key) = matches.get_opt("sort").and_then(SortKey::from_name) {
+        listing.sort_by(|(a_path, a), (b_path, b)| match key {
+            SortKey::Name => a_path.cmp(b_path),
+            key => key.compare(a, b).then_with(|| a_path.cmp(b_path))
+        });
+    }
+    if matches.has_flag("reverse") { listing.reverse(); }
+
+    if !matches.has_flag("long") {
+        for (path, _) in &listing { outln!("{}", path); }
+        return Ok(());
+    }
+    let size = |metadata: &Metadata| -> String {
+        match (metadata.is_directory, matches.has_flag("bytes")) {
+            (true, _) => String::from("<DIR>"),
+            (false, true) => metadata.size.to_string(),
+            (false, false) => format_size(metadata.size)
+        }
+    };
+    let width: usize = listing.iter().map(|(_, metadata)| size(metadata).len()).max().unwrap_or(0);
+    let cluster_width: usize = listing.iter().map(|(_, metadata)| metadata.first_cluster.to_string().len()).max().unwrap_or(0);
+    for (path, metadata) in &listing {
+        outln!("{}  {:>width$}  {}  {:>cluster_width$}  {}", metadata.get_attributes_string(), size(metadata),
+            format_timestamp(metadata.modified), metadata.first_cluster.to_string(), path);
+    }
+    Ok(())
+}
+
+/** seal <image> [--verify]: store the checksums of the image in its reserved
+ *  sectors, or check the image against them. */
+fn run_seal(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let verify: bool = matches.has_flag("verify");
+
+    // Checking an image that fails must not be refused by the open itself
+    let mut fs: Fat12Fs = fs_options().verify_seal(false).read_only(verify).open(&image_path).context("Could not open image")?;
+    if verify {
+        fs.verify_seal().context("Seal check failed")?;
+        outln!("the image matches its seal");
+        return Ok(());
+    }
+    let seal: Seal = fs.seal().context("Could not seal image")?;
+    for (region, crc) in Seal::REGIONS.iter().zip(seal.get_crcs()) { outln!("{:<17} {:08x}", region, crc); }
+    Ok(())
+}
+
+/** encrypt <image> [--output <file>] [--iterations <n>] and decrypt <image>
+ *  [--output <file>]: move the image into an encrypted container, or back
+ *  out of it. */
+fn run_crypt(name: &str, matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let output: PathBuf = PathBuf::from(matches.get_opt("output").unwrap_or(&image_path));
+    let passphrase: &String = PASSPHRASE.get().ok_or_else(|| CliError::invalid(format!("{} needs a passphrase, from --passphrase-file or RSFAT_PASSPHRASE", name)))?;
+    let iterations: u32 = match matches.get_opt("iterations") {
+        Some(iterations) => iterations.parse().map_err(|_| CliError::invalid(format!("invalid iteration count {}", iterations)))?,
+        None => crypt::DEFAULT_ITERATIONS
+    };
+    let cipher: Passphrase = Passphrase::new(passphrase).iterations(iterations);
+    match name {
+        "encrypt" => crypt::encrypt_file(Path::new(&image_path), &output, &cipher).context("Could not encrypt image")?,
+        _ => crypt::decrypt_file(Path::new(&image_path), &output, &cipher).context("Could not decrypt image")?
+    }
+    outln!("{}: {}ed to {}", image_path, name, output.display());
+    Ok(())
+}
+
+/** provision <manifest> <image> [--apply [--dry-run]]: build the image the
+ *  manifest describes, or bring an existing one in line with it. */
+fn run_provision(matches: &Matches) -> Result<(), CliError> {
+    let mut template: Template = manifest::load(Path::new(matches.get_arg(0)), config()).map_err(CliError::invalid)?;
+    let image_path: String = image_arg(matches, 1);
+    if matches.has_flag("apply") {
+        let dry_run: bool = matches.has_flag("dry-run");
+        let mut fs: Fat12Fs = fs_options().read_only(dry_run).open(&image_path).context("Could not open image")?;
+        let changes: Vec<Change> = provision::apply(&mut fs, &mut template, dry_run).context("Could not apply manifest")?;
+        for change in &changes {
+            match change {
+                Change::Added(path) => outln!("+ {}", path),
+                Change::Updated(path) => outln!("~ {}", path),
+                Change::Attributes(path) => outln!("a {}", path),
+                Change::Removed(path) => outln!("- {}", path),
+                Change::Label(label) => outln!("label {}", label.as_deref().unwrap_or("(none)")),
+                Change::ReservedRegion => outln!("~ reserved region")
+            }
+        }
+        outln!("{} changes{}", changes.len(), if dry_run { " (dry run)" } else { "" });
+        return Ok(());
+    }
+    let mut fs: Fat12Fs = provision::provision(Path::new(&image_path), &mut template, &fs_options()).context("Could not provision image")?;
+    let usage: Usage = fs.get_usage().context("Could not read image")?;
+    drop(fs);
+    if matches.has_flag("materialize") { sparse::materialize(Path::new(&image_path)).context("Could not write image")?; }
+    outln!("{}: {} files, {} directories, {} of {} clusters free", image_path, usage.files, usage.directories, usage.free_clusters, usage.total_clusters);
+    Ok(())
+}
+
+/** sort-dir <image> [--dir <image-dir>] [--sort <key>] [--reverse]: put the
+ *  slots of the directory in that order. */
+fn run_sort_dir(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let key: SortKey = matches.get_opt("sort").and_then(SortKey::from_name).unwrap_or(SortKey::Name);
+    let mut fs: Fat12Fs = fs_options().read_only(false).open(&image_path).context("Could not open image")?;
+    fs.sort_dir(matches.get_opt("dir").unwrap_or("/"), key, matches.has_flag("reverse")).context("Could not sort directory")?;
+    Ok(())
+}
+
+/** trim <image>: zero the free clusters that aren't. */
+fn run_trim(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let mut fs: Fat12Fs = fs_options().read_only(false).open(&image_path).context("Could not open image")?;
+    let report: TrimReport = fs.trim().context("Could not trim image")?;
+    outln!("{}: {} free clusters, {} zeroed", image_path, report.free_clusters, report.zeroed);
+    Ok(())
+}
+
+/** sparse <image> [--materialize]: make the zero sectors holes, or fill them. */
+fn run_sparse(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    if matches.has_flag("materialize") {
+        let size: u64 = sparse::materialize(Path::new(&image_path)).context("Could not write image")?;
+        outln!("{}: {} bytes written", image_path, size);
+        return Ok(());
+    }
+    let report: SparseReport = sparse::sparsify(Path::new(&image_path)).context("Could not rewrite image")?;
+    outln!("{}: {} sectors of data, {} zero sectors left as holes", image_path, report.data_sectors, report.zero_sectors);
+    Ok(())
+}
+
+/** resize <image> <size> [--filesystem] [--dry-run]: grow or shrink the
+ *  image file, and the volume with it if asked. */
+fn run_resize(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let options: ResizeOptions = ResizeOptions { filesystem: matches.has_flag("filesystem"), dry_run: matches.has_flag("dry-run") };
+    let mut fs: Fat12Fs = fs_options().read_only(options.dry_run).open(&image_path).context("Could not open image")?;
+    let size: u64 = match matches.get_arg(1) {
+        "min" => fs.get_minimum_size(options.filesystem),
+        size => config::parse_rate(size).ok_or_else(|| CliError::invalid(format!("invalid size {}, expected bytes with an optional K or M suffix, or min", size)))?
+    };
+    let report: ResizeReport = fs.resize(size, options).context("Could not resize image")?;
+    outln!("{}: {} -> {} bytes", image_path, report.old_size, report.new_size);
+    if options.filesystem {
+        outln!("volume: {} -> {} sectors, {} -> {} clusters", report.old_total_sectors, report.new_total_sectors, report.old_cluster_count, report.new_cluster_count);
+        if report.new_sectors_per_fat != report.old_sectors_per_fat {
+            outln!("FAT:    {} -> {} sectors, {} bytes moved after it", report.old_sectors_per_fat, report.new_sectors_per_fat, report.moved);
+        }
+    }
+    if options.dry_run { outln!("(dry run, nothing changed)"); }
+    Ok(())
+}
+
+/** identify <image>: what the image is and what made it, with what each
+ *  guess is based on. */
+fn run_identify(matches: &Matches) -> Result<(), CliError> {
+    let identification: Identification = identify::identify(&image_arg(matches, 0)).context("Could not read image")?;
+    let unknown = || String::from("(unknown)");
+    outln!("size:       {} bytes, {}", identification.image_size, identification.size_class);
+    match identification.layout {
+        Layout::Superfloppy => outln!("layout:     superfloppy (no partition table)"),
+        Layout::Partitioned { index, entry } => outln!("layout:     partition {} at sector {}", index + 1, entry.first_lba)
+    }
+    outln!("type:       {}", identification.fat_type.unwrap_or("(no boot sector)"));
+    outln!("oem id:     {}", identification.oem_id.clone().unwrap_or_else(unknown));
+    outln!("formatter:  {}", identification.formatter.map(|formatter| formatter.get_name().to_string()).unwrap_or_else(unknown));
+    outln!("dos:        {}", identification.dos_version.clone().unwrap_or_else(unknown));
+    for note in &identification.notes { outln!("  {}", note); }
+    Ok(())
+}
+
+/** catalog <host dir> [--recursive] [--output <file>]: see catalog::run,
+ *  telling on stderr how many files could not be read. */
+fn run_catalog(matches: &Matches) -> Result<(), CliError> {
+    let output: Option<&Path> = matches.get_opt("output").map(Path::new);
+    let failed: usize = catalog::run(Path::new(matches.get_arg(0)), matches.has_flag("recursive"), output).context("Could not write catalog")?;
+    if failed > 0 { eprintln!("{} files could not be read as images, see their \"error\"", failed); }
+    Ok(())
+}
+
+/** recover <image> [--write]: rebuild the boot sector from the rest of the
+ *  image, showing how, and write it with --write. */
+fn run_recover(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let disk: File = File::open(&image_path).context("Could not open image")?;
+    let recovery: Recovery = recovery::recover_boot_sector(&disk, 0).context("Could not read image")?;
+    for note in &recovery.notes { outln!("{}", note); }
+
+    let boot_sector: BootSector = recovery.boot_sector;
+    for name in ["bytes_per_sector", "sectors_per_cluster", "reserved_sectors", "fat_count", "root_entries", "total_sectors", "media_descriptor", "sectors_per_fat"] {
+        outln!("{:<20} {}", name, boot_sector.get_field(name).unwrap_or_default());
+    }
+    if !matches.has_flag("write") { return Ok(()); }
+
+    // Refuse to overwrite a boot sector that still looks fine
+    if !recovery::is_damaged(&disk, 0) { return Err(CliError::invalid("the boot sector is not damaged, it was left as it is")); }
+    let mut fs: Fat12Fs = fs_options().recover_boot_sector(true).read_only(false).open(&image_path).context("Could not open image")?;
+    fs.restore_boot_sector().context("Could not write boot sector")?;
+    outln!("boot sector written");
+    Ok(())
+}
+
+/** Size with a K or M suffix past 1024 bytes, e.g. "1.5K". */
+fn format_size(size: u64) -> String {
+    match size {
+        0..1024 => size.to_string(),
+        1024..1048576 => format!("{:.1}K", size as f64 / 1024.0),
+        _ => format!("{:.1}M", size as f64 / 1048576.0)
+    }
+}
+
+/** Filter of the --hidden, --system-only, --attributes, --min-size,
+ *  --max-size, --newer-than and --older-than options. */
+fn filter_arg(matches: &Matches) -> Result<Filter, CliError> {
+    let mut mask: String = matches.get_opt("attributes").unwrap_or_default().to_string();
+    if matches.has_flag("hidden") { mask.push_str("+H"); }
+    if matches.has_flag("system-only") { mask.push_str("+S"); }
+    let mut filter: Filter = Filter::default().with_attribute_mask(&mask)
+        .ok_or_else(|| CliError::invalid(format!("{} is not an attribute mask of R, H, S, D and A", mask)))?;
+
+    let size = |name: &str| -> Result<Option<u64>, CliError> {
+        matches.get_opt(name).map(|size| size.parse().map_err(|_| CliError::invalid(format!("--{} {} is not a number of bytes", name, size)))).transpose()
+    };
+    let date = |name: &str| -> Result<Option<u64>, CliError> {
+        matches.get_opt(name).map(|date| parse_timestamp(date).ok_or_else(|| CliError::invalid(format!("--{} {} is not a YYYY-MM-DD [HH:MM:SS] date", name, date)))).transpose()
+    };
+    (filter.min_size, filter.max_size) = (size("min-size")?, size("max-size")?);
+    (filter.newer_than, filter.older_than) = (date("newer-than")?, date("older-than")?);
+    Ok(filter)
+}
+
+/** extract <image> <host dir> [--files-from <list>] [--null] [--checkpoint <file>] [--host-names <names>] [--on-collision <policy>] [filters],
+ *  import <image> <host dir>, check <image> [--rebuild-fat] [--repair] [--trust <source>], all with [--dir <image dir>]
+ *  [--keep-going]. */
+fn run_bulk(name: &str, matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let image_dir: &str = matches.get_opt("dir").unwrap_or("/");
+    let options: BulkOptions = BulkOptions {
+        keep_going: matches.has_flag("keep-going"),
+        timezone_offset: config().timezone_offset,
+        filter: filter_arg(matches)?,
+        preserve_times: matches.has_flag("preserve-times"),
+        preserve_read_only: matches.has_flag("preserve-read-only"),
+        text_mode: TextMode {
+            line_endings: matches.has_flag("text"),
+            codepage: if matches.has_flag("utf8") { Some(config().codepage) } else { None }
+        },
+        host_names: matches.get_opt("host-names").and_then(HostNames::from_name).unwrap_or_default(),
+        on_collision: matches.get_opt("on-collision").and_then(NameCollision::from_name).unwrap_or_default(),
+        checkpoint: matches.get_opt("checkpoint").map(PathBuf::from),
+        observer: None
+    };
+
+    if matches.has_flag("null") && matches.get_opt("files-from").is_none() { return Err(CliError::invalid("--null needs --files-from")); }
+    let files: Option<Vec<String>> = match matches.get_opt("files-from") {
+        Some(list) => Some(read_file_list(list, matches.has_flag("null")).context("Could not read file list")?),
+        None => None
+    };
+
+    let audit_log: Option<&str> = matches.get_opt("audit-log");
+    let (mirror_fat, rebuild_fat, repair): (bool, bool, bool) = (matches.has_flag("mirror-fat"), matches.has_flag("rebuild-fat"), matches.has_flag("repair"));
+    let geometry_source: GeometrySource = matches.get_opt("trust").and_then(GeometrySource::from_name).unwrap_or(config().geometry_source);
+    let mut fs: Fat12Fs = fs_options().read_only(name != "import" && !mirror_fat && !rebuild_fat && !repair).audit(audit_log.is_some())
+        .geometry_source(geometry_source).open(&image_path).context("Could not open image")?;
+    if let Some(conflict) = fs.get_geometry_conflict().filter(|_| name == "check") {
+        let used: &str = match geometry_source {
+            GeometrySource::Bpb => "the BPB",
+            GeometrySource::MediaDescriptor => "the media descriptor"
+        };
+        outln!("geometry conflict  {}, using {}", conflict, used);
+    }
+    if mirror_fat {
+        fs.mirror_fat().context("Could not write the FAT copies")?;
+        outln!("FAT written to all {} copies", fs.get_boot_sector().fat_count);
+    }
+    if rebuild_fat {
+        let rebuild: FatRebuild = fs.rebuild_fat().context("Could not rebuild FAT")?;
+        for (path, reason) in &rebuild.uncertain { outln!("not recovered  {} ({})", path, reason); }
+        for (path, reason) in &rebuild.truncated { outln!("truncated      {} ({})", path, reason); }
+        outln!("FAT rebuilt, {} entries recovered, {} not", rebuild.recovered.len(), rebuild.uncertain.len());
+    }
+    if repair {
+        let recovery: OrphanRecovery = fs.recover_orphans().context("Could not recover lost chains")?;
+        for (file, clusters) in &recovery.files { outln!("recovered  {}/{} ({} clusters)", recovery.directory, file, clusters); }
+        if recovery.files.is_empty() { outln!("no lost chains"); }
+    }
+    let result: Result<BulkReport, CliError> = match name {
+        "extract" => match &files {
+            Some(files) => bulk::extract_files(&mut fs, image_dir, files, Path::new(matches.get_arg(1)), &options),
+            None => bulk::extract(&mut fs, image_dir, Path::new(matches.get_arg(1)), &options)
+        }.context("Could not extract image"),
+        "import" => bulk::import(&mut fs, Path::new(matches.get_arg(1)), image_dir, &options).context("Could not import into image"),
+        _ => bulk::check(&mut fs, image_dir, &options).context("Image is corrupt")
+    };
+
+    // The changes made before a failure are logged too
+    if let Some(audit_log) = audit_log {
+        let lines: String = fs.get_audit_log().iter().map(|record| format!("{}\n", record.to_json())).collect();
+        std::fs::write(audit_log, lines).context("Could not write audit log")?;
+    }
+    print_warnings(&mut fs);
+    let report: BulkReport = result?;
+
+    for (path, e) in &report.failed { outln!("failed  {} ({})", path, e); }
+    match report.skipped.len() {
+        0 => outln!("{} done, {} failed", report.done.len(), report.failed.len()),
+        skipped => outln!("{} done, {} skipped (already extracted or name taken), {} failed", report.done.len(), skipped, report.failed.len())
+    }
+    // Lost chains are reported by every check, --repair saved them above
+    let mut lost_chains: Vec<Vec<Cluster>> = vec![];
+    if name == "check" {
+        print_root_slots(&mut fs)?;
+        if !repair { lost_chains = fs.find_orphan_chains().context("Could not look for lost chains")?; }
+        for chain in &lost_chains { outln!("lost chain  from cluster {} ({} clusters)", chain[0], chain.len()); }
+        if !lost_chains.is_empty() {
+            outln!("{} lost chains, {} clusters, check --repair saves them", lost_chains.len(), lost_chains.iter().map(Vec::len).sum::<usize>());
+        }
+    }
+
+    // With --keep-going, the exit code follows the first failure
+    match (report.failed.first(), lost_chains.len()) {
+        (Some((path, e)), _) => Err(CliError::from_io(&format!("{} files failed, the first one is {}", report.failed.len(), path), e)),
+        (None, 0) => Ok(()),
+        (None, count) => Err(CliError::from_io("Image is corrupt", &io::Error::new(io::ErrorKind::InvalidData, format!("{} lost chains", count))))
+    }
+}
+
+/** Paths of a --files-from list, from the file or stdin ("-"): one per line,
+ *  or NUL terminated. Empty ones are skipped. */
+fn read_file_list(list: &str, null: bool) -> io::Result<Vec<String>> {
+    let mut bytes: Vec<u8> = vec![];
+    match list {
+        "-" => { io::Read::read_to_end(&mut io::stdin(), &mut bytes)?; },
+        list => bytes = std::fs::read(list)?
+    }
+    let text: String = String::from_utf8(bytes).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "the list is not UTF-8"))?;
+    let paths = match null {
+        true => text.split('\0').map(str::to_string).collect::<Vec<String>>(),
+        false => text.lines().map(|line| line.trim_end_matches('\r').to_string()).collect()
+    };
+    Ok(paths.into_iter().filter(|path| !path.is_empty()).collect())
+}
+
+/** split <host dir> <images>, merge <images> <host dir> [--preserve-times]:
+ *  spread a tree over a set of images and put it back together. */
+fn run_span(name: &str, matches: &Matches) -> Result<(), CliError> {
+    let (images, host_dir): (&str, &str) = match name {
+        "split" => (matches.get_arg(1), matches.get_arg(0)),
+        _ => (matches.get_arg(0), matches.get_arg(1))
+    };
+    let mut disks: Vec<Fat12Fs> = vec![];
+    for image in images.split(',').filter(|image| !image.is_empty()) {
+        let image_path: String = config().resolve_image(image);
+        disks.push(fs_options().read_only(name != "split").open(&image_path).context(&format!("Could not open image {}", image))?);
+    }
+
+    let options: BulkOptions = BulkOptions { timezone_offset: config().timezone_offset, preserve_times: matches.has_flag("preserve-times"), ..BulkOptions::default() };
+    if name == "merge" {
+        let files: Vec<String> = span::merge(&mut disks, Path::new(host_dir), &options).context("Could not merge set")?;
+        outln!("{} files merged from {} disks", files.len(), disks.len());
+        return Ok(());
+    }
+
+    let manifests: Vec<Manifest> = span::split(&mut disks, Path::new(host_dir), &options).context("Could not split to set")?;
+    for manifest in &manifests {
+        outln!("disk {} of {}:", manifest.disk, manifest.disk_count);
+        for part in &manifest.parts {
+            match part.length == part.size {
+                true => outln!("  {}", part.path),
+                false => outln!("  {} (bytes {}-{} of {})", part.path, part.offset, part.offset + part.length, part.size)
+            }
+        }
+    }
+    Ok(())
+}
+
+/** label <image> [--set <label>] [--clear]: show or change the volume label. */
+fn run_label(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let label: Option<&str> = match (matches.get_opt("set"), matches.has_flag("clear")) {
+        (Some(_), true) => return Err(CliError::invalid("--set and --clear can't be used together")),
+        (Some(label), false) => Some(label),
+        (None, true) => Some(""),
+        (None, false) => None
+    };
+
+    let mut fs: Fat12Fs = fs_options().read_only(label.is_none()).open(&image_path).context("Could not open image")?;
+    match label {
+        Some(label) => fs.set_volume_label(label).context("Could not set volume label"),
+        None => {
+            outln!("{}", fs.get_volume_label().context("Could not read image")?.unwrap_or_else(|| String::from("(no label)")));
+            Ok(())
+        }
+    }
+}
+
+/** info <image> [--diagnose]: show what the image is, or why it can't be
+ *  opened. */
+fn run_info(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    if matches.has_flag("diagnose") {
+        let report: Report = fs_options().diagnose(&image_path).context("Could not read image")?;
+        for diagnostic in &report.diagnostics { outln!("{:<7} {}", diagnostic.level.get_name(), diagnostic); }
+
+        let refused: Report = report.refused_by(config().validation);
+        if !refused.is_empty() {
+            return Err(CliError::from_io("Could not open image", &io::Error::new(io::ErrorKind::InvalidData, refused)));
+        }
+        outln!("{} checks failed, the {} validation level opens the image", report.diagnostics.len(), config().validation.get_name());
+        return Ok(());
+    }
+
+    let mut fs: Fat12Fs = fs_options().open(&image_path).context("Could not open image")?;
+    if matches.has_flag("fsstat") { return print_fsstat(&mut fs); }
+    let boot_sector: BootSector = *fs.get_boot_sector();
+    let geometry: Geometry = boot_sector.get_geometry();
+    let oem_id: [u8; 8] = boot_sector.oem_id;
+    let label: String = fs.get_volume_label().context("Could not read image")?.unwrap_or_else(|| String::from("(no label)"));
+    let usage: Usage = fs.get_usage().context("Could not read image")?;
+    outln!("label:      {}", label);
+    outln!("serial:     {}", boot_sector.get_volume_id().map(volume::format_volume_id).unwrap_or_else(|| String::from("(none)")));
+    outln!("oem id:     {}", String::from_utf8_lossy(&oem_id).trim_end());
+    outln!("type:       {}, {} clusters of {} bytes", geometry.get_fat_type(), geometry.cluster_count, boot_sector.get_cluster_size());
+    outln!("size:       {} sectors of {} bytes", geometry.total_sectors, boot_sector.bytes_per_sector as u32);
+    outln!("free:       {} clusters", usage.free_clusters);
+    match fs.get_fat_mirroring() {
+        FatMirroring::All => outln!("fats:       {}, all written", boot_sector.fat_count),
+        FatMirroring::Active(index) => outln!("fats:       {}, only FAT {} read and written", boot_sector.fat_count, index)
+    }
+    if let Some(fields) = fs.read_fat32_fields().context("Could not read image")? {
+        let (major, minor): (u8, u8) = fields.version;
+        outln!("fat32:      version {}.{}, ext flags 0x{:04X}, root cluster {}", major, minor, fields.ext_flags.0, fields.root_cluster);
+    }
+    if let Some(truncation) = fs.get_truncation() {
+        outln!("truncated:  {} bytes of {}, the last {} sectors read as zeros", truncation.actual, truncation.expected,
+            truncation.get_missing_sectors(boot_sector.bytes_per_sector));
+    }
+    match fs.get_partition_offset() {
+        0 => outln!("layout:     superfloppy (no partition table)"),
+        offset => outln!("layout:     partition at byte {}", offset)
+    }
+    Ok(())
+}
+
+/** info --fsstat: the categories and layout of Sleuth Kit's fsstat, so
+ *  that reports can be compared. Metadata addresses number the directory
+ *  entry slots from the root directory on, starting at 2 as fsstat does. */
+fn print_fsstat(fs: &mut Fat12Fs) -> Result<(), CliError> {
+    let boot_sector: BootSector = *fs.get_boot_sector();
+    let geometry: Geometry = boot_sector.get_geometry();
+    let (oem_id, system_id): ([u8; 8], [u8; 8]) = (boot_sector.oem_id, boot_sector.system_id);
+    let (bytes_per_sector, hidden_sectors): (u32, u32) = (boot_sector.bytes_per_sector as u32, boot_sector.hidden_sectors_count);
+    let root: Directory = fs.read_dir("/").context("Could not read root directory")?;
+    let root_label: String = root.entries().find(|entry| entry.is_volume_label()).map(|entry| fs.get_name(entry)).unwrap_or_default();
+
+    outln!("FILE SYSTEM INFORMATION\n--------------------------------------------");
+    outln!("File System Type: {}\n", geometry.get_fat_type());
+    outln!("OEM Name: {}", String::from_utf8_lossy(&oem_id));
+    if let Some(volume_id) = boot_sector.get_volume_id() { outln!("Volume ID: 0x{:x}", volume_id); }
+    outln!("Volume Label (Boot Sector): {}", boot_sector.get_volume_label().unwrap_or_default());
+    outln!("Volume Label (Root Directory): {}", root_label);
+    if boot_sector.get_volume_id().is_some() { outln!("File System Type Label: {}", String::from_utf8_lossy(&system_id)); }
+    outln!("\nSectors before file system: {}\n", hidden_sectors);
+
+    // Every region in sectors, the data area being the root directory and the clusters
+    let fat_start: u32 = boot_sector.reserved_sectors as u32;
+    let fat_sectors: u32 = boot_sector.sectors_per_fat as u32;
+    let root_start: u32 = fat_start + fat_sectors * boot_sector.fat_count as u32;
+    let last_sector: u32 = geometry.total_sectors.saturating_sub(1);
+    let clustered_end: u32 = geometry.data_start_sector + (geometry.cluster_count as u32) * boot_sector.sectors_per_cluster as u32;
+    outln!("File System Layout (in sectors)");
+    outln!("Total Range: 0 - {}", last_sector);
+    outln!("* Reserved: 0 - {}", fat_start.saturating_sub(1));
+    outln!("** Boot Sector: 0");
+    for i in 0..boot_sector.fat_count as u32 {
+        outln!("* FAT {}: {} - {}", i, fat_start + i * fat_sectors, fat_start + (i + 1) * fat_sectors - 1);
+    }
+    outln!("* Data Area: {} - {}", root_start, last_sector);
+    outln!("** Root Directory: {} - {}", root_start, geometry.data_start_sector.saturating_sub(1));
+    outln!("** Cluster Area: {} - {}", geometry.data_start_sector, clustered_end.saturating_sub(1));
+    if clustered_end <= last_sector { outln!("** Non-clustered: {} - {}", clustered_end, last_sector); }
+
+    let entries_per_sector: u32 = bytes_per_sector / 32;
+    outln!("\nMETADATA INFORMATION\n--------------------------------------------");
+    outln!("Range: 2 - {}", 2 + (clustered_end - root_start) * entries_per_sector - 1);
+    outln!("Root Directory: 2");
+
+    outln!("\nCONTENT INFORMATION\n--------------------------------------------");
+    outln!("Sector Size: {}", bytes_per_sector);
+    outln!("Cluster Size: {}", boot_sector.get_cluster_size());
+    outln!("Total Cluster Range: 2 - {}", geometry.cluster_count + 1);
+
+    // Runs of clusters that follow each other, and where each run goes next
+    let fat: &Fat = fs.get_fat();
+    let to_sector = |cluster: u32| Cluster(cluster).to_lba(&boot_sector).0;
+    let spc: u64 = boot_sector.sectors_per_cluster as u64;
+    outln!("\nFAT CONTENTS (in sectors)\n--------------------------------------------");
+    let mut cluster: u32 = 2;
+    while cluster < geometry.cluster_count as u32 + 2 {
+        let first: u32 = cluster;
+        let mut next: u16 = fat.get_entry(Cluster(cluster));
+        if next == 0 { cluster += 1; continue; }
+        while next as u32 == cluster + 1 && cluster + 1 < geometry.cluster_count as u32 + 2 {
+            cluster += 1;
+            next = fat.get_entry(Cluster(cluster));
+        }
+        let target: String = match next {
+            0xFF7 => String::from("BAD"),
+            0xFF8..=0xFFF => String::from("EOF"),
+            next => to_sector(next as u32).to_string()
+        };
+        let (start, end): (u64, u64) = (to_sector(first), to_sector(cluster) + spc - 1);
+        outln!("{}-{} ({}) -> {}", start, end, end - start + 1, target);
+        cluster += 1;
+    }
+    Ok(())
+}
+
+/** bpb <image> [--set <field>=<value>]: show or change the BIOS parameter block. */
+fn run_bpb(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let change: Option<(&str, u32)> = match matches.get_opt("set") {
+        None => None,
+        Some(change) => {
+            let (field, value): (&str, &str) = change.split_once('=').ok_or_else(|| CliError::invalid(format!("{} is not in the field=value form", change)))?;
+            Some((field, parse_number(value).ok_or_else(|| CliError::invalid(format!("{} is not a number", change)))?))
+        }
+    };
+
+    let mut fs: Fat12Fs = fs_options().read_only(change.is_none()).open(&image_path).context("Could not open image")?;
+    if let Some((field, value)) = change {
+        for warning in fs.set_bpb_field(field, value).context("Could not change boot sector")? { eprintln!("warning: {}", warning); }
+    }
+
+    let boot_sector: &BootSector = fs.get_boot_sector();
+    let oem_id: [u8; 8] = boot_sector.oem_id;
+    outln!("{:<22}{}", "oem_id", String::from_utf8_lossy(&oem_id));
+    for field in bpb::FIELDS {
+        let value: u32 = boot_sector.get_field(field).expect("BPB field not readable");
+        match *field {
+            "media_descriptor" => outln!("{:<22}{:#04X}", field, value),
+            _ => outln!("{:<22}{}", field, value)
+        }
+    }
+
+    let geometry: Geometry = boot_sector.get_geometry();
+    outln!();
+    outln!("{:<22}{}", "data start sector", geometry.data_start_sector);
+    outln!("{:<22}{} ({})", "clusters", geometry.cluster_count, geometry.get_fat_type());
+    outln!("{:<22}{}", "needed FAT sectors", geometry.needed_fat_sectors);
+    outln!("{:<22}{} (+{} sectors)", "cylinders", geometry.cylinders, geometry.extra_sectors);
+    Ok(())
+}
+
+/** reserved <image> [--read <file> | --write <file>]: the raw sectors between
+ *  the boot sector and the first FAT. */
+fn run_reserved(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let blob: Option<Vec<u8>> = match matches.get_opt("write") {
+        Some(host_path) => Some(std::fs::read(host_path).context("Could not read file")?),
+        None => None
+    };
+    let mut fs: Fat12Fs = fs_options().read_only(blob.is_none()).open(&image_path).context("Could not open image")?;
+    if let Some(blob) = &blob { fs.write_reserved_region(blob).context("Could not write reserved region")?; }
+
+    let region: Vec<u8> = fs.read_reserved_region().context("Could not read reserved region")?;
+    if let Some(host_path) = matches.get_opt("read") { std::fs::write(host_path, &region).context("Could not write file")?; }
+    let used: usize = region.iter().rposition(|byte| *byte != 0).map_or(0, |last| last + 1);
+    let sectors: u16 = fs.get_boot_sector().reserved_sectors;
+    outln!("reserved region: {} sectors after the boot sector, {} bytes, {} up to the last non-zero byte", sectors.saturating_sub(1), region.len(), used);
+    Ok(())
+}
+
+/** serial <image> [--regenerate | --from-time <unix-seconds> | --set <XXXX-XXXX>]:
+ *  show or change the volume id. */
+fn run_serial(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let choices: usize = [matches.has_flag("regenerate"), matches.get_opt("from-time").is_some(), matches.get_opt("set").is_some()].iter().filter(|&&set| set).count();
+    if choices > 1 { return Err(CliError::invalid("only one of --regenerate, --from-time and --set can be used")); }
+
+    let volume_id: Option<u32> = if matches.has_flag("regenerate") {
+        Some(volume::random_volume_id())
+    } else if let Some(time) = matches.get_opt("from-time") {
+        let timestamp: u64 = time.parse().map_err(|_| CliError::invalid(format!("{} is not a unix time in seconds", time)))?;
+        Some(volume::volume_id_from_timestamp(timestamp, 0))
+    } else if let Some(text) = matches.get_opt("set") {
+        Some(volume::parse_volume_id(text).ok_or_else(|| CliError::invalid(format!("{} is not a volume id in the XXXX-XXXX form", text)))?)
+    } else {
+        None
+    };
+
+    let mut fs: Fat12Fs = fs_options().read_only(volume_id.is_none()).open(&image_path).context("Could not open image")?;
+    if let Some(volume_id) = volume_id {
+        fs.set_volume_id(volume_id).context("Could not set volume id")?;
+    }
+    match fs.get_boot_sector().get_volume_id() {
+        Some(volume_id) => outln!("{}", volume::format_volume_id(volume_id)),
+        None => outln!("(no volume id)")
+    }
+    Ok(())
+}
+
+/** map <image> <path> [--chs] [--entry] [--image [--svg <file>]]: runs of sectors of a file, for loaders that
+ *  read them directly. */
+fn run_map(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let mut fs: Fat12Fs = fs_options().open(&image_path).context("Could not open image")?;
+    if matches.has_flag("image") { return print_cluster_map(&mut fs, matches); }
+    let entry: DirectoryEntry = fs.get_entry(matches.get_arg(1)).context("Could not find file")?;
+    let extents: Vec<Extent> = fs.get_extents(&entry);
+    if matches.has_flag("entry") {
+        let placement: Placement = fs.locate(matches.get_arg(1)).context("Could not find file")?;
+        outln!("entry at sector {}, offset {}; first cluster {}\n", placement.entry_sector.0, placement.entry_offset, placement.first_cluster.0);
+    }
+
+    let boot_sector: &BootSector = fs.get_boot_sector();
+    let chs = |lba: Lba| -> Result<Chs, CliError> { lba.to_chs(boot_sector).ok_or_else(|| CliError::invalid("the BPB has no heads or sectors per track")) };
+    match matches.has_flag("chs") {
+        true => outln!("{:>8}  {:>6}  {:>10}  {:>10}", "lba", "count", "first chs", "last chs"),
+        false => outln!("{:>8}  {:>6}", "lba", "count")
+    }
+    for extent in extents {
+        match matches.has_flag("chs") {
+            true => outln!("{:>8}  {:>6}  {:>10}  {:>10}", extent.first.0, extent.count, chs(extent.first)?.to_string(), chs(Lba(extent.first.0 + extent.count - 1))?.to_string()),
+            false => outln!("{:>8}  {:>6}", extent.first.0, extent.count)
+        }
+    }
+    Ok(())
+}
+
+/** map --image: one symbol (or SVG square) per cluster of the volume. */
+fn print_cluster_map(fs: &mut Fat12Fs, matches: &Matches) -> Result<(), CliError> {
+    let map: ClusterMap = fs.get_cluster_map(matches.get_arg(1)).context("Could not read image")?;
+    if let Some(svg_path) = matches.get_opt("svg") {
+        std::fs::write(svg_path, map.to_svg(64)).context("Could not write SVG file")?;
+    } else {
+        out!("{}", map.to_text(64));
+    }
+    let states: [(ClusterState, &str); 5] = [(ClusterState::Marked, "marked"), (ClusterState::Used, "used"), (ClusterState::Free, "free"), (ClusterState::Bad, "bad"), (ClusterState::Reserved, "reserved")];
+    let legend: Vec<String> = states.iter().map(|(state, name)| format!("{} {} {}", state.get_symbol(), name, map.count(*state))).collect();
+    outln!("{}", legend.join(", "));
+    Ok(())
+}
+
+/** df <image> [--files] [--slots]: free and used space, and slack of the files. */
+fn run_df(matches: &Matches) -> Result<(), CliError> {
+    let image_path: String = image_arg(matches, 0);
+    let mut fs: Fat12Fs = fs_options().open(&image_path).context("Could not open image")?;
+
+    if matches.has_flag("files") {
+        let allocations: Vec<(String, Allocation)> = fs.get_allocations("/").context("Could not read image")?;
+        outln!("{:>10}  {:>8}  {:>10}  {:>8}  path", "size", "clusters", "allocated", "slack");
+        for (path, allocation) in &allocations {
+            outln!("{:>10}  {:>8}  {:>10}  {:>8}  /{}", allocation.size, allocation.clusters, allocation.allocated, allocation.get_slack(), path);
+        }
+        outln!();
+    }
+
+    if matches.has_flag("slots") {
+        let usages: Vec<(String, SlotUsage)> = fs.get_slot_usages("/").context("Could not read image")?;
+        outln!("{:>6}  {:>6}  {:>6}  {:>6}  {:>6}  path", "slots", "used", "lfn", "deleted", "free");
+        for (path, slots) in &usages {
+            outln!("{:>6}  {:>6}  {:>6}  {:>6}  {:>6}  {}", slots.total, slots.used, slots.long_names, slots.deleted, slots.free, path);
+        }
+        outln!();
+    }
+
+    let usage: Usage = fs.get_usage().context("Could not read image")?;
+    let cluster_size: u64 = usage.cluster_size;
+    outln!("cluster size:  {} bytes", cluster_size);
+    outln!("total:         {} clusters ({} bytes)", usage.total_clusters, usage.total_clusters as u64 * cluster_size);
+    outln!("used:          {} clusters ({} bytes)", usage.get_used_clusters(), usage.get_used_clusters() as u64 * cluster_size);
+    outln!("free:          {} clusters ({} bytes)", usage.free_clusters, usage.free_clusters as u64 * cluster_size);
+    outln!("files:         {} in {} directories", usage.files, usage.directories);
+    outln!("file data:     {} bytes in {} allocated", usage.file_bytes, usage.allocated_bytes);
+    outln!("slack:         {} bytes ({:.1}% internal fragmentation)", usage.get_slack(), usage.get_fragmentation() * 100.0);
+    print_root_slots(&mut fs)
+}
+
+/** Root directory slot usage, with a warning when it's about to run out:
+ *  unlike subdirectories, it can't grow. */
+fn print_root_slots(fs: &mut Fat12Fs) -> Result<(), CliError> {
+    let slots: SlotUsage = fs.get_slot_usage("/").context("Could not read root directory")?;
+    outln!("root slots:    {} used ({} by long names), {} deleted, {} free of {}", slots.used + slots.long_names, slots.long_names, slots.deleted, slots.free, slots.total);
+    if slots.get_available() * 10 <= slots.total { outln!("warning: only {} root directory slots left", slots.get_available()); }
+    Ok(())
+}
+
+/** config: print the config in use and where it was read from. */
+fn run_config() -> Result<(), CliError> {
+    let config: &Config = config();
+    for source in &config.sources { outln!("# {}", source.display()); }
+    outln!("codepage = \"{}\"", config.codepage.get_name());
+    let (sign, offset): (char, i32) = if config.timezone_offset < 0 { ('-', -config.timezone_offset) } else { ('+', config.timezone_offset) };
+    outln!("timezone_offset = \"{}{:02}:{:02}\"", sign, offset / 60, offset % 60);
+    outln!("conflict_policy = \"{}\"", config.conflict_policy.get_name());
+    outln!("hash_algorithm = \"{}\"", config.hash_algorithm.get_name());
+    outln!("name_policy = \"{}\"", config.name_policy.get_name());
+    outln!("validation = \"{}\"", config.validation.get_name());
+    outln!("verify_seal = {}", config.verify_seal);
+    outln!("recover_boot_sector = {}", config.recover_boot_sector);
+    outln!("discard = {}", config.discard);
+    outln!("cache_sectors = {}", config.cache_sectors);
+    outln!("zero_missing = {}", config.zero_missing);
+    outln!("geometry_source = \"{}\"", config.geometry_source.get_name());
+    outln!("unrepresentable = \"{}\"", config.unrepresentable.get_name());
+    outln!("slot_placement = \"{}\"", config.slot_placement.get_name());
+    outln!("name_matching = \"{}\"", config.name_matching.get_name());
+    match config.fat_mirroring {
+        FatMirroring::All => outln!("active_fat = \"all\""),
+        FatMirroring::Active(index) => outln!("active_fat = {}", index)
+    }
+    outln!("short_name_tails = \"{}\"", config.short_name_tails.as_ref().map_or("none", |tails| tails.get_name()));
+
+    let limits: Limits = config.limits;
+    outln!("\n[limits]");
+    outln!("max_file_size = {}", limits.max_file_size);
+    outln!("max_depth = {}", limits.max_depth);
+    outln!("max_entries = {}", limits.max_entries);
+    outln!("max_chain_length = {}", limits.max_chain_length);
+
+    let mount: MountOptions = config.mount;
+    outln!("\n[mount]");
+    outln!("uid = {}", mount.uid);
+    outln!("gid = {}", mount.gid);
+    outln!("fmask = \"{:03o}\"", mount.fmask);
+    outln!("dmask = \"{:03o}\"", mount.dmask);
+    outln!("showexec = {}", mount.showexec);
+
+    outln!("\n[throttle]");
+    for (command, rate) in &config.throttle { outln!("{} = {}", command, rate); }
+
+    outln!("\n[images]");
+    for (alias, path) in &config.images { outln!("{} = \"{}\"", alias, path.display()); }
+    for (name, geometry) in &config.geometries {
+        outln!("\n[geometry.{}]", name);
+        for (key, value) in geometry.get_keys() { outln!("{} = {}", key, value); }
+    }
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use std::{collections::BTreeMap, fs, path::Path};
+use rs_disk_reader::bpb;
+use rs_disk_reader::provision::{self, Item, Template};
+use rs_disk_reader::recovery::FloppyFormat;
+use rs_disk_reader::source::{FileSource, HostFile};
+use rs_disk_reader::{BootSector, ATTR_ARCHIVE, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM};
+
+use crate::config::{self, Config, GeometryProfile, Value};
+
+/** Timestamp of images whose manifest gives none: 1980-01-01, the FAT epoch. */
+const DEFAULT_TIMESTAMP: u64 = 315532800;
+
+/* ==== METHODS ============================================================= */
+/** Read a provisioning manifest, in the TOML subset of the config:
+ *
+ *  [image] takes format (size in KB of a standard floppy, 1440 by default),
+ *  geometry (a [geometry.NAME] of the config, applied over the format),
+ *  label, boot_code (a boot sector binary), stage2 (a blob for the reserved
+ *  sectors, reserving more of them if needed) and timestamp (unix time of
+ *  the entries). [bpb] overrides BPB fields by the names of the bpb command.
+ *  Then a [dir."PATH"] or [file."PATH"] section each, with attributes
+ *  ("RHSA" letters) and, for files, source (a host file) or content (text).
+ *
+ *  Host paths are relative to the manifest. Entries are written sorted by
+ *  path, so that the same manifest always gives the same image. */
+pub fn load(path: &Path, config: &Config) -> Result<Template, String> {
+    let content: String = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let sections: BTreeMap<String, BTreeMap<String, Value>> = config::parse(&content).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let base: &Path = path.parent().unwrap_or(Path::new("."));
+    let empty: BTreeMap<String, Value> = BTreeMap::new();
+
+    // Geometry: the standard format, then the named profile, then [bpb]
+    let image: &BTreeMap<String, Value> = sections.get("image").unwrap_or(&empty);
+    let format: String = match image.get("format") {
+        Some(Value::Integer(kilobytes)) => kilobytes.to_string(),
+        Some(Value::String(name)) => name.clone(),
+        Some(_) => return Err(String::from("format must be a size in KB or a preset name")),
+        None => String::from("1.44M")
+    };
+    let format: &FloppyFormat = FloppyFormat::find(&format).ok_or_else(|| format!("no standard floppy format {}, see the README for the presets", format))?;
+    let mut boot_sector: BootSector = provision::format_boot_sector(format);
+    if let Some(name) = get_string(image, "geometry")? {
+        let geometry: &GeometryProfile = config.geometries.get(&name).ok_or_else(|| format!("no [geometry.{}] in the config", name))?;
+        for (key, value) in geometry.get_keys() {
+            let field: &str = if key == "sectors_per_cylinder" { "sectors_per_track" } else { key };
+            boot_sector.set_field(field, value as u32).map_err(|e| e.to_string())?;
+        }
+    }
+    for (key, value) in sections.get("bpb").unwrap_or(&empty) {
+        let Value::Integer(number) = value else { return Err(format!("bpb field {} must be an integer", key)) };
+        let number: u32 = u32::try_from(*number).map_err(|_| format!("bpb field {} out of range: {}", key, number))?;
+        boot_sector.set_field(key, number).map_err(|e| e.to_string())?;
+    }
+
+    let boot_code: Option<Vec<u8>> = match get_string(image, "boot_code")? {
+        Some(file) => Some(fs::read(base.join(&file)).map_err(|e| format!("{}: {}", file, e))?),
+        None => None
+    };
+    let reserved_region: Option<Vec<u8>> = match get_string(image, "stage2")? {
+        Some(file) => Some(fs::read(base.join(&file)).map_err(|e| format!("{}: {}", file, e))?),
+        None => None
+    };
+    if let Some(blob) = &reserved_region {
+        let needed: u16 = bpb::reserved_sectors_for(blob.len(), boot_sector.bytes_per_sector);
+        boot_sector.reserved_sectors = boot_sector.reserved_sectors.max(needed);
+    }
+    let timestamp: u64 = match get_integer(image, "timestamp")? {
+        Some(timestamp) => u64::try_from(timestamp).map_err(|_| format!("invalid timestamp {}", timestamp))?,
+        None => DEFAULT_TIMESTAMP
+    };
+
+    let mut items: Vec<Item> = vec![];
+    for (header, keys) in &sections {
+        let (kind, path): (&str, &str) = match header.split_once('.') {
+            Some((kind @ ("dir" | "file"), path)) => (kind, path.trim_matches('"')),
+            _ if ["", "image", "bpb"].contains(&header.as_str()) => continue,
+            _ => return Err(format!("unknown section [{}]", header))
+        };
+        let path: String = format!("/{}", path.trim_matches('/'));
+        let attributes: u8 = match get_string(keys, "attributes")? {
+            Some(letters) => parse_attributes(&letters)?,
+            None if kind == "file" => ATTR_ARCHIVE,
+            None => 0
+        };
+        let source: Option<Box<dyn FileSource>> = match (kind, get_string(keys, "source")?, get_string(keys, "content")?) {
+            ("dir", None, None) => None,
+            ("dir", _, _) => return Err(format!("[{}]: directories have no source or content", header)),
+            ("file", Some(source), None) => Some(Box::new(HostFile(base.join(source)))),
+            ("file", None, Some(content)) => Some(Box::new(content)),
+            _ => return Err(format!("[{}]: give either source or content", header))
+        };
+        items.push(Item { path, source, attributes });
+    }
+    Ok(Template { boot_sector, boot_code, reserved_region, label: get_string(image, "label")?, timestamp, items })
+}
+
+/* ==== UTILS =============================================================== */
+fn get_string(keys: &BTreeMap<String, Value>, key: &str) -> Result<Option<String>, String> {
+    match keys.get(key) {
+        Some(Value::String(value)) => Ok(Some(value.clone())),
+        Some(_) => Err(format!("{} must be a string", key)),
+        None => Ok(None)
+    }
+}
+
+fn get_integer(keys: &BTreeMap<String, Value>, key: &str) -> Result<Option<i64>, String> {
+    match keys.get(key) {
+        Some(Value::Integer(value)) => Ok(Some(*value)),
+        Some(_) => Err(format!("{} must be an integer", key)),
+        None => Ok(None)
+    }
+}
+
+/** "RHSA" letters, in any order and case, to attribute flags. */
+fn parse_attributes(letters: &str) -> Result<u8, String> {
+    letters.chars().try_fold(0, |attributes, c| match c.to_ascii_uppercase() {
+        'R' => Ok(attributes | ATTR_READ_ONLY),
+        'H' => Ok(attributes | ATTR_HIDDEN),
+        'S' => Ok(attributes | ATTR_SYSTEM),
+        'A' => Ok(attributes | ATTR_ARCHIVE),
+        _ => Err(format!("invalid attribute {:?}, expected R, H, S or A", c))
+    })
+}
+
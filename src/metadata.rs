@@ -0,0 +1,84 @@
+use std::{cmp::Ordering, io};
+
+use crate::{attributes_to_string, fat_datetime_to_unix, Cluster, DirectoryEntry, Fat12Fs};
+
+/* ==== STRUCTS ============================================================= */
+/** Decoded view of a directory entry: what listings need, without the
+ *  packed on-disk fields. Times are unix seconds, as the image stores them. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    /** Name decoded with the code page and quirks of the image. */
+    pub name: String,
+    pub attributes: u8,
+    pub is_directory: bool,
+
+    /** File size, 0 for directories. */
+    pub size: u64,
+    pub first_cluster: Cluster,
+    pub created: u64,
+    pub modified: u64,
+
+    /** Only the day is stored: this is its start. */
+    pub accessed: u64
+}
+
+/** Keys to sort listings by. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Cluster
+}
+
+impl Metadata {
+    /** Attribute flags as letters, as DirectoryEntry::get_attributes_string. */
+    pub fn get_attributes_string(&self) -> String {
+        attributes_to_string(self.attributes)
+    }
+}
+
+impl SortKey {
+    pub fn from_name(name: &str) -> Option<SortKey> {
+        match name {
+            "name" => Some(SortKey::Name),
+            "size" => Some(SortKey::Size),
+            "mtime" => Some(SortKey::Modified),
+            "cluster" => Some(SortKey::Cluster),
+            _ => None
+        }
+    }
+
+    /** Order of two entries, ties broken by name. */
+    pub fn compare(&self, a: &Metadata, b: &Metadata) -> Ordering {
+        let ordering: Ordering = match self {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size.cmp(&b.size),
+            SortKey::Modified => a.modified.cmp(&b.modified),
+            SortKey::Cluster => a.first_cluster.cmp(&b.first_cluster)
+        };
+        ordering.then_with(|| a.name.cmp(&b.name))
+    }
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    pub fn get_metadata(&self, entry: &DirectoryEntry) -> Metadata {
+        Metadata {
+            name: self.get_name(entry),
+            attributes: entry.attributes,
+            is_directory: entry.is_directory(),
+            size: if entry.is_directory() { 0 } else { entry.file_size as u64 },
+            first_cluster: entry.get_first_cluster(),
+            created: fat_datetime_to_unix(entry.creation_date, entry.creation_time) + entry.creation_time_tenths as u64 / 100,
+            modified: entry.get_last_change_timestamp(),
+            accessed: fat_datetime_to_unix(entry.last_access_date, 0)
+        }
+    }
+
+    /** Metadata of the entry at the given path. */
+    pub fn metadata(&mut self, path: &str) -> io::Result<Metadata> {
+        let entry: DirectoryEntry = self.get_entry(path)?;
+        Ok(self.get_metadata(&entry))
+    }
+}
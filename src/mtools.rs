@@ -0,0 +1,397 @@
+use std::{collections::{BTreeMap, HashSet}, fs, io::{self, Write}, path::{Path, PathBuf}, time::UNIX_EPOCH};
+use rs_disk_reader::{bulk::{self, BulkOptions}, text::TextMode, image_time_to_system_time, Cluster, DirectoryEntry, Fat12Fs, ATTR_HIDDEN, ATTR_SYSTEM};
+
+use crate::error::{CliError, Context};
+
+/** Commands answered in mtools style, either as the first argument or as
+ *  the name the binary is called with (e.g. through a link named mcopy). */
+pub const COMMANDS: &[&str] = &["mdir", "mcopy", "mdel", "mmd", "mtype"];
+
+/* ==== STRUCTS ============================================================= */
+/** mtools style command line: single letter flags, which can be grouped,
+ *  -i and -D with a value, then the paths. */
+struct Args {
+    flags: Vec<char>,
+    image: Option<String>,
+    clash: Option<char>,
+    paths: Vec<String>
+}
+
+/** A path argument: "::/path" in the image given with -i, "a:/path" in the
+ *  image the config gives the alias a to, anything else on the host. */
+enum Location {
+    Host(PathBuf),
+    Image { image: String, drive: String, path: String }
+}
+
+/** Images opened so far, by path: all the paths of a command that are on
+ *  the same image go through a single handle. */
+#[derive(Default)]
+struct Images {
+    opened: BTreeMap<String, Fat12Fs>
+}
+
+impl Args {
+    fn parse(name: &str, args: Vec<String>) -> Result<Args, CliError> {
+        let allowed: &str = match name {
+            "mdir" => "abswf",
+            "mcopy" => "onsQmvtb",
+            "mdel" => "v",
+            "mmd" => "",
+            _ => "ts"
+        };
+        let mut parsed: Args = Args { flags: vec![], image: None, clash: None, paths: vec![] };
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" { parsed.paths.extend(args.by_ref()); break; }
+            let Some(letters) = arg.strip_prefix('-').filter(|letters| !letters.is_empty()) else {
+                parsed.paths.push(arg);
+                continue;
+            };
+
+            for (i, letter) in letters.char_indices() {
+                // The value of -i and -D is the rest of the word, or the next one
+                if letter == 'i' || (letter == 'D' && name != "mdir" && name != "mtype") {
+                    let rest: &str = &letters[i + 1..];
+                    let value: String = if rest.is_empty() { args.next().ok_or_else(|| usage(name, format!("-{} needs a value", letter)))? } else { rest.to_string() };
+                    if letter == 'i' { parsed.image = Some(crate::config().resolve_image(&value)); } else { parsed.clash = Some(clash_arg(name, &value)?); }
+                    break;
+                }
+                if !allowed.contains(letter) { return Err(usage(name, format!("unknown option -{}", letter))); }
+                parsed.flags.push(letter);
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn has_flag(&self, flag: char) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    /** Whether an existing target is overwritten (-o, -D o) or skipped
+     *  (-D s); without either it's an error, as nobody answers the prompt. */
+    fn get_clash(&self) -> Option<char> {
+        if self.has_flag('o') { Some('o') } else { self.clash }
+    }
+
+    fn location(&self, path: &str) -> Result<Location, CliError> {
+        let image_path = |path: &str| format!("/{}", path.trim_start_matches(['/', '\\']));
+        if let Some(path) = path.strip_prefix("::") {
+            let image: String = self.image.clone().ok_or_else(|| CliError::invalid(format!("{}: :: paths need -i <image>", path)))?;
+            return Ok(Location::Image { image, drive: String::from("::"), path: image_path(path) });
+        }
+        match path.split_once(':') {
+            Some((drive, path)) if drive.len() == 1 && drive.chars().all(|letter| letter.is_ascii_alphabetic()) => {
+                let images = &crate::config().images;
+                let image: &Path = images.get(&drive.to_lowercase()).or_else(|| images.get(&drive.to_uppercase()))
+                    .ok_or_else(|| CliError::invalid(format!("drive {}: is not an image alias in the config", drive)))?;
+                Ok(Location::Image { image: image.to_string_lossy().into_owned(), drive: format!("{}:", drive.to_uppercase()), path: image_path(path) })
+            },
+            _ => Ok(Location::Host(PathBuf::from(path)))
+        }
+    }
+}
+
+impl Images {
+    /** Handle on the image, opened for writing if asked (or already opened
+     *  that way). */
+    fn get(&mut self, image: &str, write: bool) -> Result<&mut Fat12Fs, CliError> {
+        if self.opened.get(image).is_none_or(|fs| write && fs.is_read_only()) {
+            let fs: Fat12Fs = crate::fs_options().read_only(!write).open(image).context(&format!("Could not open image {}", image))?;
+            self.opened.insert(image.to_string(), fs);
+        }
+        Ok(self.opened.get_mut(image).expect("image opened above"))
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Run one of the COMMANDS with its mtools arguments. */
+pub fn run(name: &str, args: Vec<String>) -> Result<(), CliError> {
+    let args: Args = Args::parse(name, args)?;
+    let mut images: Images = Images::default();
+    match name {
+        "mdir" => run_mdir(&args, &mut images),
+        "mcopy" => run_mcopy(&args, &mut images),
+        "mdel" => each_path(name, &args.paths, args.has_flag('Q'), |path| run_mdel(&args, &mut images, path)),
+        "mmd" => each_path(name, &args.paths, args.has_flag('Q'), |path| run_mmd(&args, &mut images, path)),
+        _ => each_path(name, &args.paths, args.has_flag('Q'), |path| run_mtype(&args, &mut images, path))
+    }
+}
+
+/** mdir [-a] [-b] [-s] [-w] [-f] [paths]: list directories as DIR does,
+ *  the root of a: (or of -i) without paths. */
+fn run_mdir(args: &Args, images: &mut Images) -> Result<(), CliError> {
+    let default: &str = if args.image.is_some() { "::/" } else { "a:/" };
+    let paths: Vec<&str> = if args.paths.is_empty() { vec![default] } else { args.paths.iter().map(String::as_str).collect() };
+    for path in paths {
+        let Location::Image { image, drive, path } = args.location(path)? else { return Err(CliError::invalid(format!("{} is not an image path", path))) };
+        let fs: &mut Fat12Fs = images.get(&image, false)?;
+        let entry: Option<DirectoryEntry> = if path == "/" { None } else { Some(fs.get_entry(&path).context("Could not find directory")?) };
+        if entry.is_some_and(|entry| !entry.is_directory()) {
+            let entry: DirectoryEntry = entry.expect("checked above");
+            if args.has_flag('b') { outln!("{}{}", drive, path); } else { outln!("{}", dir_line(fs, &entry)); }
+            continue;
+        }
+
+        // First clusters of the directories listed, so that a loop in the
+        // tree gets listed once instead of until the depth limit
+        let recursive: bool = args.has_flag('s');
+        let mut listed: HashSet<Cluster> = HashSet::from([entry.map_or(Cluster(0), |entry| entry.get_first_cluster())]);
+        let mut directories: Vec<String> = vec![path];
+        while let Some(directory) = directories.pop() {
+            let listing = fs.read_dir(&directory).context("Could not read directory")?;
+            let entries: Vec<DirectoryEntry> = listing.entries()
+                .filter(|entry| !entry.is_volume_label() && (args.has_flag('a') || entry.attributes & (ATTR_HIDDEN | ATTR_SYSTEM) == 0))
+                .copied().collect();
+            let prefix: String = if directory.ends_with('/') { directory.clone() } else { format!("{}/", directory) };
+
+            if !args.has_flag('b') {
+                let letter: &str = drive.strip_suffix(':').unwrap_or(&drive);
+                match fs.get_volume_label().ok().flatten() {
+                    Some(label) => outln!(" Volume in drive {} is {}", letter, label),
+                    None => outln!(" Volume in drive {} has no label", letter)
+                }
+                outln!(" Directory for {}{}\n", drive, directory);
+            }
+            let (mut files, mut bytes): (usize, u64) = (0, 0);
+            for entry in &entries {
+                match args.has_flag('b') {
+                    true if !entry.is_dot_entry() => outln!("{}{}{}", drive, prefix, fs.get_name(entry)),
+                    true => {},
+                    false => outln!("{}", dir_line(fs, entry))
+                }
+                if !entry.is_directory() { files += 1; bytes += entry.file_size as u64; }
+            }
+            if !args.has_flag('b') {
+                let free: u64 = fs.get_free_clusters() as u64 * fs.get_boot_sector().get_cluster_size() as u64;
+                outln!("{:>9} files{:>22} bytes", files, group_digits(bytes));
+                outln!("{:>37} bytes free\n", group_digits(free));
+            }
+
+            // Deepest last on the stack, so that the tree comes out in order
+            if recursive {
+                let subdirectories: Vec<&DirectoryEntry> = entries.iter().rev()
+                    .filter(|entry| entry.is_directory() && !entry.is_dot_entry() && listed.insert(entry.get_first_cluster()))
+                    .collect();
+                directories.extend(subdirectories.into_iter().map(|entry| format!("{}{}", prefix, fs.get_name(entry))));
+            }
+        }
+    }
+    Ok(())
+}
+
+/** mcopy [-o] [-D o|s] [-s] [-m] [-t] [-v] [-Q] <sources> <target>: copy
+ *  between the host and the image, in either direction, or within images. */
+fn run_mcopy(args: &Args, images: &mut Images) -> Result<(), CliError> {
+    let Some((target, sources)) = args.paths.split_last().filter(|(_, sources)| !sources.is_empty()) else {
+        return Err(usage("mcopy", "needs <sources> and a <target>"));
+    };
+    let target: Location = args.location(target)?;
+
+    // A target that is a directory takes the sources under their own names
+    let into_directory: bool = match &target {
+        Location::Host(path) => path.is_dir(),
+        Location::Image { image, path, .. } => {
+            let fs: &mut Fat12Fs = images.get(image, true)?;
+            path == "/" || fs.get_entry(path).is_ok_and(|entry| entry.is_directory())
+        }
+    };
+    if sources.len() > 1 && !into_directory { return Err(CliError::invalid("copying several files needs a directory as the target")); }
+
+    each_path("mcopy", sources, args.has_flag('Q'), |source| {
+        let source: Location = args.location(source)?;
+        let name: String = match &source {
+            Location::Host(path) => path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default(),
+            Location::Image { path, .. } => path.rsplit('/').next().unwrap_or_default().to_string()
+        };
+        let target: Location = match (&target, into_directory) {
+            (Location::Host(path), true) => { bulk::check_host_name(&name).context("Could not copy file")?; Location::Host(path.join(&name)) },
+            (Location::Image { image, drive, path }, true) => Location::Image { image: image.clone(), drive: drive.clone(), path: format!("{}/{}", path.trim_end_matches('/'), name) },
+            (Location::Host(path), false) => Location::Host(path.clone()),
+            (Location::Image { image, drive, path }, false) => Location::Image { image: image.clone(), drive: drive.clone(), path: path.clone() }
+        };
+        if args.has_flag('v') { outln!("Copying {}", name); }
+        copy(args, images, &source, &target)
+    })
+}
+
+/** Copy a single file, or with -s a directory tree. */
+fn copy(args: &Args, images: &mut Images, source: &Location, target: &Location) -> Result<(), CliError> {
+    // Directories: only between the host and an image, with -s
+    let is_directory: bool = match source {
+        Location::Host(path) => path.is_dir(),
+        Location::Image { image, path, .. } => path == "/" || images.get(image, false)?.get_entry(path).context("Could not find file")?.is_directory()
+    };
+    if is_directory {
+        if !args.has_flag('s') { return Err(CliError::invalid(format!("{} is a directory, -s copies it", location_name(source)))); }
+        let text_mode: TextMode = if args.has_flag('t') { TextMode::line_endings() } else { TextMode::default() };
+        let options: BulkOptions = BulkOptions { timezone_offset: crate::config().timezone_offset, preserve_times: args.has_flag('m'), text_mode, ..BulkOptions::default() };
+        return match (source, target) {
+            (Location::Host(host_dir), Location::Image { image, path, .. }) => {
+                let fs: &mut Fat12Fs = images.get(image, true)?;
+                if !fs.exists(path) { fs.create_dir(path, crate::config().to_image_time(fs.now())).context("Could not create directory")?; }
+                bulk::import(fs, host_dir, path, &options).map(|_| ()).context("Could not copy directory")
+            },
+            (Location::Image { image, path, .. }, Location::Host(host_dir)) => {
+                bulk::extract(images.get(image, false)?, path, host_dir, &options).map(|_| ()).context("Could not copy directory")
+            },
+            _ => Err(CliError::invalid("directories are only copied between the host and an image"))
+        };
+    }
+
+    // Take the content, and the time to give the copy with -m
+    let (mut data, modified): (Vec<u8>, Option<u64>) = match source {
+        Location::Host(path) => {
+            let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).context("Could not read file")?;
+            let timestamp: u64 = crate::config().to_image_time(modified.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+            (fs::read(path).context("Could not read file")?, Some(timestamp))
+        },
+        Location::Image { image, path, .. } => {
+            let fs: &mut Fat12Fs = images.get(image, false)?;
+            let timestamp: u64 = fs.get_entry(path).context("Could not find file")?.get_last_change_timestamp();
+            (fs.read_file(path).context("Could not read file from image")?, Some(timestamp))
+        }
+    };
+    let modified: Option<u64> = modified.filter(|_| args.has_flag('m'));
+
+    // Text mode: DOS line ends in the image, host ones outside
+    if args.has_flag('t') {
+        data = match (source, target) {
+            (Location::Host(_), Location::Image { .. }) => TextMode::line_endings().to_image(&data).context("Could not convert file")?,
+            (Location::Image { .. }, Location::Host(_)) => TextMode::line_endings().to_host(&data),
+            _ => data
+        };
+    }
+
+    match target {
+        Location::Host(path) => {
+            if path.exists() && !check_clash(args, &path.display().to_string())? { return Ok(()); }
+            fs::write(path, data).context("Could not write file")?;
+            if let Some(timestamp) = modified {
+                let file: fs::File = fs::File::options().write(true).open(path).context("Could not write file")?;
+                file.set_modified(image_time_to_system_time(timestamp, crate::config().timezone_offset)).context("Could not write file")?;
+            }
+            Ok(())
+        },
+        Location::Image { image, path, .. } => {
+            let fs: &mut Fat12Fs = images.get(image, true)?;
+            if fs.exists(path) && !check_clash(args, path)? { return Ok(()); }
+            let timestamp: u64 = modified.unwrap_or_else(|| crate::config().to_image_time(fs.now()));
+            fs.write_file(path, &data, timestamp).context("Could not write file to image")?;
+            Ok(())
+        }
+    }
+}
+
+/** mdel [-v] <files>: delete files (not directories) from the image. */
+fn run_mdel(args: &Args, images: &mut Images, path: &str) -> Result<(), CliError> {
+    let Location::Image { image, drive, path } = args.location(path)? else { return Err(CliError::invalid(format!("{} is not an image path", path))) };
+    let fs: &mut Fat12Fs = images.get(&image, true)?;
+    if fs.get_entry(&path).context("Could not find file")?.is_directory() { return Err(CliError::invalid(format!("{}{} is a directory", drive, path))); }
+    if args.has_flag('v') { outln!("Removing {}{}", drive, path); }
+    fs.remove(&path).context("Could not delete file")
+}
+
+/** mmd [-D s] <directories>: create directories in the image. */
+fn run_mmd(args: &Args, images: &mut Images, path: &str) -> Result<(), CliError> {
+    let Location::Image { image, path, .. } = args.location(path)? else { return Err(CliError::invalid(format!("{} is not an image path", path))) };
+    let fs: &mut Fat12Fs = images.get(&image, true)?;
+    if fs.exists(&path) && args.get_clash() == Some('s') { return Ok(()); }
+    let timestamp: u64 = crate::config().to_image_time(fs.now());
+    fs.create_dir(&path, timestamp).context("Could not create directory")?;
+    Ok(())
+}
+
+/** mtype [-t] [-s] <files>: print files of the image. */
+fn run_mtype(args: &Args, images: &mut Images, path: &str) -> Result<(), CliError> {
+    let Location::Image { image, path, .. } = args.location(path)? else { return Err(CliError::invalid(format!("{} is not an image path", path))) };
+    let mut data: Vec<u8> = images.get(&image, false)?.read_file(&path).context("Could not read file from image")?;
+    if args.has_flag('t') { data = TextMode::line_endings().to_host(&data); }
+    if args.has_flag('s') { data.iter_mut().for_each(|byte| *byte &= 0x7F); }
+    io::stdout().write_all(&data).context("Could not write file")
+}
+
+/* ==== UTILS =============================================================== */
+/** Run the command on every path, going on after errors (unless -Q) as
+ *  mtools does: the exit code follows the first one. */
+fn each_path(name: &str, paths: &[String], quit: bool, mut command: impl FnMut(&str) -> Result<(), CliError>) -> Result<(), CliError> {
+    match paths {
+        [] => return Err(usage(name, "needs at least a path")),
+        [path] => return command(path),
+        _ => {}
+    }
+    let (mut failed, mut first_error): (usize, Option<CliError>) = (0, None);
+    for path in paths {
+        let Err(e) = command(path) else { continue };
+        if quit || e.is_broken_pipe() { return Err(e); }
+        eprintln!("error: {}", e.message);
+        failed += 1;
+        first_error.get_or_insert(e);
+    }
+    match first_error {
+        Some(e) => Err(CliError {
+            message: format!("{} of {} paths failed, the first one with {}", failed, paths.len(), e.message),
+            details: e.details.as_ref().map(|details| format!("{} of {} paths failed, the first one with {}", failed, paths.len(), details)),
+            ..e
+        }),
+        None => Ok(())
+    }
+}
+
+/** Whether to go on writing over an existing target. */
+fn check_clash(args: &Args, name: &str) -> Result<bool, CliError> {
+    match args.get_clash() {
+        Some('o') => Ok(true),
+        Some(_) => Ok(false),
+        None => Err(CliError::invalid(format!("{} already exists, -o or -D o overwrites it", name)))
+    }
+}
+
+fn clash_arg(name: &str, value: &str) -> Result<char, CliError> {
+    match value {
+        "o" | "O" => Ok('o'),
+        "s" | "S" => Ok('s'),
+        _ => Err(usage(name, format!("-D {} is not supported, only o (overwrite) and s (skip)", value)))
+    }
+}
+
+fn usage(name: &str, message: impl std::fmt::Display) -> CliError {
+    CliError::usage(format!("{}: {}", name, message), format!("usage: {} {}\n", name, match name {
+        "mdir" => "[-a] [-b] [-s] [-w] [-f] [-i <image>] [paths]",
+        "mcopy" => "[-o] [-D o|s] [-s] [-m] [-t] [-v] [-Q] [-i <image>] <sources> <target>",
+        "mdel" => "[-v] [-i <image>] <files>",
+        "mmd" => "[-D s] [-i <image>] <directories>",
+        _ => "[-t] [-s] [-i <image>] <files>"
+    }))
+}
+
+fn location_name(location: &Location) -> String {
+    match location {
+        Location::Host(path) => path.display().to_string(),
+        Location::Image { drive, path, .. } => format!("{}{}", drive, path)
+    }
+}
+
+/** An entry as DIR lists it: name and extension apart, size or <DIR>, date
+ *  and time of the last change. */
+fn dir_line(fs: &Fat12Fs, entry: &DirectoryEntry) -> String {
+    let name: String = fs.get_name(entry);
+    let (base, extension): (&str, &str) = match entry.is_dot_entry() {
+        true => (&name, ""),
+        false => name.rsplit_once('.').unwrap_or((&name, ""))
+    };
+    let size: String = if entry.is_directory() { String::from("<DIR>") } else { (entry.file_size as u64).to_string() };
+    let modified: String = rs_disk_reader::format_timestamp(entry.get_last_change_timestamp());
+    format!("{:<8} {:<3} {:>10} {}  {}", base, extension, size, &modified[..10], &modified[11..16])
+}
+
+/** Number with the thousands apart, as DIR prints sizes: "1 456 128". */
+fn group_digits(number: u64) -> String {
+    let digits: String = number.to_string();
+    let mut grouped: String = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) { grouped.push(' '); }
+        grouped.push(digit);
+    }
+    grouped
+}
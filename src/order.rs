@@ -0,0 +1,52 @@
+use std::{cmp::Ordering, io, mem};
+
+use crate::audit::Mutation;
+use crate::metadata::{Metadata, SortKey};
+use crate::{into_struct_vec, struct_to_bytes, Directory, DirectoryEntry, Fat12Fs, ATTR_LFN};
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Reorder the slots of the directory by the key, for the programs that
+     *  list them in physical order. Dot entries and the volume label stay
+     *  first, long name slots stay with their entry, deleted slots are
+     *  dropped and the freed ones end the directory. Entries keep their
+     *  clusters: only the directory itself is rewritten. */
+    pub fn sort_dir(&mut self, path: &str, key: SortKey, reverse: bool) -> io::Result<()> {
+        self.check_writable()?;
+        let directory: Directory = self.read_dir(path)?;
+
+        // Entries with the long name slots before them; slots of deleted
+        // entries, and orphaned ones, go with the deleted entries
+        let mut fixed: Vec<DirectoryEntry> = vec![];
+        let mut groups: Vec<(Metadata, Vec<DirectoryEntry>)> = vec![];
+        let mut pending: Vec<DirectoryEntry> = vec![];
+        for entry in directory.entries.iter().take_while(|entry| !entry.is_end()) {
+            if entry.is_deleted() {
+                pending.clear();
+            } else if entry.attributes == ATTR_LFN {
+                pending.push(*entry);
+            } else if entry.is_dot_entry() || entry.is_volume_label() {
+                pending.clear();
+                fixed.push(*entry);
+            } else {
+                pending.push(*entry);
+                groups.push((self.get_metadata(entry), mem::take(&mut pending)));
+            }
+        }
+        groups.sort_by(|(a, _), (b, _)| {
+            let ordering: Ordering = key.compare(a, b);
+            if reverse { ordering.reverse() } else { ordering }
+        });
+
+        // Slots past the old end marker already read as free
+        let slots: Vec<DirectoryEntry> = fixed.into_iter().chain(groups.into_iter().flat_map(|(_, slots)| slots)).collect();
+        let empty: DirectoryEntry = into_struct_vec::<DirectoryEntry>(vec![0; 32])[0];
+        let end: usize = directory.entries.iter().position(|entry| entry.is_end()).unwrap_or(directory.entries.len());
+        for index in 0..end {
+            let entry: &DirectoryEntry = slots.get(index).unwrap_or(&empty);
+            if struct_to_bytes(entry) != struct_to_bytes(&directory.entries[index]) { self.write_directory_entry(&directory, index, entry)?; }
+        }
+        self.record(Mutation::EntryUpdated { path: path.to_string() });
+        Ok(())
+    }
+}
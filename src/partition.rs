@@ -0,0 +1,91 @@
+use std::{fs::File, io};
+
+use crate::read_exact_at;
+
+/** Partition types that hold a FAT filesystem. */
+const FAT_PARTITION_TYPES: &[u8] = &[0x01, 0x04, 0x06, 0x0B, 0x0C, 0x0E, 0x11, 0x14, 0x16, 0x1B, 0x1C, 0x1E];
+
+/* ==== STRUCTS ============================================================= */
+/** Slot of the partition table in the MBR. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PartitionEntry {
+    /** 0x80 for the active partition, 0 otherwise. */
+    pub status: u8,
+    pub partition_type: u8,
+    pub first_lba: u32,
+    pub sector_count: u32
+}
+
+/** How the image is laid out: the filesystem right at the start, as on
+ *  floppies and "superfloppy" USB sticks, or inside an MBR partition. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Superfloppy,
+    Partitioned { index: usize, entry: PartitionEntry }
+}
+
+impl Layout {
+    /** Byte offset of the filesystem in the image. */
+    pub fn get_offset(&self) -> u64 {
+        match self {
+            Layout::Superfloppy => 0,
+            Layout::Partitioned { entry, .. } => entry.first_lba as u64 * 512
+        }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Tell whether the image starts with a FAT boot sector or with an MBR, and
+ *  in the latter case pick the first FAT partition whose first sector looks
+ *  like a boot sector. Images that are neither are taken as superfloppies,
+ *  leaving it to the validation to refuse them. */
+pub fn detect_layout(disk: &File) -> io::Result<Layout> {
+    let mut sector: [u8; 512] = [0; 512];
+    if read_exact_at(disk, &mut sector, 0).is_err() { return Ok(Layout::Superfloppy); }
+    if looks_like_boot_sector(&sector) { return Ok(Layout::Superfloppy); }
+
+    let Some(table) = read_partition_table(&sector) else { return Ok(Layout::Superfloppy) };
+    for (index, entry) in table.into_iter().enumerate() {
+        let Some(entry) = entry else { continue };
+        if !FAT_PARTITION_TYPES.contains(&entry.partition_type) { continue; }
+        let mut first: [u8; 512] = [0; 512];
+        let found: bool = read_exact_at(disk, &mut first, entry.first_lba as u64 * 512).is_ok() && looks_like_boot_sector(&first);
+        if found { return Ok(Layout::Partitioned { index, entry }); }
+    }
+    Ok(Layout::Superfloppy)
+}
+
+/** The four slots of the MBR partition table, None if the sector has no
+ *  0x55AA signature or a slot has a status other than 0 and 0x80. Empty
+ *  slots are None. */
+pub fn read_partition_table(sector: &[u8; 512]) -> Option<[Option<PartitionEntry>; 4]> {
+    if sector[510..512] != [0x55, 0xAA] { return None; }
+    let mut entries: [Option<PartitionEntry>; 4] = [None; 4];
+    for (slot, entry) in entries.iter_mut().enumerate() {
+        let bytes: &[u8] = &sector[446 + slot * 16..462 + slot * 16];
+        let word = |i: usize| u32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+        if bytes[0] != 0 && bytes[0] != 0x80 { return None; }
+        if bytes[4] == 0 || word(12) == 0 { continue; }
+        *entry = Some(PartitionEntry { status: bytes[0], partition_type: bytes[4], first_lba: word(8), sector_count: word(12) });
+    }
+    Some(entries)
+}
+
+/** Heuristic on the first sector: a x86 jump (EB xx 90 or E9 xx xx) and a
+ *  BPB with sizes that make sense. An MBR starts with code, but its bytes
+ *  don't give a plausible BPB as well. */
+pub fn looks_like_boot_sector(sector: &[u8; 512]) -> bool {
+    let jump: bool = (sector[0] == 0xEB && sector[2] == 0x90) || sector[0] == 0xE9;
+    let bytes_per_sector: u16 = u16::from_le_bytes([sector[11], sector[12]]);
+    let sectors_per_cluster: u8 = sector[13];
+    let reserved_sectors: u16 = u16::from_le_bytes([sector[14], sector[15]]);
+    let fat_count: u8 = sector[16];
+    let media_descriptor: u8 = sector[21];
+
+    jump
+        && bytes_per_sector.is_power_of_two() && (128..=4096).contains(&bytes_per_sector)
+        && sectors_per_cluster.is_power_of_two()
+        && reserved_sectors > 0
+        && (1..=2).contains(&fat_count)
+        && (media_descriptor == 0xF0 || media_descriptor >= 0xF8)
+}
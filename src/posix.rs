@@ -0,0 +1,191 @@
+use std::io;
+
+use crate::{DirectoryEntry, Fat12Fs, ATTR_ARCHIVE, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM};
+
+/** File type bits of a mode. */
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+
+/** Extended attribute holding the flags of an entry as "RHSA" letters, only
+ *  the ones set: "RA" for a read-only file to back up. */
+pub const XATTR_ATTRIBUTES: &str = "user.fat.attributes";
+
+/** Extended attributes holding a single flag each, "0" or "1". */
+pub const XATTR_FLAGS: [(&str, u8); 4] = [
+    ("user.fat.readonly", ATTR_READ_ONLY),
+    ("user.fat.hidden", ATTR_HIDDEN),
+    ("user.fat.system", ATTR_SYSTEM),
+    ("user.fat.archive", ATTR_ARCHIVE)
+];
+
+/** Flags the attributes extended attribute has a letter for. */
+const LETTERS: [(u8, char); 4] = [(ATTR_READ_ONLY, 'R'), (ATTR_HIDDEN, 'H'), (ATTR_SYSTEM, 'S'), (ATTR_ARCHIVE, 'A')];
+
+/* ==== STRUCTS ============================================================= */
+/** Owner and permissions a mount gives every entry, FAT having neither, as
+ *  the options of the same names of the Linux vfat driver: a non-root user
+ *  mounting an image passes their own uid and gid to be able to write. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MountOptions {
+    pub uid: u32,
+    pub gid: u32,
+
+    /** Permission bits taken away from files and from directories. */
+    pub fmask: u32,
+    pub dmask: u32,
+
+    /** Only give execute permission to .EXE, .COM and .BAT files, instead
+     *  of to every file. */
+    pub showexec: bool
+}
+
+/** What a mount shows of an entry, see Fat12Fs::stat. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stat {
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u64,
+
+    /** Unix time of the last change, the root having none (0). */
+    pub modified: u64
+}
+
+impl Default for MountOptions {
+    fn default() -> MountOptions {
+        MountOptions { uid: 0, gid: 0, fmask: 0o022, dmask: 0o022, showexec: false }
+    }
+}
+
+impl MountOptions {
+    /** Set both fmask and dmask. */
+    pub fn umask(mut self, umask: u32) -> MountOptions {
+        self.fmask = umask;
+        self.dmask = umask;
+        self
+    }
+
+    /** Parse options in the form of mount -o: "uid=1000,gid=1000,umask=022",
+     *  masks in octal, other options ignored as vfat ones of no use here. */
+    pub fn from_options(options: &str) -> Result<MountOptions, String> {
+        let mut mount: MountOptions = MountOptions::default();
+        for option in options.split(',').map(str::trim).filter(|option| !option.is_empty()) {
+            let (key, value): (&str, &str) = option.split_once('=').unwrap_or((option, ""));
+            let invalid = || format!("invalid mount option {}", option);
+            match key {
+                "uid" => mount.uid = value.parse().map_err(|_| invalid())?,
+                "gid" => mount.gid = value.parse().map_err(|_| invalid())?,
+                "umask" => mount = mount.umask(parse_mask(value).ok_or_else(invalid)?),
+                "fmask" => mount.fmask = parse_mask(value).ok_or_else(invalid)?,
+                "dmask" => mount.dmask = parse_mask(value).ok_or_else(invalid)?,
+                "showexec" => mount.showexec = true,
+                _ => {}
+            }
+        }
+        Ok(mount)
+    }
+
+    /** Mode of the entry: everything but the mask, without the write bits
+     *  if it's a read-only file (vfat ignores the flag on directories). */
+    pub fn get_mode(&self, entry: &DirectoryEntry) -> u32 {
+        if entry.is_directory() { return S_IFDIR | (0o777 & !self.dmask); }
+        let mut permissions: u32 = 0o777 & !self.fmask;
+        if entry.attributes & ATTR_READ_ONLY != 0 { permissions &= !0o222; }
+        if self.showexec && ![&b"EXE"[..], b"COM", b"BAT"].contains(&&entry.name[8..]) { permissions &= !0o111; }
+        S_IFREG | permissions
+    }
+
+    pub fn get_root_mode(&self) -> u32 {
+        S_IFDIR | (0o777 & !self.dmask)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** The FAT attributes, which POSIX has no place for, mapped as the Linux
+ *  vfat driver does: extended attributes, and read-only as the write bits
+ *  of the mode, owner and permissions coming from the MountOptions. Nothing
+ *  here mounts an image; the shell commands of the same names use the
+ *  mapping, and a FUSE backend of one's own would call it from its getattr,
+ *  getxattr, setxattr and setattr handlers. The root directory has no entry,
+ *  so no attributes either. */
+impl Fat12Fs {
+    /** Owner, mode, size and last change of the entry at the path, as a
+     *  mount with those options shows it. */
+    pub fn stat(&mut self, path: &str, options: &MountOptions) -> io::Result<Stat> {
+        let (mode, size, modified): (u32, u64, u64) = match is_root(path) {
+            true => (options.get_root_mode(), 0, 0),
+            false => {
+                let entry: DirectoryEntry = self.get_entry(path)?;
+                (options.get_mode(&entry), entry.file_size as u64, entry.get_last_change_timestamp())
+            }
+        };
+        Ok(Stat { mode, uid: options.uid, gid: options.gid, size, modified })
+    }
+
+    /** Names of the extended attributes of the entry at the path. */
+    pub fn list_xattrs(&mut self, path: &str) -> io::Result<Vec<&'static str>> {
+        if is_root(path) { return Ok(vec![]); }
+        self.get_entry(path)?;
+        Ok([XATTR_ATTRIBUTES].into_iter().chain(XATTR_FLAGS.iter().map(|(name, _)| *name)).collect())
+    }
+
+    pub fn get_xattr(&mut self, path: &str, name: &str) -> io::Result<Vec<u8>> {
+        let entry: DirectoryEntry = self.get_xattr_entry(path, name)?;
+        if name == XATTR_ATTRIBUTES {
+            let letters: String = LETTERS.iter().filter(|(flag, _)| entry.attributes & flag != 0).map(|(_, letter)| *letter).collect();
+            return Ok(letters.into_bytes());
+        }
+        let flag: u8 = get_flag(name).unwrap_or_default();
+        Ok(if entry.attributes & flag != 0 { b"1".to_vec() } else { b"0".to_vec() })
+    }
+
+    /** Set the flags from the value of one of the extended attributes, see
+     *  set_attributes: letters in any order and case for the attributes
+     *  one, "0" or "1" for the others. */
+    pub fn set_xattr(&mut self, path: &str, name: &str, value: &[u8]) -> io::Result<()> {
+        let entry: DirectoryEntry = self.get_xattr_entry(path, name)?;
+        let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid value for {}: {:?}", name, String::from_utf8_lossy(value)));
+        let attributes: u8 = match get_flag(name) {
+            Some(flag) => match value {
+                b"1" => entry.attributes | flag,
+                b"0" => entry.attributes & !flag,
+                _ => return Err(invalid())
+            },
+            None => value.iter().try_fold(0, |attributes, byte| {
+                LETTERS.iter().find(|(_, letter)| *letter as u8 == byte.to_ascii_uppercase()).map(|(flag, _)| attributes | flag)
+            }).ok_or_else(invalid)?
+        };
+        self.set_attributes(path, attributes)
+    }
+
+    /** Apply a chmod: no write bit at all makes the entry read-only, any
+     *  makes it writable. The other bits have nowhere to go in FAT. */
+    pub fn chmod(&mut self, path: &str, mode: u32) -> io::Result<()> {
+        if is_root(path) { return Err(io::Error::new(io::ErrorKind::PermissionDenied, "the root directory has no attributes")); }
+        let attributes: u8 = self.get_entry(path)?.attributes;
+        let attributes: u8 = if mode & 0o222 == 0 { attributes | ATTR_READ_ONLY } else { attributes & !ATTR_READ_ONLY };
+        self.set_attributes(path, attributes)
+    }
+
+    /** Entry at the path, if it has the extended attribute. */
+    fn get_xattr_entry(&mut self, path: &str, name: &str) -> io::Result<DirectoryEntry> {
+        if name != XATTR_ATTRIBUTES && get_flag(name).is_none() || is_root(path) {
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("{} has no {} attribute", path, name)));
+        }
+        self.get_entry(path)
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Permission bits in octal, as umask takes them. */
+fn parse_mask(text: &str) -> Option<u32> {
+    u32::from_str_radix(text, 8).ok().filter(|mask| *mask <= 0o777)
+}
+
+fn get_flag(name: &str) -> Option<u8> {
+    XATTR_FLAGS.iter().find(|(other, _)| *other == name).map(|(_, flag)| *flag)
+}
+
+fn is_root(path: &str) -> bool {
+    path.split(['/', '\\']).all(|component| component.is_empty())
+}
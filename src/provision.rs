@@ -0,0 +1,265 @@
+use std::{collections::BTreeSet, fs::{File, OpenOptions}, io, path::Path};
+
+use crate::bpb::Geometry;
+use crate::recovery::{FloppyFormat, FLOPPY_FORMATS};
+use crate::source::FileSource;
+use crate::volume::volume_id_from_timestamp;
+use crate::{struct_to_bytes, write_all_at, BootSector, Directory, DirectoryEntry, Fat12Fs, FsOptions, ATTR_ARCHIVE, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM, ATTR_VOLUME_ID};
+
+/* ==== STRUCTS ============================================================= */
+/** Everything a new image is built from, in one go by provision: same
+ *  template, same bytes. */
+pub struct Template {
+    pub boot_sector: BootSector,
+
+    /** Sector whose boot code is kept around the BPB of boot_sector, e.g. a
+     *  bootloader binary; the 0x55AA signature is always set. */
+    pub boot_code: Option<Vec<u8>>,
+
+    /** Blob for the reserved sectors after the boot sector, e.g. a stage 2
+     *  loader; the boot sector must reserve enough of them. */
+    pub reserved_region: Option<Vec<u8>>,
+    pub label: Option<String>,
+
+    /** Unix time of every entry, and the volume id derives from it. */
+    pub timestamp: u64,
+
+    /** Written in this order, missing parent directories included. */
+    pub items: Vec<Item>
+}
+
+/** File or directory of a template. */
+pub struct Item {
+    /** Absolute image path, e.g. "/BOOT/KERNEL.BIN". */
+    pub path: String,
+
+    /** Content of the file, None for a directory. */
+    pub source: Option<Box<dyn FileSource>>,
+
+    /** Read-only, hidden, system and archive flags, see set_attributes. */
+    pub attributes: u8
+}
+
+/** Change apply makes, or would make, to an image. Paths are absolute. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Added(String),
+
+    /** File whose content differs: it gets new clusters. */
+    Updated(String),
+
+    /** Same content, other attributes. */
+    Attributes(String),
+    Removed(String),
+    Label(Option<String>),
+    ReservedRegion
+}
+
+/* ==== METHODS ============================================================= */
+/** Boot sector of the standard DOS floppy format of that size (160K to
+ *  2880K, and the 250K and 1232K 8" ones), with an extended boot record
+ *  and no label. */
+pub fn floppy_boot_sector(kilobytes: u32) -> Option<BootSector> {
+    FLOPPY_FORMATS.iter().find(|format| format.get_size() / 1024 == kilobytes as u64).map(format_boot_sector)
+}
+
+/** Boot sector of the floppy format, see floppy_boot_sector. */
+pub fn format_boot_sector(format: &FloppyFormat) -> BootSector {
+    BootSector {
+        jump_instruction: [0xEB, 0x3C, 0x90],
+        oem_id: *b"RSFAT1.0",
+        bytes_per_sector: format.bytes_per_sector,
+        sectors_per_cluster: format.sectors_per_cluster,
+        reserved_sectors: format.reserved_sectors,
+        fat_count: 2,
+        root_entries: format.root_entries,
+        sector_count: format.total_sectors as u16,
+        media_descriptor: format.media_descriptor,
+        sectors_per_fat: format.sectors_per_fat,
+        sectors_per_cylinder: format.sectors_per_track,
+        heads_count: format.heads,
+        hidden_sectors_count: 0,
+        large_sector_count: 0,
+        drive_number: 0,
+        reserved: 0,
+        boot_signature: 0x29,
+        volume_id: 0,
+        volume_label: *b"NO NAME    ",
+        system_id: *b"FAT12   "
+    }
+}
+
+/** Create an empty volume at the path, replacing any file there: the boot
+ *  sector, FATs holding only the media byte and end of chain header, and
+ *  zeros everywhere else. */
+pub fn format(path: &Path, boot_sector: &BootSector, boot_code: Option<&[u8]>) -> io::Result<()> {
+    let bytes_per_sector: usize = boot_sector.bytes_per_sector as usize;
+    let geometry: Geometry = boot_sector.get_geometry();
+    if geometry.get_fat_type() != "FAT12" || geometry.needed_fat_sectors > boot_sector.sectors_per_fat as u32 || geometry.cluster_count == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("the geometry gives {} clusters and needs {} sectors per FAT, not a FAT12 volume with {}",
+            geometry.cluster_count, geometry.needed_fat_sectors, boot_sector.sectors_per_fat as u32)));
+    }
+
+    let mut sector: Vec<u8> = vec![0; bytes_per_sector];
+    if let Some(code) = boot_code {
+        let length: usize = code.len().min(bytes_per_sector);
+        sector[..length].copy_from_slice(&code[..length]);
+    }
+    let bpb: Vec<u8> = struct_to_bytes(boot_sector);
+    sector[3..bpb.len()].copy_from_slice(&bpb[3..]);
+    if boot_code.is_none_or(|code| code.len() < 3) { sector[..3].copy_from_slice(&boot_sector.jump_instruction); }
+    // Sectors of 128 or 256 bytes, on 8" floppies, have no room for the signature
+    if bytes_per_sector >= 512 { sector[510..512].copy_from_slice(&[0x55, 0xAA]); }
+
+    let disk: File = OpenOptions::new().write(true).create(true).truncate(true).open(path)?;
+    disk.set_len(geometry.total_sectors as u64 * bytes_per_sector as u64)?;
+    write_all_at(&disk, &sector, 0)?;
+    for fat in 0..boot_sector.fat_count as u64 {
+        let start: u64 = boot_sector.get_layout().fat_copy_start(fat);
+        write_all_at(&disk, &[boot_sector.media_descriptor, 0xFF, 0xFF], start)?;
+    }
+    disk.sync_all()
+}
+
+/** Format the image at the path and fill it as the template says. The
+ *  options are used to open it once formatted, read-write. */
+pub fn provision(path: &Path, template: &mut Template, options: &FsOptions) -> io::Result<Fat12Fs> {
+    let mut boot_sector: BootSector = template.boot_sector;
+    if boot_sector.boot_signature == 0x29 { boot_sector.volume_id = volume_id_from_timestamp(template.timestamp, 0); }
+    format(path, &boot_sector, template.boot_code.as_deref())?;
+
+    let image_path: &str = path.to_str().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "image path is not UTF-8"))?;
+    let mut fs: Fat12Fs = options.clone().read_only(false).open(image_path)?;
+    if let Some(blob) = &template.reserved_region { fs.write_reserved_region(blob)?; }
+    if let Some(label) = &template.label {
+        fs.set_volume_label(label)?;
+        fs.stamp_volume_label(template.timestamp)?;
+    }
+
+    let timestamp: u64 = template.timestamp;
+    for item in &mut template.items {
+        let parent: &str = item.path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        fs.create_dir_all(parent, timestamp)?;
+        match &mut item.source {
+            Some(source) => { fs.write_from(&item.path, source.as_mut(), timestamp)?; },
+            None => fs.create_dir_all(&item.path, timestamp)?
+        }
+        fs.set_attributes(&item.path, item.attributes)?;
+    }
+    fs.flush()?;
+    Ok(fs)
+}
+
+/** Bring an existing image in line with the template, touching only what
+ *  differs: entries the template doesn't have are removed, missing ones
+ *  added, files with other content rewritten, so the others keep their
+ *  clusters. The geometry and the boot code are left as they are, the
+ *  reserved region is rewritten if it differs. With dry_run nothing is
+ *  written, the changes are only listed. */
+pub fn apply(fs: &mut Fat12Fs, template: &mut Template, dry_run: bool) -> io::Result<Vec<Change>> {
+    if !dry_run { fs.check_writable()?; }
+    let mut changes: Vec<Change> = vec![];
+    let timestamp: u64 = template.timestamp;
+
+    // Every item is wanted, and so are the directories leading to it
+    let mut wanted: BTreeSet<String> = BTreeSet::new();
+    for item in &template.items {
+        let mut path: &str = &item.path;
+        while !path.is_empty() && wanted.insert(path.to_string()) {
+            path = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+        }
+    }
+
+    // Children sort after their parent: going backwards empties directories
+    // before they are removed
+    let mut existing: Vec<String> = vec![];
+    for item in fs.walk("/").collect::<Vec<_>>() {
+        existing.push(format!("/{}", item?.0));
+    }
+    existing.sort();
+    for path in existing.iter().rev().filter(|path| !wanted.contains(*path)) {
+        if !dry_run { fs.remove(path)?; }
+        changes.push(Change::Removed(path.clone()));
+    }
+
+    if let Some(blob) = &template.reserved_region {
+        let mut region: Vec<u8> = blob.clone();
+        region.resize(fs.get_reserved_region_size().max(blob.len()), 0);
+        if fs.read_reserved_region()? != region {
+            if !dry_run { fs.write_reserved_region(blob)?; }
+            changes.push(Change::ReservedRegion);
+        }
+    }
+
+    // Labels are stored uppercase
+    if fs.get_volume_label()? != template.label.as_ref().map(|label| label.trim_end().to_ascii_uppercase()) {
+        if !dry_run {
+            fs.set_volume_label(template.label.as_deref().unwrap_or(""))?;
+            fs.stamp_volume_label(timestamp)?;
+        }
+        changes.push(Change::Label(template.label.clone()));
+    }
+
+    let settable: u8 = ATTR_READ_ONLY | ATTR_HIDDEN | ATTR_SYSTEM | ATTR_ARCHIVE;
+    for item in &mut template.items {
+        let path: String = item.path.clone();
+        let entry: Option<DirectoryEntry> = fs.get_entry(&path).ok();
+
+        // A file where a directory is wanted, or the other way around
+        let entry: Option<DirectoryEntry> = match entry {
+            Some(entry) if entry.is_directory() != item.source.is_none() => {
+                if !dry_run { fs.remove(&path)?; }
+                changes.push(Change::Removed(path.clone()));
+                None
+            },
+            entry => entry
+        };
+
+        let change: Option<Change> = match (&mut item.source, entry) {
+            (None, None) => {
+                if !dry_run { fs.create_dir_all(&path, timestamp)?; }
+                Some(Change::Added(path.clone()))
+            },
+            (Some(source), entry) => {
+                let data: Vec<u8> = source.read_content()?;
+                let same: bool = match entry {
+                    Some(entry) => entry.file_size as usize == data.len() && fs.read_file(&path)? == data,
+                    None => false
+                };
+                if !same && !dry_run {
+                    let parent: &str = path.rsplit_once('/').map_or("", |(parent, _)| parent);
+                    fs.create_dir_all(parent, timestamp)?;
+                    fs.write_file(&path, &data, timestamp)?;
+                }
+                match (same, entry) {
+                    (true, _) => None,
+                    (false, Some(_)) => Some(Change::Updated(path.clone())),
+                    (false, None) => Some(Change::Added(path.clone()))
+                }
+            },
+            (None, Some(_)) => None
+        };
+
+        // Written entries take the attributes anyway, the others if they differ
+        let attributes_differ: bool = entry.is_none_or(|entry| entry.attributes & settable != item.attributes);
+        if (change.is_some() || attributes_differ) && !dry_run { fs.set_attributes(&path, item.attributes)?; }
+        match change {
+            Some(change) => changes.push(change),
+            None if attributes_differ => changes.push(Change::Attributes(path)),
+            None => {}
+        }
+    }
+    if !dry_run { fs.flush()?; }
+    Ok(changes)
+}
+
+impl Fat12Fs {
+    /** Give the volume label entry the timestamp instead of the current
+     *  time, creation date included. */
+    fn stamp_volume_label(&mut self, timestamp: u64) -> io::Result<()> {
+        let root: Directory = self.read_root_dir()?;
+        let Some(index) = root.entries.iter().take_while(|entry| !entry.is_end()).position(|entry| entry.is_used() && entry.is_volume_label()) else { return Ok(()) };
+        let entry: DirectoryEntry = DirectoryEntry::new(root.entries[index].get_short_name(), ATTR_VOLUME_ID, timestamp);
+        self.write_directory_entry(&root, index, &entry)
+    }
+}
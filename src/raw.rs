@@ -0,0 +1,107 @@
+use crate::{BootSector, DirectoryEntry};
+
+/* ==== STRUCTS ============================================================= */
+/** On-disk structures decoded and encoded field by field, little endian,
+ *  instead of casting pointers: slower, but no unsafe code involved. */
+pub(crate) trait RawStruct: Sized {
+    /** Build the struct from exactly size_of::<Self>() bytes. */
+    fn decode(bytes: &[u8]) -> Self;
+    fn encode(&self) -> Vec<u8>;
+}
+
+/** Reads consecutive fields from a byte slice. */
+struct Fields<'a> {
+    bytes: &'a [u8],
+    position: usize
+}
+
+impl Fields<'_> {
+    fn array<const N: usize>(&mut self) -> [u8; N] {
+        let mut array: [u8; N] = [0; N];
+        array.copy_from_slice(&self.bytes[self.position..self.position + N]);
+        self.position += N;
+        array
+    }
+
+    fn u8(&mut self) -> u8 {
+        u8::from_le_bytes(self.array())
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.array())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.array())
+    }
+}
+
+impl RawStruct for BootSector {
+    fn decode(bytes: &[u8]) -> BootSector {
+        let mut fields: Fields = Fields { bytes, position: 0 };
+        BootSector {
+            jump_instruction: fields.array(),
+            oem_id: fields.array(),
+            bytes_per_sector: fields.u16(),
+            sectors_per_cluster: fields.u8(),
+            reserved_sectors: fields.u16(),
+            fat_count: fields.u8(),
+            root_entries: fields.u16(),
+            sector_count: fields.u16(),
+            media_descriptor: fields.u8(),
+            sectors_per_fat: fields.u16(),
+            sectors_per_cylinder: fields.u16(),
+            heads_count: fields.u16(),
+            hidden_sectors_count: fields.u32(),
+            large_sector_count: fields.u32(),
+            drive_number: fields.u8(),
+            reserved: fields.u8(),
+            boot_signature: fields.u8(),
+            volume_id: fields.u32(),
+            volume_label: fields.array(),
+            system_id: fields.array()
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        // Copy the packed struct, so that the fields can be borrowed
+        let bs: BootSector = *self;
+        [
+            &bs.jump_instruction[..], &bs.oem_id, &bs.bytes_per_sector.to_le_bytes(), &[bs.sectors_per_cluster],
+            &bs.reserved_sectors.to_le_bytes(), &[bs.fat_count], &bs.root_entries.to_le_bytes(), &bs.sector_count.to_le_bytes(),
+            &[bs.media_descriptor], &bs.sectors_per_fat.to_le_bytes(), &bs.sectors_per_cylinder.to_le_bytes(), &bs.heads_count.to_le_bytes(),
+            &bs.hidden_sectors_count.to_le_bytes(), &bs.large_sector_count.to_le_bytes(), &[bs.drive_number], &[bs.reserved],
+            &[bs.boot_signature], &bs.volume_id.to_le_bytes(), &bs.volume_label, &bs.system_id
+        ].concat()
+    }
+}
+
+impl RawStruct for DirectoryEntry {
+    fn decode(bytes: &[u8]) -> DirectoryEntry {
+        let mut fields: Fields = Fields { bytes, position: 0 };
+        DirectoryEntry {
+            name: fields.array(),
+            attributes: fields.u8(),
+            reserved: fields.u8(),
+            creation_time_tenths: fields.u8(),
+            creation_time: fields.u16(),
+            creation_date: fields.u16(),
+            last_access_date: fields.u16(),
+            upper_first_cluster: fields.u16(),
+            last_change_time: fields.u16(),
+            last_change_date: fields.u16(),
+            lower_first_cluster: fields.u16(),
+            file_size: fields.u32()
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let entry: DirectoryEntry = *self;
+        [
+            &entry.name[..], &[entry.attributes], &[entry.reserved], &[entry.creation_time_tenths],
+            &entry.creation_time.to_le_bytes(), &entry.creation_date.to_le_bytes(), &entry.last_access_date.to_le_bytes(),
+            &entry.upper_first_cluster.to_le_bytes(), &entry.last_change_time.to_le_bytes(), &entry.last_change_date.to_le_bytes(),
+            &entry.lower_first_cluster.to_le_bytes(), &entry.file_size.to_le_bytes()
+        ].concat()
+    }
+}
@@ -0,0 +1,106 @@
+use std::{io, vec};
+
+use crate::stats::Counter;
+use crate::{into_struct_vec, limit_exceeded, no_clusters, split_path, Cluster, DataStart, Directory, DirectoryEntry, Fat12Fs, END_OF_CHAIN_MIN};
+
+/* ==== STRUCTS ============================================================= */
+/** Slots of a directory read a cluster at a time, as they are needed,
+ *  instead of the whole Directory at once: listing a directory of tens of
+ *  thousands of entries keeps a single cluster of them in memory. The
+ *  root, a fixed region of root_entries slots, is read at once. */
+pub struct DirStream<'a> {
+    fs: &'a mut Fat12Fs,
+
+    // Slots of the cluster being read, and the position in it
+    slots: vec::IntoIter<DirectoryEntry>,
+
+    // Next cluster of the chain, None past the last one and for the root
+    next: Option<Cluster>,
+
+    // Slots and clusters gone through, to stay within the limits
+    slot_count: usize,
+    cluster_count: usize,
+    done: bool
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Stream the slots of the directory at the path, up to its end marker:
+     *  deleted ones and long name slots too, as Directory holds them (see
+     *  DirectoryEntry::is_used). The validation level applies to every
+     *  cluster as it's read. */
+    pub fn stream_dir(&mut self, path: &str) -> io::Result<DirStream<'_>> {
+        if split_path(path).next().is_none() { return DirStream::root(self); }
+        let entry: DirectoryEntry = self.get_entry(path)?;
+        if !entry.is_directory() { return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a directory", path))); }
+        match entry.get_data_start() {
+            DataStart::Root => DirStream::root(self),
+            DataStart::Chain(cluster) => {
+                self.stats.add(Counter::ChainsWalked, 1);
+                Ok(DirStream { fs: self, slots: vec![].into_iter(), next: Some(cluster), slot_count: 0, cluster_count: 0, done: false })
+            },
+            DataStart::Empty => Err(no_clusters(&entry.get_name()))
+        }
+    }
+}
+
+impl<'a> DirStream<'a> {
+    fn root(fs: &'a mut Fat12Fs) -> io::Result<DirStream<'a>> {
+        let root: Directory = fs.read_root_dir()?;
+        Ok(DirStream { fs, slots: root.entries.into_iter(), next: None, slot_count: 0, cluster_count: 0, done: false })
+    }
+
+    /** Read the next cluster of the chain into the slots, checked as the
+     *  validation level says. False past the last one. */
+    fn read_next_cluster(&mut self) -> io::Result<bool> {
+        let Some(cluster) = self.next else { return Ok(false) };
+        let limits = self.fs.limits;
+
+        // A chain can't be longer than the volume: past that it loops
+        self.cluster_count += 1;
+        if self.cluster_count > limits.max_chain_length {
+            return Err(limit_exceeded(&format!("directory chain at cluster {}", cluster), "chain length", limits.max_chain_length));
+        }
+        if self.cluster_count > self.fs.boot_sector.get_cluster_count() { return Ok(false); }
+
+        let data: Vec<u8> = self.fs.read_disk_at(self.fs.boot_sector.get_cluster_start(cluster), self.fs.boot_sector.get_cluster_size())?;
+        let mut directory: Directory = Directory { entries: into_struct_vec::<DirectoryEntry>(data), clusters: vec![cluster] };
+        self.fs.check_directory(&mut directory)?;
+        self.slots = directory.entries.into_iter();
+
+        let next: Cluster = Cluster::from(self.fs.fat.get_entry(cluster));
+        self.next = if next.is_data() && next.0 < END_OF_CHAIN_MIN as u32 { Some(next) } else { None };
+        Ok(true)
+    }
+}
+
+impl Iterator for DirStream<'_> {
+    type Item = io::Result<DirectoryEntry>;
+
+    fn next(&mut self) -> Option<io::Result<DirectoryEntry>> {
+        if self.done { return None; }
+        loop {
+            if let Some(entry) = self.slots.next() {
+                self.slot_count += 1;
+                let max_entries: usize = self.fs.limits.max_entries;
+                if entry.is_end() || self.slot_count > max_entries {
+                    self.done = true;
+                    if entry.is_end() { return None; }
+                    return Some(Err(limit_exceeded("directory", "entries", max_entries)));
+                }
+                return Some(Ok(entry));
+            }
+            match self.read_next_cluster() {
+                Ok(true) => continue,
+                Ok(false) => {
+                    self.done = true;
+                    return None;
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
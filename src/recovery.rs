@@ -0,0 +1,226 @@
+use std::{fs::File, io};
+
+use crate::partition::looks_like_boot_sector;
+use crate::{read_exact_at, struct_to_bytes, BootSector, Fat12Fs, ATTR_DIRECTORY};
+
+/** Standard DOS floppy formats, from the single sided 5.25" ones of DOS
+ *  1.0 to 2.88M, and the 8" ones of the CP/M era DOS versions. */
+pub const FLOPPY_FORMATS: &[FloppyFormat] = &[
+    FloppyFormat { name: "160K", media: "5.25\" single sided, 8 sectors per track", bytes_per_sector: 512, total_sectors: 320, sectors_per_cluster: 1,
+        reserved_sectors: 1, root_entries: 64, media_descriptor: 0xFE, sectors_per_fat: 1, sectors_per_track: 8, heads: 1 },
+    FloppyFormat { name: "180K", media: "5.25\" single sided, 9 sectors per track", bytes_per_sector: 512, total_sectors: 360, sectors_per_cluster: 1,
+        reserved_sectors: 1, root_entries: 64, media_descriptor: 0xFC, sectors_per_fat: 2, sectors_per_track: 9, heads: 1 },
+    FloppyFormat { name: "320K", media: "5.25\" double sided, 8 sectors per track", bytes_per_sector: 512, total_sectors: 640, sectors_per_cluster: 2,
+        reserved_sectors: 1, root_entries: 112, media_descriptor: 0xFF, sectors_per_fat: 1, sectors_per_track: 8, heads: 2 },
+    FloppyFormat { name: "360K", media: "5.25\" double sided, 9 sectors per track", bytes_per_sector: 512, total_sectors: 720, sectors_per_cluster: 2,
+        reserved_sectors: 1, root_entries: 112, media_descriptor: 0xFD, sectors_per_fat: 2, sectors_per_track: 9, heads: 2 },
+    FloppyFormat { name: "720K", media: "3.5\" double density", bytes_per_sector: 512, total_sectors: 1440, sectors_per_cluster: 2,
+        reserved_sectors: 1, root_entries: 112, media_descriptor: 0xF9, sectors_per_fat: 3, sectors_per_track: 9, heads: 2 },
+    FloppyFormat { name: "1.2M", media: "5.25\" high density", bytes_per_sector: 512, total_sectors: 2400, sectors_per_cluster: 1,
+        reserved_sectors: 1, root_entries: 224, media_descriptor: 0xF9, sectors_per_fat: 7, sectors_per_track: 15, heads: 2 },
+    FloppyFormat { name: "1.44M", media: "3.5\" high density", bytes_per_sector: 512, total_sectors: 2880, sectors_per_cluster: 1,
+        reserved_sectors: 1, root_entries: 224, media_descriptor: 0xF0, sectors_per_fat: 9, sectors_per_track: 18, heads: 2 },
+    FloppyFormat { name: "2.88M", media: "3.5\" extra density", bytes_per_sector: 512, total_sectors: 5760, sectors_per_cluster: 2,
+        reserved_sectors: 1, root_entries: 240, media_descriptor: 0xF0, sectors_per_fat: 9, sectors_per_track: 36, heads: 2 },
+    FloppyFormat { name: "8in-250K", media: "8\" single sided, single density, 128 byte sectors", bytes_per_sector: 128, total_sectors: 2002, sectors_per_cluster: 4,
+        reserved_sectors: 1, root_entries: 68, media_descriptor: 0xFE, sectors_per_fat: 6, sectors_per_track: 26, heads: 1 },
+    FloppyFormat { name: "8in-1232K", media: "8\" double sided, double density, 8 sectors of 1024 bytes per track", bytes_per_sector: 1024, total_sectors: 1232,
+        sectors_per_cluster: 1, reserved_sectors: 1, root_entries: 192, media_descriptor: 0xFE, sectors_per_fat: 2, sectors_per_track: 8, heads: 2 }
+];
+
+/** How far from the start the FAT is looked for. */
+const FAT_SEARCH_SECTORS: u64 = 64;
+
+/* ==== STRUCTS ============================================================= */
+/** Geometry of a standard floppy format, see FLOPPY_FORMATS. Two FATs,
+ *  always. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloppyFormat {
+    /** Preset name, e.g. "360K" or "8in-250K". */
+    pub name: &'static str,
+
+    /** Media it was written on, e.g. "5.25\" double sided, 9 sectors per
+     *  track". */
+    pub media: &'static str,
+    pub bytes_per_sector: u16,
+    pub total_sectors: u32,
+    pub sectors_per_cluster: u8,
+    pub reserved_sectors: u16,
+    pub root_entries: u16,
+    pub media_descriptor: u8,
+    pub sectors_per_fat: u16,
+    pub sectors_per_track: u16,
+    pub heads: u16
+}
+
+/** BPB rebuilt from the rest of the image, with how each value was found. */
+#[derive(Debug, Clone)]
+pub struct Recovery {
+    pub boot_sector: BootSector,
+    pub notes: Vec<String>
+}
+
+/* ==== METHODS ============================================================= */
+impl FloppyFormat {
+    /** Bytes of an image of the format. */
+    pub fn get_size(&self) -> u64 {
+        self.total_sectors as u64 * self.bytes_per_sector as u64
+    }
+
+    /** Format with that preset name (any case, "K" and "M" optional) or
+     *  size in kilobytes, e.g. "1.2M", "360" or "8in-250K". */
+    pub fn find(name: &str) -> Option<&'static FloppyFormat> {
+        let wanted: String = name.trim().to_ascii_uppercase();
+        FLOPPY_FORMATS.iter().find(|format| {
+            let preset: String = format.name.to_ascii_uppercase();
+            wanted == preset || wanted == preset.trim_end_matches(['K', 'M']) || wanted == (format.get_size() / 1024).to_string()
+        })
+    }
+
+    /** Standard format with that many sectors of that size, if any. */
+    pub fn from_geometry(bytes_per_sector: u16, total_sectors: u32) -> Option<&'static FloppyFormat> {
+        FLOPPY_FORMATS.iter().find(|format| format.bytes_per_sector == bytes_per_sector && format.total_sectors == total_sectors)
+    }
+
+    /** Standard format of an image of that size, if any. */
+    pub fn from_size(image_size: u64) -> Option<&'static FloppyFormat> {
+        FLOPPY_FORMATS.iter().find(|format| format.get_size() == image_size)
+    }
+}
+
+/** Rebuild a plausible boot sector for a volume whose own is zeroed or
+ *  unreadable: the size gives the standard floppy format, if any, then the
+ *  FAT copies found by their media byte and the "." entries of the root's
+ *  subdirectories confirm or correct it. Sectors are taken to be 512 bytes,
+ *  unless the size is the one of an 8" format with other sectors. */
+pub fn recover_boot_sector(disk: &File, offset: u64) -> io::Result<Recovery> {
+    let mut notes: Vec<String> = vec![];
+    let image_size: u64 = disk.metadata()?.len().saturating_sub(offset);
+    let format: Option<&FloppyFormat> = FloppyFormat::from_size(image_size);
+    let bytes_per_sector: u64 = format.map_or(512, |format| format.bytes_per_sector as u64);
+    let total_sectors: u32 = (image_size / bytes_per_sector).min(u32::MAX as u64) as u32;
+    let read_sector = |sector: u64| -> Option<Vec<u8>> {
+        let mut buffer: Vec<u8> = vec![0; bytes_per_sector as usize];
+        read_exact_at(disk, &mut buffer, offset + sector * bytes_per_sector).ok().map(|_| buffer)
+    };
+
+    // Start from the format of that size, or the lenient defaults
+    let mut boot_sector: BootSector = BootSector {
+        jump_instruction: [0xEB, 0x3C, 0x90],
+        oem_id: *b"RECOVERY",
+        bytes_per_sector: bytes_per_sector as u16,
+        sectors_per_cluster: 1,
+        reserved_sectors: 1,
+        fat_count: 2,
+        root_entries: if total_sectors <= 1440 { 112 } else { 224 },
+        sector_count: 0,
+        media_descriptor: 0xF0,
+        sectors_per_fat: 0,
+        sectors_per_cylinder: 0,
+        heads_count: 0,
+        hidden_sectors_count: 0,
+        large_sector_count: 0,
+        drive_number: 0,
+        reserved: 0,
+        boot_signature: 0,
+        volume_id: 0,
+        volume_label: *b"NO NAME    ",
+        system_id: *b"FAT12   "
+    };
+    boot_sector.set_field("total_sectors", total_sectors)?;
+    match format {
+        Some(format) => {
+            (boot_sector.sectors_per_cluster, boot_sector.root_entries, boot_sector.media_descriptor) = (format.sectors_per_cluster, format.root_entries, format.media_descriptor);
+            (boot_sector.sectors_per_fat, boot_sector.sectors_per_cylinder, boot_sector.heads_count) = (format.sectors_per_fat, format.sectors_per_track, format.heads);
+            boot_sector.reserved_sectors = format.reserved_sectors;
+            notes.push(format!("{} sectors of {} bytes: the standard {} floppy format ({})", total_sectors, bytes_per_sector, format.name, format.media));
+        },
+        None => notes.push(format!("{} sectors match no standard floppy format, using defaults", total_sectors))
+    }
+
+    // The FAT starts with the media byte and two 0xFF, its copy is identical
+    let search_end: u64 = FAT_SEARCH_SECTORS.min(total_sectors as u64);
+    let first_fat: Option<(u64, Vec<u8>)> = (1..search_end)
+        .filter_map(|sector| read_sector(sector).map(|data| (sector, data)))
+        .find(|(_, data)| (data[0] == 0xF0 || data[0] >= 0xF8) && data[1] == 0xFF && data[2] == 0xFF);
+    match first_fat {
+        Some((fat_start, data)) => {
+            boot_sector.reserved_sectors = fat_start as u16;
+            boot_sector.media_descriptor = data[0];
+            notes.push(format!("FAT found at sector {}, media descriptor {:02X}", fat_start, data[0]));
+
+            match (fat_start + 1..search_end).find(|&sector| read_sector(sector).as_ref() == Some(&data)) {
+                Some(copy_start) => {
+                    (boot_sector.fat_count, boot_sector.sectors_per_fat) = (2, (copy_start - fat_start) as u16);
+                    notes.push(format!("FAT copy found at sector {}: {} sectors per FAT", copy_start, copy_start - fat_start));
+                },
+                None => notes.push(String::from("no FAT copy found"))
+            }
+        },
+        None => notes.push(String::from("no FAT found, its position is a guess"))
+    }
+
+    // Without a known size, grow the FAT until the clusters fit
+    if boot_sector.sectors_per_fat == 0 {
+        boot_sector.sectors_per_fat = 1;
+        while boot_sector.get_geometry().needed_fat_sectors > boot_sector.sectors_per_fat as u32 && boot_sector.sectors_per_fat < 12 {
+            boot_sector.sectors_per_fat += 1;
+        }
+    }
+
+    // A subdirectory's "." entry sits at the start of its first cluster,
+    // which tells the cluster size
+    match find_sectors_per_cluster(&boot_sector, &read_sector) {
+        Some(sectors_per_cluster) => {
+            boot_sector.sectors_per_cluster = sectors_per_cluster;
+            notes.push(format!("subdirectory found: {} sectors per cluster", sectors_per_cluster));
+        },
+        None => notes.push(format!("no subdirectory to check the cluster size against, {} sectors per cluster", boot_sector.sectors_per_cluster))
+    }
+    Ok(Recovery { boot_sector, notes })
+}
+
+impl Fat12Fs {
+    /** Write the boot sector in use to the image, e.g. the one rebuilt by
+     *  FsOptions::recover_boot_sector, leaving the boot code as it is. */
+    pub fn restore_boot_sector(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+        let bytes: Vec<u8> = struct_to_bytes(&self.boot_sector);
+        let bytes_per_sector: usize = self.boot_sector.bytes_per_sector as usize;
+        let mut sector: Vec<u8> = self.read_disk_at(0, bytes_per_sector)?;
+        sector[..bytes.len()].copy_from_slice(&bytes);
+        if bytes_per_sector >= 512 { sector[510..512].copy_from_slice(&[0x55, 0xAA]); }
+        self.write_disk_at(0, &sector)
+    }
+}
+
+/** Whether the first sector needs a rebuilt boot sector. */
+pub fn is_damaged(disk: &File, offset: u64) -> bool {
+    let mut sector: [u8; 512] = [0; 512];
+    read_exact_at(disk, &mut sector, offset).is_err() || !looks_like_boot_sector(&sector)
+}
+
+/* ==== UTILS =============================================================== */
+fn find_sectors_per_cluster(boot_sector: &BootSector, read_sector: &impl Fn(u64) -> Option<Vec<u8>>) -> Option<u8> {
+    let root_start: u64 = boot_sector.reserved_sectors as u64 + boot_sector.fat_count as u64 * boot_sector.sectors_per_fat as u64;
+    let data_start: u64 = root_start + (boot_sector.root_entries as u64 * 32).div_ceil(boot_sector.bytes_per_sector as u64);
+
+    let mut clusters: Vec<u64> = vec![];
+    for sector in root_start..data_start {
+        let data: Vec<u8> = read_sector(sector)?;
+        for entry in data.chunks(32) {
+            let is_subdirectory: bool = entry[0] != 0 && entry[0] != 0xE5 && entry[11] & ATTR_DIRECTORY != 0 && entry[11] != 0x0F;
+            let cluster: u64 = u16::from_le_bytes([entry[26], entry[27]]) as u64;
+            if is_subdirectory && cluster >= 2 { clusters.push(cluster); }
+        }
+    }
+
+    for cluster in clusters {
+        for sectors_per_cluster in [1u8, 2, 4, 8, 16, 32, 64, 128] {
+            let Some(data) = read_sector(data_start + (cluster - 2) * sectors_per_cluster as u64) else { break };
+            let dot: bool = &data[..11] == b".          " && data[11] & ATTR_DIRECTORY != 0;
+            if dot && u16::from_le_bytes([data[26], data[27]]) as u64 == cluster { return Some(sectors_per_cluster); }
+        }
+    }
+    None
+}
@@ -0,0 +1,259 @@
+use std::{collections::{BTreeMap, BTreeSet}, io, sync::Arc};
+
+use crate::audit::Mutation;
+use crate::chain::{ChainEnd, ClusterChain};
+use crate::validation::is_valid_short_name;
+use crate::{into_struct_vec, struct_to_bytes, Cluster, Directory, DirectoryEntry, Fat, Fat12Fs, NamePolicy, ATTR_ARCHIVE, ATTR_LFN, DELETED_ENTRY, END_OF_CHAIN, FREE_CLUSTER};
+
+/** FAT value of a cluster marked bad, kept through a rebuild. */
+const BAD_CLUSTER: u16 = 0x0FF7;
+
+/* ==== STRUCTS ============================================================= */
+/** Outcome of Fat12Fs::rebuild_fat. Paths are absolute, "/A/B.TXT". */
+#[derive(Debug, Default)]
+pub struct FatRebuild {
+    /** Files and directories given a contiguous chain. */
+    pub recovered: Vec<String>,
+
+    /** Entries left without a chain, with the reason: their clusters can't
+     *  be told apart from another entry's, or lie outside of the volume. */
+    pub uncertain: Vec<(String, String)>,
+
+    /** Directories recovered only up to where their clusters stop holding
+     *  entries, with the reason: they went on in a cluster that isn't the
+     *  next one, and the entries there are lost. */
+    pub truncated: Vec<(String, String)>
+}
+
+/** Outcome of Fat12Fs::recover_orphans. */
+#[derive(Debug, Default)]
+pub struct OrphanRecovery {
+    /** FOUND.NNN directory the chains were saved in, empty if there were none. */
+    pub directory: String,
+
+    /** Names of the FILENNNN.CHK files, with the clusters of each. */
+    pub files: Vec<(String, usize)>
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Throw the FAT away and build a new one from the directory entries
+     *  alone, taking every file and directory to be unfragmented: a file
+     *  gets as many clusters as its size needs from its first cluster on, a
+     *  directory runs until the cluster holding its end marker, through the
+     *  clusters that hold directory entries. Entries whose run overlaps
+     *  another one or leaves the volume are reported and left out: files
+     *  are emptied (first cluster and size 0), directories deleted, so that
+     *  nothing points into the chains of the others. Clusters marked bad
+     *  stay bad. The new FAT is checked before it's written: no cluster in
+     *  two chains, every chain ending where its entry says. */
+    pub fn rebuild_fat(&mut self) -> io::Result<FatRebuild> {
+        self.check_writable()?;
+        let mut report: FatRebuild = FatRebuild::default();
+        let mut owners: BTreeMap<Cluster, String> = BTreeMap::new();
+        let mut runs: Vec<(String, Vec<Cluster>)> = vec![];
+
+        // Entries left out, rewritten once the FAT is known to be sound
+        let mut left_out: Vec<(u64, DirectoryEntry)> = vec![];
+
+        // Directories still to scan, with their entries
+        let mut pending: Vec<(String, Directory)> = vec![(String::new(), self.read_dir("/")?)];
+        while let Some((directory, parent)) = pending.pop() {
+            for (index, entry) in parent.entries.iter().enumerate().take_while(|(_, entry)| !entry.is_end()) {
+                if !entry.is_used() || entry.is_volume_label() || entry.is_dot_entry() { continue; }
+                let path: String = format!("{}/{}", directory, entry.get_name());
+                let first_cluster: Cluster = entry.get_first_cluster();
+                if !first_cluster.is_data() { continue; }
+
+                let run: Vec<Cluster> = match entry.is_directory() {
+                    true => {
+                        let (run, ended): (Vec<Cluster>, bool) = self.find_directory_run(first_cluster)?;
+                        if !ended { report.truncated.push((path.clone(), format!("no end marker up to cluster {}", run[run.len() - 1]))); }
+                        run
+                    },
+                    false => (0..self.boot_sector.get_layout().chain_length(entry.file_size as u64) as u32).map(|i| Cluster(first_cluster.0 + i)).collect()
+                };
+                if let Some(reason) = self.check_run(&run, &owners) {
+                    let mut emptied: DirectoryEntry = *entry;
+                    match entry.is_directory() {
+                        true => emptied.name[0] = DELETED_ENTRY,
+                        false => { emptied.set_first_cluster(Cluster(0)); emptied.file_size = 0; }
+                    }
+                    left_out.push((self.get_entry_offset(&parent, index), emptied));
+                    report.uncertain.push((path, reason));
+                    continue;
+                }
+
+                for cluster in &run { owners.insert(*cluster, path.clone()); }
+                if entry.is_directory() {
+                    let data: Vec<u8> = self.read_clusters(&run)?;
+                    pending.push((path.clone(), Directory { entries: into_struct_vec(data), clusters: run.clone() }));
+                }
+                report.recovered.push(path.clone());
+                runs.push((path, run));
+            }
+        }
+
+        // Fresh FAT: the header, the bad clusters and the runs found
+        let cluster_count: usize = self.boot_sector.get_cluster_count();
+        let mut fat: Fat = Fat { entries: vec![0; self.fat.entries.len()] };
+        fat.set_entry(Cluster(0), 0xF00 | self.boot_sector.media_descriptor as u16)?;
+        fat.set_entry(Cluster(1), END_OF_CHAIN)?;
+        for cluster in (2..cluster_count as u32 + 2).map(Cluster) {
+            if self.fat.get_entry(cluster) == BAD_CLUSTER && !owners.contains_key(&cluster) { fat.set_entry(cluster, BAD_CLUSTER)?; }
+        }
+        for (_, run) in &runs {
+            for pair in run.windows(2) { fat.set_entry(pair[0], pair[1].0 as u16)?; }
+            if let Some(last) = run.last() { fat.set_entry(*last, END_OF_CHAIN)?; }
+        }
+        check_rebuilt_fat(&fat, cluster_count, &runs)?;
+
+        for (offset, entry) in &left_out { self.write_disk_at(*offset, &struct_to_bytes(entry))?; }
+        self.fat = Arc::new(fat);
+        self.free_clusters = self.fat.count_free_clusters(cluster_count);
+        self.next_free = Cluster(2);
+        self.record(Mutation::ClustersAllocated(owners.into_keys().collect()));
+        self.flush_fat()?;
+        report.recovered.sort();
+        Ok(report)
+    }
+
+    /** Clusters of a directory laid out contiguously: up to the one holding
+     *  the end marker, or the last one before a cluster that holds anything
+     *  but directory entries (the directory goes on somewhere else, if at
+     *  all), or the end of the volume. Gives whether the end marker was
+     *  found. */
+    fn find_directory_run(&mut self, first_cluster: Cluster) -> io::Result<(Vec<Cluster>, bool)> {
+        let last_cluster: u32 = self.boot_sector.get_cluster_count() as u32 + 1;
+        if first_cluster.0 > last_cluster { return Ok((vec![first_cluster], true)); }
+        let mut run: Vec<Cluster> = vec![];
+        let mut cluster: Cluster = first_cluster;
+        while cluster.0 <= last_cluster && run.len() < self.limits.max_chain_length {
+            let entries: Vec<DirectoryEntry> = into_struct_vec(self.read_clusters(&[cluster])?);
+
+            // Past the first cluster, another directory's start isn't a continuation
+            let continued: bool = !run.is_empty();
+            if continued && (entries[0].is_dot_entry() || !is_directory_cluster(&entries, last_cluster)) { return Ok((run, false)); }
+            run.push(cluster);
+            if entries.iter().any(DirectoryEntry::is_end) { return Ok((run, true)); }
+            cluster = Cluster(cluster.0 + 1);
+        }
+        Ok((run, false))
+    }
+
+    /** Why the run can't be trusted, if it can't. */
+    fn check_run(&self, run: &[Cluster], owners: &BTreeMap<Cluster, String>) -> Option<String> {
+        let last_cluster: u32 = self.boot_sector.get_cluster_count() as u32 + 1;
+        if let Some(cluster) = run.iter().find(|cluster| cluster.0 > last_cluster) {
+            return Some(format!("cluster {} is past the end of the volume", cluster));
+        }
+        run.iter().find_map(|cluster| owners.get(cluster).map(|owner| format!("cluster {} also belongs to {}", cluster, owner)))
+    }
+
+    /** Chains of clusters marked in use that no entry reaches, each from its
+     *  head (the cluster no other lost cluster points to) to the last lost
+     *  cluster it leads to. Loops are cut where they come back. */
+    pub fn find_orphan_chains(&mut self) -> io::Result<Vec<Vec<Cluster>>> {
+        let mut referenced: BTreeSet<Cluster> = BTreeSet::new();
+        for item in self.walk("/").collect::<Vec<_>>() {
+            let (_, entry): (String, DirectoryEntry) = item?;
+            referenced.extend(self.get_chain(entry.get_first_cluster()));
+        }
+
+        let cluster_count: u32 = self.boot_sector.get_cluster_count() as u32;
+        let lost: BTreeSet<Cluster> = (2..cluster_count + 2).map(Cluster)
+            .filter(|cluster| ![FREE_CLUSTER, BAD_CLUSTER].contains(&self.fat.get_entry(*cluster)) && !referenced.contains(cluster))
+            .collect();
+        let pointed_to: BTreeSet<Cluster> = lost.iter().map(|cluster| Cluster(self.fat.get_entry(*cluster) as u32)).filter(|next| lost.contains(next)).collect();
+
+        // Heads first, then whatever is left: clusters in loops
+        let mut chains: Vec<Vec<Cluster>> = vec![];
+        let mut visited: BTreeSet<Cluster> = BTreeSet::new();
+        let heads: Vec<Cluster> = lost.iter().filter(|cluster| !pointed_to.contains(cluster)).chain(pointed_to.iter()).copied().collect();
+        for head in heads {
+            let mut chain: Vec<Cluster> = vec![];
+            let mut cluster: Cluster = head;
+            while lost.contains(&cluster) && visited.insert(cluster) {
+                chain.push(cluster);
+                cluster = Cluster(self.fat.get_entry(cluster) as u32);
+            }
+            if !chain.is_empty() { chains.push(chain); }
+        }
+        Ok(chains)
+    }
+
+    /** Save the lost chains as FILE0000.CHK, FILE0001.CHK and on in a new
+     *  FOUND.000 directory (FOUND.001 if taken, and so on) of the root, as
+     *  chkdsk does, ending each chain where the lost clusters end. Their
+     *  sizes are whole clusters. */
+    pub fn recover_orphans(&mut self) -> io::Result<OrphanRecovery> {
+        self.check_writable()?;
+        let chains: Vec<Vec<Cluster>> = self.find_orphan_chains()?;
+        let mut recovery: OrphanRecovery = OrphanRecovery::default();
+        if chains.is_empty() { return Ok(recovery); }
+        if chains.len() > 10000 { return Err(io::Error::new(io::ErrorKind::StorageFull, format!("{} lost chains, more than FILE9999.CHK can hold", chains.len()))); }
+
+        let root: Directory = self.read_dir("/")?;
+        let directory: String = (0..1000).map(|n| format!("FOUND.{:03}", n))
+            .find(|name| root.get_entry(&format!("{:<8}{}", &name[..5], &name[6..])).is_none())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::StorageFull, "FOUND.000 to FOUND.999 are all taken"))?;
+        let timestamp: u64 = self.now();
+        self.create_dir(&format!("/{}", directory), timestamp)?;
+
+        let cluster_size: usize = self.boot_sector.get_cluster_size();
+        for (i, chain) in chains.iter().enumerate() {
+            let name: String = format!("FILE{:04}.CHK", i);
+            let path: String = format!("/{}/{}", directory, name);
+            if let Some(last) = chain.last() { Arc::make_mut(&mut self.fat).set_entry(*last, END_OF_CHAIN)?; }
+
+            // The chain is already allocated: only the entry is new
+            let (mut parent, short_name): (Directory, [u8; 11]) = self.resolve_parent(Cluster(0), &path, NamePolicy::Uppercase)?;
+            let index: usize = self.allocate_entry_slot(&mut parent)?;
+            let mut entry: DirectoryEntry = DirectoryEntry::new(short_name, ATTR_ARCHIVE, timestamp);
+            entry.set_first_cluster(chain[0]);
+            entry.file_size = (chain.len() * cluster_size) as u32;
+            self.write_directory_entry(&parent, index, &entry)?;
+            self.record(Mutation::EntryCreated { path });
+            recovery.files.push((name, chain.len()));
+        }
+        self.flush_fat()?;
+        recovery.directory = directory;
+        Ok(recovery)
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Whether the entries of a cluster could be a directory's: up to the end
+ *  marker, every one is deleted, a long name slot, or has a name DOS could
+ *  have written, known attributes and a first cluster in the volume; past
+ *  it, every one is free as well. */
+fn is_directory_cluster(entries: &[DirectoryEntry], last_cluster: u32) -> bool {
+    let end: usize = entries.iter().position(DirectoryEntry::is_end).unwrap_or(entries.len());
+    let (used, free): (&[DirectoryEntry], &[DirectoryEntry]) = entries.split_at(end);
+    free.iter().all(DirectoryEntry::is_end) && used.iter().all(|entry| {
+        let first_cluster: u32 = entry.get_first_cluster().0;
+        entry.is_deleted() || entry.attributes == ATTR_LFN
+            || (is_valid_short_name(&entry.name) && entry.attributes & 0xC0 == 0 && first_cluster <= last_cluster && first_cluster != 1)
+    })
+}
+
+/** Refuse a rebuilt FAT that doesn't give every entry its run back: the
+ *  chain of each one must be the run, ending with an end marker, and no
+ *  cluster may be in two chains. */
+fn check_rebuilt_fat(fat: &Fat, cluster_count: usize, runs: &[(String, Vec<Cluster>)]) -> io::Result<()> {
+    let mut owners: BTreeMap<Cluster, &str> = BTreeMap::new();
+    for (path, run) in runs {
+        let Some(first_cluster) = run.first() else { continue };
+        let mut chain: ClusterChain = fat.chain(*first_cluster, cluster_count);
+        let clusters: Vec<Cluster> = chain.by_ref().collect();
+        if chain.get_end() != Some(ChainEnd::EndOfChain) || clusters != *run {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("rebuilt FAT gives {} {} clusters instead of {}, not written", path, clusters.len(), run.len())));
+        }
+        for cluster in clusters {
+            if let Some(owner) = owners.insert(cluster, path) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("rebuilt FAT links cluster {} to both {} and {}, not written", cluster, owner, path)));
+            }
+        }
+    }
+    Ok(())
+}
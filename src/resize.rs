@@ -0,0 +1,231 @@
+use std::{fs::{self, File, OpenOptions}, io, path::PathBuf, sync::Arc};
+
+use crate::{lock_image, struct_to_bytes, BootSector, Cluster, Fat, Fat12Fs, FREE_CLUSTER};
+
+/** FAT value of a cluster marked bad, which can be cut with the free ones. */
+const BAD_CLUSTER: u16 = 0x0FF7;
+
+/** Most clusters a FAT12 volume can have: one more makes it FAT16. */
+const MAX_CLUSTERS: usize = 4084;
+
+/** Bytes moved at once when the data area makes room for a larger FAT. */
+const MOVE_CHUNK: u64 = 64 * 1024;
+
+/* ==== STRUCTS ============================================================= */
+/** How Fat12Fs::resize changes the image. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResizeOptions {
+    /** Resize the volume with the image: its total sectors, and the FAT when
+     *  the new cluster count needs a larger one. Without it only the image
+     *  file changes, and it can't get shorter than the volume. */
+    pub filesystem: bool,
+
+    /** Only work out the report, changing nothing: the image can be opened
+     *  read-only. */
+    pub dry_run: bool
+}
+
+/** Outcome of Fat12Fs::resize: the image and the volume before and after. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ResizeReport {
+    pub old_size: u64,
+    pub new_size: u64,
+    pub old_total_sectors: u32,
+    pub new_total_sectors: u32,
+    pub old_cluster_count: usize,
+    pub new_cluster_count: usize,
+    pub old_sectors_per_fat: u16,
+    pub new_sectors_per_fat: u16,
+
+    /** Bytes of the root directory and data area moved after the larger FAT. */
+    pub moved: u64
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Smallest size Fat12Fs::resize takes the image to: the end of the
+     *  volume, or resizing the filesystem too the end of its last cluster in
+     *  use. Free and bad clusters after it are what gets cut. */
+    pub fn get_minimum_size(&self, filesystem: bool) -> u64 {
+        let bytes_per_sector: u64 = self.boot_sector.bytes_per_sector as u64;
+        if !filesystem { return self.boot_sector.total_sectors() as u64 * bytes_per_sector; }
+
+        self.boot_sector.get_layout().volume_size(self.get_clusters_in_use() as u64)
+    }
+
+    /** Grow or shrink the image file to the size, e.g. to fit media of
+     *  another capacity. With ResizeOptions::filesystem the volume follows:
+     *  growing it may need a larger FAT, for which the root directory and
+     *  the data area are moved ahead (cluster numbers stay the same, every
+     *  FAT copy is rewritten); shrinking it only cuts free and bad clusters,
+     *  see get_minimum_size. The volume must stay FAT12, at most 4084
+     *  clusters. Only whole image files can be resized, not partitions, and
+     *  other handles on the image must be reopened after it.
+     *
+     *  Moving the data goes through a copy of the image next to it, renamed
+     *  over it once whole, so that a crash leaves the old layout rather than
+     *  half of each: it needs the path the image was opened from, and room
+     *  for the copy. */
+    pub fn resize(&mut self, size: u64, options: ResizeOptions) -> io::Result<ResizeReport> {
+        if !options.dry_run { self.check_writable()?; }
+        if self.offset != 0 { return Err(io::Error::new(io::ErrorKind::Unsupported, "only whole image files can be resized, not partitions")); }
+        if self.overlay.is_some() { return Err(io::Error::new(io::ErrorKind::Unsupported, "can't resize through an overlay, commit it first")); }
+        let bytes_per_sector: u64 = self.boot_sector.bytes_per_sector as u64;
+        if !size.is_multiple_of(bytes_per_sector) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} bytes are not a whole number of {} byte sectors", size, bytes_per_sector)));
+        }
+        self.flush()?;
+
+        let old_size: u64 = self.disk.metadata()?.len();
+        let mut boot_sector: BootSector = self.boot_sector;
+        let mut report: ResizeReport = ResizeReport {
+            old_size,
+            new_size: size,
+            old_total_sectors: boot_sector.total_sectors(),
+            new_total_sectors: boot_sector.total_sectors(),
+            old_cluster_count: boot_sector.get_cluster_count(),
+            new_cluster_count: boot_sector.get_cluster_count(),
+            old_sectors_per_fat: boot_sector.sectors_per_fat,
+            new_sectors_per_fat: boot_sector.sectors_per_fat,
+            moved: 0
+        };
+
+        if !options.filesystem {
+            let minimum: u64 = self.get_minimum_size(false);
+            if size < minimum {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} bytes would cut into the {} bytes of the volume, resize the filesystem too", size, minimum)));
+            }
+            if !options.dry_run {
+                self.disk.set_len(size)?;
+                self.truncation = None;
+                self.touch_container();
+                self.flush()?;
+            }
+            return Ok(report);
+        }
+
+        // A larger cluster count may need a larger FAT, which takes sectors
+        // from the data area and so lowers the count again
+        let total_sectors: u32 = u32::try_from(size / bytes_per_sector)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("{} bytes are more sectors than a volume can have", size)))?;
+        boot_sector.set_field("total_sectors", total_sectors)?;
+        loop {
+            let needed_fat_sectors: u32 = boot_sector.get_geometry().needed_fat_sectors;
+            if needed_fat_sectors <= boot_sector.sectors_per_fat as u32 { break; }
+            boot_sector.set_field("sectors_per_fat", needed_fat_sectors)?;
+        }
+        let cluster_count: usize = boot_sector.get_cluster_count();
+        if cluster_count > MAX_CLUSTERS {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} clusters would make it a FAT16 volume, FAT12 has at most {}", cluster_count, MAX_CLUSTERS)));
+        }
+        let clusters_in_use: usize = self.get_clusters_in_use();
+        if cluster_count < clusters_in_use {
+            let minimum: u64 = boot_sector.get_layout().volume_size(clusters_in_use as u64);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} bytes would cut into clusters in use, the volume needs at least {}", size, minimum)));
+        }
+        report.new_total_sectors = total_sectors;
+        report.new_cluster_count = cluster_count;
+        report.new_sectors_per_fat = boot_sector.sectors_per_fat;
+
+        let fat_growth: u64 = (boot_sector.get_fat_size() - self.boot_sector.get_fat_size()) * boot_sector.fat_count as u64;
+        let root_start: u64 = self.boot_sector.get_root_dir_start();
+        let volume_end: u64 = (self.boot_sector.total_sectors() as u64 * bytes_per_sector).min(old_size).min(size.saturating_sub(fat_growth));
+        if fat_growth > 0 { report.moved = volume_end.saturating_sub(root_start); }
+
+        // Moving the data goes through a copy of the image, but the scratch
+        // copy of a container only reaches it once sealed whole anyway
+        let copy_path: Option<PathBuf> = match (fat_growth > 0 && self.container.is_none(), &self.path) {
+            (false, _) => None,
+            (true, Some(path)) => Some(path.with_extension("resize.tmp")),
+            (true, None) => return Err(io::Error::new(io::ErrorKind::Unsupported, "a larger FAT moves the data through a copy of the image, which needs the path it was opened from"))
+        };
+        if options.dry_run { return Ok(report); }
+
+        let fat: Fat = match copy_path {
+            Some(copy_path) => self.write_through_copy(copy_path, |fs| fs.write_layout(&boot_sector, &report, root_start, volume_end, fat_growth))?,
+            None => self.write_layout(&boot_sector, &report, root_start, volume_end, fat_growth)?
+        };
+        self.boot_sector = boot_sector;
+        self.free_clusters = fat.count_free_clusters(cluster_count);
+        self.fat = Arc::new(fat);
+        self.next_free = Cluster(2);
+        self.truncation = None;
+        Ok(report)
+    }
+
+    /** Write the resized volume the report describes: the data moved for a
+     *  larger FAT, every FAT copy and the boot sector. Gives the new FAT. */
+    fn write_layout(&mut self, boot_sector: &BootSector, report: &ResizeReport, root_start: u64, volume_end: u64, fat_growth: u64) -> io::Result<Fat> {
+        let (size, old_size, cluster_count): (u64, u64, usize) = (report.new_size, report.old_size, report.new_cluster_count);
+
+        // Grown first, so that the data has room to move, shrunk last, once
+        // nothing points past the end anymore
+        if size > old_size { self.disk.set_len(size)?; }
+        if fat_growth > 0 { self.move_data(root_start, volume_end, fat_growth)?; }
+
+        // Entries past the old count (the slack of the last FAT sector) and
+        // past the new one (free or bad clusters that are gone) read as free
+        let mut fat: Fat = Fat { entries: self.fat.entries.clone() };
+        fat.entries.resize(boot_sector.get_fat_size() as usize, 0);
+        let (low, high): (usize, usize) = (report.old_cluster_count.min(cluster_count), report.old_cluster_count.max(cluster_count));
+        for cluster in (low as u32 + 2..high as u32 + 2).map(Cluster) { fat.set_entry(cluster, FREE_CLUSTER)?; }
+        for copy in 0..boot_sector.fat_count as u64 {
+            self.write_disk_at(boot_sector.get_layout().fat_copy_start(copy), &fat.entries)?;
+        }
+        self.write_disk_at(0, &struct_to_bytes(boot_sector))?;
+        self.flush()?;
+        if size < old_size { self.disk.set_len(size)?; }
+        Ok(fat)
+    }
+
+    /** Make the changes on a copy of the image at the path given, switching
+     *  the handle to it, then sync it and rename it over the image. If any
+     *  of it fails the copy is removed and the handle goes back to the
+     *  image, as it was. */
+    fn write_through_copy<T, F>(&mut self, copy_path: PathBuf, write: F) -> io::Result<T>
+    where F: FnOnce(&mut Fat12Fs) -> io::Result<T> {
+        let path: PathBuf = self.path.clone().expect("only handles opened from a path are written through a copy");
+        fs::copy(&path, &copy_path)?;
+        let copy: io::Result<File> = OpenOptions::new().read(true).write(true).open(&copy_path)
+            .and_then(|copy| lock_image(&copy).map(|_| copy));
+        let copy: File = match copy {
+            Ok(copy) => copy,
+            Err(e) => {
+                let _ = fs::remove_file(&copy_path);
+                return Err(e);
+            }
+        };
+
+        let image: Arc<File> = self.replace_disk(Arc::new(copy));
+        let result: io::Result<T> = write(self)
+            .and_then(|written| self.disk.sync_all().map(|_| written))
+            .and_then(|written| fs::rename(&copy_path, &path).map(|_| written));
+        if result.is_err() {
+            self.replace_disk(image);
+            let _ = fs::remove_file(&copy_path);
+        }
+        result
+    }
+
+    /** Clusters up to the last one in use, at least 1: those the volume
+     *  can't lose. */
+    fn get_clusters_in_use(&self) -> usize {
+        let cluster_count: u32 = self.boot_sector.get_cluster_count() as u32;
+        let last_used: Option<Cluster> = (2..cluster_count + 2).rev().map(Cluster)
+            .find(|cluster| ![FREE_CLUSTER, BAD_CLUSTER].contains(&self.fat.get_entry(*cluster)));
+        last_used.map_or(1, |cluster| cluster.0 as usize - 1)
+    }
+
+    /** Move the bytes from start to end that far ahead, the last ones first
+     *  so that nothing is overwritten before being moved. */
+    fn move_data(&mut self, start: u64, end: u64, distance: u64) -> io::Result<()> {
+        let mut chunk_end: u64 = end;
+        while chunk_end > start {
+            let chunk_start: u64 = chunk_end.saturating_sub(MOVE_CHUNK).max(start);
+            let data: Vec<u8> = self.read_disk_at(chunk_start, (chunk_end - chunk_start) as usize)?;
+            self.write_disk_at(chunk_start + distance, &data)?;
+            chunk_end = chunk_start;
+        }
+        Ok(())
+    }
+}
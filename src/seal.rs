@@ -0,0 +1,123 @@
+use std::io;
+
+use crate::{crc32, Fat12Fs};
+
+/** Magic at the start of the seal sector. */
+const SEAL_MAGIC: &[u8; 8] = b"RSFSEAL1";
+
+/* ==== STRUCTS ============================================================= */
+/** Checksums of the volume, stored in its last reserved sector: the sector
+ *  count (so that truncation shows) and a CRC-32 of each region, so that a
+ *  mismatch tells where the image changed. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Seal {
+    pub total_sectors: u32,
+
+    /** Reserved sectors (the seal one excluded), FAT copies, root directory
+     *  and data area. */
+    pub reserved_crc: u32,
+    pub fat_crc: u32,
+    pub root_crc: u32,
+    pub data_crc: u32
+}
+
+impl Seal {
+    /** Names of the regions, in the order of the checksums. */
+    pub const REGIONS: [&'static str; 4] = ["reserved sectors", "FAT", "root directory", "data area"];
+
+    /** Checksums in the order of REGIONS. */
+    pub fn get_crcs(&self) -> [u32; 4] {
+        [self.reserved_crc, self.fat_crc, self.root_crc, self.data_crc]
+    }
+
+    fn to_bytes(self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = SEAL_MAGIC.to_vec();
+        for value in [self.total_sectors, self.reserved_crc, self.fat_crc, self.root_crc, self.data_crc] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        // The record checks itself too, telling a broken seal from a changed image
+        let crc: u32 = crc32(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+        bytes
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Seal> {
+        if bytes.get(..8)? != SEAL_MAGIC { return None; }
+        let value = |i: usize| bytes.get(8 + i * 4..12 + i * 4).map(|word| u32::from_le_bytes(word.try_into().expect("4 bytes")));
+        if crc32(&bytes[..28]) != value(5)? { return None; }
+        Some(Seal { total_sectors: value(0)?, reserved_crc: value(1)?, fat_crc: value(2)?, root_crc: value(3)?, data_crc: value(4)? })
+    }
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Compute the seal of the volume as it is now and store it in the last
+     *  reserved sector, which must not be the boot sector. Any later change
+     *  breaks it: seal again after changing the image. */
+    pub fn seal(&mut self) -> io::Result<Seal> {
+        self.check_writable()?;
+        let sector: u64 = self.get_seal_sector()?;
+        let seal: Seal = self.compute_seal()?;
+
+        let mut data: Vec<u8> = seal.to_bytes();
+        data.resize(self.boot_sector.bytes_per_sector as usize, 0);
+        self.write_disk_at(sector * self.boot_sector.bytes_per_sector as u64, &data)?;
+        Ok(seal)
+    }
+
+    /** Stored seal, None if there is none (or it's broken). */
+    pub fn get_seal(&mut self) -> io::Result<Option<Seal>> {
+        let sector: u64 = self.get_seal_sector()?;
+        let bytes_per_sector: u64 = self.boot_sector.bytes_per_sector as u64;
+        let data: Vec<u8> = self.read_disk_at(sector * bytes_per_sector, bytes_per_sector as usize)?;
+        Ok(Seal::from_bytes(&data))
+    }
+
+    /** Check the image against its seal: an InvalidData error names what
+     *  doesn't match (missing seal, truncated image, changed regions). */
+    pub fn verify_seal(&mut self) -> io::Result<()> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+        let seal: Seal = self.get_seal()?.ok_or_else(|| invalid(String::from("the image has no seal")))?;
+
+        let total_sectors: u32 = self.boot_sector.total_sectors();
+        let image_sectors: u64 = self.disk.metadata()?.len().saturating_sub(self.offset) / self.boot_sector.bytes_per_sector as u64;
+        if seal.total_sectors != total_sectors { return Err(invalid(format!("sealed with {} sectors, the volume has {}", seal.total_sectors, total_sectors))); }
+        if image_sectors < total_sectors as u64 { return Err(invalid(format!("the image is truncated: {} sectors of {}", image_sectors, total_sectors))); }
+
+        let current: Seal = self.compute_seal()?;
+        let changed: Vec<&str> = Seal::REGIONS.iter().zip(seal.get_crcs().iter().zip(current.get_crcs()))
+            .filter(|(_, (sealed, current))| **sealed != *current)
+            .map(|(region, _)| *region)
+            .collect();
+        if !changed.is_empty() { return Err(invalid(format!("changed since it was sealed: {}", changed.join(", ")))); }
+        Ok(())
+    }
+
+    /** Last reserved sector, where the seal goes. */
+    fn get_seal_sector(&self) -> io::Result<u64> {
+        match self.boot_sector.reserved_sectors {
+            0 | 1 => Err(io::Error::new(io::ErrorKind::InvalidInput, "no reserved sector besides the boot sector to hold the seal")),
+            reserved_sectors => Ok(reserved_sectors as u64 - 1)
+        }
+    }
+
+    fn compute_seal(&mut self) -> io::Result<Seal> {
+        let bytes_per_sector: u64 = self.boot_sector.bytes_per_sector as u64;
+        let seal_start: u64 = self.get_seal_sector()? * bytes_per_sector;
+        let fat_start: u64 = self.boot_sector.get_fat_start();
+        let root_start: u64 = self.boot_sector.get_root_dir_start();
+        let data_start: u64 = self.boot_sector.get_cluster_region_start();
+        let total_sectors: u32 = self.boot_sector.total_sectors();
+        let end: u64 = total_sectors as u64 * bytes_per_sector;
+        let mut region_crc = |start: u64, end: u64| -> io::Result<u32> { Ok(crc32(&self.read_disk_at(start, end.saturating_sub(start) as usize)?)) };
+
+        Ok(Seal {
+            total_sectors,
+            reserved_crc: region_crc(0, seal_start)?,
+            fat_crc: region_crc(fat_start, root_start)?,
+            root_crc: region_crc(root_start, data_start)?,
+            data_crc: region_crc(data_start, end)?
+        })
+    }
+}
@@ -0,0 +1,219 @@
+use std::{io::{self, BufRead, BufReader, Read, Write}, net::{Shutdown, TcpListener, TcpStream}, thread, time::Duration};
+use rs_disk_reader::{format_rfc3339, format_timestamp, readdir::DirStream, DirectoryEntry, Fat12Fs};
+
+/** Default address the server listens on: local connections only. */
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+
+/** Most bytes read of the request line and headers together, so that a
+ *  client can't make them grow without end. */
+const MAX_REQUEST_HEAD: u64 = 16 * 1024;
+
+/* ==== STRUCTS ============================================================= */
+/** What a request resolved to, ready to be written on the socket. */
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: Vec<u8>,
+
+    // Where the client is sent instead, for redirects
+    location: Option<String>
+}
+
+impl Response {
+    fn ok(content_type: &'static str, body: Vec<u8>) -> Response {
+        Response { status: "200 OK", content_type, body, location: None }
+    }
+
+    fn error(status: &'static str) -> Response {
+        Response { status, content_type: "text/plain; charset=utf-8", body: format!("{}\n", status).into_bytes(), location: None }
+    }
+
+    fn redirect(location: String) -> Response {
+        Response { location: Some(location), ..Response::error("301 Moved Permanently") }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** serve <image> [--listen <address>]: expose the image read-only over HTTP.
+ *  Directories are listed as HTML, or JSON with "?format=json"; files are
+ *  sent as they are. Every connection has its own thread, reading through
+ *  its own handle on the image (see Fat12Fs::reader). */
+pub fn run(image_path: &str, listen: &str) -> io::Result<()> {
+    let fs: Fat12Fs = crate::fs_options().open(image_path)?;
+
+    let listener: TcpListener = TcpListener::bind(listen)?;
+    writeln!(io::stdout().lock(), "serving {} on http://{}/", image_path, listen)?;
+
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let mut fs: Fat12Fs = fs.reader();
+        thread::spawn(move || {
+            if let Err(e) = handle(stream, &mut fs) { eprintln!("connection error: {}", e); }
+        });
+    }
+    Ok(())
+}
+
+/* ==== UTILS =============================================================== */
+/** Read a single request and answer it, closing the connection after. */
+fn handle(stream: TcpStream, fs: &mut Fat12Fs) -> io::Result<()> {
+    let mut reader: BufReader<io::Take<&TcpStream>> = BufReader::new((&stream).take(MAX_REQUEST_HEAD));
+    let mut request_line: String = String::new();
+    reader.read_line(&mut request_line)?;
+    if !request_line.ends_with('\n') { return refuse(&stream, Response::error("414 URI Too Long")); }
+
+    // Skip the headers: nothing in them changes the answer. Running out of
+    // bytes before the blank line means they were too long (or cut)
+    let mut header: String = String::new();
+    loop {
+        header.clear();
+        reader.read_line(&mut header)?;
+        if !header.ends_with('\n') { return refuse(&stream, Response::error("431 Request Header Fields Too Large")); }
+        if header.trim_end().is_empty() { break; }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (method, target): (&str, &str) = match (parts.next(), parts.next()) {
+        (Some(method), Some(target)) => (method, target),
+        _ => return respond(&stream, Response::error("400 Bad Request"), false)
+    };
+    if method != "GET" && method != "HEAD" { return respond(&stream, Response::error("405 Method Not Allowed"), false); }
+
+    let (path, query): (&str, &str) = target.split_once('?').unwrap_or((target, ""));
+    let Some(path) = percent_decode(path) else { return respond(&stream, Response::error("400 Bad Request"), false) };
+    let json: bool = query.split('&').any(|parameter| parameter == "format=json");
+
+    let response: Response = match resolve(fs, &path, json) {
+        Some(response) => response,
+
+        // Directories are only listed at their path with a trailing slash,
+        // that the relative links of the listing start from
+        None => Response::redirect(format!("{}/{}{}", target.split('?').next().unwrap_or(""), if query.is_empty() { "" } else { "?" }, query))
+    };
+    respond(&stream, response, method == "HEAD")
+}
+
+/** Build the answer for a path: a listing for directories, the content for
+ *  files, 404 for anything that can't be found. None for a directory
+ *  without the trailing slash, to redirect to it. */
+fn resolve(fs: &mut Fat12Fs, path: &str, json: bool) -> Option<Response> {
+    let is_root: bool = path.trim_matches('/').is_empty();
+    let entry: Option<DirectoryEntry> = if is_root { None } else {
+        match fs.get_entry(path) {
+            Ok(entry) => Some(entry),
+            Err(_) => return Some(Response::error("404 Not Found"))
+        }
+    };
+
+    Some(match entry {
+        Some(entry) if !entry.is_directory() => match fs.read_file(path) {
+            Ok(body) => Response::ok(guess_content_type(&entry.get_name()), body),
+            Err(_) => Response::error("500 Internal Server Error")
+        },
+        _ if !path.ends_with('/') => return None,
+        _ => match fs.stream_dir(path).and_then(|stream| if json { list_json(stream) } else { list_html(path, stream) }) {
+            Ok(listing) if json => Response::ok("application/json", listing.into_bytes()),
+            Ok(listing) => Response::ok("text/html; charset=utf-8", listing.into_bytes()),
+            Err(_) => Response::error("500 Internal Server Error")
+        }
+    })
+}
+
+fn respond(mut stream: &TcpStream, response: Response, head_only: bool) -> io::Result<()> {
+    // Content-Length is the file size: the data was already cut to it
+    write!(stream, "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n", response.status, response.content_type, response.body.len())?;
+    if let Some(location) = &response.location { write!(stream, "Location: {}\r\n", location)?; }
+    write!(stream, "Connection: close\r\n\r\n")?;
+    if !head_only { stream.write_all(&response.body)?; }
+    stream.flush()
+}
+
+/** Answer a request that wasn't read whole, then read (a bounded amount
+ *  of) what's left of it: closing with unread data would reset the
+ *  connection, and the client could lose the answer. */
+fn refuse(stream: &TcpStream, response: Response) -> io::Result<()> {
+    respond(stream, response, false)?;
+    stream.shutdown(Shutdown::Write)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let _ = io::copy(&mut stream.take(1024 * 1024), &mut io::sink());
+    Ok(())
+}
+
+/** Visible entries of the directory, read a cluster at a time so that huge
+ *  directories don't sit in memory: no volume label, no "." and "..". */
+fn visible_entries(stream: DirStream<'_>) -> impl Iterator<Item = io::Result<DirectoryEntry>> + '_ {
+    stream.filter(|entry| entry.as_ref().map_or(true, |entry| entry.is_used() && !entry.is_volume_label() && !entry.is_dot_entry()))
+}
+
+fn list_html(path: &str, stream: DirStream<'_>) -> io::Result<String> {
+    let base: String = format!("{}/", path.trim_end_matches('/'));
+    let mut html: String = format!("<!DOCTYPE html>\n<html><head><title>{0}</title></head><body>\n<h1>{0}</h1>\n<table>\n", escape_html(&base));
+    if base != "/" { html.push_str("<tr><td><a href=\"../\">../</a></td><td></td><td></td></tr>\n"); }
+
+    for entry in visible_entries(stream) {
+        let entry: DirectoryEntry = entry?;
+        let file_size: u32 = entry.file_size;
+        let (name, size): (String, String) = match entry.is_directory() {
+            true => (format!("{}/", entry.get_name()), String::from("-")),
+            false => (entry.get_name(), file_size.to_string())
+        };
+        html.push_str(&format!("<tr><td><a href=\"{}\">{}</a></td><td>{}</td><td>{}</td></tr>\n",
+            escape_html(&percent_encode(&name)), escape_html(&name), size, format_timestamp(entry.get_last_change_timestamp())));
+    }
+    html.push_str("</table>\n</body></html>\n");
+    Ok(html)
+}
+
+fn list_json(stream: DirStream<'_>) -> io::Result<String> {
+    let entries: Vec<String> = visible_entries(stream).map(|entry| {
+        let entry: DirectoryEntry = entry?;
+        let file_size: u32 = entry.file_size;
+        Ok(format!("{{\"name\":\"{}\",\"directory\":{},\"size\":{},\"attributes\":\"{}\",\"modified\":\"{}\"}}",
+            escape_json(&entry.get_name()), entry.is_directory(), file_size, entry.get_attributes_string(),
+            format_rfc3339(entry.get_last_change_timestamp(), crate::config().timezone_offset)))
+    }).collect::<io::Result<Vec<String>>>()?;
+    Ok(format!("[{}]\n", entries.join(",")))
+}
+
+/** Decode %XX escapes, None if they don't form valid UTF-8. */
+fn percent_decode(text: &str) -> Option<String> {
+    let mut bytes: Vec<u8> = vec![];
+    let mut iterator = text.bytes();
+    while let Some(byte) = iterator.next() {
+        if byte != b'%' { bytes.push(byte); continue; }
+        let hex: [u8; 2] = [iterator.next()?, iterator.next()?];
+        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/** Escape every byte but the unreserved characters of RFC 3986 and '/' as
+ *  %XX, so that a name can't end the path of a link ("?", "#") or be
+ *  decoded into something else ("%"). */
+fn percent_encode(text: &str) -> String {
+    text.bytes().map(|byte| match byte {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => (byte as char).to_string(),
+        _ => format!("%{:02X}", byte)
+    }).collect()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub(crate) fn escape_json(text: &str) -> String {
+    text.chars().map(|c| match c {
+        '"' => String::from("\\\""),
+        '\\' => String::from("\\\\"),
+        c if (c as u32) < 0x20 => format!("\\u{:04x}", c as u32),
+        c => c.to_string()
+    }).collect()
+}
+
+fn guess_content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("TXT" | "BAT" | "CFG" | "INI" | "ASM" | "C" | "H") => "text/plain",
+        Some("HTM") => "text/html",
+        _ => "application/octet-stream"
+    }
+}
@@ -0,0 +1,350 @@
+use std::{fs, io::{self, BufRead, IsTerminal, Read, Write}, time::UNIX_EPOCH};
+use rs_disk_reader::posix::Stat;
+use rs_disk_reader::{bulk, format_rfc3339, format_timestamp, Cluster, Directory, DirectoryEntry, Fat12Fs, FsOptions};
+use rs_disk_reader::{ATTR_ARCHIVE, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM};
+
+use crate::error::{CliError, Context};
+use crate::terminal::RawMode;
+
+const HELP: &str = "\
+commands:
+  pwd                        print the current image directory
+  cd <dir>                   change the current image directory
+  ls [-s] [dir]              list a directory, by 8.3 name with -s
+  stat <path>                show the details of an entry
+  get <path> [host path]     copy a file from the image to the host
+  put <host path> [path]     copy a file from the host to the image (also add)
+  mkdir <dir>                create a directory
+  rm <path>                  remove a file or an empty directory
+  attrib [+R|-R ...] <path>  show or change the RHSA attributes of an entry
+  label [label]              show or change the volume label
+  chmod <mode> <path>        make read-only with no write bit, writable otherwise
+  getfattr <path> [name]     show the FAT attributes as extended attributes
+  setfattr <path> <name> <v> set the FAT attributes through an extended attribute
+  commit                     write the changes kept in memory (--overlay)
+  discard                    drop the changes kept in memory (--overlay)
+  help                       show this message
+  exit                       leave the shell
+names with spaces take \"quotes\" or a backslash before each space";
+
+/* ==== STRUCTS ============================================================= */
+/** Interactive session over an opened image, remembering the current
+ *  directory between commands. */
+struct Shell {
+    fs: Fat12Fs,
+    cwd: String
+}
+
+impl Shell {
+    /** Run a single command line, returning false when the shell should exit. */
+    fn execute(&mut self, line: &str) -> io::Result<bool> {
+        let words: Vec<String> = split_words(line)?;
+        let words: Vec<&str> = words.iter().map(String::as_str).collect();
+        let Some((command, args)) = words.split_first() else { return Ok(true) };
+
+        match (*command, args) {
+            ("exit" | "quit", _) => return Ok(false),
+            ("help", _) => writeln!(io::stdout().lock(), "{}", HELP)?,
+            ("pwd", []) => writeln!(io::stdout().lock(), "{}", self.cwd)?,
+            ("cd", []) => self.cwd = String::from("/"),
+            ("cd", [path]) => {
+                let path: String = self.resolve(path);
+
+                // Reading the directory is what tells us that it exists
+                self.fs.read_dir(&path)?;
+                self.cwd = path;
+            },
+            ("ls", []) => self.list(&self.cwd.clone(), false)?,
+            ("ls", ["-s"]) => self.list(&self.cwd.clone(), true)?,
+            ("ls", [path]) => self.list(&self.resolve(path), false)?,
+            ("ls", ["-s", path]) => self.list(&self.resolve(path), true)?,
+            ("stat", [path]) => self.stat(&self.resolve(path))?,
+            ("get", [path]) => self.get(&self.resolve(path), None)?,
+            ("get", [path, host_path]) => self.get(&self.resolve(path), Some(host_path))?,
+            ("put" | "add", [host_path]) => self.put(host_path, None)?,
+            ("put" | "add", [host_path, path]) => self.put(host_path, Some(&self.resolve(path)))?,
+            ("mkdir", [path]) => { self.fs.create_dir(&self.resolve(path), crate::config().to_image_time(self.fs.now()))?; },
+            ("rm", [path]) => self.fs.remove(&self.resolve(path))?,
+            ("attrib", [path]) => writeln!(io::stdout().lock(), "{}", self.fs.get_entry(&self.resolve(path))?.get_attributes_string())?,
+            ("attrib", [changes @ .., path]) => {
+                let path: String = self.resolve(path);
+                let attributes: u8 = changes.iter().try_fold(self.fs.get_entry(&path)?.attributes, |attributes, change| apply_attrib(attributes, change))
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid attributes {} (+ or - then R, H, S or A)", changes.join(" "))))?;
+                self.fs.set_attributes(&path, attributes)?;
+            },
+            ("label", []) => writeln!(io::stdout().lock(), "{}", self.fs.get_volume_label()?.unwrap_or_else(|| String::from("(no label)")))?,
+            ("label", words) => self.fs.set_volume_label(&words.join(" "))?,
+            ("chmod", [mode, path]) => {
+                let mode: u32 = u32::from_str_radix(mode, 8).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid mode {}", mode)))?;
+                self.fs.chmod(&self.resolve(path), mode)?;
+            },
+            ("getfattr", [path]) => {
+                let path: String = self.resolve(path);
+                for name in self.fs.list_xattrs(&path)? {
+                    writeln!(io::stdout().lock(), "{}=\"{}\"", name, String::from_utf8_lossy(&self.fs.get_xattr(&path, name)?))?;
+                }
+            },
+            ("getfattr", [path, name]) => writeln!(io::stdout().lock(), "{}", String::from_utf8_lossy(&self.fs.get_xattr(&self.resolve(path), name)?))?,
+            ("setfattr", [path, name, value]) => self.fs.set_xattr(&self.resolve(path), name, value.as_bytes())?,
+            ("commit", []) if self.fs.has_overlay() => self.fs.commit()?,
+            ("discard", []) if self.fs.has_overlay() => self.fs.discard()?,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid command: {} (type \"help\" for the list)", line.trim())))
+        }
+        Ok(true)
+    }
+
+    /** List the directory by long name, or by 8.3 name with short_names. */
+    fn list(&mut self, path: &str, short_names: bool) -> io::Result<()> {
+        let directory: Directory = self.fs.read_dir(path)?;
+        for pair in self.fs.get_name_pairs(&directory) {
+            let (entry, file_size): (&DirectoryEntry, u32) = (&pair.entry, pair.entry.file_size);
+            let size: String = if entry.is_directory() { String::from("<DIR>") } else { file_size.to_string() };
+            let name: &str = if short_names { &pair.short_name } else { pair.get_display_name() };
+            writeln!(io::stdout().lock(), "{}  {:>8}  {}  {}", entry.get_attributes_string(), size, format_timestamp(entry.get_last_change_timestamp()), name)?;
+        }
+        Ok(())
+    }
+
+    fn stat(&mut self, path: &str) -> io::Result<()> {
+        let entry: DirectoryEntry = self.fs.get_entry(path)?;
+        let clusters: usize = self.fs.get_chain(entry.get_first_cluster()).len();
+        let (file_size, first_cluster): (u32, Cluster) = (entry.file_size, entry.get_first_cluster());
+        writeln!(io::stdout().lock(), "name:          {}", self.fs.get_name(&entry))?;
+        writeln!(io::stdout().lock(), "attributes:    {}", entry.get_attributes_string())?;
+        let stat: Stat = self.fs.stat(path, &crate::config().mount)?;
+        writeln!(io::stdout().lock(), "mode:          {:04o} (uid {}, gid {})", stat.mode & 0o7777, stat.uid, stat.gid)?;
+        writeln!(io::stdout().lock(), "size:          {}", file_size)?;
+        writeln!(io::stdout().lock(), "first cluster: {}", first_cluster)?;
+        writeln!(io::stdout().lock(), "clusters:      {}", clusters)?;
+        writeln!(io::stdout().lock(), "modified:      {}", format_rfc3339(entry.get_last_change_timestamp(), crate::config().timezone_offset))?;
+        Ok(())
+    }
+
+    fn get(&mut self, path: &str, host_path: Option<&str>) -> io::Result<()> {
+        let data: Vec<u8> = self.fs.read_file(path)?;
+
+        // Without a destination, use the file name in the host working dir
+        let name: String = self.fs.get_entry(path)?.get_name();
+        if host_path.is_none() { bulk::check_host_name(&name)?; }
+        fs::write(host_path.unwrap_or(&name), data)
+    }
+
+    fn put(&mut self, host_path: &str, path: Option<&str>) -> io::Result<()> {
+        let data: Vec<u8> = fs::read(host_path)?;
+        let timestamp: u64 = crate::config().to_image_time(fs::metadata(host_path)?.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+
+        // Without a destination, use the host file name in the current dir
+        let path: String = match path {
+            Some(path) => path.to_string(),
+            None => {
+                let name: &str = host_path.rsplit(['/', '\\']).next().unwrap_or(host_path);
+                self.resolve(name)
+            }
+        };
+        self.fs.write_file(&path, &data, timestamp)?;
+        Ok(())
+    }
+
+    /** Make the path absolute against the current directory, applying any
+     *  "." and ".." component. */
+    fn resolve(&self, path: &str) -> String {
+        let mut components: Vec<&str> = vec![];
+        let base: &str = if path.starts_with(['/', '\\']) { "" } else { &self.cwd };
+        for component in base.split(['/', '\\']).chain(path.split(['/', '\\'])) {
+            match component {
+                "" | "." => {},
+                ".." => { components.pop(); },
+                _ => components.push(component)
+            }
+        }
+        format!("/{}", components.join("/"))
+    }
+
+    /** Candidates for the last word of the line: the names in the directory
+     *  it points to that start with what was typed so far. */
+    fn complete(&mut self, line: &str) -> Vec<String> {
+        let word: &str = line.rsplit(' ').next().unwrap_or("");
+        let (directory, prefix): (&str, &str) = match word.rfind(['/', '\\']) {
+            Some(i) => (&word[..=i], &word[i + 1..]),
+            None => ("", word)
+        };
+
+        let Ok(entries) = self.fs.read_dir(&self.resolve(directory)) else { return vec![] };
+        entries.entries()
+            .filter(|entry| !entry.is_volume_label() && !entry.is_dot_entry())
+            .map(|entry| (entry.get_name(), entry.is_directory()))
+            .filter(|(name, _)| name.to_uppercase().starts_with(&prefix.to_uppercase()))
+            .map(|(name, is_directory)| format!("{}{}{}", directory, name, if is_directory { "/" } else { "" }))
+            .collect()
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** shell <image> [--read-only] [--overlay]: run commands against the image
+ *  until exit. */
+pub fn run(image_path: &str, read_only: bool, overlay: bool) -> io::Result<()> {
+    // Fall back to read only if the image can't be written
+    let options: FsOptions = crate::fs_options().overlay(overlay);
+    let fs: Fat12Fs = match read_only {
+        true => options.open(image_path),
+        false => options.clone().read_only(false).open(image_path).or_else(|_| options.open(image_path))
+    }?;
+    if fs.is_read_only() { writeln!(io::stdout().lock(), "image opened read-only")?; }
+    if fs.has_overlay() { writeln!(io::stdout().lock(), "changes are kept in memory until commit")?; }
+
+    let mut shell: Shell = Shell { fs, cwd: String::from("/") };
+    let interactive: bool = io::stdin().is_terminal();
+    loop {
+        let prompt: String = format!("{}> ", shell.cwd);
+        let line: Option<String> = if interactive { read_line_raw(&prompt, &mut shell) } else { read_line_plain() };
+        let Some(line) = line else { break };
+
+        match shell.execute(&line) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Err(e),
+            Err(e) => writeln!(io::stdout().lock(), "error: {}", e)?
+        }
+    }
+    if shell.fs.get_overlay_size() > 0 { writeln!(io::stdout().lock(), "{} changed sectors discarded", shell.fs.get_overlay_size())?; }
+    Ok(())
+}
+
+/** batch <script> <image> [--keep-going]: run the shell commands of the
+ *  script ("-" for stdin), one per line, against a single handle. Changes
+ *  are kept in memory and written at the end in one go: a failing command
+ *  stops the script with the image left as it was, unless keep_going skips
+ *  it. Blank lines and lines starting with # are ignored. */
+pub fn run_batch(script_path: &str, image_path: &str, keep_going: bool) -> Result<(), CliError> {
+    let script: String = match script_path {
+        "-" => io::read_to_string(io::stdin()),
+        path => fs::read_to_string(path)
+    }.context("Could not read script")?;
+    let fs: Fat12Fs = crate::fs_options().overlay(true).read_only(false).open(image_path).context("Could not open image")?;
+
+    let mut shell: Shell = Shell { fs, cwd: String::from("/") };
+    let mut failed: Vec<(usize, io::Error)> = vec![];
+    for (number, line) in script.lines().enumerate().map(|(index, line)| (index + 1, line.trim())) {
+        if line.is_empty() || line.starts_with('#') { continue; }
+        match shell.execute(line) {
+            Ok(true) => {},
+            Ok(false) => break,
+            Err(e) if keep_going && e.kind() != io::ErrorKind::BrokenPipe => { eprintln!("line {}: {}", number, e); failed.push((number, e)); },
+            Err(e) => return Err(CliError::from_io(&format!("Line {} failed, the image was left as it was", number), &e))
+        }
+    }
+    shell.fs.commit().context("Could not write image")?;
+
+    // With --keep-going, the exit code follows the first failure
+    match failed.first() {
+        Some((number, e)) => Err(CliError::from_io(&format!("{} commands failed, the first one on line {}", failed.len(), number), e)),
+        None => Ok(())
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Words of a command line, split on whitespace outside of "..." quotes.
+ *  A backslash before a space, a quote or another backslash takes it as
+ *  is, so that long names with spaces can be given either way: "Long
+ *  name.txt" or Long\ name.txt. Anywhere else it stays a path separator,
+ *  as in \DOS\IO.SYS. */
+fn split_words(line: &str) -> io::Result<Vec<String>> {
+    let mut words: Vec<String> = vec![];
+    let mut word: Option<String> = None;
+    let mut quoted: bool = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek().is_some_and(|next| next.is_whitespace() || matches!(next, '"' | '\\')) => {
+                word.get_or_insert_with(String::new).extend(chars.next());
+            },
+            '"' => { quoted = !quoted; word.get_or_insert_with(String::new); },
+            c if c.is_whitespace() && !quoted => words.extend(word.take()),
+            c => word.get_or_insert_with(String::new).push(c)
+        }
+    }
+    if quoted { return Err(io::Error::new(io::ErrorKind::InvalidInput, "unterminated \" quote")); }
+    words.extend(word);
+    Ok(words)
+}
+
+/** Attributes with a "+R" or "-A" change applied, as DOS attrib takes them. */
+fn apply_attrib(attributes: u8, change: &str) -> Option<u8> {
+    let mut chars = change.chars();
+    let (sign, letter): (char, char) = (chars.next()?, chars.next()?.to_ascii_uppercase());
+    if chars.next().is_some() { return None; }
+    let flag: u8 = match letter {
+        'R' => ATTR_READ_ONLY,
+        'H' => ATTR_HIDDEN,
+        'S' => ATTR_SYSTEM,
+        'A' => ATTR_ARCHIVE,
+        _ => return None
+    };
+    match sign {
+        '+' => Some(attributes | flag),
+        '-' => Some(attributes & !flag),
+        _ => None
+    }
+}
+
+/** Read a line as is, for piped input. None at the end of the input. */
+fn read_line_plain() -> Option<String> {
+    let mut line: String = String::new();
+    match io::stdin().lock().read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(line)
+    }
+}
+
+/** Read a line from the terminal handling the keys one at a time, so that
+ *  tab can complete image paths. None on Ctrl-D with an empty line. */
+fn read_line_raw(prompt: &str, shell: &mut Shell) -> Option<String> {
+    // Switch off line buffering and echo until the line is read
+    let _raw_mode: RawMode = RawMode::enable();
+
+    // The echo can't fail a line: a closed output shows with the next command
+    let mut out: io::StdoutLock = io::stdout().lock();
+    let _ = write!(out, "{}", prompt);
+    let _ = out.flush();
+
+    let mut line: String = String::new();
+    let mut byte: [u8; 1] = [0];
+    loop {
+        if io::stdin().read(&mut byte).unwrap_or(0) == 0 { return None; }
+        match byte[0] {
+            b'\n' | b'\r' => { let _ = writeln!(out); return Some(line); },
+
+            // Ctrl-D only ends the input on an empty line, like shells do
+            0x04 if line.is_empty() => { let _ = writeln!(out); return None; },
+            0x7F | 0x08 if line.pop().is_some() => { let _ = write!(out, "\x08 \x08"); },
+            b'\t' => {
+                let candidates: Vec<String> = shell.complete(&line);
+                let word_start: usize = line.rfind(' ').map(|i| i + 1).unwrap_or(0);
+                let common: String = common_prefix(&candidates);
+
+                // Extend the word as far as all the candidates agree
+                if common.len() > line.len() - word_start {
+                    let _ = write!(out, "{}{}", "\x08".repeat(line.len() - word_start), common);
+                    line.replace_range(word_start.., &common);
+                } else if candidates.len() > 1 {
+                    let _ = writeln!(out);
+                    let _ = writeln!(out, "{}", candidates.join("  "));
+                    let _ = write!(out, "{}{}", prompt, line);
+                }
+            },
+            c if c.is_ascii() && !c.is_ascii_control() => { line.push(c as char); let _ = write!(out, "{}", c as char); },
+            _ => {}
+        }
+        let _ = out.flush();
+    }
+}
+
+/** Longest prefix shared by all the given strings, ignoring case. */
+fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else { return String::new() };
+    let mut length: usize = first.len();
+    for candidate in &candidates[1..] {
+        length = first.bytes().zip(candidate.bytes()).take(length).take_while(|(a, b)| a.eq_ignore_ascii_case(b)).count();
+    }
+    first[..length].to_string()
+}
@@ -0,0 +1,123 @@
+use std::{fmt, io, sync::Arc};
+
+use crate::{to_short_name, Cluster, Directory, Fat12Fs, NamePolicy};
+
+/** Longest "~N" tail numeric tails go up to, as DOS and Windows do. */
+const MAX_TAIL: u32 = 999999;
+
+/* ==== STRUCTS ============================================================= */
+/** How a short name is made unique when NamePolicy::Sanitize or LongNames
+ *  has to change a name to fit 8.3, e.g. for image builders that must
+ *  match the names Windows 95 would give, or want the same names whatever
+ *  else the directory holds. See FsOptions::short_name_tails. */
+pub trait TailStrategy: fmt::Debug + Send + Sync {
+    /** Short name to try for the long name at that attempt, counting from
+     *  1, until one isn't taken in the directory; None gives up. The basis
+     *  is the name Sanitize made. Candidates should differ from each other:
+     *  only as many attempts as the directory has slots, plus one, are made. */
+    fn candidate(&self, long_name: &str, basis: &[u8; 11], attempt: u32) -> Option<[u8; 11]>;
+
+    /** Name of the strategy in the config, "custom" for the ones it can't
+     *  select. */
+    fn get_name(&self) -> &str {
+        "custom"
+    }
+}
+
+/** "~1", "~2" and so on after as much of the basis as fits, as Windows 95
+ *  does: "Long file name.txt" becomes "LONGFI~1.TXT". */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NumericTail;
+
+/** Numeric tails for the first attempts, then two characters of the basis,
+ *  four hex digits of a hash of the long name and a numeric tail, as
+ *  Windows NT does: "LO3F2A~1.TXT". With no numeric attempts, the name
+ *  depends only on the long name unless two of them hash alike. The hash is
+ *  FNV-1a folded to 16 bits, not the one of Windows. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashTail {
+    pub numeric_attempts: u32
+}
+
+impl Default for HashTail {
+    fn default() -> HashTail {
+        HashTail { numeric_attempts: 4 }
+    }
+}
+
+impl TailStrategy for NumericTail {
+    fn candidate(&self, _: &str, basis: &[u8; 11], attempt: u32) -> Option<[u8; 11]> {
+        with_tail(basis, attempt)
+    }
+
+    fn get_name(&self) -> &str {
+        "numeric"
+    }
+}
+
+impl TailStrategy for HashTail {
+    fn candidate(&self, long_name: &str, basis: &[u8; 11], attempt: u32) -> Option<[u8; 11]> {
+        if attempt <= self.numeric_attempts { return with_tail(basis, attempt); }
+        let hash: u32 = long_name.bytes().fold(0x811C9DC5, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+        let hex: String = format!("{:04X}", (hash >> 16) ^ (hash & 0xFFFF));
+        let mut hashed: [u8; 11] = *basis;
+        let kept: usize = basis[..2].iter().take_while(|byte| **byte != b' ').count();
+        hashed[kept..kept + 4].copy_from_slice(hex.as_bytes());
+        hashed[kept + 4..8].fill(b' ');
+        with_tail(&hashed, attempt - self.numeric_attempts)
+    }
+
+    fn get_name(&self) -> &str {
+        "hash"
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Built-in strategy by its config name: "numeric" or "hash". */
+pub fn from_name(name: &str) -> Option<Arc<dyn TailStrategy>> {
+    match name {
+        "numeric" => Some(Arc::new(NumericTail)),
+        "hash" => Some(Arc::new(HashTail::default())),
+        _ => None
+    }
+}
+
+/** Basis with "~N" replacing the end of its base name as needed to fit, None
+ *  past ~999999. */
+pub fn with_tail(basis: &[u8; 11], number: u32) -> Option<[u8; 11]> {
+    if number == 0 || number > MAX_TAIL { return None; }
+    let tail: String = format!("~{}", number);
+    let length: usize = basis[..8].iter().take_while(|byte| **byte != b' ').count().min(8 - tail.len());
+    let mut short_name: [u8; 11] = *basis;
+    short_name[length..length + tail.len()].copy_from_slice(tail.as_bytes());
+    short_name[length + tail.len()..8].fill(b' ');
+    Some(short_name)
+}
+
+impl Fat12Fs {
+    /** Raw 11 bytes a new entry with that name would get in the directory,
+     *  tail included, as write_file and create_dir make them. */
+    pub fn get_short_name(&mut self, directory: &str, name: &str) -> io::Result<[u8; 11]> {
+        let parent: Directory = self.read_dir_at(Cluster(0), directory)?;
+        let basis: [u8; 11] = self.make_short_name(name, self.name_policy)?;
+        self.unique_short_name(&parent, name, basis)
+    }
+
+    /** The basis as it is, or with a tail from FsOptions::short_name_tails
+     *  if Sanitize changed more than the case of the name. LongNames always
+     *  takes a tail then, numeric without one. A basis that is the short
+     *  name of the entry with that long name stays as it is. */
+    pub(crate) fn unique_short_name(&self, parent: &Directory, long_name: &str, basis: [u8; 11]) -> io::Result<[u8; 11]> {
+        let tails: &dyn TailStrategy = match (&self.short_name_tails, self.name_policy) {
+            (Some(tails), NamePolicy::Sanitize | NamePolicy::LongNames) => tails.as_ref(),
+            (None, NamePolicy::LongNames) => &NumericTail,
+            _ => return Ok(basis)
+        };
+        if to_short_name(long_name) == Some(basis) { return Ok(basis); }
+        if self.find_long_name(parent, long_name).is_some_and(|index| parent.entries[index].get_short_name() == basis) { return Ok(basis); }
+        (1..=parent.entries.len() as u32 + 1)
+            .map_while(|attempt| tails.candidate(long_name, &basis, attempt))
+            .find(|candidate| parent.find_index(candidate).is_none())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::AlreadyExists, format!("no free short name left for {}", long_name)))
+    }
+}
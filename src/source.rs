@@ -0,0 +1,69 @@
+use std::{fs, io::{self, Read}, path::PathBuf};
+
+use crate::usage::Placement;
+use crate::Fat12Fs;
+
+/* ==== STRUCTS ============================================================= */
+/** Where the content of a file written to the image comes from, so that
+ *  generated files can go in without a temporary host file. */
+pub trait FileSource {
+    fn read_content(&mut self) -> io::Result<Vec<u8>>;
+}
+
+/** File on the host, read when the content is asked for. */
+#[derive(Debug, Clone)]
+pub struct HostFile(pub PathBuf);
+
+/** Closure giving the content, called once per write. */
+pub struct Generator<F: FnMut() -> io::Result<Vec<u8>>>(pub F);
+
+/** Reader consumed to its end, e.g. stdin or a decompressor. */
+pub struct ReaderSource<R: Read>(pub R);
+
+impl FileSource for Vec<u8> {
+    fn read_content(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.clone())
+    }
+}
+
+impl FileSource for &[u8] {
+    fn read_content(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.to_vec())
+    }
+}
+
+impl FileSource for String {
+    fn read_content(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.as_bytes().to_vec())
+    }
+}
+
+impl FileSource for HostFile {
+    fn read_content(&mut self) -> io::Result<Vec<u8>> {
+        fs::read(&self.0)
+    }
+}
+
+impl<F: FnMut() -> io::Result<Vec<u8>>> FileSource for Generator<F> {
+    fn read_content(&mut self) -> io::Result<Vec<u8>> {
+        (self.0)()
+    }
+}
+
+impl<R: Read> FileSource for ReaderSource<R> {
+    fn read_content(&mut self) -> io::Result<Vec<u8>> {
+        let mut data: Vec<u8> = vec![];
+        self.0.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Create or overwrite the file at the given path with what the source
+     *  gives, see write_file. Nothing changes if the source fails. */
+    pub fn write_from(&mut self, path: &str, source: &mut dyn FileSource, timestamp: u64) -> io::Result<Placement> {
+        let data: Vec<u8> = source.read_content()?;
+        self.write_file(path, &data, timestamp)
+    }
+}
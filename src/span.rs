@@ -0,0 +1,233 @@
+use std::{collections::BTreeMap, fs, io::{self, Write}, path::{Path, PathBuf}};
+
+use crate::bulk::{host_timestamp, join_image_path, preserve_metadata, safe_host_path, walk_host_tree, BulkOptions, HostChild};
+use crate::volume::random_volume_id;
+use crate::{invalid_name, short_name_to_string, DirectoryEntry, Fat12Fs};
+
+/** Root directory file of each disk of a set, telling what it holds. */
+pub const MANIFEST_NAME: &str = "SPANSET.TXT";
+const MANIFEST_HEADER: &str = "RSFAT SPAN 1";
+
+/* ==== STRUCTS ============================================================= */
+/** Piece of a host file stored on a disk of the set, at the same path. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpanPart {
+    pub path: String,
+    pub offset: u64,
+    pub length: u64,
+
+    /** Size of the whole file. */
+    pub size: u64
+}
+
+/** Continuation manifest of a disk: which set it belongs to, its place in
+ *  the set and the directories and file pieces it holds. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub set_id: u32,
+
+    /** Counting from 1, as printed on the labels. */
+    pub disk: usize,
+    pub disk_count: usize,
+    pub directories: Vec<String>,
+    pub parts: Vec<SpanPart>
+}
+
+impl Manifest {
+    /** Text form, one line per item and the path last:
+     *  "file <offset> <length> <size> <path>". */
+    pub fn to_text(&self) -> String {
+        let mut text: String = format!("{}\r\nset {:08X}\r\ndisk {} of {}\r\n", MANIFEST_HEADER, self.set_id, self.disk, self.disk_count);
+        for directory in &self.directories { text.push_str(&format!("dir {}\r\n", directory)); }
+        for part in &self.parts { text.push_str(&format!("file {} {} {} {}\r\n", part.offset, part.length, part.size, part.path)); }
+        text
+    }
+
+    pub fn parse(text: &str) -> io::Result<Manifest> {
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", MANIFEST_NAME, message));
+        let mut lines = text.lines().map(|line| line.trim_end_matches('\r'));
+        if lines.next() != Some(MANIFEST_HEADER) { return Err(invalid(String::from("not a span manifest"))); }
+
+        let mut manifest: Manifest = Manifest { set_id: 0, disk: 0, disk_count: 0, directories: vec![], parts: vec![] };
+        for line in lines.filter(|line| !line.is_empty()) {
+            let bad_line = || invalid(format!("bad line {:?}", line));
+            let (key, value): (&str, &str) = line.split_once(' ').ok_or_else(bad_line)?;
+            match key {
+                "set" => manifest.set_id = u32::from_str_radix(value, 16).map_err(|_| bad_line())?,
+                "disk" => {
+                    let (disk, count): (&str, &str) = value.split_once(" of ").ok_or_else(bad_line)?;
+                    manifest.disk = disk.parse().map_err(|_| bad_line())?;
+                    manifest.disk_count = count.parse().map_err(|_| bad_line())?;
+                },
+                "dir" => manifest.directories.push(value.to_string()),
+                "file" => {
+                    let fields: Vec<&str> = value.splitn(4, ' ').collect();
+                    let [offset, length, size, path] = fields[..] else { return Err(bad_line()) };
+                    let number = |field: &str| field.parse::<u64>().map_err(|_| bad_line());
+                    manifest.parts.push(SpanPart { path: path.to_string(), offset: number(offset)?, length: number(length)?, size: number(size)? });
+                },
+                _ => return Err(bad_line())
+            }
+        }
+        if manifest.disk == 0 || manifest.disk > manifest.disk_count { return Err(invalid(format!("bad disk number {} of {}", manifest.disk, manifest.disk_count))); }
+        Ok(manifest)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Copy the host tree to the root of the given disks in order, moving to the
+ *  next disk when one is full and cutting the file that doesn't fit in two.
+ *  Every disk used gets a manifest; the ones left over are not touched. */
+pub fn split(disks: &mut [Fat12Fs], host_dir: &Path, options: &BulkOptions) -> io::Result<Vec<Manifest>> {
+    let mut items: Vec<(String, PathBuf, bool)> = vec![];
+    collect_host_tree(disks.first().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no disks to split to"))?, host_dir, &mut items)?;
+
+    let set_id: u32 = random_volume_id();
+    let mut manifests: Vec<Manifest> = vec![];
+    let mut current: usize = 0;
+    start_disk(&mut disks[current], &mut manifests, set_id)?;
+
+    for (path, host_path, is_directory) in items {
+        let timestamp: u64 = host_timestamp(&host_path, options);
+        if is_directory {
+            disks[current].create_dir_all(&join_image_path("/", &path), timestamp)?;
+            manifests[current].directories.push(path);
+            continue;
+        }
+
+        let data: Vec<u8> = fs::read(&host_path)?;
+        let mut offset: usize = 0;
+        loop {
+            // Keep room for the manifest as it will be, and for a directory
+            // that has to grow by a cluster
+            let manifest_bytes: usize = manifests[current].to_text().len() + path.len() + 64;
+            let cluster_size: usize = disks[current].get_boot_sector().get_cluster_size();
+            let reserved: usize = manifest_bytes.div_ceil(cluster_size) + 1;
+            let available: usize = disks[current].get_free_clusters().saturating_sub(reserved) * cluster_size;
+
+            let length: usize = available.min(data.len() - offset);
+            if length > 0 || data.is_empty() {
+                // A piece carried over to a new disk needs its directories there too
+                if let Some((parent, _)) = path.rsplit_once('/') { disks[current].create_dir_all(&join_image_path("/", parent), timestamp)?; }
+                disks[current].write_file(&join_image_path("/", &path), &data[offset..offset + length], timestamp)?;
+                manifests[current].parts.push(SpanPart { path: path.clone(), offset: offset as u64, length: length as u64, size: data.len() as u64 });
+                offset += length;
+                if offset == data.len() { break; }
+            }
+
+            current += 1;
+            if current == disks.len() {
+                return Err(io::Error::new(io::ErrorKind::StorageFull, format!("{} disks are not enough, {} bytes of {} left over", disks.len(), data.len() - offset, path)));
+            }
+            start_disk(&mut disks[current], &mut manifests, set_id)?;
+        }
+    }
+
+    // Only now the number of disks is known
+    let disk_count: usize = manifests.len();
+    for (disk, manifest) in disks.iter_mut().zip(manifests.iter_mut()) {
+        manifest.disk_count = disk_count;
+        let timestamp: u64 = disk.now();
+        disk.write_file(&join_image_path("/", MANIFEST_NAME), manifest.to_text().as_bytes(), timestamp)?;
+    }
+    Ok(manifests)
+}
+
+/** Put the files of a set back together under the host directory. The
+ *  disks can be given in any order, but all of them must be there. Returns
+ *  the paths of the files written. */
+pub fn merge(disks: &mut [Fat12Fs], host_dir: &Path, options: &BulkOptions) -> io::Result<Vec<String>> {
+    let mut manifests: Vec<(Manifest, usize)> = vec![];
+    for (index, disk) in disks.iter_mut().enumerate() {
+        let text: Vec<u8> = disk.read_file(&join_image_path("/", MANIFEST_NAME))
+            .map_err(|e| io::Error::new(e.kind(), format!("image {} has no {}: {}", index + 1, MANIFEST_NAME, e)))?;
+        manifests.push((Manifest::parse(&String::from_utf8_lossy(&text))?, index));
+    }
+    check_set(&mut manifests)?;
+
+    // Pieces of each file in disk order, the files in the order they were split
+    fs::create_dir_all(host_dir)?;
+    let mut files: Vec<String> = vec![];
+    let mut pieces: BTreeMap<String, Vec<(SpanPart, usize)>> = BTreeMap::new();
+    for (manifest, index) in &manifests {
+        for directory in &manifest.directories { fs::create_dir_all(safe_host_path(host_dir, directory)?)?; }
+        for part in &manifest.parts {
+            if !pieces.contains_key(&part.path) { files.push(part.path.clone()); }
+            pieces.entry(part.path.clone()).or_default().push((part.clone(), *index));
+        }
+    }
+
+    for path in &files {
+        let host_path: PathBuf = safe_host_path(host_dir, path)?;
+        if let Some(parent) = host_path.parent() { fs::create_dir_all(parent)?; }
+        let mut file: fs::File = fs::File::create(&host_path)?;
+
+        let mut offset: u64 = 0;
+        let mut entry: Option<DirectoryEntry> = None;
+        for (part, index) in &pieces[path] {
+            let missing = |what: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path, what));
+            if part.offset != offset { return Err(missing(format!("the piece at {} is missing", offset))); }
+
+            let image_path: String = join_image_path("/", path);
+            let data: Vec<u8> = disks[*index].read_file(&image_path)?;
+            if data.len() as u64 != part.length { return Err(missing(format!("disk {} holds {} bytes of it, the manifest says {}", index + 1, data.len(), part.length))); }
+            file.write_all(&data)?;
+            offset += part.length;
+            entry = Some(disks[*index].get_entry(&image_path)?);
+        }
+        let size: u64 = pieces[path].last().map(|(part, _)| part.size).unwrap_or(0);
+        if offset != size { return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: {} bytes of {} in the set", path, offset, size))); }
+
+        drop(file);
+        if let Some(entry) = entry { preserve_metadata(&host_path, &entry, options)?; }
+    }
+    Ok(files)
+}
+
+/* ==== UTILS =============================================================== */
+/** Claim the root entry of the manifest before anything else, so that a
+ *  full root directory can't keep it out at the end. */
+fn start_disk(disk: &mut Fat12Fs, manifests: &mut Vec<Manifest>, set_id: u32) -> io::Result<()> {
+    let path: String = join_image_path("/", MANIFEST_NAME);
+    if disk.get_entry(&path).is_ok() {
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("disk {} is already part of a set", manifests.len() + 1)));
+    }
+    let timestamp: u64 = disk.now();
+    disk.write_file(&path, &[], timestamp)?;
+    manifests.push(Manifest { set_id, disk: manifests.len() + 1, disk_count: 0, directories: vec![], parts: vec![] });
+    Ok(())
+}
+
+/** Order the manifests by disk number, refusing disks of other sets, the
+ *  same disk twice and missing ones. */
+fn check_set(manifests: &mut [(Manifest, usize)]) -> io::Result<()> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    manifests.sort_by_key(|(manifest, _)| manifest.disk);
+    let Some((first, _)) = manifests.first() else { return Err(io::Error::new(io::ErrorKind::InvalidInput, "no disks to merge")) };
+    let (set_id, disk_count): (u32, usize) = (first.set_id, first.disk_count);
+
+    for (position, (manifest, index)) in manifests.iter().enumerate() {
+        if manifest.set_id != set_id || manifest.disk_count != disk_count {
+            return Err(invalid(format!("image {} is disk {} of another set", index + 1, manifest.disk)));
+        }
+        if manifest.disk < position + 1 { return Err(invalid(format!("disk {} is given twice", manifest.disk))); }
+        if manifest.disk > position + 1 { return Err(io::Error::new(io::ErrorKind::NotFound, format!("disk {} of {} is missing", position + 1, disk_count))); }
+    }
+    if manifests.len() < disk_count { return Err(io::Error::new(io::ErrorKind::NotFound, format!("disk {} of {} is missing", manifests.len() + 1, disk_count))); }
+    Ok(())
+}
+
+/** Host tree in the order it's copied, as (image path, host path, is dir):
+ *  directories before their content, names sorted. */
+fn collect_host_tree(fs: &Fat12Fs, directory: &Path, items: &mut Vec<(String, PathBuf, bool)>) -> io::Result<()> {
+    walk_host_tree(directory, "", &mut |child: &HostChild, prefix: &str| {
+        let short_name: [u8; 11] = fs.get_name_policy().to_short_name(&child.file_name).ok_or_else(|| invalid_name(&child.file_name))?;
+        let path: String = format!("{}{}", prefix, short_name_to_string(&short_name));
+        if path.eq_ignore_ascii_case(MANIFEST_NAME) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is the name of the set manifest", MANIFEST_NAME)));
+        }
+        if child.file_type.is_symlink() { return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{}: symbolic link", path))); }
+        items.push((path.clone(), child.path.clone(), child.file_type.is_dir()));
+        Ok(Some(format!("{}/", path)))
+    })
+}
@@ -0,0 +1,68 @@
+use std::{fs::{self, File, OpenOptions}, io::{self, Read}, path::{Path, PathBuf}};
+
+use crate::write_all_at;
+
+/** Granularity of the holes: zeros are only skipped a whole sector at once. */
+const SECTOR_SIZE: usize = 512;
+
+/* ==== STRUCTS ============================================================= */
+/** Sectors of an image file by what they hold, see sparsify. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SparseReport {
+    pub data_sectors: u64,
+
+    /** Sectors holding only zeros, left as holes. */
+    pub zero_sectors: u64
+}
+
+/* ==== METHODS ============================================================= */
+/** Rewrite the image file as a sparse one, where the sectors holding only
+ *  zeros are holes that take no space on the host, as formatting leaves a
+ *  new image. Goes through a copy next to the image renamed over it, so
+ *  the image must not be open meanwhile. */
+pub fn sparsify(path: &Path) -> io::Result<SparseReport> {
+    let mut image: File = File::open(path)?;
+    let length: u64 = image.metadata()?.len();
+    let copy_path: PathBuf = path.with_extension("sparse.tmp");
+    let copy: File = OpenOptions::new().write(true).create_new(true).open(&copy_path)?;
+
+    let result: io::Result<SparseReport> = (|| {
+        copy.set_len(length)?;
+        let mut report: SparseReport = SparseReport::default();
+        let mut sector: [u8; SECTOR_SIZE] = [0; SECTOR_SIZE];
+        let mut offset: u64 = 0;
+        while offset < length {
+            let size: usize = (length - offset).min(SECTOR_SIZE as u64) as usize;
+            image.read_exact(&mut sector[..size])?;
+            match sector[..size].iter().all(|byte| *byte == 0) {
+                true => report.zero_sectors += 1,
+                false => {
+                    write_all_at(&copy, &sector[..size], offset)?;
+                    report.data_sectors += 1;
+                }
+            }
+            offset += size as u64;
+        }
+        copy.sync_all()?;
+        fs::set_permissions(&copy_path, image.metadata()?.permissions())?;
+        Ok(report)
+    })();
+
+    match result {
+        Ok(report) => fs::rename(&copy_path, path).map(|_| report),
+        Err(e) => {
+            let _ = fs::remove_file(&copy_path);
+            Err(e)
+        }
+    }
+}
+
+/** Write every sector of the image file, holes included, for tools and
+ *  devices that don't take sparse files. Gives the bytes written. */
+pub fn materialize(path: &Path) -> io::Result<u64> {
+    let data: Vec<u8> = fs::read(path)?;
+    let image: File = OpenOptions::new().write(true).open(path)?;
+    write_all_at(&image, &data, 0)?;
+    image.sync_all()?;
+    Ok(data.len() as u64)
+}
@@ -0,0 +1,102 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::Fat12Fs;
+
+/* ==== STRUCTS ============================================================= */
+/** Work done through the handles sharing the counters, to tune the cache
+ *  size and the allocation strategy. See Fat12Fs::get_stats. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    /** Sectors read from and written to the image file, not counting the
+     *  ones served by the cache, the overlay or the unflushed sectors. */
+    pub sectors_read: u64,
+    pub sectors_written: u64,
+
+    /** Sectors looked up in the sector cache, see FsOptions::cache_sectors. */
+    pub cache_hits: u64,
+    pub cache_misses: u64,
+
+    /** Cluster chains followed through the FAT, to read, grow or free them. */
+    pub chains_walked: u64,
+
+    /** File content read and written, whole files or parts of them. */
+    pub bytes_copied: u64
+}
+
+/** Counters behind Stats, which handles opened with the same FsOptions::stats
+ *  share, as do a handle and its clones (see Fat12Fs::reader). */
+#[derive(Debug, Default)]
+pub struct Counters {
+    sectors_read: AtomicU64,
+    sectors_written: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    chains_walked: AtomicU64,
+    bytes_copied: AtomicU64
+}
+
+/** One of the Stats, to count through a shared reference. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Counter {
+    SectorsRead,
+    SectorsWritten,
+    CacheHits,
+    CacheMisses,
+    ChainsWalked,
+    BytesCopied
+}
+
+impl Counters {
+    pub fn get(&self) -> Stats {
+        Stats {
+            sectors_read: self.sectors_read.load(Ordering::Relaxed),
+            sectors_written: self.sectors_written.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            chains_walked: self.chains_walked.load(Ordering::Relaxed),
+            bytes_copied: self.bytes_copied.load(Ordering::Relaxed)
+        }
+    }
+
+    pub fn reset(&self) {
+        for counter in self.all() { counter.store(0, Ordering::Relaxed); }
+    }
+
+    pub(crate) fn add(&self, counter: Counter, count: u64) {
+        let counter: &AtomicU64 = match counter {
+            Counter::SectorsRead => &self.sectors_read,
+            Counter::SectorsWritten => &self.sectors_written,
+            Counter::CacheHits => &self.cache_hits,
+            Counter::CacheMisses => &self.cache_misses,
+            Counter::ChainsWalked => &self.chains_walked,
+            Counter::BytesCopied => &self.bytes_copied
+        };
+        counter.fetch_add(count, Ordering::Relaxed);
+    }
+
+    fn all(&self) -> [&AtomicU64; 6] {
+        [&self.sectors_read, &self.sectors_written, &self.cache_hits, &self.cache_misses, &self.chains_walked, &self.bytes_copied]
+    }
+}
+
+impl Stats {
+    /** Fraction of the cache lookups that found the sector, None without
+     *  any lookup. */
+    pub fn get_hit_rate(&self) -> Option<f64> {
+        let lookups: u64 = self.cache_hits + self.cache_misses;
+        if lookups == 0 { None } else { Some(self.cache_hits as f64 / lookups as f64) }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** What was done so far through this handle and the others sharing its
+     *  counters. */
+    pub fn get_stats(&self) -> Stats {
+        self.stats.get()
+    }
+
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+    }
+}
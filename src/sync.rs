@@ -0,0 +1,310 @@
+use std::{collections::{btree_map::Entry, BTreeMap, BTreeSet}, fs, io, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::digest::{self, ContentDigest, Crc32};
+use crate::{short_name_to_string, to_short_name, DirectoryEntry, Fat12Fs};
+use crate::bulk::{safe_host_path, walk_host_tree, HostChild};
+
+/** Name of the file, kept in the host directory, that remembers the content
+ *  of every file as of the last sync. It's what lets us tell "changed on one
+ *  side" apart from "changed on both sides". */
+pub const STATE_FILE_NAME: &str = ".rsfat-sync";
+
+/* ==== STRUCTS ============================================================= */
+/** What to do when a file changed on both sides since the last sync. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /** Leave both copies untouched and report the conflict. */
+    Skip,
+    /** The host copy wins. */
+    Host,
+    /** The image copy wins. */
+    Image,
+    /** The copy with the latest modification time wins. */
+    Newer
+}
+
+impl ConflictPolicy {
+    pub fn from_name(name: &str) -> Option<ConflictPolicy> {
+        match name {
+            "skip" => Some(ConflictPolicy::Skip),
+            "host" => Some(ConflictPolicy::Host),
+            "image" => Some(ConflictPolicy::Image),
+            "newer" => Some(ConflictPolicy::Newer),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            ConflictPolicy::Skip => "skip",
+            ConflictPolicy::Host => "host",
+            ConflictPolicy::Image => "image",
+            ConflictPolicy::Newer => "newer"
+        }
+    }
+}
+
+pub struct SyncOptions {
+    /** Only compute the report, without touching either side. */
+    pub dry_run: bool,
+    pub on_conflict: ConflictPolicy,
+
+    /** Offset from UTC of the image timestamps, in minutes: FAT stores
+     *  local times, while host times are always UTC. */
+    pub timezone_offset: i32
+}
+
+impl Default for SyncOptions {
+    fn default() -> SyncOptions {
+        SyncOptions { dry_run: false, on_conflict: ConflictPolicy::Skip, timezone_offset: 0 }
+    }
+}
+
+/** A single step performed (or planned, on dry runs) by the sync.
+ *  Paths are relative to the synced directories, in the image "A/B.TXT" form. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncAction {
+    CopyToImage(String),
+    CopyToHost(String),
+    DeleteFromImage(String),
+    DeleteFromHost(String)
+}
+
+/** A file that changed on both sides and was left alone. */
+#[derive(Debug, Clone)]
+pub struct SyncConflict {
+    pub path: String,
+    pub reason: String
+}
+
+#[derive(Debug, Default)]
+pub struct SyncReport {
+    pub actions: Vec<SyncAction>,
+    pub conflicts: Vec<SyncConflict>,
+
+    /** Host files that can't be represented in the image, with the reason. */
+    pub skipped: Vec<(PathBuf, String)>
+}
+
+/** State of a single file on one side of the sync. */
+struct FileState {
+    digest: Vec<u8>,
+    timestamp: u64,
+    host_path: Option<PathBuf>
+}
+
+/* ==== METHODS ============================================================= */
+/** Synchronize the image directory with the host directory in both ways.
+ *  Files changed on a single side since the last sync are copied (or deleted)
+ *  on the other one; files changed on both sides are handled according to
+ *  the conflict policy, and by default only reported. */
+pub fn sync(fs: &mut Fat12Fs, image_dir: &str, host_dir: &Path, options: &SyncOptions) -> io::Result<SyncReport> {
+    sync_with::<Crc32>(fs, image_dir, host_dir, options)
+}
+
+/** Same as sync, comparing the content of the files with that digest. The
+ *  state file keeps the digests of the last sync: entries made with another
+ *  algorithm are ignored, as if the files had never been synced. */
+pub fn sync_with<D: ContentDigest>(fs: &mut Fat12Fs, image_dir: &str, host_dir: &Path, options: &SyncOptions) -> io::Result<SyncReport> {
+    let mut report: SyncReport = SyncReport::default();
+
+    // Snapshot both sides and what they looked like at the last sync
+    let image_files: BTreeMap<String, FileState> = scan_image::<D>(fs, image_dir)?;
+
+    let mut host_files: BTreeMap<String, FileState> = BTreeMap::new();
+    scan_host::<D>(host_dir, &mut host_files, &mut report)?;
+    for host in host_files.values_mut() { host.timestamp = shift_timestamp(host.timestamp, options.timezone_offset); }
+
+    let state_path: PathBuf = host_dir.join(STATE_FILE_NAME);
+    let mut state: BTreeMap<String, Vec<u8>> = read_state(&state_path, D::OUTPUT_SIZE)?;
+
+    // Decide what to do with every file seen on either side
+    let paths: BTreeSet<String> = image_files.keys().chain(host_files.keys()).cloned().collect();
+    for path in paths {
+        let image: Option<&FileState> = image_files.get(&path);
+        let host: Option<&FileState> = host_files.get(&path);
+        let last: Option<&Vec<u8>> = state.get(&path);
+
+        let action: Option<SyncAction> = match (host, image) {
+            (Some(host), Some(image)) if host.digest == image.digest => None,
+            (Some(host), Some(image)) => {
+                let host_changed: bool = last != Some(&host.digest);
+                let image_changed: bool = last != Some(&image.digest);
+                match (host_changed, image_changed) {
+                    (true, false) => Some(SyncAction::CopyToImage(path.clone())),
+                    (false, true) => Some(SyncAction::CopyToHost(path.clone())),
+                    _ => resolve_conflict(&mut report, &path, "changed on both sides", options.on_conflict,
+                        SyncAction::CopyToImage(path.clone()), SyncAction::CopyToHost(path.clone()),
+                        host.timestamp >= image.timestamp)
+                }
+            },
+
+            // On a single side: either new there, or deleted from the other
+            (Some(host), None) => match last {
+                None => Some(SyncAction::CopyToImage(path.clone())),
+                Some(digest) if *digest == host.digest => Some(SyncAction::DeleteFromHost(path.clone())),
+                Some(_) => resolve_conflict(&mut report, &path, "changed on host, deleted from image", options.on_conflict,
+                    SyncAction::CopyToImage(path.clone()), SyncAction::DeleteFromHost(path.clone()), true)
+            },
+            (None, Some(image)) => match last {
+                None => Some(SyncAction::CopyToHost(path.clone())),
+                Some(digest) if *digest == image.digest => Some(SyncAction::DeleteFromImage(path.clone())),
+                Some(_) => resolve_conflict(&mut report, &path, "changed in image, deleted from host", options.on_conflict,
+                    SyncAction::DeleteFromImage(path.clone()), SyncAction::CopyToHost(path.clone()), false)
+            },
+            (None, None) => None
+        };
+
+        // Files already equal on both sides are in sync as they are
+        if let (Some(host), Some(image)) = (host, image) {
+            if host.digest == image.digest { state.insert(path.clone(), host.digest.clone()); }
+        }
+
+        let Some(action) = action else { continue };
+        if !options.dry_run {
+            apply(fs, image_dir, host_dir, &action, host, image, options)?;
+        }
+
+        // Record the content both sides now share
+        match &action {
+            SyncAction::CopyToImage(_) => { state.insert(path.clone(), host.map(|host| host.digest.clone()).unwrap_or_default()); },
+            SyncAction::CopyToHost(_) => { state.insert(path.clone(), image.map(|image| image.digest.clone()).unwrap_or_default()); },
+            SyncAction::DeleteFromImage(_) | SyncAction::DeleteFromHost(_) => { state.remove(&path); }
+        }
+        report.actions.push(action);
+    }
+
+    if !options.dry_run { write_state(&state_path, &state)?; }
+    Ok(report)
+}
+
+/* ==== UTILS =============================================================== */
+/** Pick the winning action of a conflict according to the policy, or record
+ *  the conflict in the report if the policy is to skip it. */
+fn resolve_conflict(report: &mut SyncReport, path: &str, reason: &str, policy: ConflictPolicy,
+                    host_wins: SyncAction, image_wins: SyncAction, host_is_newer: bool) -> Option<SyncAction> {
+    match policy {
+        ConflictPolicy::Skip => {
+            report.conflicts.push(SyncConflict { path: path.to_string(), reason: reason.to_string() });
+            None
+        },
+        ConflictPolicy::Host => Some(host_wins),
+        ConflictPolicy::Image => Some(image_wins),
+        ConflictPolicy::Newer => Some(if host_is_newer { host_wins } else { image_wins })
+    }
+}
+
+/** Carry out a single action on the image or on the host. */
+fn apply(fs: &mut Fat12Fs, image_dir: &str, host_dir: &Path, action: &SyncAction,
+         host: Option<&FileState>, image: Option<&FileState>, options: &SyncOptions) -> io::Result<()> {
+    match action {
+        SyncAction::CopyToImage(path) => {
+            let host: &FileState = host.expect("copying a file missing from host");
+            let host_path: &PathBuf = host.host_path.as_ref().expect("host file without a path");
+            let data: Vec<u8> = fs::read(host_path)?;
+            let image_path: String = join_image_path(image_dir, path);
+
+            // Make sure the parent directories exist before writing the file
+            if let Some((parent, _)) = image_path.rsplit_once('/') { fs.create_dir_all(parent, host.timestamp)?; }
+            fs.write_file(&image_path, &data, host.timestamp).map(|_| ())
+        },
+        SyncAction::CopyToHost(path) => {
+            let image: &FileState = image.expect("copying a file missing from image");
+            let data: Vec<u8> = fs.read_file(&join_image_path(image_dir, path))?;
+
+            // Overwrite the existing host file, or create it with the image name
+            let host_path: PathBuf = match host.and_then(|host| host.host_path.clone()) {
+                Some(host_path) => host_path,
+                None => safe_host_path(host_dir, path)?
+            };
+            if let Some(parent) = host_path.parent() { fs::create_dir_all(parent)?; }
+            fs::write(&host_path, data)?;
+
+            // Keep the image timestamp, so that "newer" keeps making sense
+            let timestamp: u64 = shift_timestamp(image.timestamp, -options.timezone_offset);
+            let modified: SystemTime = UNIX_EPOCH + std::time::Duration::from_secs(timestamp);
+            fs::File::options().write(true).open(&host_path)?.set_modified(modified)
+        },
+        SyncAction::DeleteFromImage(path) => fs.remove(&join_image_path(image_dir, path)),
+        SyncAction::DeleteFromHost(_) => {
+            let host_path: &PathBuf = host.and_then(|host| host.host_path.as_ref()).expect("deleting a file missing from host");
+            fs::remove_file(host_path)
+        }
+    }
+}
+
+/** Collect every file under the given image directory, recursively. */
+fn scan_image<D: ContentDigest>(fs: &mut Fat12Fs, image_dir: &str) -> io::Result<BTreeMap<String, FileState>> {
+    let entries: Vec<(String, DirectoryEntry)> = fs.walk(image_dir).collect::<io::Result<_>>()?;
+    let mut files: BTreeMap<String, FileState> = BTreeMap::new();
+    for (path, entry) in entries.into_iter().filter(|(_, entry)| !entry.is_directory()) {
+        let data: Vec<u8> = fs.read_file(&join_image_path(image_dir, &path))?;
+        files.insert(path, FileState { digest: D::digest(&data), timestamp: entry.get_last_change_timestamp(), host_path: None });
+    }
+    Ok(files)
+}
+
+/** Collect every regular file under the given host directory, recursively.
+ *  Files whose name can't be stored as 8.3 are reported as skipped. */
+fn scan_host<D: ContentDigest>(directory: &Path, files: &mut BTreeMap<String, FileState>, report: &mut SyncReport) -> io::Result<()> {
+    if !directory.exists() { return Ok(()); }
+
+    walk_host_tree(directory, "", &mut |child: &HostChild, prefix: &str| {
+        if prefix.is_empty() && child.file_name == STATE_FILE_NAME { return Ok(None); }
+        if child.file_type.is_symlink() {
+            report.skipped.push((child.path.clone(), "symbolic link".to_string()));
+            return Ok(None);
+        }
+
+        // The key is the name as the image would store it, so that host
+        // "kernel.bin" and image "KERNEL.BIN" are recognized as the same file
+        let Some(short_name) = to_short_name(&child.file_name) else {
+            report.skipped.push((child.path.clone(), "not a valid 8.3 name".to_string()));
+            return Ok(None);
+        };
+        let path: String = format!("{}{}", prefix, short_name_to_string(&short_name));
+
+        if child.file_type.is_dir() {
+            return Ok(Some(format!("{}/", path)));
+        } else if let Entry::Vacant(slot) = files.entry(path.clone()) {
+            let timestamp: u64 = fs::metadata(&child.path)?.modified()?.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            let data: Vec<u8> = fs::read(&child.path)?;
+            slot.insert(FileState { digest: D::digest(&data), timestamp, host_path: Some(child.path.clone()) });
+        } else {
+            report.skipped.push((child.path.clone(), format!("same 8.3 name as another file ({})", path)));
+        }
+        Ok(None)
+    })
+}
+
+/** Read the state file: one "<digest hex> <path>" line per synced file,
+ *  skipping the digests that aren't of that size. */
+fn read_state(path: &Path, digest_size: usize) -> io::Result<BTreeMap<String, Vec<u8>>> {
+    let content: String = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e)
+    };
+
+    let mut state: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+    for line in content.lines() {
+        let Some((hex, file)) = line.split_once(' ') else { continue };
+        if let Some(digest) = digest::from_hex(hex, digest_size) { state.insert(file.to_string(), digest); }
+    }
+    Ok(state)
+}
+
+fn write_state(path: &Path, state: &BTreeMap<String, Vec<u8>>) -> io::Result<()> {
+    let content: String = state.iter().map(|(file, digest)| format!("{} {}\n", digest::to_hex(digest), file)).collect();
+    if let Some(parent) = path.parent() { fs::create_dir_all(parent)?; }
+    fs::write(path, content)
+}
+
+/** Move a timestamp by the given minutes, without going before the epoch. */
+fn shift_timestamp(timestamp: u64, minutes: i32) -> u64 {
+    timestamp.saturating_add_signed(minutes as i64 * 60)
+}
+
+fn join_image_path(image_dir: &str, path: &str) -> String {
+    format!("{}/{}", image_dir.trim_end_matches('/'), path)
+}
@@ -0,0 +1,45 @@
+use std::process::{Command, Stdio};
+
+/* ==== STRUCTS ============================================================= */
+/** Keeps the terminal in raw mode (no line buffering, no echo) while alive,
+ *  restoring the previous settings when dropped. */
+pub struct RawMode {
+    saved: Option<String>
+}
+
+impl RawMode {
+    pub fn enable() -> RawMode {
+        // Save the terminal settings so that they can be put back as they were
+        let saved: Option<String> = stty(&["-g"]);
+        stty(&["-icanon", "-echo", "min", "1"]);
+        RawMode { saved }
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        match &self.saved {
+            Some(saved) => { stty(&[saved.trim()]); },
+            None => { stty(&["icanon", "echo"]); }
+        }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Terminal size as (rows, columns), with a sane default if unknown. */
+#[cfg_attr(not(feature = "tui"), allow(dead_code))]
+pub fn size() -> (usize, usize) {
+    let output: Option<String> = stty(&["size"]);
+    let mut values = output.as_deref().unwrap_or("").split_whitespace().map(|value| value.parse::<usize>().ok());
+    match (values.next().flatten(), values.next().flatten()) {
+        (Some(rows), Some(columns)) if rows > 0 && columns > 0 => (rows, columns),
+        _ => (24, 80)
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Run stty on the terminal attached to stdin, returning its output. */
+fn stty(args: &[&str]) -> Option<String> {
+    let output = Command::new("stty").args(args).stdin(Stdio::inherit()).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
@@ -0,0 +1,72 @@
+use std::mem;
+
+use crate::{struct_from_bytes, BootSector, Cluster, DirectoryEntry, Fat, ATTR_LFN};
+
+/* ==== METHODS ============================================================= */
+// Each helper damages a valid in-memory image in a single, targeted way, to
+// check that corrupt images are reported instead of hanging or panicking.
+
+/** Cut the image in the middle of the first FAT, as a partial dump would. */
+pub fn truncate_fat(image: &mut Vec<u8>) {
+    let boot_sector: BootSector = struct_from_bytes(image);
+    image.truncate(boot_sector.get_fat_start() as usize + boot_sector.get_fat_size() as usize / 2);
+}
+
+/** Claim a single sector for each FAT in the boot sector, too few for the
+ *  clusters of the volume, as a BPB with a mistyped size would. */
+pub fn shrink_fat(image: &mut [u8]) {
+    image[22..24].copy_from_slice(&1u16.to_le_bytes());
+}
+
+/** Point the last cluster of the chain starting at the given cluster back to
+ *  the first one, in every FAT copy, so that the chain never ends. */
+pub fn loop_chain(image: &mut [u8], first_cluster: Cluster) {
+    let boot_sector: BootSector = struct_from_bytes(image);
+    let last: Cluster = *get_fat(image).get_chain(first_cluster, boot_sector.get_cluster_count()).last().unwrap_or(&first_cluster);
+    set_fat_entry(image, last, first_cluster.0 as u16);
+}
+
+/** Set a FAT entry to any 12 bit value, in every FAT copy. False if the
+ *  cluster is past the FAT or the value doesn't fit. */
+pub fn set_fat_entry(image: &mut [u8], cluster: Cluster, value: u16) -> bool {
+    let boot_sector: BootSector = struct_from_bytes(image);
+    let mut fat: Fat = get_fat(image);
+    if fat.set_entry(cluster, value).is_err() { return false; }
+
+    let (start, size): (usize, usize) = (boot_sector.get_fat_start() as usize, boot_sector.get_fat_size() as usize);
+    for i in 0..boot_sector.fat_count as usize {
+        image[start + i * size..start + (i + 1) * size].copy_from_slice(fat.as_bytes());
+    }
+    true
+}
+
+/** Change the checksum of the first long file name entry of the root
+ *  directory, orphaning its name. False if there is no such entry. */
+pub fn break_lfn_checksum(image: &mut [u8]) -> bool {
+    let boot_sector: BootSector = struct_from_bytes(image);
+    let entry_size: usize = mem::size_of::<DirectoryEntry>();
+    let start: usize = boot_sector.get_root_dir_start() as usize;
+    for offset in (start..start + boot_sector.get_root_dir_size() as usize).step_by(entry_size) {
+        let entry: &mut [u8] = &mut image[offset..offset + entry_size];
+        if entry[0] == 0x00 { break; }
+
+        // The checksum of the short name is byte 13 of every LFN entry
+        if entry[11] == ATTR_LFN && entry[0] != 0xE5 {
+            entry[13] = entry[13].wrapping_add(1);
+            return true;
+        }
+    }
+    false
+}
+
+/** Swap the 0x55AA boot signature for 0xAA55. */
+pub fn flip_boot_signature(image: &mut [u8]) {
+    image.swap(510, 511);
+}
+
+/* ==== UTILS =============================================================== */
+fn get_fat(image: &[u8]) -> Fat {
+    let boot_sector: BootSector = struct_from_bytes(image);
+    let start: usize = boot_sector.get_fat_start() as usize;
+    Fat { entries: image[start..start + boot_sector.get_fat_size() as usize].to_vec() }
+}
@@ -0,0 +1,72 @@
+use std::io;
+
+use crate::codepage::Codepage;
+
+/* ==== STRUCTS ============================================================= */
+/** Conversion of text files copied between the image and the host, as
+ *  mtools' -t: DOS files end lines with CR LF and are in an OEM code page.
+ *  The default converts nothing. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TextMode {
+    /** CR LF in the image, LF on the host. */
+    pub line_endings: bool,
+
+    /** Code page of the image files, UTF-8 on the host. */
+    pub codepage: Option<Codepage>
+}
+
+impl TextMode {
+    /** Line endings only, as mtools' -t. */
+    pub fn line_endings() -> TextMode {
+        TextMode { line_endings: true, codepage: None }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        !self.line_endings && self.codepage.is_none()
+    }
+
+    /** Content of an image file as the host gets it. */
+    pub fn to_host(&self, data: &[u8]) -> Vec<u8> {
+        let data: Vec<u8> = match self.codepage {
+            Some(codepage) => codepage.decode(data).into_bytes(),
+            None => data.to_vec()
+        };
+        if self.line_endings { from_crlf(&data) } else { data }
+    }
+
+    /** Content of a host file as the image stores it. Fails on host files
+     *  that are not UTF-8, or hold characters the code page doesn't have. */
+    pub fn to_image(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let data: Vec<u8> = match self.codepage {
+            Some(codepage) => encode(codepage, data)?,
+            None => data.to_vec()
+        };
+        Ok(if self.line_endings { to_crlf(&data) } else { data })
+    }
+}
+
+/* ==== UTILS =============================================================== */
+fn encode(codepage: Codepage, data: &[u8]) -> io::Result<Vec<u8>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, message);
+    let text: &str = std::str::from_utf8(data).map_err(|e| invalid(format!("not UTF-8 text: {}", e)))?;
+    text.chars().map(|c| codepage.encode_char(c).ok_or_else(|| invalid(format!("{:?} is not in code page {}", c, codepage.get_name())))).collect()
+}
+
+/** LF to CR LF, leaving the lines that already end with CR LF alone. */
+fn to_crlf(data: &[u8]) -> Vec<u8> {
+    let mut converted: Vec<u8> = Vec::with_capacity(data.len());
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == b'\n' && (i == 0 || data[i - 1] != b'\r') { converted.push(b'\r'); }
+        converted.push(*byte);
+    }
+    converted
+}
+
+fn from_crlf(data: &[u8]) -> Vec<u8> {
+    let mut converted: Vec<u8> = Vec::with_capacity(data.len());
+    for (i, byte) in data.iter().enumerate() {
+        if *byte == b'\r' && data.get(i + 1) == Some(&b'\n') { continue; }
+        converted.push(*byte);
+    }
+    converted
+}
@@ -0,0 +1,41 @@
+use std::{sync::Mutex, thread, time::{Duration, Instant}};
+
+/* ==== STRUCTS ============================================================= */
+/** Cap on the bytes per second read from and written to the image, for
+ *  devices that give up when pushed too hard: real floppy drives behind USB
+ *  bridges, worn out memory cards. Handles opened with the same Throttle
+ *  (see FsOptions::throttle) share it, as they share the device; sectors
+ *  served by the cache don't count. */
+#[derive(Debug)]
+pub struct Throttle {
+    bytes_per_second: u64,
+
+    // When the current burst started and what it moved so far
+    state: Mutex<(Instant, u64)>
+}
+
+impl Throttle {
+    pub fn new(bytes_per_second: u64) -> Throttle {
+        Throttle { bytes_per_second: bytes_per_second.max(1), state: Mutex::new((Instant::now(), 0)) }
+    }
+
+    pub fn get_rate(&self) -> u64 {
+        self.bytes_per_second
+    }
+
+    /** Account for the bytes about to be moved, sleeping until the rate
+     *  allows them. The lock is held while sleeping, so that handles on
+     *  other threads queue up behind it instead of bursting together. */
+    pub(crate) fn wait(&self, bytes: u64) {
+        let mut state = self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let (start, moved): &mut (Instant, u64) = &mut state;
+
+        // Time spent idle is not credited: the next burst starts afresh
+        let due: Duration = Duration::from_secs_f64(*moved as f64 / self.bytes_per_second as f64);
+        if start.elapsed() > due { (*start, *moved) = (Instant::now(), 0); }
+
+        *moved += bytes;
+        let due: Duration = Duration::from_secs_f64(*moved as f64 / self.bytes_per_second as f64);
+        if let Some(ahead) = due.checked_sub(start.elapsed()) { thread::sleep(ahead); }
+    }
+}
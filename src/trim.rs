@@ -0,0 +1,52 @@
+use std::{io, mem};
+
+use crate::{Cluster, Fat12Fs, FREE_CLUSTER};
+
+/* ==== STRUCTS ============================================================= */
+/** Outcome of Fat12Fs::trim. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TrimReport {
+    pub free_clusters: usize,
+
+    /** Free clusters that still held data and were zeroed. */
+    pub zeroed: usize
+}
+
+/* ==== METHODS ============================================================= */
+impl Fat12Fs {
+    /** Zero every free cluster that still holds data, e.g. left by deletes
+     *  made without FsOptions::discard. Clusters already zero are only read,
+     *  not to wear flash media for nothing. On an image file,
+     *  sparse::sparsify then turns them into holes. */
+    pub fn trim(&mut self) -> io::Result<TrimReport> {
+        self.check_writable()?;
+        let cluster_count: u32 = self.boot_sector.get_cluster_count() as u32;
+        let mut report: TrimReport = TrimReport::default();
+        for cluster in (2..cluster_count + 2).map(Cluster) {
+            if self.fat.get_entry(cluster) != FREE_CLUSTER { continue; }
+            report.free_clusters += 1;
+            if self.zero_cluster(cluster)? { report.zeroed += 1; }
+        }
+        self.flush()?;
+        Ok(report)
+    }
+
+    /** Zero the clusters freed since the last call that are still free, when
+     *  FsOptions::discard asks to. */
+    pub(crate) fn discard_freed(&mut self) -> io::Result<()> {
+        let Some(freed) = self.discard.as_mut().map(mem::take) else { return Ok(()) };
+        for cluster in freed {
+            if self.fat.get_entry(cluster) == FREE_CLUSTER { self.zero_cluster(cluster)?; }
+        }
+        Ok(())
+    }
+
+    /** Whether the cluster held anything but zeros, and had to be written. */
+    fn zero_cluster(&mut self, cluster: Cluster) -> io::Result<bool> {
+        let start: u64 = self.boot_sector.get_cluster_start(cluster);
+        let data: Vec<u8> = self.read_disk_at(start, self.boot_sector.get_cluster_size())?;
+        if data.iter().all(|byte| *byte == 0) { return Ok(false); }
+        self.write_disk_at(start, &vec![0; data.len()])?;
+        Ok(true)
+    }
+}
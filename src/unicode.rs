@@ -0,0 +1,153 @@
+use std::cmp::Ordering;
+
+/* ==== STRUCTS ============================================================= */
+/** How a name given in a path is matched against the long names of the
+ *  entries, for callers that don't know how the name was composed, e.g.
+ *  "résumé.txt" typed on Linux (precomposed é) against one written by macOS
+ *  (e and a combining accent). Short names always match without regard to
+ *  case, as DOS does. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameMatching {
+    /** The same characters. */
+    Exact,
+
+    /** The same characters but for their case, as Windows does. */
+    #[default]
+    IgnoreCase,
+
+    /** The same once both are normalized to NFC and case folded, see
+     *  normalize. */
+    Normalized
+}
+
+impl NameMatching {
+    pub fn from_name(name: &str) -> Option<NameMatching> {
+        match name {
+            "exact" => Some(NameMatching::Exact),
+            "ignore-case" => Some(NameMatching::IgnoreCase),
+            "normalized" => Some(NameMatching::Normalized),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            NameMatching::Exact => "exact",
+            NameMatching::IgnoreCase => "ignore-case",
+            NameMatching::Normalized => "normalized"
+        }
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Whether the two names are the same as the matching sees them. */
+pub fn names_match(a: &str, b: &str, matching: NameMatching) -> bool {
+    compare_names(a, b, matching) == Ordering::Equal
+}
+
+/** Order of the two names as the matching sees them, for sorting and
+ *  deduplicating by the same rule lookups use. */
+pub fn compare_names(a: &str, b: &str, matching: NameMatching) -> Ordering {
+    match matching {
+        NameMatching::Exact => a.cmp(b),
+        NameMatching::IgnoreCase => a.chars().flat_map(char::to_lowercase).cmp(b.chars().flat_map(char::to_lowercase)),
+        NameMatching::Normalized => fold_case(&to_nfc(a)).cmp(&fold_case(&to_nfc(b)))
+    }
+}
+
+/** NFC form of the name, case folded: the key NameMatching::Normalized
+ *  compares. */
+pub fn normalize(name: &str) -> String {
+    fold_case(&to_nfc(name))
+}
+
+/** Canonical composition (NFC) of the letters with one accent that FAT code
+ *  pages hold, those of Latin-1 and Latin Extended-A: a letter followed by a
+ *  combining accent becomes the precomposed letter. Other characters are
+ *  left as they are, there being no Unicode tables here. */
+pub fn to_nfc(name: &str) -> String {
+    let mut composed: String = String::with_capacity(name.len());
+    for c in name.chars().flat_map(decompose) {
+        let last: Option<char> = composed.chars().next_back();
+        match last.and_then(|base| compose(base, c)) {
+            Some(precomposed) => {
+                composed.pop();
+                composed.push(precomposed);
+            },
+            None => composed.push(c)
+        }
+    }
+    composed
+}
+
+/** Case folding: the lower case of each character, with the few folds that
+ *  differ from it (ß and ẞ as "ss", final sigma as sigma, long s as s). */
+pub fn fold_case(name: &str) -> String {
+    name.chars().flat_map(|c| -> Vec<char> {
+        match c {
+            'ß' | 'ẞ' => vec!['s', 's'],
+            'ς' => vec!['σ'],
+            'ſ' => vec!['s'],
+            c => c.to_lowercase().collect()
+        }
+    }).collect()
+}
+
+/* ==== UTILS =============================================================== */
+/** The letter and the accent of a precomposed letter, or the character. */
+fn decompose(c: char) -> Vec<char> {
+    match COMPOSITIONS.iter().find(|(precomposed, _, _)| *precomposed == c) {
+        Some((_, base, accent)) => vec![*base, *accent],
+        None => vec![c]
+    }
+}
+
+fn compose(base: char, accent: char) -> Option<char> {
+    COMPOSITIONS.iter().find(|(_, other, mark)| *other == base && *mark == accent).map(|(precomposed, _, _)| *precomposed)
+}
+
+/** Precomposed letters of Latin-1 and Latin Extended-A with their canonical
+ *  decomposition into a letter and a combining accent. */
+const COMPOSITIONS: [(char, char, char); 161] = [
+    ('\u{00C0}', 'A', '\u{0300}'), ('\u{00C1}', 'A', '\u{0301}'), ('\u{00C2}', 'A', '\u{0302}'), ('\u{00C3}', 'A', '\u{0303}'),
+    ('\u{00C4}', 'A', '\u{0308}'), ('\u{00C5}', 'A', '\u{030A}'), ('\u{00C7}', 'C', '\u{0327}'), ('\u{00C8}', 'E', '\u{0300}'),
+    ('\u{00C9}', 'E', '\u{0301}'), ('\u{00CA}', 'E', '\u{0302}'), ('\u{00CB}', 'E', '\u{0308}'), ('\u{00CC}', 'I', '\u{0300}'),
+    ('\u{00CD}', 'I', '\u{0301}'), ('\u{00CE}', 'I', '\u{0302}'), ('\u{00CF}', 'I', '\u{0308}'), ('\u{00D1}', 'N', '\u{0303}'),
+    ('\u{00D2}', 'O', '\u{0300}'), ('\u{00D3}', 'O', '\u{0301}'), ('\u{00D4}', 'O', '\u{0302}'), ('\u{00D5}', 'O', '\u{0303}'),
+    ('\u{00D6}', 'O', '\u{0308}'), ('\u{00D9}', 'U', '\u{0300}'), ('\u{00DA}', 'U', '\u{0301}'), ('\u{00DB}', 'U', '\u{0302}'),
+    ('\u{00DC}', 'U', '\u{0308}'), ('\u{00DD}', 'Y', '\u{0301}'), ('\u{00E0}', 'a', '\u{0300}'), ('\u{00E1}', 'a', '\u{0301}'),
+    ('\u{00E2}', 'a', '\u{0302}'), ('\u{00E3}', 'a', '\u{0303}'), ('\u{00E4}', 'a', '\u{0308}'), ('\u{00E5}', 'a', '\u{030A}'),
+    ('\u{00E7}', 'c', '\u{0327}'), ('\u{00E8}', 'e', '\u{0300}'), ('\u{00E9}', 'e', '\u{0301}'), ('\u{00EA}', 'e', '\u{0302}'),
+    ('\u{00EB}', 'e', '\u{0308}'), ('\u{00EC}', 'i', '\u{0300}'), ('\u{00ED}', 'i', '\u{0301}'), ('\u{00EE}', 'i', '\u{0302}'),
+    ('\u{00EF}', 'i', '\u{0308}'), ('\u{00F1}', 'n', '\u{0303}'), ('\u{00F2}', 'o', '\u{0300}'), ('\u{00F3}', 'o', '\u{0301}'),
+    ('\u{00F4}', 'o', '\u{0302}'), ('\u{00F5}', 'o', '\u{0303}'), ('\u{00F6}', 'o', '\u{0308}'), ('\u{00F9}', 'u', '\u{0300}'),
+    ('\u{00FA}', 'u', '\u{0301}'), ('\u{00FB}', 'u', '\u{0302}'), ('\u{00FC}', 'u', '\u{0308}'), ('\u{00FD}', 'y', '\u{0301}'),
+    ('\u{00FF}', 'y', '\u{0308}'), ('\u{0100}', 'A', '\u{0304}'), ('\u{0101}', 'a', '\u{0304}'), ('\u{0102}', 'A', '\u{0306}'),
+    ('\u{0103}', 'a', '\u{0306}'), ('\u{0104}', 'A', '\u{0328}'), ('\u{0105}', 'a', '\u{0328}'), ('\u{0106}', 'C', '\u{0301}'),
+    ('\u{0107}', 'c', '\u{0301}'), ('\u{0108}', 'C', '\u{0302}'), ('\u{0109}', 'c', '\u{0302}'), ('\u{010A}', 'C', '\u{0307}'),
+    ('\u{010B}', 'c', '\u{0307}'), ('\u{010C}', 'C', '\u{030C}'), ('\u{010D}', 'c', '\u{030C}'), ('\u{010E}', 'D', '\u{030C}'),
+    ('\u{010F}', 'd', '\u{030C}'), ('\u{0112}', 'E', '\u{0304}'), ('\u{0113}', 'e', '\u{0304}'), ('\u{0114}', 'E', '\u{0306}'),
+    ('\u{0115}', 'e', '\u{0306}'), ('\u{0116}', 'E', '\u{0307}'), ('\u{0117}', 'e', '\u{0307}'), ('\u{0118}', 'E', '\u{0328}'),
+    ('\u{0119}', 'e', '\u{0328}'), ('\u{011A}', 'E', '\u{030C}'), ('\u{011B}', 'e', '\u{030C}'), ('\u{011C}', 'G', '\u{0302}'),
+    ('\u{011D}', 'g', '\u{0302}'), ('\u{011E}', 'G', '\u{0306}'), ('\u{011F}', 'g', '\u{0306}'), ('\u{0120}', 'G', '\u{0307}'),
+    ('\u{0121}', 'g', '\u{0307}'), ('\u{0122}', 'G', '\u{0327}'), ('\u{0123}', 'g', '\u{0327}'), ('\u{0124}', 'H', '\u{0302}'),
+    ('\u{0125}', 'h', '\u{0302}'), ('\u{0128}', 'I', '\u{0303}'), ('\u{0129}', 'i', '\u{0303}'), ('\u{012A}', 'I', '\u{0304}'),
+    ('\u{012B}', 'i', '\u{0304}'), ('\u{012C}', 'I', '\u{0306}'), ('\u{012D}', 'i', '\u{0306}'), ('\u{012E}', 'I', '\u{0328}'),
+    ('\u{012F}', 'i', '\u{0328}'), ('\u{0130}', 'I', '\u{0307}'), ('\u{0134}', 'J', '\u{0302}'), ('\u{0135}', 'j', '\u{0302}'),
+    ('\u{0136}', 'K', '\u{0327}'), ('\u{0137}', 'k', '\u{0327}'), ('\u{0139}', 'L', '\u{0301}'), ('\u{013A}', 'l', '\u{0301}'),
+    ('\u{013B}', 'L', '\u{0327}'), ('\u{013C}', 'l', '\u{0327}'), ('\u{013D}', 'L', '\u{030C}'), ('\u{013E}', 'l', '\u{030C}'),
+    ('\u{0143}', 'N', '\u{0301}'), ('\u{0144}', 'n', '\u{0301}'), ('\u{0145}', 'N', '\u{0327}'), ('\u{0146}', 'n', '\u{0327}'),
+    ('\u{0147}', 'N', '\u{030C}'), ('\u{0148}', 'n', '\u{030C}'), ('\u{014C}', 'O', '\u{0304}'), ('\u{014D}', 'o', '\u{0304}'),
+    ('\u{014E}', 'O', '\u{0306}'), ('\u{014F}', 'o', '\u{0306}'), ('\u{0150}', 'O', '\u{030B}'), ('\u{0151}', 'o', '\u{030B}'),
+    ('\u{0154}', 'R', '\u{0301}'), ('\u{0155}', 'r', '\u{0301}'), ('\u{0156}', 'R', '\u{0327}'), ('\u{0157}', 'r', '\u{0327}'),
+    ('\u{0158}', 'R', '\u{030C}'), ('\u{0159}', 'r', '\u{030C}'), ('\u{015A}', 'S', '\u{0301}'), ('\u{015B}', 's', '\u{0301}'),
+    ('\u{015C}', 'S', '\u{0302}'), ('\u{015D}', 's', '\u{0302}'), ('\u{015E}', 'S', '\u{0327}'), ('\u{015F}', 's', '\u{0327}'),
+    ('\u{0160}', 'S', '\u{030C}'), ('\u{0161}', 's', '\u{030C}'), ('\u{0162}', 'T', '\u{0327}'), ('\u{0163}', 't', '\u{0327}'),
+    ('\u{0164}', 'T', '\u{030C}'), ('\u{0165}', 't', '\u{030C}'), ('\u{0168}', 'U', '\u{0303}'), ('\u{0169}', 'u', '\u{0303}'),
+    ('\u{016A}', 'U', '\u{0304}'), ('\u{016B}', 'u', '\u{0304}'), ('\u{016C}', 'U', '\u{0306}'), ('\u{016D}', 'u', '\u{0306}'),
+    ('\u{016E}', 'U', '\u{030A}'), ('\u{016F}', 'u', '\u{030A}'), ('\u{0170}', 'U', '\u{030B}'), ('\u{0171}', 'u', '\u{030B}'),
+    ('\u{0172}', 'U', '\u{0328}'), ('\u{0173}', 'u', '\u{0328}'), ('\u{0174}', 'W', '\u{0302}'), ('\u{0175}', 'w', '\u{0302}'),
+    ('\u{0176}', 'Y', '\u{0302}'), ('\u{0177}', 'y', '\u{0302}'), ('\u{0178}', 'Y', '\u{0308}'), ('\u{0179}', 'Z', '\u{0301}'),
+    ('\u{017A}', 'z', '\u{0301}'), ('\u{017B}', 'Z', '\u{0307}'), ('\u{017C}', 'z', '\u{0307}'), ('\u{017D}', 'Z', '\u{030C}'),
+    ('\u{017E}', 'z', '\u{030C}')
+];
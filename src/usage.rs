@@ -0,0 +1,320 @@
+use std::io;
+
+use crate::{Cluster, Directory, DirectoryEntry, Fat12Fs, Lba, NamePolicy, ATTR_LFN, DELETED_ENTRY, FREE_CLUSTER};
+
+/** FAT values a cluster can't hold data with: reserved ones, then bad. */
+const RESERVED_CLUSTERS: std::ops::RangeInclusive<u16> = 0x0FF0..=0x0FF6;
+const BAD_CLUSTER: u16 = 0x0FF7;
+
+/** Pixels of a cluster in the SVG map. */
+const SVG_CELL: usize = 8;
+
+/* ==== STRUCTS ============================================================= */
+/** Space a file takes in the data region, compared to its size. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Allocation {
+    pub size: u64,
+    pub clusters: usize,
+
+    /** Bytes of the clusters in the chain, a multiple of the cluster size. */
+    pub allocated: u64
+}
+
+impl Allocation {
+    /** Bytes allocated past the end of the file, wasted to padding. */
+    pub fn get_slack(&self) -> u64 {
+        self.allocated.saturating_sub(self.size)
+    }
+}
+
+/** Run of consecutive sectors of a file, made of contiguous clusters. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Extent {
+    pub first: Lba,
+    pub count: u64
+}
+
+/** Where an entry and its data are on the volume, for tools that patch
+ *  references to them, e.g. a boot sector holding the first sector of the
+ *  kernel. Sectors count from the boot sector. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Placement {
+    /** Sector holding the directory entry, and its byte offset there. */
+    pub entry_sector: Lba,
+    pub entry_offset: usize,
+
+    /** Cluster 0 for empty files. */
+    pub first_cluster: Cluster,
+    pub extents: Vec<Extent>
+}
+
+/** Usage of the whole volume, see Fat12Fs::get_usage. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Usage {
+    pub cluster_size: u64,
+    pub total_clusters: usize,
+    pub free_clusters: usize,
+    pub files: usize,
+    pub directories: usize,
+
+    /** Sum of the file sizes and of the clusters allocated to files. */
+    pub file_bytes: u64,
+    pub allocated_bytes: u64
+}
+
+impl Usage {
+    pub fn get_used_clusters(&self) -> usize {
+        self.total_clusters - self.free_clusters
+    }
+
+    /** Bytes lost to partially filled last clusters, over every file. */
+    pub fn get_slack(&self) -> u64 {
+        self.allocated_bytes.saturating_sub(self.file_bytes)
+    }
+
+    /** Internal fragmentation: the slack as a fraction of the space
+     *  allocated to files, 0 when there are none. */
+    pub fn get_fragmentation(&self) -> f64 {
+        if self.allocated_bytes == 0 { return 0.0; }
+        self.get_slack() as f64 / self.allocated_bytes as f64
+    }
+}
+
+/** How the slots of a directory are taken. Slots past the end marker count
+ *  as free, whatever they hold. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SlotUsage {
+    pub total: usize,
+
+    /** Short entries, dot entries and the volume label included. */
+    pub used: usize,
+
+    /** Slots taken by long file names, on top of their short entries. */
+    pub long_names: usize,
+    pub deleted: usize,
+    pub free: usize
+}
+
+impl SlotUsage {
+    /** Slots a new entry can take: free and deleted ones. */
+    pub fn get_available(&self) -> usize {
+        self.free + self.deleted
+    }
+
+    /** Fraction of the slots taken by live entries, long names included. */
+    pub fn get_fill(&self) -> f64 {
+        if self.total == 0 { return 0.0; }
+        (self.used + self.long_names) as f64 / self.total as f64
+    }
+}
+
+/** State of a data cluster in the FAT, see Fat12Fs::get_cluster_map. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClusterState {
+    Free,
+    Used,
+
+    /** Used by the entries asked for, to tell them apart from the rest. */
+    Marked,
+    Bad,
+    Reserved
+}
+
+impl ClusterState {
+    pub fn get_symbol(&self) -> char {
+        match self {
+            ClusterState::Free => '.',
+            ClusterState::Used => '#',
+            ClusterState::Marked => '@',
+            ClusterState::Bad => 'B',
+            ClusterState::Reserved => 'R'
+        }
+    }
+
+    pub fn get_color(&self) -> &'static str {
+        match self {
+            ClusterState::Free => "#e8e8e8",
+            ClusterState::Used => "#4a7ab5",
+            ClusterState::Marked => "#e0a020",
+            ClusterState::Bad => "#c03030",
+            ClusterState::Reserved => "#707070"
+        }
+    }
+}
+
+/** State of every data cluster, the first being cluster 2. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClusterMap {
+    pub states: Vec<ClusterState>
+}
+
+impl ClusterMap {
+    /** One symbol per cluster, in rows of the given width, each starting
+     *  with the number of its first cluster. */
+    pub fn to_text(&self, columns: usize) -> String {
+        let mut text: String = String::new();
+        for (row, states) in self.states.chunks(columns.max(1)).enumerate() {
+            let symbols: String = states.iter().map(ClusterState::get_symbol).collect();
+            text.push_str(&format!("{:>6}  {}\n", row * columns.max(1) + 2, symbols));
+        }
+        text
+    }
+
+    /** The same grid as an SVG picture, a square of color per cluster. */
+    pub fn to_svg(&self, columns: usize) -> String {
+        let columns: usize = columns.max(1);
+        let rows: usize = self.states.len().div_ceil(columns);
+        let mut svg: String = format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\">\n", columns * SVG_CELL, rows * SVG_CELL);
+        for (i, state) in self.states.iter().enumerate() {
+            let (x, y): (usize, usize) = (i % columns * SVG_CELL, i / columns * SVG_CELL);
+            svg.push_str(&format!("<rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" fill=\"{}\"><title>cluster {}</title></rect>\n", x, y, SVG_CELL - 1, SVG_CELL - 1, state.get_color(), i + 2));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /** Clusters in each state. */
+    pub fn count(&self, state: ClusterState) -> usize {
+        self.states.iter().filter(|s| **s == state).count()
+    }
+}
+
+impl Fat12Fs {
+    /** State of every data cluster from the FAT, the clusters of the path
+     *  (and of the tree under it, for a directory) marked. "/" marks every
+     *  cluster some entry reaches, leaving lost ones as plain used. */
+    pub fn get_cluster_map(&mut self, path: &str) -> io::Result<ClusterMap> {
+        let cluster_count: u32 = self.boot_sector.get_cluster_count() as u32;
+        let mut states: Vec<ClusterState> = (2..cluster_count + 2).map(|cluster| match self.fat.get_entry(Cluster(cluster)) {
+            FREE_CLUSTER => ClusterState::Free,
+            BAD_CLUSTER => ClusterState::Bad,
+            value if RESERVED_CLUSTERS.contains(&value) => ClusterState::Reserved,
+            _ => ClusterState::Used
+        }).collect();
+
+        let mut marked: Vec<Cluster> = vec![];
+        let is_root: bool = path.trim_matches(['/', '\\']).is_empty();
+        let is_directory: bool = is_root || {
+            let entry: DirectoryEntry = self.get_entry(path)?;
+            marked.extend(self.get_chain(entry.get_first_cluster()));
+            entry.is_directory()
+        };
+        if is_directory {
+            for item in self.walk(path).collect::<Vec<_>>() {
+                let (_, entry): (String, DirectoryEntry) = item?;
+                marked.extend(self.get_chain(entry.get_first_cluster()));
+            }
+        }
+        for cluster in marked {
+            if let Some(state) = states.get_mut(cluster.0 as usize - 2) {
+                if *state == ClusterState::Used { *state = ClusterState::Marked; }
+            }
+        }
+        Ok(ClusterMap { states })
+    }
+
+    /** Clusters allocated to the entry against its size. */
+    pub fn get_allocation(&self, entry: &DirectoryEntry) -> Allocation {
+        let clusters: usize = self.get_chain(entry.get_first_cluster()).len();
+        let size: u64 = if entry.is_directory() { 0 } else { entry.file_size as u64 };
+        Allocation { size, clusters, allocated: (clusters * self.boot_sector.get_cluster_size()) as u64 }
+    }
+
+    /** Sectors of the entry's clusters, merged where the chain is contiguous.
+     *  Whole clusters are given, the last one may hold less of the file. */
+    pub fn get_extents(&self, entry: &DirectoryEntry) -> Vec<Extent> {
+        let sectors_per_cluster: u64 = self.boot_sector.sectors_per_cluster as u64;
+        let mut extents: Vec<Extent> = vec![];
+        for cluster in self.get_chain(entry.get_first_cluster()) {
+            let first: Lba = cluster.to_lba(&self.boot_sector);
+            match extents.last_mut() {
+                Some(last) if last.first.0 + last.count == first.0 => last.count += sectors_per_cluster,
+                _ => extents.push(Extent { first, count: sectors_per_cluster })
+            }
+        }
+        extents
+    }
+
+    /** Where the entry at the path and its data are, as write_file gives it. */
+    pub fn locate(&mut self, path: &str) -> io::Result<Placement> {
+        let (parent, name): (Directory, [u8; 11]) = self.resolve_parent(Cluster(0), path, NamePolicy::Uppercase)?;
+        let index: usize = parent.find_index(&name).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path)))?;
+        let entry: DirectoryEntry = parent.entries[index];
+        Ok(self.get_placement(&parent, index, &entry))
+    }
+
+    pub(crate) fn get_placement(&self, directory: &Directory, index: usize, entry: &DirectoryEntry) -> Placement {
+        let offset: u64 = self.get_entry_offset(directory, index);
+        let bytes_per_sector: u64 = self.boot_sector.bytes_per_sector as u64;
+        Placement {
+            entry_sector: Lba(offset / bytes_per_sector),
+            entry_offset: (offset % bytes_per_sector) as usize,
+            first_cluster: entry.get_first_cluster(),
+            extents: self.get_extents(entry)
+        }
+    }
+
+    /** Allocation of every file in the tree under the given directory. */
+    pub fn get_allocations(&mut self, path: &str) -> io::Result<Vec<(String, Allocation)>> {
+        let mut allocations: Vec<(String, Allocation)> = vec![];
+        for item in self.walk(path).collect::<Vec<_>>() {
+            let (path, entry): (String, DirectoryEntry) = item?;
+            if !entry.is_directory() { allocations.push((path, self.get_allocation(&entry))); }
+        }
+        Ok(allocations)
+    }
+
+    /** Slot usage of the directory. Subdirectories grow when they run out,
+     *  the root directory has the fixed size of the BPB. */
+    pub fn get_slot_usage(&mut self, path: &str) -> io::Result<SlotUsage> {
+        let directory: Directory = self.read_dir(path)?;
+        let mut usage: SlotUsage = SlotUsage { total: directory.entries.len(), ..SlotUsage::default() };
+        let end: usize = directory.entries.iter().position(DirectoryEntry::is_end).unwrap_or(directory.entries.len());
+        for entry in &directory.entries[..end] {
+            match entry {
+                entry if entry.name[0] == DELETED_ENTRY => usage.deleted += 1,
+                entry if entry.attributes == ATTR_LFN => usage.long_names += 1,
+                _ => usage.used += 1
+            }
+        }
+        usage.free = usage.total - end;
+        Ok(usage)
+    }
+
+    /** Slot usage of the directory and of every directory under it, with
+     *  their absolute paths. */
+    pub fn get_slot_usages(&mut self, path: &str) -> io::Result<Vec<(String, SlotUsage)>> {
+        let base: String = format!("/{}", path.trim_matches('/'));
+        let mut usages: Vec<(String, SlotUsage)> = vec![(base.clone(), self.get_slot_usage(&base)?)];
+        for item in self.walk(&base).collect::<Vec<_>>() {
+            let (path, entry): (String, DirectoryEntry) = item?;
+            if !entry.is_directory() { continue; }
+            let path: String = format!("{}/{}", base.trim_end_matches('/'), path);
+            let usage: SlotUsage = self.get_slot_usage(&path)?;
+            usages.push((path, usage));
+        }
+        Ok(usages)
+    }
+
+    /** Free and used clusters, and how well the files fill theirs. */
+    pub fn get_usage(&mut self) -> io::Result<Usage> {
+        let total_clusters: usize = self.boot_sector.get_cluster_count();
+        let free_clusters: usize = self.free_clusters;
+        let mut usage: Usage = Usage {
+            cluster_size: self.boot_sector.get_cluster_size() as u64,
+            total_clusters, free_clusters,
+            files: 0, directories: 0, file_bytes: 0, allocated_bytes: 0
+        };
+
+        for item in self.walk("/").collect::<Vec<_>>() {
+            let (_, entry): (String, DirectoryEntry) = item?;
+            if entry.is_directory() { usage.directories += 1; continue; }
+
+            let allocation: Allocation = self.get_allocation(&entry);
+            usage.files += 1;
+            usage.file_bytes += allocation.size;
+            usage.allocated_bytes += allocation.allocated;
+        }
+        Ok(usage)
+    }
+}
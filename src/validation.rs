@@ -0,0 +1,428 @@
+use std::{error::Error, fmt, fs::File, io::{self, Seek, SeekFrom}, mem};
+
+use crate::layout::VolumeLayout;
+use crate::provision;
+use crate::recovery::{FloppyFormat, FLOPPY_FORMATS};
+use crate::{into_struct_vec, read_buffer, read_padded_at, to_short_name_char, BootSector, Cluster, Directory, DirectoryEntry, Fat, Fat12Fs, FatMirroring, Validation};
+
+/* ==== STRUCTS ============================================================= */
+/** A check the image failed, e.g. "bytes_per_sector=0 at offset 0x0B,
+ *  expected a power of two, at least 128". */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /** What was checked: a BPB field, a FAT copy, a directory entry. */
+    pub check: String,
+
+    /** Byte offset of the value from the start of the filesystem, None for
+     *  values that follow from several fields (e.g. the cluster count). */
+    pub offset: Option<u64>,
+
+    /** Bytes the value takes at the offset, 0 without one. */
+    pub length: usize,
+
+    /** Image bytes around the value, the 16 byte rows it starts and ends
+     *  in, to show it in place. Empty without an offset, or if they can't
+     *  be read. */
+    pub bytes: Vec<u8>,
+    pub observed: String,
+    pub expected: String,
+
+    /** Least strict level that refuses the image for it. */
+    pub level: Validation
+}
+
+/** Every check an image failed. Errors refusing an image carry one: get it
+ *  back with io::Error::get_ref and downcast_ref::<Report>. */
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Report {
+    pub diagnostics: Vec<Diagnostic>
+}
+
+/** Sizes of an image shorter than its geometry, e.g. a dump trimmed after
+ *  the last used sector. Open refuses it with this as the error payload,
+ *  unless FsOptions::zero_missing. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncation {
+    /** Bytes the sector count of the boot sector calls for. */
+    pub expected: u64,
+    pub actual: u64
+}
+
+/** Boot sector whose sector count is none of the standard floppy formats
+ *  its media descriptor stands for, e.g. the 2880 sectors of a 1.44M floppy
+ *  with the 0xF9 of a 720K one, as some formatters leave them. See
+ *  FsOptions::geometry_source for which one to trust. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeometryConflict {
+    pub media_descriptor: u8,
+    pub total_sectors: u32,
+
+    /** Sector counts of the formats with that media descriptor, e.g. 1440
+     *  and 2400 for 0xF9, used by both 720K and 1.2M floppies. */
+    pub media_sectors: Vec<u32>
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.check, self.observed)?;
+        if let Some(offset) = self.offset { write!(f, " at offset 0x{:02X}", offset)?; }
+        write!(f, ", expected {}", self.expected)
+    }
+}
+
+impl Report {
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /** The diagnostics the given level refuses the image for. */
+    pub fn refused_by(&self, validation: Validation) -> Report {
+        Report { diagnostics: self.diagnostics.iter().filter(|diagnostic| validation.refuses(diagnostic)).cloned().collect() }
+    }
+
+    /** Add a check on the value at the offset and of the length given, if
+     *  any: its bytes are read along with the others, see read_bytes. */
+    fn push(&mut self, level: Validation, check: &str, span: Option<(u64, usize)>, observed: impl fmt::Display, expected: impl fmt::Display) {
+        let (offset, length): (Option<u64>, usize) = match span {
+            Some((offset, length)) => (Some(offset), length),
+            None => (None, 0)
+        };
+        self.diagnostics.push(Diagnostic {
+            check: check.to_string(), offset, length, bytes: vec![], observed: observed.to_string(), expected: expected.to_string(), level
+        });
+    }
+
+    /** Add a check on one of the BPB fields, at its offset. */
+    fn push_field(&mut self, level: Validation, field: &str, observed: u32, expected: &str) {
+        let span: Option<(u64, usize)> = BootSector::get_field_offset(field).zip(BootSector::get_field_size(field));
+        self.push(level, field, span, observed, expected);
+    }
+
+    /** Fill the bytes of the diagnostics with an offset, reading the rows
+     *  around the value with the given function. */
+    fn read_bytes(&mut self, mut read: impl FnMut(u64, usize) -> io::Result<Vec<u8>>) {
+        for diagnostic in &mut self.diagnostics {
+            let Some(offset) = diagnostic.offset else { continue };
+            let start: u64 = offset / 16 * 16;
+            let end: u64 = (offset + diagnostic.length.max(1) as u64).div_ceil(16) * 16;
+            diagnostic.bytes = read(start, (end - start) as usize).unwrap_or_default();
+        }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let diagnostics: Vec<String> = self.diagnostics.iter().map(Diagnostic::to_string).collect();
+        write!(f, "not a valid FAT12 image: {}", diagnostics.join("; "))
+    }
+}
+
+impl Error for Report {}
+
+impl Truncation {
+    /** Sectors missing from the end of the image, the last one possibly
+     *  only in part. */
+    pub fn get_missing_sectors(&self, bytes_per_sector: u16) -> u64 {
+        (self.expected - self.actual).div_ceil(bytes_per_sector as u64)
+    }
+}
+
+impl fmt::Display for Truncation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the image is truncated: {} bytes of the {} its geometry calls for", self.actual, self.expected)
+    }
+}
+
+impl Error for Truncation {}
+
+impl GeometryConflict {
+    /** Which of the media descriptor's formats to use instead of the BPB:
+     *  the one the size of the image holds exactly, else the first. */
+    pub fn get_media_format(&self, image_size: u64) -> &'static FloppyFormat {
+        let formats: Vec<&'static FloppyFormat> = FLOPPY_FORMATS.iter().filter(|format| format.media_descriptor == self.media_descriptor).collect();
+        let exact: Option<&'static FloppyFormat> = formats.iter().copied().find(|format| format.get_size() == image_size);
+        exact.unwrap_or(formats[0])
+    }
+}
+
+impl fmt::Display for GeometryConflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let media_sectors: Vec<String> = self.media_sectors.iter().map(u32::to_string).collect();
+        write!(f, "the BPB has {} sectors, the media descriptor 0x{:02X} stands for {}", self.total_sectors, self.media_descriptor, media_sectors.join(" or "))
+    }
+}
+
+/* ==== METHODS ============================================================= */
+impl Validation {
+    pub fn from_name(name: &str) -> Option<Validation> {
+        match name {
+            "strict" => Some(Validation::Strict),
+            "normal" => Some(Validation::Normal),
+            "lenient" => Some(Validation::Lenient),
+            _ => None
+        }
+    }
+
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Validation::Strict => "strict",
+            Validation::Normal => "normal",
+            Validation::Lenient => "lenient"
+        }
+    }
+
+    /** Whether this level refuses an image failing the check. Lenient fixes
+     *  what it can before checking, and refuses the rest as normal does. */
+    pub fn refuses(&self, diagnostic: &Diagnostic) -> bool {
+        *self == Validation::Strict || diagnostic.level != Validation::Strict
+    }
+}
+
+impl BootSector {
+    /** Take the geometry of the standard floppy format, keeping the rest
+     *  (OEM name, label, serial) as it is. */
+    pub(crate) fn set_floppy_geometry(&mut self, format: &FloppyFormat) {
+        let format: BootSector = provision::format_boot_sector(format);
+        (self.bytes_per_sector, self.sectors_per_cluster, self.reserved_sectors, self.fat_count) =
+            (format.bytes_per_sector, format.sectors_per_cluster, format.reserved_sectors, format.fat_count);
+        (self.root_entries, self.sector_count, self.large_sector_count) = (format.root_entries, format.sector_count, 0);
+        (self.sectors_per_fat, self.sectors_per_cylinder, self.heads_count) = (format.sectors_per_fat, format.sectors_per_cylinder, format.heads_count);
+    }
+
+    /** Replace the values DOS formatters are known to leave out or get
+     *  wrong with the ones a standard floppy would have, so that the geometry
+     *  makes sense again. The image size stands for a missing sector count. */
+    pub(crate) fn repair(&mut self, image_size: u64) {
+        let bytes_per_sector: u16 = self.bytes_per_sector;
+        if bytes_per_sector < 128 || !bytes_per_sector.is_power_of_two() { self.bytes_per_sector = 512; }
+        if self.sectors_per_cluster == 0 || !self.sectors_per_cluster.is_power_of_two() { self.sectors_per_cluster = 1; }
+        if self.reserved_sectors == 0 { self.reserved_sectors = 1; }
+        if self.fat_count == 0 { self.fat_count = 2; }
+        if self.total_sectors() == 0 {
+            let _ = self.set_field("total_sectors", (image_size / self.bytes_per_sector as u64).min(u32::MAX as u64) as u32);
+        }
+
+        // 720K floppies and smaller have 112 root entries, bigger ones 224
+        if self.root_entries == 0 { self.root_entries = if self.total_sectors() <= 1440 { 112 } else { 224 }; }
+
+        // The clusters depend on the FAT size: grow it until they fit
+        if self.sectors_per_fat == 0 { self.sectors_per_fat = 1; }
+        while self.get_geometry().needed_fat_sectors > self.sectors_per_fat as u32 && self.sectors_per_fat < 12 {
+            self.sectors_per_fat += 1;
+        }
+    }
+}
+
+impl Fat12Fs {
+    /** Apply the validation level to a directory just read: strict refuses
+     *  names DOS couldn't have written and clusters outside of the volume,
+     *  lenient reads entries with such clusters as empty. */
+    pub(crate) fn check_directory(&self, directory: &mut Directory) -> io::Result<()> {
+        if self.validation == Validation::Normal { return Ok(()); }
+
+        let cluster_count: u64 = self.boot_sector.get_cluster_count() as u64;
+        let mut report: Report = Report::default();
+        for index in 0..directory.entries.len() {
+            let offset: u64 = self.get_entry_offset(directory, index);
+            let entry: &mut DirectoryEntry = &mut directory.entries[index];
+            if entry.is_end() { break; }
+
+            let mut entry_report: Report = Report::default();
+            check_entry(&mut entry_report, entry, offset, cluster_count);
+            if self.validation == Validation::Lenient && entry_report.diagnostics.iter().any(|diagnostic| diagnostic.check.ends_with("first_cluster")) {
+                entry.lower_first_cluster = 0;
+                entry.file_size = 0;
+            }
+            report.diagnostics.append(&mut entry_report.diagnostics);
+        }
+
+        let mut refused: Report = report.refused_by(self.validation);
+        if self.validation == Validation::Strict && !refused.is_empty() {
+            refused.read_bytes(|offset, size| self.read_disk(offset, size));
+            return Err(io::Error::new(io::ErrorKind::InvalidData, refused));
+        }
+        Ok(())
+    }
+}
+
+/** Check the boot sector and, with full, the boot signature, the image size,
+ *  every FAT copy and the root directory too, with the bytes of the values
+ *  that fail. */
+pub(crate) fn diagnose_at(disk: &mut File, boot_sector: &BootSector, base: u64, full: bool) -> io::Result<Report> {
+    let mut report: Report = check_volume(disk, boot_sector, base, full)?;
+    report.read_bytes(|offset, size| read_at(disk, base + offset, size));
+    Ok(report)
+}
+
+/** Checks of diagnose_at. Offsets are counted in u64, so that nonsense
+ *  values can't overflow the BootSector getters. */
+fn check_volume(disk: &mut File, boot_sector: &BootSector, base: u64, full: bool) -> io::Result<Report> {
+    let mut report: Report = Report::default();
+    let bs: BootSector = *boot_sector;
+    let (bytes_per_sector, sectors_per_cluster) = (bs.bytes_per_sector as u64, bs.sectors_per_cluster as u64);
+
+    // Values that would make the geometry math meaningless
+    if bytes_per_sector < 128 || !bytes_per_sector.is_power_of_two() {
+        report.push_field(Validation::Normal, "bytes_per_sector", bytes_per_sector as u32, "a power of two, at least 128");
+    }
+    if sectors_per_cluster == 0 { report.push_field(Validation::Normal, "sectors_per_cluster", 0, "at least 1"); }
+    if bs.fat_count == 0 { report.push_field(Validation::Normal, "fat_count", 0, "at least 1"); }
+    if bs.sectors_per_fat == 0 { report.push_field(Validation::Normal, "sectors_per_fat", 0, "at least 1"); }
+    if !report.is_empty() { return Ok(report); }
+
+    // Clusters past the end of the FAT have no entry to read or write, and
+    // nothing after open copes with them
+    let layout: VolumeLayout = bs.get_layout();
+    let cluster_count: u64 = layout.cluster_count();
+    let needed_fat_sectors: u64 = layout.needed_fat_sectors();
+    if (bs.sectors_per_fat as u64) < needed_fat_sectors {
+        report.push_field(Validation::Normal, "sectors_per_fat", bs.sectors_per_fat as u32, &format!("at least {} for {} clusters", needed_fat_sectors, cluster_count));
+    }
+    if !full { return Ok(report); }
+
+    // Deviations from the spec
+    let jump: [u8; 3] = bs.jump_instruction;
+    if !(jump[0] == 0xEB && jump[2] == 0x90) && jump[0] != 0xE9 {
+        report.push(Validation::Strict, "jump_instruction", Some((0, 3)), hex_bytes(&jump), "EB xx 90 or E9 xx xx");
+    }
+    if !(512..=4096).contains(&bytes_per_sector) {
+        report.push_field(Validation::Strict, "bytes_per_sector", bytes_per_sector as u32, "512 to 4096");
+    }
+    if !sectors_per_cluster.is_power_of_two() || sectors_per_cluster > 128 {
+        report.push_field(Validation::Strict, "sectors_per_cluster", sectors_per_cluster as u32, "a power of two up to 128");
+    }
+    if bs.reserved_sectors == 0 { report.push_field(Validation::Strict, "reserved_sectors", 0, "at least 1, for the boot sector"); }
+    if bs.root_entries == 0 { report.push_field(Validation::Strict, "root_entries", 0, "at least 1"); }
+    if bs.total_sectors() == 0 { report.push_field(Validation::Strict, "total_sectors", 0, "at least 1"); }
+    let media_descriptor: u8 = bs.media_descriptor;
+    if media_descriptor != 0xF0 && media_descriptor < 0xF8 {
+        report.push(Validation::Strict, "media_descriptor", Some((0x15, 1)), format!("0x{:02X}", media_descriptor), "0xF0 or 0xF8 to 0xFF");
+    }
+
+    if let Some(conflict) = check_media_geometry(&bs) {
+        let media_sectors: Vec<String> = conflict.media_sectors.iter().map(u32::to_string).collect();
+        report.push(Validation::Strict, "media_geometry", None, format!("{} sectors with 0x{:02X}", conflict.total_sectors, media_descriptor),
+            format!("{} sectors for 0x{:02X}", media_sectors.join(" or "), media_descriptor));
+    }
+
+    // Layout, as the read and write paths compute it
+    let (fat_start, fat_size): (u64, u64) = (layout.fat_start(), layout.fat_size());
+    let (root_start, root_size): (u64, u64) = (layout.root_dir_start(), layout.root_dir_size());
+    if cluster_count >= 4085 { report.push(Validation::Strict, "cluster_count", None, cluster_count, "less than 4085 for FAT12"); }
+
+    if let Some(truncation) = check_size(disk, &bs, base)? {
+        report.push(Validation::Strict, "image_size", None, truncation.actual, format!("at least {} bytes", truncation.expected));
+    }
+
+    // Parts of the image that may be missing from a short one are skipped,
+    // and the sectors under 512 bytes of 8" floppies have no signature
+    if let Some(signature) = read_at(disk, base + 510, 2).ok().filter(|_| bytes_per_sector >= 512) {
+        if signature != [0x55, 0xAA] { report.push(Validation::Strict, "boot_signature", Some((510, 2)), hex_bytes(&signature), "55 AA"); }
+    }
+
+    let Ok(first_fat) = read_at(disk, base + fat_start, fat_size as usize) else { return Ok(report) };
+    let fat: Fat = Fat { entries: first_fat };
+    if !fat.get_header().matches_media(&bs) {
+        report.push(Validation::Strict, "fat_id", Some((fat_start, 3)), format!("0x{:03X}", fat.get_header().fat_id), format!("0x{:03X}", 0xF00 | media_descriptor as u16));
+    }
+    for index in 1..bs.fat_count as u64 {
+        let Ok(copy) = read_at(disk, base + fat_start + index * fat_size, fat_size as usize) else { break };
+        if let Some(i) = (0..copy.len()).find(|&i| copy[i] != fat.entries[i]) {
+            report.push(Validation::Strict, &format!("fat_copy_{}", index + 1), Some((fat_start + index * fat_size + i as u64, 1)),
+                format!("0x{:02X}", copy[i]), format!("0x{:02X} as in the first FAT", fat.entries[i]));
+        }
+    }
+
+    let Ok(root) = read_at(disk, base + root_start, root_size as usize) else { return Ok(report) };
+    for (index, entry) in into_struct_vec::<DirectoryEntry>(root).iter().enumerate() {
+        if entry.is_end() { break; }
+        check_entry(&mut report, entry, root_start + (index * mem::size_of::<DirectoryEntry>()) as u64, cluster_count);
+    }
+    Ok(report)
+}
+
+/** How the sector count disagrees with the media descriptor, if it's one
+ *  of the standard floppy ones and the count is none of its formats. 0xF8,
+ *  for fixed disks, goes with any geometry. */
+pub(crate) fn check_media_geometry(boot_sector: &BootSector) -> Option<GeometryConflict> {
+    let media_descriptor: u8 = boot_sector.media_descriptor;
+    let media_sectors: Vec<u32> = FLOPPY_FORMATS.iter().filter(|format| format.media_descriptor == media_descriptor).map(|format| format.total_sectors).collect();
+    let total_sectors: u32 = boot_sector.total_sectors();
+    if media_sectors.is_empty() || media_sectors.contains(&total_sectors) { return None; }
+    Some(GeometryConflict { media_descriptor, total_sectors, media_sectors })
+}
+
+/** Sizes of the image, if it's shorter than the volume starting at the base. */
+pub(crate) fn check_size(disk: &File, boot_sector: &BootSector, base: u64) -> io::Result<Option<Truncation>> {
+    let actual: u64 = disk.metadata()?.len().saturating_sub(base);
+    let expected: u64 = boot_sector.total_sectors() as u64 * boot_sector.bytes_per_sector as u64;
+    Ok(if actual < expected { Some(Truncation { expected, actual }) } else { None })
+}
+
+/** Read the FAT the validation level asks for: lenient moves to the first
+ *  copy with a valid ID if the first one doesn't have it. */
+pub(crate) fn read_valid_fat(disk: &File, boot_sector: &BootSector, base: u64, validation: Validation) -> io::Result<Fat> {
+    let fat: Fat = read_fat_copy(disk, boot_sector, base, 0)?;
+    if validation != Validation::Lenient || fat.get_header().matches_media(boot_sector) { return Ok(fat); }
+
+    for index in 1..boot_sector.fat_count {
+        let Ok(copy) = read_fat_copy(disk, boot_sector, base, index) else { break };
+        if copy.get_header().matches_media(boot_sector) { return Ok(copy); }
+    }
+    Ok(fat)
+}
+
+/** Read the FAT in use: the copy FatMirroring::Active names, or the one
+ *  read_valid_fat picks when they are all kept in step. */
+pub(crate) fn read_fat_in_use(disk: &File, boot_sector: &BootSector, base: u64, fat_mirroring: FatMirroring, validation: Validation) -> io::Result<Fat> {
+    match fat_mirroring {
+        FatMirroring::All => read_valid_fat(disk, boot_sector, base, validation),
+        FatMirroring::Active(index) if index < boot_sector.fat_count => read_fat_copy(disk, boot_sector, base, index),
+        FatMirroring::Active(index) => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("no FAT {}, the volume has {}", index, boot_sector.fat_count)))
+    }
+}
+
+/* ==== UTILS =============================================================== */
+pub(crate) fn read_fat_copy(disk: &File, boot_sector: &BootSector, base: u64, index: u8) -> io::Result<Fat> {
+    let start: u64 = boot_sector.get_layout().fat_copy_start(index as u64);
+    // Open refused truncated images, unless asked to read what they lack as zeros
+    let mut entries: Vec<u8> = vec![0; boot_sector.get_fat_size() as usize];
+    read_padded_at(disk, &mut entries, base + start)?;
+    Ok( Fat { entries } )
+}
+
+fn read_at(disk: &mut File, offset: u64, size: usize) -> io::Result<Vec<u8>> {
+    disk.seek(SeekFrom::Start(offset))?;
+    read_buffer(disk, size)
+}
+
+/** Check a used entry: a name DOS could have written, a first cluster that
+ *  is 0 or inside the volume. */
+fn check_entry(report: &mut Report, entry: &DirectoryEntry, offset: u64, cluster_count: u64) {
+    if !entry.is_used() || entry.is_volume_label() { return; }
+
+    let name: String = entry.get_name();
+    if !is_valid_short_name(&entry.name) {
+        report.push(Validation::Strict, &format!("{} name", name), Some((offset, 11)), hex_bytes(&entry.name), "upper case letters, digits and DOS symbols");
+    }
+    let first_cluster: Cluster = entry.get_first_cluster();
+    if first_cluster != Cluster(0) && !(2..cluster_count + 2).contains(&(first_cluster.0 as u64)) {
+        report.push(Validation::Strict, &format!("{} first_cluster", name), Some((offset + 26, 2)), first_cluster, format!("0 or 2 to {}", cluster_count + 1));
+    }
+}
+
+/** Whether DOS could have written the raw name: upper case letters, digits
+ *  and the allowed symbols, code page characters, padding spaces. */
+pub(crate) fn is_valid_short_name(name: &[u8; 11]) -> bool {
+    if name == b".          " || name == b"..         " { return true; }
+    name.iter().enumerate().all(|(i, &c)| match c {
+        b' ' => i != 0,
+        0x05 => i == 0,
+        0x80.. => true,
+        _ => to_short_name_char(c) == Some(c)
+    })
+}
+
+fn hex_bytes(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02X}", byte)).collect::<Vec<String>>().join(" ")
+}
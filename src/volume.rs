@@ -0,0 +1,151 @@
+use std::{collections::hash_map::RandomState, hash::{BuildHasher, Hasher}, io, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{civil_from_days, struct_to_bytes, BootSector, Directory, DirectoryEntry, Fat12Fs, ATTR_VOLUME_ID, DELETED_ENTRY};
+
+/** Boot signature telling that the volume id, label and system id follow. */
+const EXTENDED_BOOT_SIGNATURE: u8 = 0x29;
+
+/** Label stored in the boot sector of volumes without one. */
+const NO_LABEL: [u8; 11] = *b"NO NAME    ";
+
+/** Characters DOS doesn't accept in volume labels. */
+const FORBIDDEN_LABEL_CHARS: &str = "\"*+,./:;<=>?[\\]|";
+
+impl Fat12Fs {
+    /** Volume label, as DOS shows it: the one of the root directory entry,
+     *  or the one in the boot sector if there is no such entry. */
+    pub fn get_volume_label(&mut self) -> io::Result<Option<String>> {
+        let root: Directory = self.read_root_dir()?;
+        let label: [u8; 11] = match root.entries().find(|entry| entry.is_volume_label()) {
+            Some(entry) => entry.get_short_name(),
+            None if self.has_extended_boot_record() && self.boot_sector.volume_label != NO_LABEL => self.boot_sector.volume_label,
+            None => return Ok(None)
+        };
+        Ok(Some(self.codepage.decode(&label).trim_end().to_string()))
+    }
+
+    /** Set the label in both the boot sector and the root directory entry,
+     *  creating the entry if missing. Letters are uppercased; an empty label
+     *  removes it. */
+    pub fn set_volume_label(&mut self, label: &str) -> io::Result<()> {
+        self.check_writable()?;
+        let name: Option<[u8; 11]> = match label.trim_end() {
+            "" => None,
+            label => Some(self.encode_label(label)?)
+        };
+
+        // The boot sector copy only exists with the extended boot record
+        if self.has_extended_boot_record() {
+            self.boot_sector.volume_label = name.unwrap_or(NO_LABEL);
+            self.write_boot_sector()?;
+        }
+
+        let mut root: Directory = self.read_root_dir()?;
+        let index: Option<usize> = root.entries.iter()
+            .take_while(|entry| !entry.is_end())
+            .position(|entry| entry.is_used() && entry.is_volume_label());
+        match (index, name) {
+            (Some(index), Some(name)) => {
+                let mut entry: DirectoryEntry = root.entries[index];
+                entry.set_short_name(name);
+                entry.set_last_change_timestamp(self.now());
+                self.write_directory_entry(&root, index, &entry)
+            },
+            (None, Some(name)) => {
+                let index: usize = self.allocate_entry_slot(&mut root)?;
+                self.write_directory_entry(&root, index, &DirectoryEntry::new(name, ATTR_VOLUME_ID, self.now()))
+            },
+            (Some(index), None) => {
+                let mut entry: DirectoryEntry = root.entries[index];
+                entry.name[0] = DELETED_ENTRY;
+                self.write_directory_entry(&root, index, &entry)
+            },
+            (None, None) => Ok(())
+        }
+    }
+
+    /** Change the volume id (serial number) in the boot sector, e.g. to one
+     *  of random_volume_id or volume_id_from_timestamp after cloning an
+     *  image. Images without the extended boot record have no id. */
+    pub fn set_volume_id(&mut self, volume_id: u32) -> io::Result<()> {
+        self.check_writable()?;
+        if !self.has_extended_boot_record() {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, "the boot sector has no volume id (no extended boot record)"));
+        }
+        self.boot_sector.volume_id = volume_id;
+        self.write_boot_sector()
+    }
+
+    fn has_extended_boot_record(&self) -> bool {
+        self.boot_sector.boot_signature == EXTENDED_BOOT_SIGNATURE
+    }
+
+    fn write_boot_sector(&mut self) -> io::Result<()> {
+        let bytes: Vec<u8> = struct_to_bytes(&self.boot_sector);
+        self.write_disk_at(0, &bytes)
+    }
+
+    /** Raw 11 bytes of the label, padded with spaces. */
+    fn encode_label(&self, label: &str) -> io::Result<[u8; 11]> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("invalid volume label {}: {}", label, reason));
+        if label.starts_with(' ') { return Err(invalid("it can't start with a space")); }
+
+        let mut name: [u8; 11] = [b' '; 11];
+        for (i, c) in label.chars().flat_map(char::to_uppercase).enumerate() {
+            if c.is_control() || FORBIDDEN_LABEL_CHARS.contains(c) { return Err(invalid(&format!("{:?} is not allowed", c))); }
+            let byte: u8 = self.codepage.encode_char(c).ok_or_else(|| invalid(&format!("{:?} is not in the code page", c)))?;
+            if i == name.len() { return Err(invalid("longer than 11 characters")); }
+            name[i] = byte;
+        }
+        Ok(name)
+    }
+}
+
+impl BootSector {
+    /** Label in the boot sector, None without the extended boot record. */
+    pub fn get_volume_label(&self) -> Option<String> {
+        if self.boot_signature != EXTENDED_BOOT_SIGNATURE { return None; }
+        Some(String::from_utf8_lossy(&self.volume_label).trim_end().to_string())
+    }
+
+    /** Volume id (serial number), None without the extended boot record. */
+    pub fn get_volume_id(&self) -> Option<u32> {
+        if self.boot_signature != EXTENDED_BOOT_SIGNATURE { return None; }
+        Some(self.volume_id)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Volume id as DOS derives it from the time of formatting (local time,
+ *  hundredths of second included): the high word adds hours and minutes to
+ *  the year, the low word adds month and day to seconds and hundredths. */
+pub fn volume_id_from_timestamp(timestamp: u64, hundredths: u8) -> u32 {
+    let (year, month, day): (i64, i64, i64) = civil_from_days((timestamp / 86400) as i64);
+    let seconds: u64 = timestamp % 86400;
+    let (hours, minutes, seconds): (u32, u32, u32) = ((seconds / 3600) as u32, ((seconds / 60) % 60) as u32, (seconds % 60) as u32);
+
+    let low: u16 = (((month as u32) << 8 | day as u32) + (seconds << 8 | hundredths.min(99) as u32)) as u16;
+    let high: u16 = ((hours << 8 | minutes) + year as u32) as u16;
+    (high as u32) << 16 | low as u32
+}
+
+/** Volume id from the randomly seeded hasher of the standard library, mixed
+ *  with the current time. */
+pub fn random_volume_id() -> u32 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0));
+    let hash: u64 = hasher.finish();
+    (hash ^ (hash >> 32)) as u32
+}
+
+/** Volume id as DOS shows it, e.g. "1A2B-3C4D". */
+pub fn format_volume_id(volume_id: u32) -> String {
+    format!("{:04X}-{:04X}", volume_id >> 16, volume_id & 0xFFFF)
+}
+
+/** Inverse of format_volume_id, the dash is optional. */
+pub fn parse_volume_id(text: &str) -> Option<u32> {
+    let digits: String = text.chars().filter(|&c| c != '-').collect();
+    if digits.len() != 8 || !digits.chars().all(|c| c.is_ascii_hexdigit()) { return None; }
+    u32::from_str_radix(&digits, 16).ok()
+}
@@ -0,0 +1,199 @@
+use std::{collections::HashSet, io};
+
+use crate::{limit_exceeded, Cluster, DataStart, DirectoryEntry, Fat12Fs, ATTR_ARCHIVE, ATTR_DIRECTORY, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM};
+
+/* ==== STRUCTS ============================================================= */
+/** Depth-first iterator over a directory tree, see Fat12Fs::walk.
+ *  Directories come before their content; volume labels are never yielded,
+ *  dot entries only when asked for, and they are never followed. A directory whose content can't be read is followed by
+ *  the error, after which the walk goes on with its siblings.
+ *
+ *  The walk keeps its own stack instead of recursing, and lists every
+ *  directory once: a subdirectory whose chain starts at one already listed
+ *  (e.g. pointing back to its parent in a crafted image) is an error, as
+ *  going deeper than the max_depth of the limits is. */
+pub struct Walk<'a> {
+    fs: &'a mut Fat12Fs,
+
+    // Directory to start from, until the first call to next reads it
+    root: Option<String>,
+
+    // Directories being listed: path prefix, entries, next index, depth
+    stack: Vec<(String, Vec<DirectoryEntry>, usize, usize)>,
+
+    // Directory just yielded, to be listed on the next call: path and depth
+    pending: Option<(String, DirectoryEntry, usize)>,
+
+    // First clusters of the directories listed so far, the root being 0
+    listed: HashSet<Cluster>,
+    filter: Filter,
+    skip_hidden: bool,
+    skip_system: bool,
+    dot_entries: bool,
+    max_depth: Option<usize>
+}
+
+/** Which entries to pick, by attributes, size and last change. Every bound
+ *  is optional: the default filter picks everything. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Filter {
+    /** Attribute bits that must all be set, and those that must all be clear. */
+    pub attributes_set: u8,
+    pub attributes_clear: u8,
+
+    /** File size bounds, inclusive: directories never match them. */
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+
+    /** Last change bounds, exclusive, in unix time as the image stores it. */
+    pub newer_than: Option<u64>,
+    pub older_than: Option<u64>
+}
+
+impl Filter {
+    pub fn matches(&self, entry: &DirectoryEntry) -> bool {
+        let size: u64 = entry.file_size as u64;
+        let has_size_bound: bool = self.min_size.is_some() || self.max_size.is_some();
+        let timestamp: u64 = entry.get_last_change_timestamp();
+        entry.attributes & self.attributes_set == self.attributes_set
+            && entry.attributes & self.attributes_clear == 0
+            && !(has_size_bound && entry.is_directory())
+            && self.min_size.is_none_or(|min_size| size >= min_size)
+            && self.max_size.is_none_or(|max_size| size <= max_size)
+            && self.newer_than.is_none_or(|newer_than| timestamp > newer_than)
+            && self.older_than.is_none_or(|older_than| timestamp < older_than)
+    }
+
+    /** Add an attribute mask, as the letters of get_attributes_string: "H"
+     *  must be set, "-H" must be clear, e.g. "HS-D". */
+    pub fn with_attribute_mask(mut self, mask: &str) -> Option<Filter> {
+        let mut clear: bool = false;
+        for c in mask.chars() {
+            let flag: u8 = match c.to_ascii_uppercase() {
+                '-' => { clear = true; continue; },
+                '+' => { clear = false; continue; },
+                'R' => ATTR_READ_ONLY,
+                'H' => ATTR_HIDDEN,
+                'S' => ATTR_SYSTEM,
+                'D' => ATTR_DIRECTORY,
+                'A' => ATTR_ARCHIVE,
+                _ => return None
+            };
+            match clear {
+                true => self.attributes_clear |= flag,
+                false => self.attributes_set |= flag
+            }
+            clear = false;
+        }
+        Some(self)
+    }
+}
+
+impl Fat12Fs {
+    /** Walk the tree under the given directory, yielding (path, entry) pairs
+     *  with the path relative to it, e.g. "SUB/FILE.TXT". */
+    pub fn walk(&mut self, path: &str) -> Walk<'_> {
+        Walk { fs: self, root: Some(path.to_string()), stack: vec![], pending: None, listed: HashSet::new(), filter: Filter::default(), skip_hidden: false, skip_system: false, dot_entries: false, max_depth: None }
+    }
+}
+
+impl Walk<'_> {
+    /** Leave out hidden entries (and whatever is inside hidden directories). */
+    pub fn skip_hidden(mut self, skip: bool) -> Self {
+        self.skip_hidden = skip;
+        self
+    }
+
+    /** Leave out system entries (and whatever is inside system directories). */
+    pub fn skip_system(mut self, skip: bool) -> Self {
+        self.skip_system = skip;
+        self
+    }
+
+    /** Only yield the entries the filter picks. Unlike the skip options, it
+     *  doesn't prune: the walk still goes into directories it leaves out. */
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /** Also yield the "." and ".." entries of subdirectories. */
+    pub fn dot_entries(mut self, dot_entries: bool) -> Self {
+        self.dot_entries = dot_entries;
+        self
+    }
+
+    /** Don't go deeper than this: 1 only lists the starting directory. */
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /** Push the content of the directory, to be walked next. */
+    fn descend(&mut self, path: &str, entry: &DirectoryEntry, depth: usize) -> io::Result<()> {
+        // Loops in the tree would otherwise be walked forever
+        let max_depth: usize = self.fs.limits.max_depth;
+        if depth >= max_depth { return Err(limit_exceeded(path, "depth", max_depth)); }
+        if let DataStart::Chain(cluster) = entry.get_data_start() {
+            if !self.listed.insert(cluster) {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{}: directory loop, cluster {} is already listed", path, cluster.0)));
+            }
+        }
+        let directory = self.fs.read_directory_entry(entry).map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path, e)))?;
+        self.stack.push((format!("{}/", path), directory.entries().copied().collect(), 0, depth + 1));
+        Ok(())
+    }
+
+    fn is_skipped(&self, entry: &DirectoryEntry) -> bool {
+        entry.is_volume_label() || (entry.is_dot_entry() && !self.dot_entries)
+            || (self.skip_hidden && entry.attributes & ATTR_HIDDEN != 0)
+            || (self.skip_system && entry.attributes & ATTR_SYSTEM != 0)
+    }
+}
+
+impl Iterator for Walk<'_> {
+    type Item = io::Result<(String, DirectoryEntry)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(root) = self.root.take() {
+            match self.fs.read_dir(&root) {
+                Ok(directory) => {
+                    self.listed.insert(directory.clusters.first().copied().unwrap_or(Cluster(0)));
+                    self.stack.push((String::new(), directory.entries().copied().collect(), 0, 1));
+                },
+                Err(e) => return Some(Err(e))
+            }
+        }
+
+        if let Some((path, entry, depth)) = self.pending.take() {
+            if let Err(e) = self.descend(&path, &entry, depth) { return Some(Err(e)); }
+        }
+
+        loop {
+            let (prefix, entries, index, depth) = self.stack.last_mut()?;
+            let Some(entry) = entries.get(*index).copied() else {
+                self.stack.pop();
+                continue;
+            };
+            *index += 1;
+            let (path, depth): (String, usize) = (format!("{}{}", prefix, self.fs.get_name(&entry)), *depth);
+            if self.is_skipped(&entry) { continue; }
+
+            let descend: bool = entry.is_directory() && !entry.is_dot_entry() && self.max_depth.is_none_or(|max_depth| depth < max_depth);
+            if !self.filter.matches(&entry) {
+                if !descend { continue; }
+                match self.descend(&path, &entry, depth) {
+                    Ok(()) => continue,
+                    Err(e) => return Some(Err(e))
+                }
+            }
+
+            if descend {
+                // List the content on the next call, so that it comes right
+                // after the directory (and so does any error reading it)
+                self.pending = Some((path.clone(), entry, depth));
+            }
+            return Some(Ok((path, entry)));
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::fmt::{self, Write};
+
+use crate::audit::escape_json;
+use crate::format_timestamp;
+
+/* ==== STRUCTS ============================================================= */
+/** Something an operation couldn't keep as it was, without failing: the
+ *  data it gave or wrote differs from what it was given in a way a pipeline
+ *  may want to log. See Fat12Fs::take_warnings. Paths are in the image, as
+ *  given to the call or, for bulk operations, as they join the directory
+ *  they work on. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Warning {
+    /** The name doesn't fit 8.3 and only the short name the entry got is
+     *  kept: written with NamePolicy::Sanitize, or extracted with host names
+     *  other than the long ones. */
+    LongNameDropped { path: String, short_name: String },
+
+    /** Extract: the host name is the short one with its characters outside
+     *  of ASCII transliterated, see HostNames::Transliterated. */
+    NameTransliterated { path: String, host_name: String },
+
+    /** Extract: the host name was taken by another entry of the directory,
+     *  a tail was added, see NameCollision::Rename. */
+    NameCollisionRenamed { path: String, host_name: String },
+
+    /** The time is outside of what FAT can hold (1980 to 2107), the entry
+     *  got the nearest end of the range. Unix seconds, in image time. */
+    TimestampClamped { path: String, timestamp: u64, clamped: u64 },
+
+    /** Read with Validation::Lenient: the cluster chain holds less than the
+     *  size of the file, the content was cut to what it holds. */
+    ContentTruncated { path: String, size: u64, read: u64 }
+}
+
+impl Warning {
+    /** Short stable name of the kind of warning, e.g. "long_name_dropped". */
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Warning::LongNameDropped { .. } => "long_name_dropped",
+            Warning::NameTransliterated { .. } => "name_transliterated",
+            Warning::NameCollisionRenamed { .. } => "name_collision_renamed",
+            Warning::TimestampClamped { .. } => "timestamp_clamped",
+            Warning::ContentTruncated { .. } => "content_truncated"
+        }
+    }
+
+    pub fn get_path(&self) -> &str {
+        match self {
+            Warning::LongNameDropped { path, .. } | Warning::NameTransliterated { path, .. } | Warning::NameCollisionRenamed { path, .. }
+                | Warning::TimestampClamped { path, .. } | Warning::ContentTruncated { path, .. } => path
+        }
+    }
+
+    /** Single line JSON object, e.g.
+     *  {"warning":"long_name_dropped","path":"/Long name.txt","short_name":"LONGNA~1.TXT"}. */
+    pub fn to_json(&self) -> String {
+        let mut json: String = format!("{{\"warning\":\"{}\",\"path\":\"{}\"", self.get_name(), escape_json(self.get_path()));
+        let _ = match self {
+            Warning::LongNameDropped { short_name, .. } => write!(json, ",\"short_name\":\"{}\"", escape_json(short_name)),
+            Warning::NameTransliterated { host_name, .. } | Warning::NameCollisionRenamed { host_name, .. } =>
+                write!(json, ",\"host_name\":\"{}\"", escape_json(host_name)),
+            Warning::TimestampClamped { timestamp, clamped, .. } => write!(json, ",\"timestamp\":{},\"clamped\":{}", timestamp, clamped),
+            Warning::ContentTruncated { size, read, .. } => write!(json, ",\"size\":{},\"read\":{}", size, read)
+        };
+        json.push('}');
+        json
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: ", self.get_path())?;
+        match self {
+            Warning::LongNameDropped { short_name, .. } => write!(f, "long name dropped, named {}", short_name),
+            Warning::NameTransliterated { host_name, .. } => write!(f, "name transliterated to {}", host_name),
+            Warning::NameCollisionRenamed { host_name, .. } => write!(f, "name taken, renamed to {}", host_name),
+            Warning::TimestampClamped { timestamp, clamped, .. } => write!(f, "time {} out of the FAT range, clamped to {}",
+                format_time(*timestamp), format_timestamp(*clamped)),
+            Warning::ContentTruncated { size, read, .. } => write!(f, "cluster chain holds {} of the {} bytes, content cut", read, size)
+        }
+    }
+}
+
+/* ==== UTILS =============================================================== */
+/** Time as "YYYY-MM-DD HH:MM:SS" if it fits 4 digits of year, as unix
+ *  seconds otherwise. */
+fn format_time(timestamp: u64) -> String {
+    match timestamp < 253402300800 {
+        true => format_timestamp(timestamp),
+        false => format!("{} (unix seconds)", timestamp)
+    }
+}
@@ -0,0 +1,153 @@
+use std::{collections::BTreeMap, io, sync::{Arc, Mutex}};
+
+use crate::digest::{ContentDigest, Crc32};
+use crate::{cache::SectorCache, DirectoryEntry, Fat12Fs};
+
+/** Sectors kept by a workspace cache unless told otherwise: a whole 1.44MB
+ *  floppy, so that a couple of images can be compared without evictions. */
+pub const DEFAULT_CACHE_SECTORS: usize = 2880;
+
+/* ==== STRUCTS ============================================================= */
+/** Several images opened at once, sharing a single sector cache.
+ *  Files are referenced as "image_id:/path". */
+pub struct Workspace {
+    images: Vec<(String, Fat12Fs)>,
+    cache: Arc<Mutex<SectorCache>>
+}
+
+/** A difference found between two directory trees. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffEntry {
+    OnlyLeft(String),
+    OnlyRight(String),
+    Different(String)
+}
+
+impl Workspace {
+    pub fn new(cache_sectors: usize) -> Workspace {
+        Workspace { images: vec![], cache: SectorCache::shared(cache_sectors) }
+    }
+
+    /** Open the image read-only and register it under the given id. */
+    pub fn open(&mut self, id: &str, path: &str) -> io::Result<()> {
+        self.add(id, Fat12Fs::open(path)?)
+    }
+
+    /** Open the image for reading and writing and register it under the id. */
+    pub fn open_rw(&mut self, id: &str, path: &str) -> io::Result<()> {
+        self.add(id, Fat12Fs::open_rw(path)?)
+    }
+
+    /** Register an already opened image, hooking it to the shared cache. */
+    pub fn add(&mut self, id: &str, mut fs: Fat12Fs) -> io::Result<()> {
+        if self.images.iter().any(|(other, _)| other == id) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("image id {} already in use", id)));
+        }
+
+        // The position is never reused, so it's a stable id for the cache
+        fs.attach_cache(Arc::clone(&self.cache), self.images.len());
+        self.images.push((id.to_string(), fs));
+        Ok(())
+    }
+
+    pub fn get(&mut self, id: &str) -> Option<&mut Fat12Fs> {
+        self.images.iter_mut().find(|(other, _)| other == id).map(|(_, fs)| fs)
+    }
+
+    pub fn get_ids(&self) -> impl Iterator<Item = &str> {
+        self.images.iter().map(|(id, _)| id.as_str())
+    }
+
+    /** (hits, misses) of the shared sector cache. */
+    pub fn get_cache_stats(&self) -> (u64, u64) {
+        self.cache.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get_stats()
+    }
+
+    /** Split "image_id:/path" and get the image it refers to. */
+    pub fn resolve<'a>(&mut self, location: &'a str) -> io::Result<(&mut Fat12Fs, &'a str)> {
+        let (id, path): (&str, &str) = parse_location(location)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not in the image_id:/path form", location)))?;
+        let fs: &mut Fat12Fs = self.get(id).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("no image with id {}", id)))?;
+        Ok((fs, path))
+    }
+
+    pub fn read_file(&mut self, location: &str) -> io::Result<Vec<u8>> {
+        let (fs, path): (&mut Fat12Fs, &str) = self.resolve(location)?;
+        fs.read_file(path)
+    }
+
+    /** Copy a file between two locations, possibly on different images,
+     *  keeping its last change time. */
+    pub fn copy(&mut self, from: &str, to: &str) -> io::Result<()> {
+        let (fs, path): (&mut Fat12Fs, &str) = self.resolve(from)?;
+        let timestamp: u64 = fs.get_entry(path)?.get_last_change_timestamp();
+        let data: Vec<u8> = fs.read_file(path)?;
+
+        let (fs, path): (&mut Fat12Fs, &str) = self.resolve(to)?;
+        fs.write_file(path, &data, timestamp)?;
+        Ok(())
+    }
+
+    /** Compare two directory trees, possibly on different images, by name
+     *  and content. Results are sorted by path. */
+    pub fn diff(&mut self, left: &str, right: &str) -> io::Result<Vec<DiffEntry>> {
+        self.diff_with::<Crc32>(left, right)
+    }
+
+    /** Same as diff, comparing the content of the files with that digest. */
+    pub fn diff_with<D: ContentDigest>(&mut self, left: &str, right: &str) -> io::Result<Vec<DiffEntry>> {
+        let left_files: BTreeMap<String, Vec<u8>> = self.collect_files::<D>(left)?;
+        let right_files: BTreeMap<String, Vec<u8>> = self.collect_files::<D>(right)?;
+
+        let mut differences: Vec<DiffEntry> = vec![];
+        for (path, digest) in &left_files {
+            match right_files.get(path) {
+                None => differences.push(DiffEntry::OnlyLeft(path.clone())),
+                Some(other) if other != digest => differences.push(DiffEntry::Different(path.clone())),
+                Some(_) => {}
+            }
+        }
+        differences.extend(right_files.keys().filter(|path| !left_files.contains_key(*path)).map(|path| DiffEntry::OnlyRight(path.clone())));
+        differences.sort_by(|a, b| diff_path(a).cmp(diff_path(b)));
+        Ok(differences)
+    }
+
+    /** Every file under the location, with the digest of its content.
+     *  Directories are listed too (with a trailing "/") so that empty ones
+     *  show up in the comparison. */
+    fn collect_files<D: ContentDigest>(&mut self, location: &str) -> io::Result<BTreeMap<String, Vec<u8>>> {
+        let (fs, path): (&mut Fat12Fs, &str) = self.resolve(location)?;
+        let entries: Vec<(String, DirectoryEntry)> = fs.walk(path).collect::<io::Result<_>>()?;
+
+        let mut files: BTreeMap<String, Vec<u8>> = BTreeMap::new();
+        for (name, entry) in entries {
+            if entry.is_directory() {
+                files.insert(format!("{}/", name), vec![]);
+            } else {
+                let data: Vec<u8> = fs.read_file(&format!("{}/{}", path.trim_end_matches('/'), name))?;
+                files.insert(name, D::digest(&data));
+            }
+        }
+        Ok(files)
+    }
+}
+
+impl Default for Workspace {
+    fn default() -> Workspace {
+        Workspace::new(DEFAULT_CACHE_SECTORS)
+    }
+}
+
+/* ==== METHODS ============================================================= */
+/** Split "image_id:/path" in its two parts. The id is everything up to the
+ *  last ':', since FAT paths can't contain one but host paths might. */
+pub fn parse_location(location: &str) -> Option<(&str, &str)> {
+    location.rsplit_once(':').filter(|(id, _)| !id.is_empty())
+}
+
+/* ==== UTILS =============================================================== */
+fn diff_path(entry: &DiffEntry) -> &str {
+    match entry {
+        DiffEntry::OnlyLeft(path) | DiffEntry::OnlyRight(path) | DiffEntry::Different(path) => path
+    }
+}
@@ -0,0 +1,71 @@
+use rs_disk_reader::chain::{ChainEnd, ClusterChain};
+use rs_disk_reader::{Cluster, Fat};
+
+/** Data clusters of a 1.44M floppy, whose FAT has room for 3072 entries. */
+const CLUSTER_COUNT: usize = 2847;
+
+/** FAT of a 1.44M floppy holding the chain 2, 3, 4, the last entry being
+ *  the value given. */
+fn fat_with_chain(last: u16) -> Fat {
+    let mut fat: Fat = Fat::from_bytes(vec![0; 9 * 512]);
+    fat.set_entry(Cluster(2), 3).unwrap();
+    fat.set_entry(Cluster(3), 4).unwrap();
+    fat.set_entry(Cluster(4), last).unwrap();
+    fat
+}
+
+/** Clusters the chain from 2 yields, and why it stopped. */
+fn walk(fat: &Fat) -> (Vec<Cluster>, Option<ChainEnd>) {
+    let mut chain: ClusterChain = fat.chain(Cluster(2), CLUSTER_COUNT);
+    let clusters: Vec<Cluster> = chain.by_ref().collect();
+    (clusters, chain.get_end())
+}
+
+#[test]
+fn whole_chains_end_with_the_marker() {
+    for marker in 0xFF8..=0xFFF {
+        assert_eq!(walk(&fat_with_chain(marker)), (vec![Cluster(2), Cluster(3), Cluster(4)], Some(ChainEnd::EndOfChain)));
+    }
+    assert_eq!(fat_with_chain(0xFFF).get_chain(Cluster(2), CLUSTER_COUNT), [Cluster(2), Cluster(3), Cluster(4)]);
+}
+
+#[test]
+fn loops_stop_before_a_cluster_comes_back() {
+    assert_eq!(walk(&fat_with_chain(2)), (vec![Cluster(2), Cluster(3), Cluster(4)], Some(ChainEnd::Loop(Cluster(2)))));
+    assert_eq!(walk(&fat_with_chain(4)), (vec![Cluster(2), Cluster(3), Cluster(4)], Some(ChainEnd::Loop(Cluster(4)))));
+}
+
+#[test]
+fn clusters_past_the_volume_are_out_of_range() {
+    // 2849 is the first cluster past the last data one, though the FAT has
+    // an entry for it
+    let last: u16 = CLUSTER_COUNT as u16 + 1;
+    let mut fat: Fat = fat_with_chain(last);
+    fat.set_entry(Cluster(last as u32), 0xFFF).unwrap();
+    assert_eq!(walk(&fat), (vec![Cluster(2), Cluster(3), Cluster(4), Cluster(last as u32)], Some(ChainEnd::EndOfChain)));
+
+    let fat: Fat = fat_with_chain(last + 1);
+    assert_eq!(walk(&fat), (vec![Cluster(2), Cluster(3), Cluster(4)], Some(ChainEnd::OutOfRange(Cluster(last as u32 + 1)))));
+    assert_eq!(walk(&fat_with_chain(0xEFF)).1, Some(ChainEnd::OutOfRange(Cluster(0xEFF))));
+
+    // A start past the volume yields nothing
+    let mut chain: ClusterChain = fat.chain(Cluster(3000), CLUSTER_COUNT);
+    assert_eq!(chain.next(), None);
+    assert_eq!(chain.get_end(), Some(ChainEnd::OutOfRange(Cluster(3000))));
+}
+
+#[test]
+fn bad_free_and_reserved_values_cut_the_chain() {
+    let cut: Vec<Cluster> = vec![Cluster(2), Cluster(3), Cluster(4)];
+    assert_eq!(walk(&fat_with_chain(0xFF7)), (cut.clone(), Some(ChainEnd::Bad)));
+    assert_eq!(walk(&fat_with_chain(0)), (cut.clone(), Some(ChainEnd::Free)));
+    for reserved in [1].into_iter().chain(0xFF0..=0xFF6) {
+        assert_eq!(walk(&fat_with_chain(reserved)), (cut.clone(), Some(ChainEnd::Reserved(reserved))), "value 0x{:X}", reserved);
+    }
+
+    // A file without data starts at 0: nothing to yield, and nothing wrong
+    let fat: Fat = fat_with_chain(0xFFF);
+    let mut chain: ClusterChain = fat.chain(Cluster(0), CLUSTER_COUNT);
+    assert_eq!(chain.next(), None);
+    assert_eq!(chain.get_end(), Some(ChainEnd::Free));
+}
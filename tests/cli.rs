@@ -0,0 +1,133 @@
+mod common;
+
+use std::{fs, io::{self, PipeReader, PipeWriter, Write}, process::{Command, Output, Stdio}};
+use common::{check_consistency, temp_path, TempDir, TempImage};
+use rs_disk_reader::{Cluster, Fat, Fat12Fs, NamePolicy};
+
+/** Run the binary in the temporary directory, away from any rsfat.toml. */
+fn run(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rs-disk-reader")).args(args).current_dir(std::env::temp_dir()).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).to_string()
+}
+
+/** Mark free clusters of a 1.44M floppy as a chain in both FATs, with no
+ *  entry pointing to it. */
+fn lose_chain(image: &TempImage, clusters: &[u32]) {
+    let mut data: Vec<u8> = fs::read(&image.0).unwrap();
+    let mut fat: Fat = Fat::from_bytes(data[512..10 * 512].to_vec());
+    for pair in clusters.windows(2) { fat.set_entry(Cluster(pair[0]), pair[1] as u16).unwrap(); }
+    fat.set_entry(Cluster(clusters[clusters.len() - 1]), 0xFFF).unwrap();
+    data[512..10 * 512].copy_from_slice(fat.as_bytes());
+    data[10 * 512..19 * 512].copy_from_slice(fat.as_bytes());
+    fs::write(&image.0, data).unwrap();
+}
+
+#[test]
+fn check_reports_lost_chains_without_repair() {
+    let image: TempImage = TempImage::test_floppy("cli-lost-chain");
+    lose_chain(&image, &[2000, 2001, 2002]);
+
+    let output: Output = run(&["check", image.path()]);
+    assert!(stdout(&output).contains("lost chain  from cluster 2000 (3 clusters)"), "{}", stdout(&output));
+    assert_eq!(output.status.code(), Some(3));
+
+    let output: Output = run(&["check", image.path(), "--repair"]);
+    assert!(stdout(&output).contains("recovered  FOUND.000/FILE0000.CHK (3 clusters)"), "{}", stdout(&output));
+    assert!(output.status.success());
+    let output: Output = run(&["check", image.path()]);
+    assert!(!stdout(&output).contains("lost chain"), "{}", stdout(&output));
+    assert!(output.status.success());
+}
+
+#[test]
+fn closed_output_quits_quietly() {
+    let image: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test_floppy.img");
+    for args in [&["ls", image][..], &["read", image, "BIGFILE TXT"], &["info", image], &["config"], &["shell", image]] {
+        // The reader is gone before the first byte is written, as with "| head -0"
+        let (reader, writer): (PipeReader, PipeWriter) = io::pipe().unwrap();
+        drop(reader);
+        let mut child = Command::new(env!("CARGO_BIN_EXE_rs-disk-reader")).args(args).current_dir(std::env::temp_dir())
+            .stdin(Stdio::piped()).stdout(writer).stderr(Stdio::piped()).spawn().unwrap();
+        // Only the shell reads its input, the others may be gone before it is written
+        let _ = child.stdin.take().unwrap().write_all(b"ls\npwd\n");
+        let output: Output = child.wait_with_output().unwrap();
+        assert_eq!(String::from_utf8_lossy(&output.stderr), "", "{:?}", args);
+        assert_eq!(output.status.code(), Some(141), "{:?}", args);
+    }
+}
+
+#[test]
+fn shell_takes_quoted_and_escaped_names() {
+    let image: TempImage = TempImage::blank_floppy("cli-quoted-names");
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).name_policy(NamePolicy::LongNames).open(image.path()).unwrap();
+    fs.create_dir("/Old games", 946684800).unwrap();
+    fs.write_file("/Old games/Read me first.txt", b"spaces", 946684800).unwrap();
+    drop(fs);
+
+    let copy: TempImage = TempImage(temp_path("quoted copy"));
+    let script: TempImage = TempImage(temp_path("quoted-script"));
+    fs::write(&script.0, format!("stat \"Old games/Read me first.txt\"\ncd Old\\ games\nget Read\\ me\\ first.txt \"{}\"\nrm \"Read me first.txt\"\n", copy.path())).unwrap();
+    let output: Output = run(&["batch", script.path(), image.path()]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout(&output).contains("name:          README~1.TXT"), "{}", stdout(&output));
+    assert_eq!(fs::read(&copy.0).unwrap(), b"spaces");
+
+    let mut fs: Fat12Fs = image.open();
+    assert!(fs.get_entry("/Old games/Read me first.txt").is_err());
+    check_consistency(&mut fs);
+
+    // An unterminated quote fails the line, leaving the image as it was
+    fs::write(&script.0, "mkdir /NEW\nrm \"Old games\n").unwrap();
+    let output: Output = run(&["batch", script.path(), image.path()]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("unterminated \" quote"), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(image.open().get_entry("/NEW").is_err());
+}
+
+#[test]
+fn ls_shows_long_names_unless_asked_for_short_ones() {
+    let image: TempImage = TempImage::blank_floppy("cli-ls-long-names");
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).name_policy(NamePolicy::LongNames).open(image.path()).unwrap();
+    fs.create_dir("/Old games", 946684800).unwrap();
+    fs.write_file("/Old games/Read me first.txt", b"spaces", 946684800).unwrap();
+    fs.write_file("/PLAIN.TXT", b"no long name", 946684800).unwrap();
+    drop(fs);
+
+    let output: Output = run(&["ls", image.path(), "--recursive"]);
+    assert_eq!(stdout(&output), "Old games\nOld games/Read me first.txt\nPLAIN.TXT\n");
+    let output: Output = run(&["ls", image.path(), "--recursive", "--short-names"]);
+    assert_eq!(stdout(&output), "OLDGAM~1\nOLDGAM~1/README~1.TXT\nPLAIN.TXT\n");
+    let output: Output = run(&["ls", image.path(), "--dir", "/Old games", "--all"]);
+    assert_eq!(stdout(&output), ".\n..\nRead me first.txt\n");
+
+    let script: TempImage = TempImage(temp_path("ls-script"));
+    fs::write(&script.0, "ls \"/Old games\"\nls -s /Old\\ games\n").unwrap();
+    let output: Output = run(&["batch", script.path(), image.path()]);
+    let listing: String = stdout(&output);
+    let lines: Vec<&str> = listing.lines().collect();
+    assert_eq!(lines.len(), 2, "{:?}", lines);
+    assert!(lines[0].ends_with("  Read me first.txt") && lines[1].ends_with("  README~1.TXT"), "{:?}", lines);
+}
+
+#[test]
+fn shell_stat_shows_the_mount_options_of_the_config() {
+    let image: TempImage = TempImage::test_floppy("cli-mount");
+    let project: TempDir = TempDir::new("cli-mount-project");
+    fs::write(project.0.join("rsfat.toml"), "[mount]\nuid = 1000\ngid = 100\numask = \"027\"\nshowexec = true\n").unwrap();
+    let script: TempImage = TempImage(temp_path("mount-script"));
+    fs::write(&script.0, "stat KERNEL.BIN\n").unwrap();
+
+    // The user config is looked up in the project too, leaving out the one
+    // of whoever runs the tests
+    let output: Output = Command::new(env!("CARGO_BIN_EXE_rs-disk-reader")).args(["batch", script.path(), image.path()])
+        .current_dir(&project.0).env("HOME", &project.0).env("XDG_CONFIG_HOME", &project.0).output().unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout(&output).contains("mode:          0640 (uid 1000, gid 100)"), "{}", stdout(&output));
+
+    fs::write(project.0.join("rsfat.toml"), "[mount]\numask = \"888\"\n").unwrap();
+    let output: Output = Command::new(env!("CARGO_BIN_EXE_rs-disk-reader")).arg("config")
+        .current_dir(&project.0).env("HOME", &project.0).env("XDG_CONFIG_HOME", &project.0).output().unwrap();
+    assert!(String::from_utf8_lossy(&output.stderr).contains("umask must be octal permission bits, not 888"), "{}", String::from_utf8_lossy(&output.stderr));
+}
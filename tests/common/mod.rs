@@ -0,0 +1,101 @@
+// Helpers shared by the integration tests: not every test uses all of them
+#![allow(dead_code)]
+
+use std::{collections::BTreeMap, fs, io, path::PathBuf};
+use rs_disk_reader::chain::{ChainEnd, ClusterChain};
+use rs_disk_reader::{provision, Cluster, DirectoryEntry, Fat12Fs};
+
+/** Image in a temporary file named after the test, removed on drop. */
+pub struct TempImage(pub PathBuf);
+
+impl TempImage {
+    /** Copy of test_floppy.img, holding KERNEL.BIN and BIGFILE.TXT. */
+    pub fn test_floppy(name: &str) -> TempImage {
+        let image: TempImage = TempImage(temp_path(name));
+        fs::copy(concat!(env!("CARGO_MANIFEST_DIR"), "/test_floppy.img"), &image.0).unwrap();
+        image
+    }
+
+    /** Freshly formatted 1.44M floppy. */
+    pub fn blank_floppy(name: &str) -> TempImage {
+        let image: TempImage = TempImage(temp_path(name));
+        provision::format(&image.0, &provision::floppy_boot_sector(1440).unwrap(), None).unwrap();
+        image
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+
+    pub fn open(&self) -> Fat12Fs {
+        Fat12Fs::open(self.path()).unwrap()
+    }
+
+    pub fn open_rw(&self) -> Fat12Fs {
+        Fat12Fs::open_rw(self.path()).unwrap()
+    }
+}
+
+impl Drop for TempImage {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/** Host directory in the temporary directory named after the test, removed
+ *  with its content on drop. */
+pub struct TempDir(pub PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> TempDir {
+        let dir: TempDir = TempDir(temp_path(name));
+        let _ = fs::remove_dir_all(&dir.0);
+        fs::create_dir_all(&dir.0).unwrap();
+        dir
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rs-disk-reader-{}-{}.img", std::process::id(), name))
+}
+
+/** Bytes of the given size, different for every seed and from one offset
+ *  to the next, so that a cluster read from the wrong place shows. */
+pub fn pattern(size: usize, seed: u8) -> Vec<u8> {
+    (0..size as u32).map(|i| (i.wrapping_mul(2654435761).wrapping_add(seed as u32) >> 13) as u8).collect()
+}
+
+/** Check that the FAT and the directories agree, as fsck would: every chain
+ *  ends with an end marker, files have as many clusters as their size
+ *  needs, no cluster is in two chains and every cluster in use is in one.
+ *  Gives the paths walked, with their entries. */
+pub fn check_consistency(fs: &mut Fat12Fs) -> BTreeMap<String, DirectoryEntry> {
+    let entries: BTreeMap<String, DirectoryEntry> = fs.walk("/").collect::<io::Result<_>>().unwrap();
+    let cluster_size: u64 = fs.get_boot_sector().get_cluster_size() as u64;
+    let mut owners: BTreeMap<Cluster, &str> = BTreeMap::new();
+    for (path, entry) in &entries {
+        let first_cluster: Cluster = entry.get_first_cluster();
+        if first_cluster == Cluster(0) {
+            assert!(entry.is_directory() || entry.file_size == 0, "{} has {} bytes and no clusters", path, { entry.file_size });
+            continue;
+        }
+
+        let mut chain: ClusterChain = fs.chain(first_cluster);
+        let clusters: Vec<Cluster> = chain.by_ref().collect();
+        assert_eq!(chain.get_end(), Some(ChainEnd::EndOfChain), "chain of {}", path);
+        if !entry.is_directory() {
+            assert_eq!(clusters.len() as u64, (entry.file_size as u64).div_ceil(cluster_size), "clusters of {}", path);
+        }
+        for cluster in clusters {
+            if let Some(owner) = owners.insert(cluster, path) { panic!("cluster {} is in both {} and {}", cluster, owner, path); }
+        }
+    }
+    assert_eq!(fs.find_orphan_chains().unwrap(), Vec::<Vec<Cluster>>::new(), "lost chains");
+    entries
+}
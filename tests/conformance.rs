@@ -0,0 +1,244 @@
+// Interoperability of the write path with other FAT implementations:
+// images written here are checked with mtools, dosfstools and the Linux
+// vfat driver, and images they write are read back here. Every test needs
+// an outside tool, so they are all ignored by default:
+//
+//     cargo test --test conformance -- --ignored
+//
+// The kernel ones also need root, for loop mounts. A missing tool fails the
+// test with its name rather than passing silently.
+mod common;
+
+use std::{collections::BTreeSet, fs, path::{Path, PathBuf}, process::{Command, Output}, time::{Duration, UNIX_EPOCH}};
+use common::{pattern, TempDir};
+use rs_disk_reader::{provision, DirectoryEntry, Fat12Fs};
+
+/** 1994-03-01 12:00:00 UTC, even seconds as FAT stores them. */
+const TIMESTAMP: u64 = 762523200;
+
+/** Directories and files to write, in creation order, with content sized
+ *  around the cluster size (512 bytes on a 1.44M floppy). MANY gets more
+ *  entries than a cluster holds, so that its directory spans a chain. */
+fn sample_tree() -> (Vec<&'static str>, Vec<(String, Vec<u8>)>) {
+    let directories: Vec<&str> = vec!["/SUB", "/SUB/DEEP", "/MANY"];
+    let mut files: Vec<(String, Vec<u8>)> = vec![
+        (String::from("/EMPTY.TXT"), vec![]),
+        (String::from("/ONE.BIN"), vec![0xA5]),
+        (String::from("/CLUSTER.BIN"), pattern(512, 1)),
+        (String::from("/SUB/SPAN.BIN"), pattern(513, 2)),
+        (String::from("/SUB/DEEP/LARGE.BIN"), pattern(70000, 3))
+    ];
+    for i in 0..20 { files.push((format!("/MANY/FILE{:02}.TXT", i), format!("file {}\r\n", i).into_bytes())); }
+    (directories, files)
+}
+
+/** Format a 1.44M floppy and write the sample tree to it, with this crate. */
+fn write_sample_image(path: &Path) {
+    provision::format(path, &provision::floppy_boot_sector(1440).unwrap(), None).unwrap();
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).open(path.to_str().unwrap()).unwrap();
+    let (directories, files): (Vec<&str>, Vec<(String, Vec<u8>)>) = sample_tree();
+    for directory in directories { fs.create_dir(directory, TIMESTAMP).unwrap(); }
+    for (path, data) in &files { fs.write_file(path, data, TIMESTAMP).unwrap(); }
+    fs.flush().unwrap();
+}
+
+/** Write the sample tree under the host directory, every file with the
+ *  sample time. */
+fn write_sample_host_tree(root: &Path) {
+    let (directories, files): (Vec<&str>, Vec<(String, Vec<u8>)>) = sample_tree();
+    for directory in directories { fs::create_dir_all(root.join(&directory[1..])).unwrap(); }
+    for (path, data) in &files {
+        let host_path: PathBuf = root.join(&path[1..]);
+        fs::write(&host_path, data).unwrap();
+        fs::File::options().write(true).open(&host_path).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(TIMESTAMP)).unwrap();
+    }
+}
+
+/** Check that the image holds the sample tree, no more and no less, with
+ *  the sample time, as read by this crate. */
+fn check_sample_image(path: &Path) {
+    let mut fs: Fat12Fs = Fat12Fs::open(path.to_str().unwrap()).unwrap();
+    let (directories, files): (Vec<&str>, Vec<(String, Vec<u8>)>) = sample_tree();
+    let expected: BTreeSet<String> = directories.iter().map(|path| path.to_string()).chain(files.iter().map(|(path, _)| path.clone())).collect();
+
+    let entries: Vec<(String, DirectoryEntry)> = fs.walk("/").collect::<Result<_, _>>().unwrap();
+    let found: BTreeSet<String> = entries.iter().map(|(path, _)| format!("/{}", path)).collect();
+    assert_eq!(found, expected);
+    for (path, data) in &files {
+        assert_eq!(&fs.read_file(path).unwrap(), data, "{}", path);
+        assert_eq!(fs.get_entry(path).unwrap().get_last_change_timestamp(), TIMESTAMP, "{}", path);
+    }
+}
+
+/** Run the tool, giving its stdout; fails the test with its stderr if it
+ *  can't be run or exits with an error. mtools run without its config,
+ *  with UTC times. */
+fn run(program: &str, args: &[&str]) -> Vec<u8> {
+    let output: Output = Command::new(program).args(args).env("TZ", "UTC").env("MTOOLSRC", "/dev/null").env("MTOOLS_SKIP_CHECK", "1").output()
+        .unwrap_or_else(|e| panic!("can't run {} (is it installed?): {}", program, e));
+    assert!(output.status.success(), "{} {} failed: {}", program, args.join(" "), String::from_utf8_lossy(&output.stderr));
+    output.stdout
+}
+
+/** Whether the tests run as root, which loop mounts need. */
+#[cfg(target_os = "linux")]
+fn is_root() -> bool {
+    Command::new("id").arg("-u").output().is_ok_and(|output| output.stdout.starts_with(b"0\n"))
+}
+
+/* ==== MTOOLS ============================================================== */
+#[test]
+#[ignore = "needs mtools"]
+fn mtools_reads_what_we_write() {
+    let scratch: TempDir = TempDir::new("conformance-mtools-reads");
+    let image: PathBuf = scratch.0.join("ours.img");
+    write_sample_image(&image);
+    let image: &str = image.to_str().unwrap();
+
+    run("minfo", &["-i", image, "::"]);
+    let (directories, files): (Vec<&str>, Vec<(String, Vec<u8>)>) = sample_tree();
+    for directory in directories {
+        let listing: String = String::from_utf8_lossy(&run("mdir", &["-b", "-i", image, &format!("::{}", directory)])).to_uppercase();
+        for (path, _) in files.iter().filter(|(path, _)| path.rsplit_once('/').map(|(parent, _)| parent) == Some(directory)) {
+            let name: &str = path.rsplit('/').next().unwrap();
+            assert!(listing.contains(name), "mdir of {} doesn't list {}:\n{}", directory, name, listing);
+        }
+    }
+    for (path, data) in &files {
+        assert_eq!(&run("mtype", &["-i", image, &format!("::{}", path)]), data, "mtype {}", path);
+    }
+}
+
+#[test]
+#[ignore = "needs mtools"]
+fn we_read_what_mtools_writes() {
+    let scratch: TempDir = TempDir::new("conformance-mtools-writes");
+    let host: PathBuf = scratch.0.join("tree");
+    write_sample_host_tree(&host);
+    let image: PathBuf = scratch.0.join("theirs.img");
+    let image: &str = image.to_str().unwrap();
+
+    run("mformat", &["-i", image, "-C", "-f", "1440", "::"]);
+    let (directories, files): (Vec<&str>, Vec<(String, Vec<u8>)>) = sample_tree();
+    for directory in directories { run("mmd", &["-i", image, &format!("::{}", directory)]); }
+    for (path, _) in &files {
+        let host_path: PathBuf = host.join(&path[1..]);
+        run("mcopy", &["-m", "-i", image, host_path.to_str().unwrap(), &format!("::{}", path)]);
+    }
+    check_sample_image(Path::new(image));
+}
+
+#[test]
+#[ignore = "needs mtools"]
+fn mtools_changes_keep_our_images_readable() {
+    // Deleting and adding through mtools, over what we wrote
+    let scratch: TempDir = TempDir::new("conformance-mtools-changes");
+    let image: PathBuf = scratch.0.join("ours.img");
+    write_sample_image(&image);
+    let extra: PathBuf = scratch.0.join("EXTRA.BIN");
+    fs::write(&extra, pattern(3000, 4)).unwrap();
+    let image_path: &str = image.to_str().unwrap();
+    run("mdel", &["-i", image_path, "::/SUB/SPAN.BIN"]);
+    run("mcopy", &["-i", image_path, extra.to_str().unwrap(), "::/SUB/EXTRA.BIN"]);
+
+    let mut fs: Fat12Fs = Fat12Fs::open(image_path).unwrap();
+    assert!(!fs.exists("/SUB/SPAN.BIN"));
+    assert_eq!(fs.read_file("/SUB/EXTRA.BIN").unwrap(), pattern(3000, 4));
+    assert_eq!(fs.read_file("/SUB/DEEP/LARGE.BIN").unwrap(), pattern(70000, 3));
+}
+
+/* ==== DOSFSTOOLS ========================================================== */
+#[test]
+#[ignore = "needs dosfstools"]
+fn fsck_finds_nothing_to_fix_in_what_we_write() {
+    let scratch: TempDir = TempDir::new("conformance-fsck");
+    let image: PathBuf = scratch.0.join("ours.img");
+    write_sample_image(&image);
+
+    // -n never writes: any change it would make fails the run
+    run("fsck.fat", &["-n", "-V", image.to_str().unwrap()]);
+
+    // And again after overwriting and removing, which free clusters
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).open(image.to_str().unwrap()).unwrap();
+    fs.write_file("/SUB/DEEP/LARGE.BIN", &pattern(1000, 5), TIMESTAMP).unwrap();
+    fs.remove("/ONE.BIN").unwrap();
+    fs.flush().unwrap();
+    drop(fs);
+    run("fsck.fat", &["-n", "-V", image.to_str().unwrap()]);
+}
+
+#[test]
+#[ignore = "needs dosfstools"]
+fn we_read_what_mkfs_formats() {
+    let scratch: TempDir = TempDir::new("conformance-mkfs");
+    let image: PathBuf = scratch.0.join("theirs.img");
+    run("mkfs.fat", &["-C", "-F", "12", image.to_str().unwrap(), "1440"]);
+
+    // An empty volume we can write the sample tree to
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).open(image.to_str().unwrap()).unwrap();
+    assert_eq!(fs.walk("/").count(), 0);
+    let (directories, files): (Vec<&str>, Vec<(String, Vec<u8>)>) = sample_tree();
+    for directory in directories { fs.create_dir(directory, TIMESTAMP).unwrap(); }
+    for (path, data) in &files { fs.write_file(path, data, TIMESTAMP).unwrap(); }
+    fs.flush().unwrap();
+    drop(fs);
+    check_sample_image(&image);
+    run("fsck.fat", &["-n", "-V", image.to_str().unwrap()]);
+}
+
+/* ==== KERNEL VFAT ========================================================= */
+/** Loop mount the image at the mount point, run the closure, unmount. */
+#[cfg(target_os = "linux")]
+fn with_mount(image: &Path, mount_point: &Path, options: &str, f: impl FnOnce(&Path)) {
+    assert!(is_root(), "loop mounts need root");
+    fs::create_dir_all(mount_point).unwrap();
+    run("mount", &["-t", "vfat", "-o", &format!("loop,{}", options), image.to_str().unwrap(), mount_point.to_str().unwrap()]);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| f(mount_point)));
+    run("umount", &[mount_point.to_str().unwrap()]);
+    if let Err(panic) = result { std::panic::resume_unwind(panic); }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+#[ignore = "needs root and the vfat driver"]
+fn kernel_vfat_reads_what_we_write() {
+    let scratch: TempDir = TempDir::new("conformance-vfat-reads");
+    let image: PathBuf = scratch.0.join("ours.img");
+    write_sample_image(&image);
+
+    // shortname=winnt shows 8.3 names as they are stored, uppercase
+    with_mount(&image, &scratch.0.join("mnt"), "ro,shortname=winnt,tz=UTC", |root| {
+        let (directories, files): (Vec<&str>, Vec<(String, Vec<u8>)>) = sample_tree();
+        for directory in directories { assert!(root.join(&directory[1..]).is_dir(), "{}", directory); }
+        for (path, data) in &files {
+            let host_path: PathBuf = root.join(&path[1..]);
+            assert_eq!(&fs::read(&host_path).unwrap(), data, "{}", path);
+            let modified: u64 = fs::metadata(&host_path).unwrap().modified().unwrap().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            assert_eq!(modified, TIMESTAMP, "{}", path);
+        }
+    });
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+#[ignore = "needs root and the vfat driver"]
+fn we_read_what_kernel_vfat_writes() {
+    let scratch: TempDir = TempDir::new("conformance-vfat-writes");
+    let image: PathBuf = scratch.0.join("theirs.img");
+    provision::format(&image, &provision::floppy_boot_sector(1440).unwrap(), None).unwrap();
+
+    // Uppercase 8.3 names get no long name with shortname=winnt
+    let host: PathBuf = scratch.0.join("tree");
+    write_sample_host_tree(&host);
+    with_mount(&image, &scratch.0.join("mnt"), "rw,shortname=winnt,tz=UTC", |root| {
+        let (directories, files): (Vec<&str>, Vec<(String, Vec<u8>)>) = sample_tree();
+        for directory in directories { fs::create_dir(root.join(&directory[1..])).unwrap(); }
+        for (path, _) in &files {
+            let target: PathBuf = root.join(&path[1..]);
+            fs::copy(host.join(&path[1..]), &target).unwrap();
+            fs::File::options().write(true).open(&target).unwrap().set_modified(UNIX_EPOCH + Duration::from_secs(TIMESTAMP)).unwrap();
+        }
+    });
+    check_sample_image(&image);
+}
+
@@ -0,0 +1,137 @@
+use std::{fs, io, path::PathBuf};
+use rs_disk_reader::lfn::{self, NamePair};
+use rs_disk_reader::{read_boot_sector, read_entry_content, read_fat, read_root_directory, testing, BootSector, Cluster, Directory, DirectoryEntry, Fat, Fat12Fs, Validation};
+
+/** Write the image to a temporary file named after the test and open it. */
+fn open_image(name: &str, image: &[u8], validation: Validation) -> io::Result<Fat12Fs> {
+    let path: PathBuf = std::env::temp_dir().join(format!("rs-disk-reader-{}-{}.img", std::process::id(), name));
+    fs::write(&path, image)?;
+    let fs: io::Result<Fat12Fs> = Fat12Fs::options().validation(validation).open(path.to_str().unwrap());
+    fs::remove_file(&path)?;
+    fs
+}
+
+fn test_image() -> Vec<u8> {
+    fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/test_floppy.img")).unwrap()
+}
+
+/** The test image with a long name for BIGFILE.TXT, its slots written by
+ *  hand in the root (slots 2 and 3, the entry moving to slot 4). */
+fn test_image_with_long_name(long_name: &str) -> Vec<u8> {
+    const ROOT: usize = 19 * 512;
+    let mut image: Vec<u8> = test_image();
+    let entry: [u8; 32] = image[ROOT + 2 * 32..ROOT + 3 * 32].try_into().unwrap();
+    assert_eq!(&entry[..11], b"BIGFILE TXT");
+    let checksum: u8 = lfn::checksum(entry[..11].try_into().unwrap());
+
+    let mut units: Vec<u16> = long_name.encode_utf16().collect();
+    units.push(0);
+    units.resize(26, 0xFFFF);
+    for (i, chunk) in units.chunks(13).enumerate() {
+        let mut slot: [u8; 32] = [0; 32];
+        slot[0] = (i + 1) as u8 | if i == 1 { lfn::LAST_SLOT } else { 0 };
+        slot[11] = 0x0F;
+        slot[13] = checksum;
+        for (unit, offset) in chunk.iter().zip([1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30]) {
+            slot[offset..offset + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        image[ROOT + (3 - i) * 32..ROOT + (4 - i) * 32].copy_from_slice(&slot);
+    }
+    image[ROOT + 4 * 32..ROOT + 5 * 32].copy_from_slice(&entry);
+    image
+}
+
+#[test]
+fn truncated_fat_fails_to_open() {
+    let mut image: Vec<u8> = test_image();
+    testing::truncate_fat(&mut image);
+    let e: io::Error = open_image("truncated", &image, Validation::Lenient).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+}
+
+#[test]
+fn shrunk_fat_only_opens_lenient() {
+    let mut image: Vec<u8> = test_image();
+    testing::shrink_fat(&mut image);
+    for validation in [Validation::Strict, Validation::Normal] {
+        let e: io::Error = open_image("shrunk", &image, validation).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(e.to_string().contains("sectors_per_fat"), "{}", e);
+    }
+
+    // Lenient grows the FAT back to the size the clusters need
+    let mut fs: Fat12Fs = open_image("shrunk-lenient", &image, Validation::Lenient).unwrap();
+    assert_eq!({ fs.get_boot_sector().sectors_per_fat }, 9);
+    assert_eq!(fs.read_file("/BIGFILE.TXT").unwrap().len(), 1024);
+}
+
+#[test]
+fn flipped_signature_only_fails_strict() {
+    let mut image: Vec<u8> = test_image();
+    testing::flip_boot_signature(&mut image);
+    assert!(open_image("signature-lenient", &image, Validation::Lenient).is_ok());
+    let e: io::Error = open_image("signature-strict", &image, Validation::Strict).err().unwrap();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn looped_chain_terminates() {
+    let mut image: Vec<u8> = test_image();
+    testing::loop_chain(&mut image, Cluster(3));
+    let mut fs: Fat12Fs = open_image("loop", &image, Validation::Lenient).unwrap();
+    assert_eq!(fs.get_fat().get_entry(Cluster(4)), 3);
+
+    // The loop guard cuts the chain: reading ends, with an error or at most
+    // the clusters of the volume
+    match fs.read_file("/BIGFILE.TXT") {
+        Ok(data) => assert!(data.len() <= 1024, "{} bytes read", data.len()),
+        Err(e) => assert_eq!(e.kind(), io::ErrorKind::InvalidData)
+    }
+}
+
+#[test]
+fn lfn_checksum_without_lfn_entries() {
+    let mut image: Vec<u8> = test_image();
+    assert!(!testing::break_lfn_checksum(&mut image));
+    assert_eq!(image, test_image());
+}
+
+#[test]
+fn broken_lfn_checksum_drops_the_long_name() {
+    let mut image: Vec<u8> = test_image_with_long_name("A rather big file.txt");
+    let mut fs: Fat12Fs = open_image("lfn", &image, Validation::Lenient).unwrap();
+    let pairs: Vec<NamePair> = fs.read_name_pairs("/").unwrap();
+    let pair: &NamePair = pairs.iter().find(|pair| pair.short_name == "BIGFILE.TXT").unwrap();
+    assert_eq!(pair.long_name.as_deref(), Some("A rather big file.txt"));
+    assert_eq!(pair.lfn_slots.len(), 2);
+
+    // The short entry is still there, without the orphaned slots
+    assert!(testing::break_lfn_checksum(&mut image));
+    let mut fs: Fat12Fs = open_image("lfn-broken", &image, Validation::Lenient).unwrap();
+    let pairs: Vec<NamePair> = fs.read_name_pairs("/").unwrap();
+    let pair: &NamePair = pairs.iter().find(|pair| pair.short_name == "BIGFILE.TXT").unwrap();
+    assert_eq!(pair.long_name, None);
+    assert!(pair.lfn_slots.is_empty());
+    assert_eq!(fs.read_file("/BIGFILE.TXT").unwrap().len(), 1024);
+}
+
+#[test]
+fn short_chain_fails_read_entry_content() {
+    // BIGFILE.TXT takes clusters 3 and 4: end its chain at the first one
+    let mut image: Vec<u8> = test_image();
+    assert!(testing::set_fat_entry(&mut image, Cluster(3), 0xFFF));
+    let path: PathBuf = std::env::temp_dir().join(format!("rs-disk-reader-{}-short-chain.img", std::process::id()));
+    fs::write(&path, &image).unwrap();
+    let mut disk: fs::File = fs::File::open(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let boot_sector: BootSector = read_boot_sector(&mut disk).unwrap();
+    let fat: Fat = read_fat(&mut disk, &boot_sector).unwrap();
+    let root: Directory = read_root_directory(&mut disk, &boot_sector).unwrap();
+    let entry: &DirectoryEntry = root.get_entry("BIGFILE TXT").unwrap();
+    let e: io::Error = read_entry_content(&mut disk, entry, &fat, &boot_sector).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+
+    let entry: &DirectoryEntry = root.get_entry("KERNEL  BIN").unwrap();
+    assert_eq!(read_entry_content(&mut disk, entry, &fat, &boot_sector).unwrap().len(), 512);
+}
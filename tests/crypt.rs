@@ -0,0 +1,129 @@
+mod common;
+
+use std::{io, sync::Arc};
+use common::{pattern, TempImage};
+use rs_disk_reader::crypt::{self, ContainerCipher, Passphrase};
+use rs_disk_reader::Fat12Fs;
+
+fn hex(text: &str) -> Vec<u8> {
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+}
+
+#[test]
+fn chacha20_poly1305_of_rfc_8439() {
+    // 2.8.2, the AEAD example
+    let key: [u8; 32] = std::array::from_fn(|i| 0x80 + i as u8);
+    let nonce: [u8; 12] = [0x07, 0x00, 0x00, 0x00, 0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47];
+    let aad: Vec<u8> = hex("50515253c0c1c2c3c4c5c6c7");
+    let plaintext: &[u8] = b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+    let expected: Vec<u8> = hex(concat!(
+        "d31a8d34648e60db7b86afbc53ef7ec2a4aded51296e08fea9e2b5a736ee62d63dbea45e8ca9671282fafb69da92728b",
+        "1a71de0a9e060b2905d6a5b67ecd3b3692ddbd7f2d778b8c9803aee328091b58fab324e4fad675945585808b4831d7bc",
+        "3ff4def08e4b7a9de576d26586cec64b6116",
+        "1ae10b594f09e26a7e902ecbd0600691"));
+    assert_eq!(crypt::seal(&key, &nonce, &aad, plaintext), expected);
+    assert_eq!(crypt::open(&key, &nonce, &aad, &expected).unwrap(), plaintext);
+}
+
+#[test]
+fn poly1305_of_rfc_8439() {
+    // 2.5.2, then the edge cases of A.3 where the sum nears 2^130 - 5
+    let key: [u8; 32] = hex("85d6be7857556d337f4452fe42d506a80103808afb0db2fd4abff6af4149f51b").try_into().unwrap();
+    assert_eq!(crypt::poly1305(&key, b"Cryptographic Forum Research Group").to_vec(), hex("a8061dc1305136c6c22b8baf0c0127a9"));
+
+    let r = |first: u8, s: [u8; 16]| -> [u8; 32] {
+        let mut key: [u8; 32] = [0; 32];
+        key[0] = first;
+        key[16..].copy_from_slice(&s);
+        key
+    };
+    let three: Vec<u8> = hex("03000000000000000000000000000000");
+    assert_eq!(crypt::poly1305(&r(2, [0; 16]), &[0xFF; 16]).to_vec(), three);
+    let mut message: Vec<u8> = vec![2];
+    message.resize(16, 0);
+    assert_eq!(crypt::poly1305(&r(2, [0xFF; 16]), &message).to_vec(), three);
+    let message: Vec<u8> = [vec![0xFF; 16], vec![0xF0], vec![0xFF; 15], vec![0x11], vec![0; 15]].concat();
+    assert_eq!(crypt::poly1305(&r(1, [0; 16]), &message).to_vec(), hex("05000000000000000000000000000000"));
+    let message: Vec<u8> = [vec![0xFF; 16], vec![0xFB], vec![0xFE; 15], vec![0x01; 16]].concat();
+    assert_eq!(crypt::poly1305(&r(1, [0; 16]), &message).to_vec(), vec![0; 16]);
+    assert_eq!(crypt::poly1305(&r(2, [0; 16]), &hex("fdffffffffffffffffffffffffffffff")).to_vec(), hex("faffffffffffffffffffffffffffffff"));
+}
+
+#[test]
+fn pbkdf2_sha256_known_answers() {
+    // The inputs of RFC 6070, as commonly published for SHA-256, then a
+    // passphrase longer than a block, which HMAC hashes first
+    let vectors: [(&[u8], &[u8], u32, &str); 5] = [
+        (b"password", b"salt", 1, "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b"),
+        (b"password", b"salt", 2, "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43"),
+        (b"password", b"salt", 4096, "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a"),
+        (b"passwordPASSWORDpassword", b"saltSALTsaltSALTsaltSALTsaltSALTsalt", 4096, "348c89dbcbd32b2f32d814b8116e84cf2b17347ebc1800181c4e2a1fb8dd53e1"),
+        (&[b'x'; 100], b"salt", 3, "59bfa49750dd5462ce38370a1e7abe0736ff334cf1c2f0d84f23a03435d660d1")
+    ];
+    for (passphrase, salt, iterations, expected) in vectors {
+        assert_eq!(crypt::pbkdf2_sha256(passphrase, salt, iterations).to_vec(), hex(expected), "{} iterations", iterations);
+    }
+}
+
+#[test]
+fn containers_refuse_a_wrong_passphrase_and_changed_bytes() {
+    let image: Vec<u8> = pattern(4096, 7);
+    let container: Vec<u8> = Passphrase::new("secret").iterations(10).encrypt(&image).unwrap();
+    assert!(crypt::is_container(&container));
+    assert_eq!(Passphrase::new("secret").decrypt(&container).unwrap(), image);
+
+    let e: io::Error = Passphrase::new("Secret").decrypt(&container).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::PermissionDenied);
+
+    // Every part is authenticated: the header, the image and the tag
+    for offset in [9, 20, 100, container.len() - 1] {
+        let mut changed: Vec<u8> = container.clone();
+        changed[offset] ^= 1;
+        assert!(Passphrase::new("secret").decrypt(&changed).is_err(), "byte {} changed", offset);
+    }
+    assert!(Passphrase::new("secret").decrypt(&container[..container.len() - 1]).is_err());
+}
+
+#[test]
+fn encrypted_images_round_trip_through_the_fat_layer() {
+    let image: TempImage = TempImage::test_floppy("crypt-round-trip");
+    let cipher: Arc<dyn ContainerCipher> = Arc::new(Passphrase::new("secret").iterations(10));
+    crypt::encrypt_file(&image.0, &image.0, cipher.as_ref()).unwrap();
+    assert!(Fat12Fs::open(image.path()).is_err());
+
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).cipher(Some(cipher.clone())).open(image.path()).unwrap();
+    assert!(fs.is_encrypted());
+    fs.write_file("/NOTE.TXT", b"kept secret", 0).unwrap();
+    drop(fs);
+
+    let mut fs: Fat12Fs = Fat12Fs::options().cipher(Some(cipher)).open(image.path()).unwrap();
+    assert_eq!(fs.read_file("/NOTE.TXT").unwrap(), b"kept secret");
+    common::check_consistency(&mut fs);
+}
+
+#[test]
+fn sealing_replaces_the_container_and_keeps_it_locked() {
+    let image: TempImage = TempImage::test_floppy("crypt-replace");
+    let cipher: Arc<dyn ContainerCipher> = Arc::new(Passphrase::new("secret").iterations(10));
+    crypt::encrypt_file(&image.0, &image.0, cipher.as_ref()).unwrap();
+    #[cfg(unix)]
+    std::fs::set_permissions(&image.0, std::os::unix::fs::PermissionsExt::from_mode(0o600)).unwrap();
+
+    // Sealed twice, the second time into the copy the first one left
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).cipher(Some(cipher.clone())).open(image.path()).unwrap();
+    for (i, content) in [&b"first"[..], b"second"].into_iter().enumerate() {
+        fs.write_file(&format!("/NOTE{}.TXT", i), content, 0).unwrap();
+        fs.flush().unwrap();
+        assert!(!image.0.with_extension("crypt.tmp").exists());
+        let e: io::Error = Fat12Fs::options().read_only(false).cipher(Some(cipher.clone())).open(image.path()).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::ResourceBusy);
+    }
+    drop(fs);
+    #[cfg(unix)]
+    assert_eq!(std::os::unix::fs::PermissionsExt::mode(&std::fs::metadata(&image.0).unwrap().permissions()) & 0o777, 0o600);
+
+    let mut fs: Fat12Fs = Fat12Fs::options().cipher(Some(cipher)).open(image.path()).unwrap();
+    assert_eq!(fs.read_file("/NOTE0.TXT").unwrap(), b"first");
+    assert_eq!(fs.read_file("/NOTE1.TXT").unwrap(), b"second");
+    common::check_consistency(&mut fs);
+}
@@ -0,0 +1,58 @@
+use rs_disk_reader::digest::{self, ContentDigest, Crc32, HashAlgorithm, Md5, Sha1, Sha256};
+
+/** Digests of the FIPS 180 and RFC 1321 test messages, the last one a
+ *  million "a", in hex: (message, md5, sha1, sha256, crc32). */
+fn vectors() -> Vec<(Vec<u8>, &'static str, &'static str, &'static str, &'static str)> {
+    vec![
+        (b"".to_vec(), "d41d8cd98f00b204e9800998ecf8427e", "da39a3ee5e6b4b0d3255bfef95601890afd80709",
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855", "00000000"),
+        (b"abc".to_vec(), "900150983cd24fb0d6963f7d28e17f72", "a9993e364706816aba3e25717850c26c9cd0d89d",
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad", "352441c2"),
+        (b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq".to_vec(), "8215ef0796a20bcaaae116d3876c664a",
+            "84983e441c3bd26ebaae4aa1f95129e5e54670f1", "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1", "171a3f5f"),
+        (vec![b'a'; 1_000_000], "7707d6ae4e027c70eea2a935c2296f21", "34aa973cd4c4daa4f61eeb2bdbad27316534016f",
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0", "dc25bfbc")
+    ]
+}
+
+/** Digest of the message given in uneven pieces, across block boundaries. */
+fn digest_in_pieces<D: ContentDigest>(message: &[u8]) -> Vec<u8> {
+    let mut digest: D = D::new();
+    let mut rest: &[u8] = message;
+    for size in [1, 63, 2, 64, 127].iter().cycle() {
+        if rest.is_empty() { break; }
+        let (piece, tail): (&[u8], &[u8]) = rest.split_at((*size).min(rest.len()));
+        digest.update(piece);
+        rest = tail;
+    }
+    digest.finalize()
+}
+
+#[test]
+fn known_answers() {
+    for (message, md5, sha1, sha256, crc32) in vectors() {
+        for (algorithm, expected) in [(HashAlgorithm::Md5, md5), (HashAlgorithm::Sha1, sha1), (HashAlgorithm::Sha256, sha256), (HashAlgorithm::Crc32, crc32)] {
+            assert_eq!(digest::to_hex(&algorithm.digest(&message)), expected, "{} of {} bytes", algorithm.get_name(), message.len());
+        }
+        assert_eq!(digest::to_hex(&digest_in_pieces::<Md5>(&message)), md5);
+        assert_eq!(digest::to_hex(&digest_in_pieces::<Sha1>(&message)), sha1);
+        assert_eq!(digest::to_hex(&digest_in_pieces::<Sha256>(&message)), sha256);
+        assert_eq!(digest::to_hex(&digest_in_pieces::<Crc32>(&message)), crc32);
+    }
+}
+
+#[test]
+fn crc32_check_value() {
+    // The check value of the CRC catalogues
+    assert_eq!(digest::crc32(b"123456789"), 0xCBF43926);
+    assert_eq!(Crc32::digest(b"123456789"), vec![0xCB, 0xF4, 0x39, 0x26]);
+}
+
+#[test]
+fn output_sizes_match_the_digests() {
+    assert_eq!(Crc32::digest(b"abc").len(), Crc32::OUTPUT_SIZE);
+    assert_eq!(Md5::digest(b"abc").len(), Md5::OUTPUT_SIZE);
+    assert_eq!(Sha1::digest(b"abc").len(), Sha1::OUTPUT_SIZE);
+    assert_eq!(Sha256::digest(b"abc").len(), Sha256::OUTPUT_SIZE);
+    assert_eq!(digest::from_hex(&digest::to_hex(&Sha256::digest(b"abc")), Sha256::OUTPUT_SIZE), Some(Sha256::digest(b"abc")));
+}
@@ -0,0 +1,119 @@
+use std::{fs::File, io};
+use rs_disk_reader::{read_boot_sector, read_fat, BootSector, Cluster, Fat};
+
+/** FAT of a 1.44M floppy, 9 sectors, with every byte set to the filler. */
+fn floppy_fat(filler: u8) -> Fat {
+    Fat::from_bytes(vec![filler; 9 * 512])
+}
+
+#[test]
+fn even_entries_take_a_byte_and_the_low_nibble_of_the_next() {
+    let mut fat: Fat = floppy_fat(0xFF);
+    fat.set_entry(Cluster(2), 0xABC).unwrap();
+    assert_eq!(&fat.as_bytes()[2..6], &[0xFF, 0xBC, 0xFA, 0xFF]);
+    assert_eq!(fat.get_entry(Cluster(2)), 0xABC);
+    assert_eq!(fat.get_entry(Cluster(3)), 0xFFF);
+}
+
+#[test]
+fn odd_entries_take_the_high_nibble_of_a_byte_and_the_next() {
+    let mut fat: Fat = floppy_fat(0xFF);
+    fat.set_entry(Cluster(3), 0x123).unwrap();
+    assert_eq!(&fat.as_bytes()[2..6], &[0xFF, 0xFF, 0x3F, 0x12]);
+    assert_eq!(fat.get_entry(Cluster(3)), 0x123);
+    assert_eq!(fat.get_entry(Cluster(2)), 0xFFF);
+}
+
+#[test]
+fn neighbours_sharing_a_byte_are_left_alone() {
+    // Clearing one entry of a pair keeps every bit of the other
+    let mut fat: Fat = floppy_fat(0xFF);
+    fat.set_entry(Cluster(4), 0).unwrap();
+    assert_eq!(&fat.as_bytes()[5..9], &[0xFF, 0x00, 0xF0, 0xFF]);
+    fat.set_entry(Cluster(5), 0).unwrap();
+    assert_eq!(&fat.as_bytes()[5..9], &[0xFF, 0x00, 0x00, 0x00]);
+    assert_eq!(fat.get_entry(Cluster(3)), 0xFFF);
+    assert_eq!(fat.get_entry(Cluster(6)), 0xFFF);
+
+    let mut fat: Fat = floppy_fat(0x00);
+    fat.set_entry(Cluster(5), 0xFFF).unwrap();
+    assert_eq!(&fat.as_bytes()[6..9], &[0x00, 0xF0, 0xFF]);
+    assert_eq!(fat.get_entry(Cluster(4)), 0);
+    assert_eq!(fat.get_entry(Cluster(6)), 0);
+}
+
+#[test]
+fn header_entries_are_packed_like_the_others() {
+    let mut fat: Fat = floppy_fat(0x00);
+    fat.set_entry(Cluster(0), 0xFF0).unwrap();
+    fat.set_entry(Cluster(1), 0xFFF).unwrap();
+    assert_eq!(&fat.as_bytes()[..3], &[0xF0, 0xFF, 0xFF]);
+    assert_eq!(fat.get_header().fat_id, 0xFF0);
+    assert_eq!(fat.get_header().end_of_chain, 0xFFF);
+}
+
+#[test]
+fn every_entry_reads_back_what_was_written() {
+    // Written in order, then read back after all of them, so that any
+    // entry spilling over a neighbour would show
+    let mut fat: Fat = floppy_fat(0x5A);
+    let count: u32 = fat.get_entry_count() as u32;
+    let value = |cluster: u32| -> u16 { (cluster.wrapping_mul(2654435761) >> 20) as u16 & 0x0FFF };
+    for cluster in 0..count { fat.set_entry(Cluster(cluster), value(cluster)).unwrap(); }
+    for cluster in 0..count { assert_eq!(fat.get_entry(Cluster(cluster)), value(cluster), "cluster {}", cluster); }
+
+    // And again backwards, over what is there now
+    for cluster in (0..count).rev() { fat.set_entry(Cluster(cluster), !value(cluster) & 0x0FFF).unwrap(); }
+    for cluster in 0..count { assert_eq!(fat.get_entry(Cluster(cluster)), !value(cluster) & 0x0FFF, "cluster {}", cluster); }
+}
+
+#[test]
+fn last_entry_of_the_fat_is_the_last_one_written() {
+    // 4608 bytes hold 3072 entries, the last one odd and ending the FAT
+    let mut fat: Fat = floppy_fat(0x00);
+    assert_eq!(fat.get_entry_count(), 3072);
+    fat.set_entry(Cluster(3071), 0xFFF).unwrap();
+    assert_eq!(&fat.as_bytes()[4605..], &[0x00, 0xF0, 0xFF]);
+
+    // A FAT ending in the middle of a pair has no room for the odd entry
+    let mut fat: Fat = Fat::from_bytes(vec![0; 8]);
+    assert_eq!(fat.get_entry_count(), 5);
+    fat.set_entry(Cluster(4), 0xABC).unwrap();
+    assert_eq!(fat.set_entry(Cluster(5), 0xABC).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fat.as_bytes(), &[0, 0, 0, 0, 0, 0, 0xBC, 0x0A]);
+}
+
+#[test]
+fn entries_past_the_fat_are_refused() {
+    let mut fat: Fat = floppy_fat(0x00);
+    for cluster in [3072, 3073, 4095, u32::MAX] {
+        let error: io::Error = fat.set_entry(Cluster(cluster), 0xFFF).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "cluster {}", cluster);
+    }
+    assert!(fat.as_bytes().iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn values_over_12_bits_are_refused() {
+    let mut fat: Fat = floppy_fat(0x00);
+    for value in [0x1000, 0x1FFF, 0xFFFF] {
+        let error: io::Error = fat.set_entry(Cluster(2), value).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "value 0x{:X}", value);
+    }
+    assert!(fat.as_bytes().iter().all(|byte| *byte == 0));
+}
+
+#[test]
+fn rewriting_an_image_fat_keeps_its_bytes() {
+    let mut disk: File = File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_floppy.img")).unwrap();
+    let boot_sector: BootSector = read_boot_sector(&mut disk).unwrap();
+    let fat: Fat = read_fat(&mut disk, &boot_sector).unwrap();
+    assert_eq!(fat.get_chain(Cluster(3), boot_sector.get_cluster_count()), [Cluster(3), Cluster(4)]);
+
+    // Entry by entry, over a FAT of the same size filled with garbage
+    let mut copy: Fat = Fat::from_bytes(vec![0xA5; fat.as_bytes().len()]);
+    for cluster in (0..fat.get_entry_count() as u32).map(Cluster) {
+        copy.set_entry(cluster, fat.get_entry(cluster)).unwrap();
+    }
+    assert_eq!(copy.as_bytes(), fat.as_bytes());
+}
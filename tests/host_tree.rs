@@ -0,0 +1,58 @@
+mod common;
+
+use std::{fs, io};
+use common::{check_consistency, pattern, TempDir, TempImage};
+use rs_disk_reader::bulk::{self, BulkOptions, BulkReport};
+use rs_disk_reader::sync::{self, SyncOptions, SyncReport};
+use rs_disk_reader::{span, Fat12Fs};
+
+/** Host tree with a nested file and a link to a directory outside of it. */
+fn host_tree_with_link(name: &str, outside: &TempDir) -> TempDir {
+    let host: TempDir = TempDir::new(name);
+    fs::create_dir_all(host.0.join("B").join("C")).unwrap();
+    fs::write(host.0.join("A.TXT"), b"first").unwrap();
+    fs::write(host.0.join("B").join("C").join("D.BIN"), pattern(2000, 1)).unwrap();
+    fs::write(outside.0.join("SECRET.TXT"), b"outside").unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&outside.0, host.0.join("LINK")).unwrap();
+    host
+}
+
+#[test]
+fn import_sync_and_split_walk_the_same_tree_without_following_links() {
+    let outside: TempDir = TempDir::new("host-tree-outside");
+
+    let image: TempImage = TempImage::blank_floppy("host-tree-import");
+    let host: TempDir = host_tree_with_link("host-tree-import-host", &outside);
+    let mut fs: Fat12Fs = image.open_rw();
+    let options: BulkOptions = BulkOptions { keep_going: true, ..BulkOptions::default() };
+    let report: BulkReport = bulk::import(&mut fs, &host.0, "/", &options).unwrap();
+    assert_eq!(report.failed.iter().map(|(path, _)| path.as_str()).collect::<Vec<_>>(), if cfg!(unix) { vec!["LINK"] } else { vec![] });
+    drop(fs);
+    let mut fs: Fat12Fs = image.open();
+    assert_eq!(check_consistency(&mut fs).into_keys().collect::<Vec<_>>(), ["A.TXT", "B", "B/C", "B/C/D.BIN"]);
+    drop(fs);
+
+    let image: TempImage = TempImage::blank_floppy("host-tree-sync");
+    let host: TempDir = host_tree_with_link("host-tree-sync-host", &outside);
+    let mut fs: Fat12Fs = image.open_rw();
+    let report: SyncReport = sync::sync(&mut fs, "/", &host.0, &SyncOptions::default()).unwrap();
+    assert_eq!(report.actions.len(), 2, "{:?}", report.actions);
+    assert_eq!(report.skipped.len(), if cfg!(unix) { 1 } else { 0 }, "{:?}", report.skipped);
+    drop(fs);
+    let mut fs: Fat12Fs = image.open();
+    assert_eq!(fs.read_file("/B/C/D.BIN").unwrap(), pattern(2000, 1));
+    check_consistency(&mut fs);
+    drop(fs);
+
+    // Splitting refuses the link outright, as the set would lack it
+    let image: TempImage = TempImage::blank_floppy("host-tree-split");
+    let host: TempDir = host_tree_with_link("host-tree-split-host", &outside);
+    let mut disks: Vec<Fat12Fs> = vec![image.open_rw()];
+    let result: io::Result<Vec<span::Manifest>> = span::split(&mut disks, &host.0, &BulkOptions::default());
+    if cfg!(unix) {
+        assert!(result.unwrap_err().to_string().contains("LINK: symbolic link"));
+    } else {
+        result.unwrap();
+    }
+}
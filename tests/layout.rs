@@ -0,0 +1,128 @@
+use std::fs::File;
+use rs_disk_reader::layout::{fat_entry_offset, VolumeLayout};
+use rs_disk_reader::{read_boot_sector, BootSector, Cluster, Lba};
+
+/** Layout of a floppy as DOS formats them: 512 byte sectors, the boot
+ *  sector reserved, two FATs. */
+fn floppy(sectors_per_cluster: u64, sectors_per_fat: u64, root_entries: u64, total_sectors: u64) -> VolumeLayout {
+    VolumeLayout { bytes_per_sector: 512, sectors_per_cluster, reserved_sectors: 1, fat_count: 2, sectors_per_fat, root_entries, total_sectors }
+}
+
+#[test]
+fn regions_of_a_144m_floppy() {
+    let layout: VolumeLayout = floppy(1, 9, 224, 2880);
+    assert_eq!(layout.fat_start(), 512);
+    assert_eq!(layout.fat_size(), 9 * 512);
+    assert_eq!(layout.fat_copy_start(1), 10 * 512);
+    assert_eq!(layout.root_dir_start(), 19 * 512);
+    assert_eq!(layout.root_dir_size(), 224 * 32);
+    assert_eq!(layout.root_dir_sectors(), 14);
+    assert_eq!(layout.data_start_sector(), 33);
+    assert_eq!(layout.data_start(), 33 * 512);
+    assert_eq!(layout.cluster_size(), 512);
+    assert_eq!(layout.cluster_count(), 2847);
+    assert_eq!(layout.needed_fat_sectors(), 9);
+}
+
+#[test]
+fn data_area_of_every_floppy_format() {
+    // (layout, RootDirSectors, FirstDataSector, data clusters)
+    let formats: [(VolumeLayout, u64, u64, u64); 6] = [
+        (floppy(1, 1, 64, 320), 4, 7, 313),      // 160K
+        (floppy(2, 2, 112, 720), 7, 12, 354),    // 360K
+        (floppy(2, 3, 112, 1440), 7, 14, 713),   // 720K
+        (floppy(1, 7, 224, 2400), 14, 29, 2371), // 1.2M
+        (floppy(1, 9, 224, 2880), 14, 33, 2847), // 1.44M
+        (floppy(2, 9, 240, 5760), 15, 34, 2863)  // 2.88M
+    ];
+    for (layout, root_dir_sectors, data_start_sector, cluster_count) in formats {
+        assert_eq!(layout.root_dir_sectors(), root_dir_sectors, "{:?}", layout);
+        assert_eq!(layout.data_start_sector(), data_start_sector, "{:?}", layout);
+        assert_eq!(layout.cluster_count(), cluster_count, "{:?}", layout);
+        assert!(layout.needed_fat_sectors() <= layout.sectors_per_fat, "{:?}", layout);
+    }
+}
+
+#[test]
+fn root_directory_rounds_up_to_whole_sectors() {
+    // 20 entries take 640 bytes, the data area starts at the next sector
+    let layout: VolumeLayout = floppy(1, 9, 20, 2880);
+    assert_eq!(layout.root_dir_sectors(), 2);
+    assert_eq!(layout.data_start_sector(), 21);
+    assert_eq!(layout.cluster_count(), 2880 - 21);
+}
+
+#[test]
+fn clusters_map_to_sectors_and_back() {
+    let layout: VolumeLayout = floppy(2, 3, 112, 1440);
+    assert_eq!(layout.cluster_lba(0), None);
+    assert_eq!(layout.cluster_lba(1), None);
+    assert_eq!(layout.cluster_lba(2), Some(14));
+    assert_eq!(layout.cluster_lba(3), Some(16));
+    assert_eq!(layout.cluster_offset(714), Some((14 + 712 * 2) * 512));
+
+    assert_eq!(layout.lba_cluster(13), None);
+    assert_eq!(layout.lba_cluster(14), Some(2));
+    assert_eq!(layout.lba_cluster(15), Some(2));
+    assert_eq!(layout.lba_cluster(16), Some(3));
+    assert_eq!(layout.lba_cluster(14 + 713 * 2 - 1), Some(714));
+    assert_eq!(layout.lba_cluster(1440), None);
+
+    // A sector left over after the last whole cluster belongs to none
+    let layout: VolumeLayout = floppy(2, 3, 112, 1441);
+    assert_eq!(layout.cluster_count(), 713);
+    assert_eq!(layout.lba_cluster(1440), None);
+}
+
+#[test]
+fn chain_length_rounds_up_to_whole_clusters() {
+    let layout: VolumeLayout = floppy(2, 3, 112, 1440);
+    assert_eq!(layout.chain_length(0), 0);
+    assert_eq!(layout.chain_length(1), 1);
+    assert_eq!(layout.chain_length(1024), 1);
+    assert_eq!(layout.chain_length(1025), 2);
+    assert_eq!(layout.chain_length(u32::MAX as u64), 4194304);
+}
+
+#[test]
+fn fat_entries_are_packed_in_a_byte_and_a_half() {
+    assert_eq!(fat_entry_offset(0), (0, false));
+    assert_eq!(fat_entry_offset(1), (1, true));
+    assert_eq!(fat_entry_offset(2), (3, false));
+    assert_eq!(fat_entry_offset(3), (4, true));
+    assert_eq!(fat_entry_offset(4084), (6126, false));
+    assert_eq!(fat_entry_offset(4085), (6127, true));
+}
+
+#[test]
+fn fat_sectors_needed_for_the_largest_fat12_volume() {
+    // 4084 clusters and the 2 header entries take 6129 bytes: 12 sectors
+    let layout: VolumeLayout = floppy(1, 12, 224, 1 + 24 + 14 + 4084);
+    assert_eq!(layout.cluster_count(), 4084);
+    assert_eq!(layout.needed_fat_sectors(), 12);
+    assert_eq!(layout.volume_size(4084), layout.total_sectors * 512);
+}
+
+#[test]
+fn zero_sizes_give_zero_counts() {
+    let layout: VolumeLayout = VolumeLayout { bytes_per_sector: 0, sectors_per_cluster: 0, ..floppy(1, 9, 224, 2880) };
+    assert_eq!(layout.root_dir_sectors(), 0);
+    assert_eq!(layout.cluster_count(), 0);
+    assert_eq!(layout.chain_length(100), 0);
+    assert_eq!(layout.needed_fat_sectors(), 0);
+    assert_eq!(layout.lba_cluster(100), None);
+}
+
+#[test]
+fn boot_sector_offsets_follow_the_layout() {
+    let boot_sector: BootSector = read_boot_sector(&mut File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test_floppy.img")).unwrap()).unwrap();
+    let layout: VolumeLayout = boot_sector.get_layout();
+    assert_eq!(layout, floppy(1, 9, 224, 2880));
+    assert_eq!(boot_sector.get_fat_start(), layout.fat_start());
+    assert_eq!(boot_sector.get_root_dir_start(), layout.root_dir_start());
+    assert_eq!(boot_sector.get_cluster_region_start(), layout.data_start());
+    assert_eq!(boot_sector.get_cluster_count(), 2847);
+    assert_eq!(Cluster(2).to_lba(&boot_sector), Lba(33));
+    assert_eq!(boot_sector.get_cluster_start(Cluster(3)), 34 * 512);
+    assert_eq!(boot_sector.get_geometry().data_start_sector, 33);
+}
@@ -0,0 +1,129 @@
+mod common;
+
+use common::{check_consistency, pattern, TempImage};
+use rs_disk_reader::codepage::{self, Unrepresentable};
+use rs_disk_reader::lfn::{self, NamePair};
+use rs_disk_reader::{DirectoryEntry, Fat12Fs, NamePolicy};
+
+/** 2000-01-01, well within the FAT range: nothing gets clamped. */
+const TIME: u64 = 946684800;
+
+fn open_long_names(image: &TempImage, unrepresentable: Unrepresentable) -> Fat12Fs {
+    Fat12Fs::options().read_only(false).name_policy(NamePolicy::LongNames).unrepresentable(unrepresentable).open(image.path()).unwrap()
+}
+
+fn find_pair<'a>(pairs: &'a [NamePair], short_name: &str) -> &'a NamePair {
+    pairs.iter().find(|pair| pair.short_name == short_name).unwrap_or_else(|| panic!("no {} in {:?}", short_name, pairs))
+}
+
+#[test]
+fn long_names_are_written_and_read_back() {
+    let image: TempImage = TempImage::blank_floppy("lfn-write");
+    let mut fs: Fat12Fs = open_long_names(&image, Unrepresentable::Replace);
+    fs.write_file("/Long file name.txt", b"first", TIME).unwrap();
+    fs.write_file("/Long file name 2.txt", b"second", TIME).unwrap();
+    fs.write_file("/readme.txt", b"case only", TIME).unwrap();
+    fs.write_file("/PLAIN.TXT", b"no long name", TIME).unwrap();
+    fs.create_dir("/Résumés de l'année", TIME).unwrap();
+    assert_eq!(fs.take_warnings(), vec![]);
+
+    let pairs: Vec<NamePair> = fs.read_name_pairs("/").unwrap();
+    assert_eq!(find_pair(&pairs, "LONGFI~1.TXT").long_name.as_deref(), Some("Long file name.txt"));
+    assert_eq!(find_pair(&pairs, "LONGFI~2.TXT").long_name.as_deref(), Some("Long file name 2.txt"));
+    assert_eq!(find_pair(&pairs, "README.TXT").long_name.as_deref(), Some("readme.txt"));
+    assert_eq!(find_pair(&pairs, "PLAIN.TXT").long_name, None);
+    assert_eq!(find_pair(&pairs, "R_SUM_~1").long_name.as_deref(), Some("Résumés de l'année"));
+    assert_eq!(find_pair(&pairs, "R_SUM_~1").lfn_slots.len(), 2);
+
+    // Writing the long name again overwrites the entry instead of adding one
+    fs.write_file("/Long file name.txt", b"rewritten", TIME).unwrap();
+    assert_eq!(fs.read_name_pairs("/").unwrap().len(), pairs.len());
+    assert_eq!(fs.read_file("/LONGFI~1.TXT").unwrap(), b"rewritten");
+    drop(fs);
+
+    let mut fs: Fat12Fs = image.open();
+    assert_eq!(fs.read_file("/Long file name 2.txt").unwrap(), b"second");
+    check_consistency(&mut fs);
+}
+
+#[test]
+fn long_names_grow_subdirectories_across_clusters() {
+    // 16 slots a cluster: names of 2 slots plus the entry straddle clusters
+    let image: TempImage = TempImage::blank_floppy("lfn-grow");
+    let mut fs: Fat12Fs = open_long_names(&image, Unrepresentable::Replace);
+    fs.create_dir("/Many long names", TIME).unwrap();
+    let files: Vec<(String, Vec<u8>)> = (0..12).map(|i| (format!("/Many long names/File number {:02}.data", i), pattern(100, i as u8))).collect();
+    for (path, data) in &files { fs.write_file(path, data, TIME).unwrap(); }
+    drop(fs);
+
+    let mut fs: Fat12Fs = image.open();
+    let pairs: Vec<NamePair> = fs.read_name_pairs("/Many long names").unwrap();
+    assert_eq!(pairs.len(), files.len());
+    for (path, data) in &files { assert_eq!(&fs.read_file(path).unwrap(), data, "{}", path); }
+    check_consistency(&mut fs);
+}
+
+#[test]
+fn removing_an_entry_removes_its_long_name() {
+    let image: TempImage = TempImage::blank_floppy("lfn-remove");
+    let mut fs: Fat12Fs = open_long_names(&image, Unrepresentable::Replace);
+    fs.write_file("/A rather long name.txt", b"gone soon", TIME).unwrap();
+    let slots: usize = fs.read_name_pairs("/").unwrap()[0].lfn_slots.len();
+    fs.remove("/A rather long name.txt").unwrap();
+
+    fs.flush().unwrap();
+    let root: Vec<u8> = std::fs::read(&image.0).unwrap()[19 * 512..33 * 512].to_vec();
+    assert!(root.chunks(32).take(slots + 1).all(|slot| slot[0] == 0xE5));
+    assert!(fs.read_name_pairs("/").unwrap().is_empty());
+
+    // The freed slots are taken again, for a name as long
+    fs.write_file("/Another long name.txt", b"here", TIME).unwrap();
+    assert_eq!(fs.read_name_pairs("/").unwrap()[0].index, slots);
+    check_consistency(&mut fs);
+}
+
+#[test]
+fn characters_past_ucs2_follow_the_policy() {
+    let image: TempImage = TempImage::blank_floppy("lfn-unrepresentable");
+    let mut fs: Fat12Fs = open_long_names(&image, Unrepresentable::Error);
+    let e: std::io::Error = fs.write_file("/smile \u{1F600}.txt", b"", TIME).unwrap_err();
+    assert_eq!(e.kind(), std::io::ErrorKind::InvalidInput);
+    assert!(e.to_string().contains("U+1F600"), "{}", e);
+    assert!(fs.read_name_pairs("/").unwrap().is_empty());
+    check_consistency(&mut fs);
+    drop(fs);
+
+    let mut fs: Fat12Fs = open_long_names(&image, Unrepresentable::Replace);
+    fs.write_file("/smile \u{1F600}.txt", b"", TIME).unwrap();
+    let pairs: Vec<NamePair> = fs.read_name_pairs("/").unwrap();
+    assert_eq!(pairs[0].long_name.as_deref(), Some("smile _.txt"));
+    assert_eq!(pairs[0].short_name, "SMILE_~1.TXT");
+    assert!(fs.write_file("/a:b.txt", b"", TIME).is_err());
+}
+
+#[test]
+fn unpaired_surrogates_follow_the_policy() {
+    // "A", a lone high surrogate, "B", then a lone low surrogate
+    let units: [u16; 4] = [0x0041, 0xD83D, 0x0042, 0xDE00];
+    let e: String = codepage::validate_ucs2(&units, Unrepresentable::Error).unwrap_err();
+    assert!(e.contains("unpaired surrogate U+D83D"), "{}", e);
+    assert_eq!(codepage::validate_ucs2(&units, Unrepresentable::Replace).unwrap(), "A_B_".encode_utf16().collect::<Vec<u16>>());
+
+    // A whole pair is one character past U+FFFF, which UCS-2 can't hold
+    let pair: [u16; 2] = [0xD83D, 0xDE00];
+    assert!(codepage::validate_ucs2(&pair, Unrepresentable::Error).unwrap_err().contains("U+1F600"));
+    assert_eq!(codepage::validate_ucs2(&pair, Unrepresentable::Replace).unwrap(), vec![b'_' as u16]);
+    assert!(codepage::validate_ucs2(&[0x0041; 256], Unrepresentable::Replace).is_err());
+    assert_eq!(codepage::encode_ucs2("\u{1F600}", Unrepresentable::Replace).unwrap(), vec![b'_' as u16]);
+}
+
+#[test]
+fn encoded_slots_decode_to_the_same_name() {
+    let short_name: [u8; 11] = *b"LONGFI~1TXT";
+    for name in ["a", "exactly 13 ch", "exactly 26 characters long", &"x".repeat(255)] {
+        let units: Vec<u16> = name.encode_utf16().collect();
+        let slots: Vec<DirectoryEntry> = lfn::encode_long_name(&units, &short_name);
+        assert_eq!(slots.len(), units.len().div_ceil(13), "{}", name);
+        assert_eq!(lfn::decode_long_name(&slots, &DirectoryEntry::new(short_name, 0, 0)).as_deref(), Some(name));
+    }
+}
@@ -0,0 +1,98 @@
+mod common;
+
+use std::io;
+use common::{check_consistency, TempImage};
+use rs_disk_reader::posix::{MountOptions, Stat, S_IFDIR, S_IFREG, XATTR_ATTRIBUTES, XATTR_FLAGS};
+use rs_disk_reader::{Fat12Fs, ATTR_ARCHIVE, ATTR_HIDDEN, ATTR_READ_ONLY, ATTR_SYSTEM};
+
+#[test]
+fn xattrs_show_and_set_the_flags() {
+    let image: TempImage = TempImage::test_floppy("posix-xattrs");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.set_attributes("/KERNEL.BIN", ATTR_READ_ONLY | ATTR_ARCHIVE).unwrap();
+
+    assert_eq!(fs.list_xattrs("/KERNEL.BIN").unwrap(), [XATTR_ATTRIBUTES].into_iter().chain(XATTR_FLAGS.iter().map(|(name, _)| *name)).collect::<Vec<_>>());
+    assert_eq!(fs.get_xattr("/KERNEL.BIN", XATTR_ATTRIBUTES).unwrap(), b"RA");
+    assert_eq!(fs.get_xattr("/KERNEL.BIN", "user.fat.readonly").unwrap(), b"1");
+    assert_eq!(fs.get_xattr("/KERNEL.BIN", "user.fat.hidden").unwrap(), b"0");
+
+    // Letters in any order and case replace all the flags, the others one each
+    fs.set_xattr("/KERNEL.BIN", XATTR_ATTRIBUTES, b"sh").unwrap();
+    assert_eq!(fs.get_entry("/KERNEL.BIN").unwrap().attributes, ATTR_HIDDEN | ATTR_SYSTEM);
+    fs.set_xattr("/KERNEL.BIN", "user.fat.archive", b"1").unwrap();
+    fs.set_xattr("/KERNEL.BIN", "user.fat.system", b"0").unwrap();
+    assert_eq!(fs.get_xattr("/KERNEL.BIN", XATTR_ATTRIBUTES).unwrap(), b"HA");
+    drop(fs);
+
+    let mut fs: Fat12Fs = image.open();
+    assert_eq!(fs.get_entry("/KERNEL.BIN").unwrap().attributes, ATTR_HIDDEN | ATTR_ARCHIVE);
+    check_consistency(&mut fs);
+}
+
+#[test]
+fn xattrs_refuse_unknown_names_values_and_the_root() {
+    let image: TempImage = TempImage::test_floppy("posix-xattrs-refused");
+    let mut fs: Fat12Fs = image.open_rw();
+    let attributes: u8 = fs.get_entry("/KERNEL.BIN").unwrap().attributes;
+
+    assert_eq!(fs.get_xattr("/KERNEL.BIN", "user.fat.other").unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.set_xattr("/KERNEL.BIN", "user.fat.hidden", b"yes").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fs.set_xattr("/KERNEL.BIN", XATTR_ATTRIBUTES, b"RX").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    assert_eq!(fs.get_entry("/KERNEL.BIN").unwrap().attributes, attributes);
+
+    assert_eq!(fs.list_xattrs("/").unwrap(), Vec::<&str>::new());
+    assert_eq!(fs.get_xattr("/", XATTR_ATTRIBUTES).unwrap_err().kind(), io::ErrorKind::NotFound);
+    assert_eq!(fs.chmod("/", 0o555).unwrap_err().kind(), io::ErrorKind::PermissionDenied);
+    assert_eq!(fs.list_xattrs("/MISSING.TXT").unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn chmod_maps_the_write_bits_to_read_only() {
+    let image: TempImage = TempImage::test_floppy("posix-chmod");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.set_attributes("/BIGFILE.TXT", ATTR_ARCHIVE).unwrap();
+
+    // Any write bit is enough to be writable, the other bits are dropped
+    fs.chmod("/BIGFILE.TXT", 0o444).unwrap();
+    assert_eq!(fs.get_entry("/BIGFILE.TXT").unwrap().attributes, ATTR_READ_ONLY | ATTR_ARCHIVE);
+    fs.chmod("/BIGFILE.TXT", 0o020).unwrap();
+    assert_eq!(fs.get_entry("/BIGFILE.TXT").unwrap().attributes, ATTR_ARCHIVE);
+    drop(fs);
+
+    let mut fs: Fat12Fs = image.open();
+    check_consistency(&mut fs);
+}
+
+#[test]
+fn mount_options_parse_as_vfat_takes_them() {
+    let mount: MountOptions = MountOptions::from_options("uid=1000,gid=100,umask=027,showexec,noatime").unwrap();
+    assert_eq!(mount, MountOptions { uid: 1000, gid: 100, fmask: 0o027, dmask: 0o027, showexec: true });
+    let mount: MountOptions = MountOptions::from_options("fmask=133, dmask=022").unwrap();
+    assert_eq!((mount.fmask, mount.dmask, mount.uid), (0o133, 0o022, 0));
+
+    for options in ["uid=me", "umask=999", "fmask=1000", "gid="] {
+        assert!(MountOptions::from_options(options).is_err(), "{}", options);
+    }
+}
+
+#[test]
+fn stat_maps_owner_and_permissions() {
+    let image: TempImage = TempImage::test_floppy("posix-stat");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.create_dir("/GAMES", 946684800).unwrap();
+    fs.write_file("/GAMES/RUN.EXE", b"MZ", 946684800).unwrap();
+    fs.set_attributes("/BIGFILE.TXT", ATTR_READ_ONLY | ATTR_ARCHIVE).unwrap();
+    let mount: MountOptions = MountOptions::from_options("uid=1000,gid=100,fmask=022,dmask=027").unwrap();
+
+    let stat: Stat = fs.stat("/KERNEL.BIN", &mount).unwrap();
+    assert_eq!((stat.mode, stat.uid, stat.gid, stat.size), (S_IFREG | 0o755, 1000, 100, 58));
+    assert_eq!(fs.stat("/BIGFILE.TXT", &mount).unwrap().mode, S_IFREG | 0o555);
+    assert_eq!(fs.stat("/GAMES", &mount).unwrap().mode, S_IFDIR | 0o750);
+    assert_eq!(fs.stat("/", &mount).unwrap(), Stat { mode: S_IFDIR | 0o750, uid: 1000, gid: 100, size: 0, modified: 0 });
+
+    // Showexec keeps the execute bits for programs only
+    let mount: MountOptions = MountOptions { showexec: true, ..mount };
+    assert_eq!(fs.stat("/KERNEL.BIN", &mount).unwrap().mode, S_IFREG | 0o644);
+    assert_eq!(fs.stat("/GAMES/RUN.EXE", &mount).unwrap().mode, S_IFREG | 0o755);
+    assert_eq!(fs.stat("/MISSING.TXT", &mount).unwrap_err().kind(), io::ErrorKind::NotFound);
+}
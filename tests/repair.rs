@@ -0,0 +1,69 @@
+mod common;
+
+use std::{collections::BTreeMap, fs, io::{Seek, SeekFrom, Write}};
+use common::{check_consistency, pattern, TempImage};
+use rs_disk_reader::repair::FatRebuild;
+use rs_disk_reader::{DirectoryEntry, Fat12Fs};
+
+/** Overwrite bytes of the image file. */
+fn patch(image: &TempImage, offset: u64, data: &[u8]) {
+    let mut file: fs::File = fs::File::options().write(true).open(&image.0).unwrap();
+    file.seek(SeekFrom::Start(offset)).unwrap();
+    file.write_all(data).unwrap();
+}
+
+/** Zero both FATs of a 1.44M floppy, as a trashed disk may have them. */
+fn zero_fats(image: &TempImage) {
+    patch(image, 512, &[0; 18 * 512]);
+}
+
+#[test]
+fn rebuild_stops_a_fragmented_directory_where_its_entries_stop() {
+    // /MANY fills its first cluster (16 entries) while the files take the
+    // next ones: its second cluster comes after all of them
+    let image: TempImage = TempImage::blank_floppy("rebuild-fragmented");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.create_dir("/MANY", 0).unwrap();
+    let files: Vec<(String, Vec<u8>)> = (0..20).map(|i| (format!("/MANY/FILE{:02}.TXT", i), pattern(300, i as u8))).collect();
+    for (path, data) in &files { fs.write_file(path, data, 0).unwrap(); }
+    fs.flush().unwrap();
+    drop(fs);
+    zero_fats(&image);
+
+    let mut fs: Fat12Fs = image.open_rw();
+    let rebuild: FatRebuild = fs.rebuild_fat().unwrap();
+    assert_eq!(rebuild.uncertain, vec![]);
+    assert_eq!(rebuild.truncated.len(), 1);
+    assert_eq!(rebuild.truncated[0].0, "/MANY");
+    drop(fs);
+
+    // The 14 files of the first cluster are back, whole, the others are lost
+    let mut fs: Fat12Fs = image.open();
+    let entries: BTreeMap<String, DirectoryEntry> = check_consistency(&mut fs);
+    assert_eq!(entries.len(), 15);
+    for (path, data) in &files[..14] { assert_eq!(&fs.read_file(path).unwrap(), data, "{}", path); }
+}
+
+#[test]
+fn rebuild_empties_entries_it_leaves_out() {
+    // BIGFILE.TXT claims the first cluster of KERNEL.BIN as its own
+    let image: TempImage = TempImage::test_floppy("rebuild-overlap");
+    let kernel: Vec<u8> = image.open().read_file("/KERNEL.BIN").unwrap();
+    let root: Vec<u8> = fs::read(&image.0).unwrap()[19 * 512..33 * 512].to_vec();
+    let slot = |name: &[u8]| 19 * 512 + root.chunks(32).position(|entry| &entry[..11] == name).unwrap() as u64 * 32;
+    let kernel_cluster: [u8; 2] = root[(slot(b"KERNEL  BIN") - 19 * 512 + 26) as usize..][..2].try_into().unwrap();
+    patch(&image, slot(b"BIGFILE TXT") + 26, &kernel_cluster);
+    zero_fats(&image);
+
+    let mut fs: Fat12Fs = image.open_rw();
+    let rebuild: FatRebuild = fs.rebuild_fat().unwrap();
+    assert_eq!(rebuild.recovered, vec!["/KERNEL.BIN"]);
+    assert_eq!(rebuild.uncertain.len(), 1);
+    assert_eq!(rebuild.uncertain[0].0, "/BIGFILE.TXT");
+    drop(fs);
+
+    let mut fs: Fat12Fs = image.open();
+    let entries: BTreeMap<String, DirectoryEntry> = check_consistency(&mut fs);
+    assert_eq!({ entries["BIGFILE.TXT"].file_size }, 0);
+    assert_eq!(fs.read_file("/KERNEL.BIN").unwrap(), kernel);
+}
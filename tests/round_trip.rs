@@ -0,0 +1,420 @@
+mod common;
+
+use std::{collections::BTreeMap, fs, io, sync::Arc};
+use common::{check_consistency, pattern, TempDir, TempImage};
+use rs_disk_reader::bulk::{self, BulkOptions};
+use rs_disk_reader::metadata::SortKey;
+use rs_disk_reader::provision::{self, Item, Template};
+use rs_disk_reader::repair::OrphanRecovery;
+use rs_disk_reader::resize::ResizeOptions;
+use rs_disk_reader::shortname::NumericTail;
+use rs_disk_reader::source::{FileSource, Generator};
+use rs_disk_reader::sync::{self, SyncAction, SyncOptions, SyncReport};
+use rs_disk_reader::text::TextMode;
+use rs_disk_reader::{sparse, span, Cluster, DirectoryEntry, Fat12Fs, FatMirroring, NamePolicy, ATTR_HIDDEN, ATTR_READ_ONLY};
+
+/* Every operation that writes is followed by a fresh open of the image and
+ * the fsck-like check of common::check_consistency, then by reading back
+ * what was written. */
+
+/** 2000-01-01, well within the FAT range: nothing gets clamped. */
+const TIME: u64 = 946684800;
+
+/** Open the image again, as another run would, and check it. */
+fn reopen(image: &TempImage) -> (Fat12Fs, BTreeMap<String, DirectoryEntry>) {
+    let mut fs: Fat12Fs = image.open();
+    let entries: BTreeMap<String, DirectoryEntry> = check_consistency(&mut fs);
+    (fs, entries)
+}
+
+#[test]
+fn files_and_directories_are_written_overwritten_and_removed() {
+    let image: TempImage = TempImage::test_floppy("round-trip-basic");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.create_dir("/SUB", TIME).unwrap();
+    fs.create_dir("/SUB/DEEP", TIME).unwrap();
+    fs.write_file("/SUB/DEEP/DATA.BIN", &pattern(5000, 1), TIME).unwrap();
+    fs.write_file("/SUB/EMPTY.TXT", b"", TIME).unwrap();
+    fs.write_file("/BIGFILE.TXT", &pattern(100, 2), TIME).unwrap();
+    fs.remove("/KERNEL.BIN").unwrap();
+    drop(fs);
+
+    let (mut fs, entries): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert!(!entries.contains_key("KERNEL.BIN"));
+    assert_eq!(fs.read_file("/SUB/DEEP/DATA.BIN").unwrap(), pattern(5000, 1));
+    assert_eq!(fs.read_file("/SUB/EMPTY.TXT").unwrap(), b"");
+    assert_eq!(fs.read_file("/BIGFILE.TXT").unwrap(), pattern(100, 2));
+    drop(fs);
+
+    // Emptying the tree frees every cluster
+    let mut fs: Fat12Fs = image.open_rw();
+    let free: usize = fs.get_free_clusters();
+    for path in ["/SUB/DEEP/DATA.BIN", "/SUB/DEEP", "/SUB/EMPTY.TXT", "/SUB"] { fs.remove(path).unwrap(); }
+    assert_eq!(fs.get_free_clusters(), free + 10 + 1 + 1);
+    drop(fs);
+    reopen(&image);
+}
+
+#[test]
+fn sync_copies_both_ways() {
+    let image: TempImage = TempImage::blank_floppy("round-trip-sync");
+    let host: TempDir = TempDir::new("round-trip-sync-host");
+    fs::create_dir(host.0.join("SUB")).unwrap();
+    fs::write(host.0.join("A.TXT"), b"host a").unwrap();
+    fs::write(host.0.join("SUB").join("B.BIN"), pattern(3000, 3)).unwrap();
+
+    let mut fs: Fat12Fs = image.open_rw();
+    let report: SyncReport = sync::sync(&mut fs, "/", &host.0, &SyncOptions::default()).unwrap();
+    assert_eq!(report.actions.len(), 2, "{:?}", report.actions);
+    drop(fs);
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.read_file("/SUB/B.BIN").unwrap(), pattern(3000, 3));
+    drop(fs);
+
+    // A change on the image goes to the host, a delete on the host to the image
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.write_file("/SUB/B.BIN", &pattern(700, 4), TIME).unwrap();
+    fs::remove_file(host.0.join("A.TXT")).unwrap();
+    let report: SyncReport = sync::sync(&mut fs, "/", &host.0, &SyncOptions::default()).unwrap();
+    assert!(report.actions.contains(&SyncAction::CopyToHost(String::from("SUB/B.BIN"))), "{:?}", report.actions);
+    assert!(report.actions.contains(&SyncAction::DeleteFromImage(String::from("A.TXT"))), "{:?}", report.actions);
+    drop(fs);
+
+    let (_, entries): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert!(!entries.contains_key("A.TXT"));
+    assert_eq!(fs::read(host.0.join("SUB").join("B.BIN")).unwrap(), pattern(700, 4));
+}
+
+#[test]
+fn bulk_import_and_text_mode_write_whole_trees() {
+    let image: TempImage = TempImage::blank_floppy("round-trip-import");
+    let host: TempDir = TempDir::new("round-trip-import-host");
+    fs::create_dir_all(host.0.join("DOCS").join("OLD")).unwrap();
+    fs::write(host.0.join("DOCS").join("README.TXT"), b"one\ntwo\n").unwrap();
+    fs::write(host.0.join("DOCS").join("OLD").join("DATA.TXT"), b"line\n".repeat(2000)).unwrap();
+
+    let mut fs: Fat12Fs = image.open_rw();
+    let options: BulkOptions = BulkOptions { text_mode: TextMode::line_endings(), ..BulkOptions::default() };
+    bulk::import(&mut fs, &host.0, "/", &options).unwrap();
+    drop(fs);
+
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.read_file("/DOCS/README.TXT").unwrap(), b"one\r\ntwo\r\n");
+    assert_eq!(fs.read_file("/DOCS/OLD/DATA.TXT").unwrap(), b"line\r\n".repeat(2000));
+}
+
+#[test]
+fn sources_are_written_through_the_same_path() {
+    let image: TempImage = TempImage::blank_floppy("round-trip-sources");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.create_dir("/GEN", TIME).unwrap();
+    let mut sources: Vec<(String, Box<dyn FileSource>)> = vec![
+        (String::from("ONE.BIN"), Box::new(pattern(1500, 6))),
+        (String::from("TWO.TXT"), Box::new(Generator(|| Ok(b"generated".to_vec()))))
+    ];
+    bulk::import_sources(&mut fs, &mut sources, "/GEN", TIME, &BulkOptions::default()).unwrap();
+    fs.write_from("/THREE.TXT", &mut String::from("from a string"), TIME).unwrap();
+    drop(fs);
+
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.read_file("/GEN/ONE.BIN").unwrap(), pattern(1500, 6));
+    assert_eq!(fs.read_file("/GEN/TWO.TXT").unwrap(), b"generated");
+    assert_eq!(fs.read_file("/THREE.TXT").unwrap(), b"from a string");
+}
+
+#[test]
+fn entry_handles_write_relative_to_their_directory() {
+    let image: TempImage = TempImage::test_floppy("round-trip-handles");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.create_dir("/SUB", TIME).unwrap();
+    let mut sub = fs.open_dir("/SUB").unwrap();
+    sub.create_dir("INNER", TIME).unwrap();
+    sub.create_file("INNER/FILE.BIN", &pattern(2000, 7), TIME).unwrap();
+    sub.create_file("GONE.TXT", b"removed", TIME).unwrap();
+    sub.remove("GONE.TXT").unwrap();
+    drop(fs);
+
+    let (mut fs, entries): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert!(!entries.contains_key("SUB/GONE.TXT"));
+    assert_eq!(fs.read_file("/SUB/INNER/FILE.BIN").unwrap(), pattern(2000, 7));
+}
+
+#[test]
+fn overlays_write_only_on_commit() {
+    let image: TempImage = TempImage::test_floppy("round-trip-overlay");
+    let before: Vec<u8> = fs::read(&image.0).unwrap();
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).overlay(true).open(image.path()).unwrap();
+    fs.write_file("/NEW.BIN", &pattern(4000, 8), TIME).unwrap();
+    fs.remove("/KERNEL.BIN").unwrap();
+    fs.flush().unwrap();
+    assert_eq!(fs::read(&image.0).unwrap(), before);
+    fs.commit().unwrap();
+    drop(fs);
+
+    let (mut fs, entries): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert!(!entries.contains_key("KERNEL.BIN"));
+    assert_eq!(fs.read_file("/NEW.BIN").unwrap(), pattern(4000, 8));
+}
+
+#[test]
+fn label_serial_and_attributes_keep_the_tree() {
+    let image: TempImage = TempImage::test_floppy("round-trip-volume");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.set_volume_label("NEW LABEL").unwrap();
+    fs.set_volume_id(0x1234ABCD).unwrap();
+    fs.set_attributes("/KERNEL.BIN", ATTR_READ_ONLY | ATTR_HIDDEN).unwrap();
+    drop(fs);
+
+    let (mut fs, entries): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.get_volume_label().unwrap().as_deref(), Some("NEW LABEL"));
+    assert_eq!({ entries["KERNEL.BIN"].attributes }, ATTR_READ_ONLY | ATTR_HIDDEN);
+    assert_eq!(fs.read_file("/BIGFILE.TXT").unwrap().len(), 1024);
+}
+
+#[test]
+fn in_place_writes_grow_files_by_whole_clusters() {
+    let image: TempImage = TempImage::test_floppy("round-trip-in-place");
+    let mut expected: Vec<u8> = image.open().read_file("/BIGFILE.TXT").unwrap();
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.append("/BIGFILE.TXT", &pattern(700, 9), TIME).unwrap();
+    expected.extend(pattern(700, 9));
+    fs.write_at("/BIGFILE.TXT", 1000, &pattern(100, 10), TIME).unwrap();
+    expected[1000..1100].copy_from_slice(&pattern(100, 10));
+
+    // Past the end, the gap reads as zeros
+    fs.write_at("/BIGFILE.TXT", 2000, b"tail", TIME).unwrap();
+    expected.resize(2000, 0);
+    expected.extend(b"tail");
+    drop(fs);
+
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.read_file("/BIGFILE.TXT").unwrap(), expected);
+}
+
+#[test]
+fn sorting_a_directory_keeps_every_entry_and_chain() {
+    let image: TempImage = TempImage::blank_floppy("round-trip-sort");
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).name_policy(NamePolicy::LongNames).open(image.path()).unwrap();
+    fs.create_dir("/SUB", TIME).unwrap();
+    for (i, name) in ["Charlie.txt", "ALPHA.TXT", "Bravo long name.bin"].iter().enumerate() {
+        fs.write_file(&format!("/SUB/{}", name), &pattern(600 * (i + 1), i as u8), TIME).unwrap();
+    }
+    fs.remove("/SUB/Charlie.txt").unwrap();
+    fs.sort_dir("/SUB", SortKey::Size, true).unwrap();
+    drop(fs);
+
+    let (mut fs, entries): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(entries.len(), 3);
+    let names: Vec<String> = fs.read_name_pairs("/SUB").unwrap().iter().map(|pair| pair.get_display_name().to_string()).collect();
+    assert_eq!(names, ["Bravo long name.bin", "ALPHA.TXT"]);
+    assert_eq!(fs.read_file("/SUB/Bravo long name.bin").unwrap(), pattern(1800, 2));
+}
+
+#[test]
+fn active_fat_writes_leave_the_other_copy() {
+    let image: TempImage = TempImage::test_floppy("round-trip-active-fat");
+    let options = || Fat12Fs::options().fat_mirroring(FatMirroring::Active(1));
+    let mut fs: Fat12Fs = options().read_only(false).open(image.path()).unwrap();
+    fs.write_file("/SECOND.BIN", &pattern(3000, 11), TIME).unwrap();
+    drop(fs);
+
+    // The first copy still describes the clusters as they were, free
+    let data: Vec<u8> = fs::read(&image.0).unwrap();
+    assert_ne!(data[512..10 * 512], data[10 * 512..19 * 512]);
+    let mut fs: Fat12Fs = options().open(image.path()).unwrap();
+    check_consistency(&mut fs);
+    assert_eq!(fs.read_file("/SECOND.BIN").unwrap(), pattern(3000, 11));
+    drop(fs);
+
+    let mut fs: Fat12Fs = options().read_only(false).open(image.path()).unwrap();
+    fs.mirror_fat().unwrap();
+    drop(fs);
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.read_file("/SECOND.BIN").unwrap(), pattern(3000, 11));
+}
+
+#[test]
+fn discarding_an_overlay_reads_back_the_active_fat() {
+    let image: TempImage = TempImage::test_floppy("round-trip-active-discard");
+    let options = || Fat12Fs::options().fat_mirroring(FatMirroring::Active(1)).read_only(false);
+    let mut fs: Fat12Fs = options().open(image.path()).unwrap();
+    fs.write_file("/SECOND.BIN", &pattern(3000, 13), TIME).unwrap();
+    drop(fs);
+
+    let data: Vec<u8> = fs::read(&image.0).unwrap();
+    let mut fs: Fat12Fs = options().overlay(true).open(image.path()).unwrap();
+    let free_clusters: usize = fs.get_free_clusters();
+    fs.write_file("/THIRD.BIN", &pattern(2000, 14), TIME).unwrap();
+    fs.discard().unwrap();
+    assert_eq!(fs.get_fat().as_bytes(), &data[10 * 512..19 * 512]);
+    assert_eq!(fs.get_free_clusters(), free_clusters);
+    assert_eq!(fs.read_file("/SECOND.BIN").unwrap(), pattern(3000, 13));
+    drop(fs);
+    assert_eq!(fs::read(&image.0).unwrap(), data);
+}
+
+#[test]
+fn resizing_the_volume_keeps_the_files() {
+    let image: TempImage = TempImage::test_floppy("round-trip-resize");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.write_file("/DATA.BIN", &pattern(20000, 12), TIME).unwrap();
+    let minimum: u64 = fs.get_minimum_size(true);
+    fs.resize(minimum, ResizeOptions { filesystem: true, dry_run: false }).unwrap();
+    drop(fs);
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.read_file("/DATA.BIN").unwrap(), pattern(20000, 12));
+    drop(fs);
+
+    // Growing to 2M needs a larger FAT, moving the data after it
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.resize(2 * 1024 * 1024, ResizeOptions { filesystem: true, dry_run: false }).unwrap();
+    fs.write_file("/MORE.BIN", &pattern(50000, 13), TIME).unwrap();
+    drop(fs);
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.read_file("/DATA.BIN").unwrap(), pattern(20000, 12));
+    assert_eq!(fs.read_file("/MORE.BIN").unwrap(), pattern(50000, 13));
+}
+
+#[test]
+fn growing_the_fat_replaces_the_image_only_once_whole() {
+    let image: TempImage = TempImage::test_floppy("round-trip-resize-copy");
+    let copy_path: std::path::PathBuf = image.0.with_extension("resize.tmp");
+    let options: ResizeOptions = ResizeOptions { filesystem: true, dry_run: false };
+
+    // A copy that can't be written leaves the image and the handle as they were
+    let before: Vec<u8> = fs::read(&image.0).unwrap();
+    fs::create_dir(&copy_path).unwrap();
+    let mut fs: Fat12Fs = image.open_rw();
+    assert!(fs.resize(2 * 1024 * 1024, options).is_err());
+    fs::remove_dir(&copy_path).unwrap();
+    assert_eq!(fs::read(&image.0).unwrap(), before);
+    assert_eq!(fs.read_file("/BIGFILE.TXT").unwrap().len(), 1024);
+
+    fs.resize(2 * 1024 * 1024, options).unwrap();
+    assert!(!copy_path.exists());
+    fs.write_file("/AFTER.BIN", &pattern(3000, 15), TIME).unwrap();
+    drop(fs);
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs::metadata(&image.0).unwrap().len(), 2 * 1024 * 1024);
+    assert_eq!(fs.read_file("/AFTER.BIN").unwrap(), pattern(3000, 15));
+    drop(fs);
+
+    // Without the path of the image the data isn't moved in place
+    let image: TempImage = TempImage::test_floppy("round-trip-resize-file");
+    let file: fs::File = fs::OpenOptions::new().read(true).write(true).open(&image.0).unwrap();
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).open_file(file).unwrap();
+    assert_eq!(fs.resize(2 * 1024 * 1024, options).unwrap_err().kind(), io::ErrorKind::Unsupported);
+    drop(fs);
+    assert_eq!(fs::read(&image.0).unwrap(), before);
+}
+
+#[test]
+fn recovered_orphans_become_files() {
+    let image: TempImage = TempImage::test_floppy("round-trip-orphans");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.write_file("/LOST.BIN", &pattern(1500, 14), TIME).unwrap();
+    let first_cluster: Cluster = fs.get_entry("/LOST.BIN").unwrap().get_first_cluster();
+    drop(fs);
+
+    // Drop the entry but not the chain, as a crash between the two would
+    let mut data: Vec<u8> = fs::read(&image.0).unwrap();
+    let slot: usize = data[19 * 512..33 * 512].chunks(32).position(|entry| &entry[..11] == b"LOST    BIN").unwrap();
+    data[19 * 512 + slot * 32] = 0xE5;
+    fs::write(&image.0, data).unwrap();
+
+    let mut fs: Fat12Fs = image.open_rw();
+    let recovery: OrphanRecovery = fs.recover_orphans().unwrap();
+    assert_eq!(recovery.files, vec![(String::from("FILE0000.CHK"), 3)]);
+    drop(fs);
+
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    let path: String = format!("/{}/FILE0000.CHK", recovery.directory);
+    assert_eq!(fs.get_entry(&path).unwrap().get_first_cluster(), first_cluster);
+    assert_eq!(fs.read_file(&path).unwrap()[..1500], pattern(1500, 14));
+}
+
+#[test]
+fn split_sets_merge_back() {
+    let disks: Vec<TempImage> = (0..2).map(|i| TempImage::blank_floppy(&format!("round-trip-span-{}", i))).collect();
+    let host: TempDir = TempDir::new("round-trip-span-host");
+    let merged: TempDir = TempDir::new("round-trip-span-merged");
+    fs::create_dir(host.0.join("SUB")).unwrap();
+    fs::write(host.0.join("SUB").join("LARGE.BIN"), pattern(2_000_000, 15)).unwrap();
+    fs::write(host.0.join("SMALL.TXT"), b"small").unwrap();
+
+    let mut images: Vec<Fat12Fs> = disks.iter().map(TempImage::open_rw).collect();
+    span::split(&mut images, &host.0, &BulkOptions::default()).unwrap();
+    drop(images);
+
+    let mut images: Vec<Fat12Fs> = disks.iter().map(|disk| reopen(disk).0).collect();
+    span::merge(&mut images, &merged.0, &BulkOptions::default()).unwrap();
+    assert_eq!(fs::read(merged.0.join("SUB").join("LARGE.BIN")).unwrap(), pattern(2_000_000, 15));
+    assert_eq!(fs::read(merged.0.join("SMALL.TXT")).unwrap(), b"small");
+}
+
+#[test]
+fn provisioned_images_are_updated_in_place() {
+    let image: TempImage = TempImage(common::temp_path("round-trip-provision"));
+    let template = |items: Vec<Item>| Template {
+        boot_sector: provision::floppy_boot_sector(1440).unwrap(),
+        boot_code: None,
+        reserved_region: None,
+        label: Some(String::from("PROVISION")),
+        timestamp: TIME,
+        items
+    };
+    let item = |path: &str, source: Option<Box<dyn FileSource>>| Item { path: path.to_string(), source, attributes: 0 };
+
+    let mut first: Template = template(vec![
+        item("/BOOT", None),
+        item("/BOOT/KERNEL.BIN", Some(Box::new(pattern(5000, 16)))),
+        item("/OLD.TXT", Some(Box::new(String::from("old"))))
+    ]);
+    drop(provision::provision(&image.0, &mut first, &Fat12Fs::options()).unwrap());
+    reopen(&image);
+
+    // The second template drops a file and changes another
+    let mut second: Template = template(vec![
+        item("/BOOT", None),
+        item("/BOOT/KERNEL.BIN", Some(Box::new(pattern(9000, 17))))
+    ]);
+    let mut fs: Fat12Fs = image.open_rw();
+    provision::apply(&mut fs, &mut second, false).unwrap();
+    drop(fs);
+    let (mut fs, entries): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert!(!entries.contains_key("OLD.TXT"));
+    assert_eq!(fs.read_file("/BOOT/KERNEL.BIN").unwrap(), pattern(9000, 17));
+}
+
+#[test]
+fn trimmed_and_sparse_images_read_the_same() {
+    let image: TempImage = TempImage::test_floppy("round-trip-trim");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.write_file("/KEPT.BIN", &pattern(3000, 18), TIME).unwrap();
+    fs.write_file("/GONE.BIN", &pattern(3000, 19), TIME).unwrap();
+    fs.remove("/GONE.BIN").unwrap();
+    fs.trim().unwrap();
+    drop(fs);
+    sparse::sparsify(&image.0).unwrap();
+
+    let (mut fs, _): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(fs.read_file("/KEPT.BIN").unwrap(), pattern(3000, 18));
+    assert_eq!(fs.read_file("/KERNEL.BIN").unwrap().len(), 58);
+}
+
+#[test]
+fn sanitized_names_get_unique_tails() {
+    let image: TempImage = TempImage::blank_floppy("round-trip-sanitize");
+    let mut fs: Fat12Fs = Fat12Fs::options().read_only(false).name_policy(NamePolicy::Sanitize).short_name_tails(Some(Arc::new(NumericTail))).open(image.path()).unwrap();
+    for (i, name) in ["report+2020.txt", "report=2021.txt", "report;2022.txt"].iter().enumerate() {
+        fs.write_file(&format!("/{}", name), &pattern(100, i as u8), TIME).unwrap();
+    }
+    drop(fs);
+    let e: io::Error = Fat12Fs::options().read_only(false).name_policy(NamePolicy::Strict).open(image.path()).unwrap()
+        .write_file("/lower.txt", b"refused", TIME).unwrap_err();
+    assert_eq!(e.kind(), io::ErrorKind::InvalidInput);
+
+    let (mut fs, entries): (Fat12Fs, BTreeMap<String, DirectoryEntry>) = reopen(&image);
+    assert_eq!(entries.keys().collect::<Vec<_>>(), ["REPORT~1.TXT", "REPORT~2.TXT", "REPORT~3.TXT"]);
+    assert_eq!(fs.read_file("/REPORT~3.TXT").unwrap(), pattern(100, 2));
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, process::{Child, Command, Stdio}, thread, time::Duration};
+use common::TempImage;
+use rs_disk_reader::Fat12Fs;
+
+const NBD_IHAVEOPT: u64 = 0x49484156454F5054;
+const NBD_OPT_LIST: u32 = 3;
+const NBD_OPT_ABORT: u32 = 2;
+const NBD_REP_ACK: u32 = 1;
+const NBD_REP_SERVER: u32 = 2;
+const NBD_REP_ERR_TOO_BIG: u32 = (1 << 31) + 9;
+
+/** The binary serving an image with the command, killed on drop. */
+struct Server {
+    child: Child,
+    address: String
+}
+
+impl Server {
+    fn start(command: &str, image: &str) -> Server {
+        // A port free a moment ago, most likely still free
+        let address: String = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+        let child: Child = Command::new(env!("CARGO_BIN_EXE_rs-disk-reader"))
+            .args([command, image, "--listen", &address])
+            .current_dir(std::env::temp_dir())
+            .stdout(Stdio::null()).stderr(Stdio::null())
+            .spawn().unwrap();
+        let server: Server = Server { child, address };
+        for _ in 0..100 {
+            if TcpStream::connect(&server.address).is_ok() { return server; }
+            thread::sleep(Duration::from_millis(50));
+        }
+        panic!("{} didn't start listening on {}", command, server.address);
+    }
+
+    fn connect(&self) -> TcpStream {
+        let stream: TcpStream = TcpStream::connect(&self.address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream
+    }
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/** Send a raw HTTP request, give the whole response. */
+fn http(server: &Server, request: &[u8]) -> String {
+    let mut stream: TcpStream = server.connect();
+    stream.write_all(request).unwrap();
+    let mut response: Vec<u8> = vec![];
+    stream.read_to_end(&mut response).unwrap();
+    String::from_utf8_lossy(&response).to_string()
+}
+
+fn read_bytes<const N: usize>(stream: &mut TcpStream) -> [u8; N] {
+    let mut bytes: [u8; N] = [0; N];
+    stream.read_exact(&mut bytes).unwrap();
+    bytes
+}
+
+/** Reply to an option: its type and data. */
+fn read_option_reply(stream: &mut TcpStream, option: u32) -> (u32, Vec<u8>) {
+    assert_eq!(u64::from_be_bytes(read_bytes(stream)), 0x0003E889045565A9);
+    assert_eq!(u32::from_be_bytes(read_bytes(stream)), option);
+    let reply: u32 = u32::from_be_bytes(read_bytes(stream));
+    let mut data: Vec<u8> = vec![0; u32::from_be_bytes(read_bytes(stream)) as usize];
+    stream.read_exact(&mut data).unwrap();
+    (reply, data)
+}
+
+fn send_option_header(stream: &mut TcpStream, option: u32, length: u32) {
+    stream.write_all(&[NBD_IHAVEOPT.to_be_bytes().as_slice(), &option.to_be_bytes(), &length.to_be_bytes()].concat()).unwrap();
+}
+
+/** Read the greeting and agree on no zeroes. */
+fn nbd_handshake(stream: &mut TcpStream) {
+    assert_eq!(&read_bytes::<8>(stream), b"NBDMAGIC");
+    assert_eq!(u64::from_be_bytes(read_bytes(stream)), NBD_IHAVEOPT);
+    let _flags: [u8; 2] = read_bytes(stream);
+    stream.write_all(&2u32.to_be_bytes()).unwrap();
+}
+
+#[test]
+fn nbd_refuses_oversized_options_and_goes_on() {
+    let server: Server = Server::start("export", concat!(env!("CARGO_MANIFEST_DIR"), "/test_floppy.img"));
+    let mut stream: TcpStream = server.connect();
+    nbd_handshake(&mut stream);
+
+    // Past the limit, the data is skipped and the option refused
+    send_option_header(&mut stream, NBD_OPT_LIST, 20 * 1024);
+    stream.write_all(&[0; 20 * 1024]).unwrap();
+    assert_eq!(read_option_reply(&mut stream, NBD_OPT_LIST).0, NBD_REP_ERR_TOO_BIG);
+
+    // Options within the limit still get their answer
+    send_option_header(&mut stream, NBD_OPT_LIST, 0);
+    assert_eq!(read_option_reply(&mut stream, NBD_OPT_LIST), (NBD_REP_SERVER, [&6u32.to_be_bytes()[..], b"floppy"].concat()));
+    assert_eq!(read_option_reply(&mut stream, NBD_OPT_LIST).0, NBD_REP_ACK);
+    send_option_header(&mut stream, NBD_OPT_ABORT, 0);
+    assert_eq!(read_option_reply(&mut stream, NBD_OPT_ABORT).0, NBD_REP_ACK);
+}
+
+#[test]
+fn http_links_survive_names_with_url_characters() {
+    let image: TempImage = TempImage::test_floppy("serve-names");
+    let mut fs: Fat12Fs = image.open_rw();
+    fs.create_dir("/DIR#1", 0).unwrap();
+    fs.write_file("/DIR#1/100%.TXT", b"all of it", 0).unwrap();
+    drop(fs);
+    let server: Server = Server::start("serve", image.path());
+
+    // Directories without the trailing slash are sent to it, the query kept
+    let response: String = http(&server, b"GET /DIR%231?format=json HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 301"), "{}", response);
+    assert!(response.contains("\r\nLocation: /DIR%231/?format=json\r\n"), "{}", response);
+
+    // Links are percent-encoded, so that they lead back to the entry
+    let response: String = http(&server, b"GET /DIR%231/ HTTP/1.1\r\n\r\n");
+    assert!(response.contains("<a href=\"100%25.TXT\">100%.TXT</a>"), "{}", response);
+    let response: String = http(&server, b"GET /DIR%231/100%25.TXT HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 200 OK") && response.ends_with("\r\n\r\nall of it"), "{}", response);
+    let response: String = http(&server, b"GET / HTTP/1.1\r\n\r\n");
+    assert!(response.contains("<a href=\"DIR%231/\">DIR#1/</a>"), "{}", response);
+}
+
+#[test]
+fn http_request_heads_are_bounded() {
+    let server: Server = Server::start("serve", concat!(env!("CARGO_MANIFEST_DIR"), "/test_floppy.img"));
+    let request: Vec<u8> = [&b"GET / HTTP/1.1\r\nX-Padding: "[..], &vec![b'a'; 64 * 1024], b"\r\n\r\n"].concat();
+    assert!(http(&server, &request).starts_with("HTTP/1.1 431"));
+    let request: Vec<u8> = [&b"GET /"[..], &vec![b'a'; 64 * 1024], b" HTTP/1.1\r\n\r\n"].concat();
+    assert!(http(&server, &request).starts_with("HTTP/1.1 414"));
+    assert!(http(&server, b"GET /KERNEL.BIN HTTP/1.1\r\nHost: localhost\r\n\r\n").starts_with("HTTP/1.1 200 OK"));
+}
@@ -0,0 +1,53 @@
+mod common;
+
+use common::TempImage;
+use rs_disk_reader::Fat12Fs;
+
+fn open_write_back(image: &TempImage) -> Fat12Fs {
+    Fat12Fs::options().read_only(false).write_back(true).open(image.path()).unwrap()
+}
+
+#[test]
+fn dropping_a_clone_keeps_later_writes() {
+    let image: TempImage = TempImage::test_floppy("write-back-clone");
+    let mut fs: Fat12Fs = open_write_back(&image);
+    fs.write_file("/NOTE.TXT", b"first", 0).unwrap();
+    let clone: Fat12Fs = fs.clone();
+    fs.write_file("/NOTE.TXT", b"second", 0).unwrap();
+    fs.flush().unwrap();
+    assert_eq!(clone.get_dirty_size(), 0);
+
+    // The clone has nothing of its own to write back
+    drop(clone);
+    drop(fs);
+    assert_eq!(image.open().read_file("/NOTE.TXT").unwrap(), b"second");
+}
+
+#[test]
+fn writes_through_a_clone_are_flushed_once() {
+    let image: TempImage = TempImage::test_floppy("write-back-clone-writer");
+    let mut fs: Fat12Fs = open_write_back(&image);
+    let mut clone: Fat12Fs = fs.clone();
+    clone.write_file("/NOTE.TXT", b"from the clone", 0).unwrap();
+    assert_eq!(fs.get_dirty_size(), clone.get_dirty_size());
+    drop(clone);
+    fs.flush().unwrap();
+    assert_eq!(fs.get_dirty_size(), 0);
+    drop(fs);
+    assert_eq!(image.open().read_file("/NOTE.TXT").unwrap(), b"from the clone");
+}
+
+#[test]
+fn readers_keep_the_sectors_they_were_made_with() {
+    let image: TempImage = TempImage::test_floppy("write-back-reader");
+    let mut fs: Fat12Fs = open_write_back(&image);
+    fs.write_file("/NOTE.TXT", b"first", 0).unwrap();
+    let mut reader: Fat12Fs = fs.reader();
+    fs.write_file("/NOTE.TXT", b"second", 0).unwrap();
+    assert_eq!(reader.read_file("/NOTE.TXT").unwrap(), b"first");
+
+    // Dropping the reader writes nothing, flushing the writer everything
+    drop(reader);
+    drop(fs);
+    assert_eq!(image.open().read_file("/NOTE.TXT").unwrap(), b"second");
+}